@@ -0,0 +1,229 @@
+use crate::{byte_value, SegmentMap, ValueMap, IHEX_SEGMENT_BYTES};
+
+/* The reflected polynomial of the common CRC-32, as used by zlib, Ethernet and most bootloaders */
+const CRC32_POLY: u32 = 0xEDB8_8320;
+
+/* A CRC-32 which can be fed in pieces */
+#[derive(Debug, Clone)]
+pub struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = [0u32; 256];
+        for (idx, entry) in table.iter_mut().enumerate() {
+            *entry = (0..8).fold(idx as u32, |crc, _| if crc & 1 != 0 {(crc >> 1) ^ CRC32_POLY} else {crc >> 1});
+        }
+        Crc32 { table, crc: 0xFFFF_FFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.crc = self.table[((self.crc ^ *byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/* A SHA-256 (FIPS 180-4) which can be fed in pieces */
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// Bytes waiting for a full 64 byte block
+    pending: Vec<u8>,
+    length: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256::new()
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19],
+            pending: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (idx, word) in block.chunks(4).enumerate() {
+            w[idx] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for idx in 16..64 {
+            let s0 = w[idx - 15].rotate_right(7) ^ w[idx - 15].rotate_right(18) ^ (w[idx - 15] >> 3);
+            let s1 = w[idx - 2].rotate_right(17) ^ w[idx - 2].rotate_right(19) ^ (w[idx - 2] >> 10);
+            w[idx] = w[idx - 16].wrapping_add(s0).wrapping_add(w[idx - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for idx in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(SHA256_K[idx]).wrapping_add(w[idx]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(t1), c, b, a, t1.wrapping_add(t2));
+        }
+        for (word, add) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.pending.is_empty() {
+            let take = (64 - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() < 64 {
+                return;
+            }
+            let block = std::mem::take(&mut self.pending);
+            self.compress(&block);
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.compress(block);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bits = self.length * 8;
+        let mut padding = vec![0x80u8];
+        padding.resize(1 + (119 - self.pending.len()) % 64, 0);
+        padding.extend_from_slice(&bits.to_be_bytes());
+        self.update(&padding);
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+/* The CRC-32 of the flat image of [start, end), with unoccupied bytes set to fill_byte */
+fn range_crc(segment_map: &SegmentMap, values: &ValueMap, start: u64, end: u64, fill_byte: u8) -> u32 {
+    let mut crc = Crc32::new();
+    let bytes: Vec<u8> = (start..end).map(|addr| byte_value(segment_map, values, addr as u32).unwrap_or(fill_byte)).collect();
+    crc.update(&bytes);
+    crc.finish()
+}
+
+/**
+ * The CRC-32 of every page_size aligned page holding data, as [start, end) and CRC in address order, each page whole
+ * with its unoccupied bytes set to fill_byte. This is what bootloaders verifying a page at a time compute on the device.
+ */
+pub fn page_crcs(ranges: &[(u64, u64)], segment_map: &SegmentMap, values: &ValueMap, page_size: u32, fill_byte: u8) -> Vec<(u64, u64, u32)> {
+    let page_size = page_size as u64;
+    let mut pages: Vec<u64> = ranges.iter()
+        .flat_map(|(start, end)| start / page_size..=(end - 1) / page_size)
+        .collect();
+    pages.dedup();
+    pages.iter()
+        .map(|page| {
+            let (start, end) = (page * page_size, ((page + 1) * page_size).min(1 << 32));
+            (start, end, range_crc(segment_map, values, start, end, fill_byte))
+        })
+        .collect()
+}
+
+/**
+ * The CRC-32 and SHA-256 of the flat image of [start, end), with unoccupied bytes set to fill_byte, as a release
+ * tool building a .bin from the hex file would see it. The image is fed in pages so a wide range is never held whole.
+ */
+pub fn image_checksums(segment_map: &SegmentMap, values: &ValueMap, start: u64, end: u64, fill_byte: u8) -> (u32, [u8; 32]) {
+    let mut crc = Crc32::new();
+    let mut sha = Sha256::new();
+    let mut chunk_start = start;
+    while chunk_start < end {
+        let chunk_end = ((chunk_start / IHEX_SEGMENT_BYTES as u64 + 1) * IHEX_SEGMENT_BYTES as u64).min(end);
+        let chunk: Vec<u8> = (chunk_start..chunk_end)
+            .map(|addr| byte_value(segment_map, values, addr as u32).unwrap_or(fill_byte))
+            .collect();
+        crc.update(&chunk);
+        sha.update(&chunk);
+        chunk_start = chunk_end;
+    }
+    (crc.finish(), sha.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksums::{page_crcs, Crc32, Sha256};
+    use crate::{fill_bytes, SegmentMap, ValueMap, IHEX_SEGMENT_BYTES, SEGMENT_BYTES};
+
+    fn sha256_hex(data: &[u8]) -> String {
+        let mut sha = Sha256::new();
+        sha.update(data);
+        sha.finish().iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn test_checksums() -> Result<(),String> {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(0xCBF4_3926, crc.finish());
+        assert_eq!(0, Crc32::new().finish());
+
+        assert_eq!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", sha256_hex(b""));
+        assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad", sha256_hex(b"abc"));
+        assert_eq!("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+                   sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"));
+        /* Fed in odd pieces across block boundaries */
+        let mut sha = Sha256::new();
+        for piece in vec![b'a'; 1000].chunks(37) {
+            sha.update(piece);
+        }
+        assert_eq!(sha256_hex(&[b'a'; 1000]), sha.finish().iter().map(|byte| format!("{byte:02x}")).collect::<String>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_crcs() -> Result<(),String> {
+        let mut segment = vec![0; SEGMENT_BYTES as usize];
+        fill_bytes(&mut segment, 0xFE, 4);
+        let segment_map = SegmentMap::from([(0, segment)]);
+        let values = ValueMap::from([(0, vec![0; IHEX_SEGMENT_BYTES as usize])]);
+        /* The data straddles two pages, each of which is checked whole with the fill around the zeroes */
+        let mut first = vec![0xFF; 0xFE];
+        first.extend([0, 0]);
+        let mut crc = Crc32::new();
+        crc.update(&first);
+        let mut second = vec![0, 0];
+        second.extend([0xFF; 0xFE]);
+        let mut second_crc = Crc32::new();
+        second_crc.update(&second);
+        assert_eq!(vec![(0x0, 0x100, crc.finish()), (0x100, 0x200, second_crc.finish())],
+                   page_crcs(&[(0xFE, 0x102)], &segment_map, &values, 0x100, 0xFF));
+        Ok(())
+    }
+}
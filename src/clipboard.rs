@@ -0,0 +1,43 @@
+use std::io::{self, Write};
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/* Standard base64 with padding, as OSC 52 expects */
+pub fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, byte)| bits | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_CHARS[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/**
+ * Puts text on the system clipboard with the OSC 52 escape sequence. The terminal does the copying, so this works
+ * over SSH too, but terminals may ignore the sequence (some only allow it once enabled) and nothing reports back.
+ */
+pub fn copy(out: &mut impl Write, text: &str) -> io::Result<()> {
+    write!(out, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clipboard::base64;
+
+    #[test]
+    fn test_base64() -> Result<(),String> {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("MHgwODAwMDAwMA==", base64(b"0x08000000"));
+        Ok(())
+    }
+}
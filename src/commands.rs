@@ -0,0 +1,734 @@
+use std::io::{self, Write};
+use std::path::Path;
+use log::warn;
+use crate::devices::{self, Flash, SectorLayout};
+use crate::error::{read_file, read_text, write_file, Error};
+use crate::hex_loader::{self, HexImage, LoadOptions};
+use crate::mcu::McuFamily;
+use crate::memory_map::MemoryMap;
+use crate::regions::{self, Region};
+use crate::structured::{OutputFormat, Value};
+use crate::{checksums, cortex_m, elf, exports, gif_export, hex_check, history, linker_map, narration, pattern, pdf_export,
+            record_stats, reports, signatures, stats, svd};
+use crate::{changed_ranges, clip_ranges, map_line_cells, occupied_ranges, overlay_image, subtract_ranges,
+            touched_sector_spans, SegmentMap, ValueMap, IHEX_SEGMENT_BYTES, SEGMENT_BYTES};
+
+/*
+ * What the command line does once its options are read, from loading the files and their labels to the checks,
+ * exports, commands and reports. Each step takes its own options rather than the command line's, so build tooling can
+ * run the same steps, and prints to the writer it is given. Files go with their images as (path, image) pairs.
+ */
+
+/* How much of the flash an image may use, from --max-used */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Budget {
+    Bytes(u32),
+    /// A percentage of the flash
+    Percent(f64),
+}
+
+/* Where to write each export of the first file, None leaving it out */
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub ld: Option<String>,
+    pub bitmask: Option<String>,
+    pub regions: Option<String>,
+    pub gdb: Option<String>,
+    /// Whether the GDB script of a hex file compares the ranges it loads, which needs a GDB built with Python
+    pub gdb_python: bool,
+    pub openocd: Option<String>,
+    pub pyocd: Option<String>,
+    pub jlink: Option<String>,
+    pub gaps_hex: Option<String>,
+    /// An animated GIF with one frame per file, the only export covering every file
+    pub gif: Option<String>,
+    pub pdf: Option<String>,
+    /// The end of the device flash, which the last free region of the ld block runs to
+    pub memory_end: Option<u64>,
+    /// The erase sector size of the flash scripts
+    pub sector_size: u32,
+    /// The byte the gaps hex file is filled with
+    pub fill_byte: u8,
+    pub bytes_per_line: u16,
+    pub width_symbols: u16,
+}
+
+/* What diff prints from each file to the next */
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// The ranges added, removed and, with the byte values loaded, changed
+    pub diff: bool,
+    /// The sectors to erase and write again
+    pub delta: bool,
+    pub sector_layout: SectorLayout,
+    pub format: OutputFormat,
+}
+
+/* Which of the reports to print under each file, in this order, and what they need */
+#[derive(Debug, Clone)]
+pub struct ReportOptions {
+    pub stats: bool,
+    pub check: bool,
+    pub order: bool,
+    pub records: bool,
+    pub pages: bool,
+    pub sectors: bool,
+    pub ranges: bool,
+    /// How many of the largest free ranges to list
+    pub free: Option<usize>,
+    pub gaps: bool,
+    /// Free ranges smaller than this are left out of the gaps
+    pub min_gap: u32,
+    pub checksum: bool,
+    /// The checksum window, by default the span of the data
+    pub checksum_start: Option<u32>,
+    pub checksum_size: Option<u32>,
+    /// A pattern to list the addresses of, hex bytes or text
+    pub find: Option<String>,
+    /// The shortest string to list
+    pub strings: Option<usize>,
+    pub histogram: bool,
+    /// A raw binary to compare the data with, loaded at base or else the first used byte
+    pub reference: Option<String>,
+    pub base: Option<u32>,
+    pub padding: bool,
+    pub compress: bool,
+    /// The page size to list CRCs for
+    pub page_crc: Option<u32>,
+    pub entropy: bool,
+    pub entropy_block: u32,
+    /// The byte gaps are filled with for the checksums
+    pub fill_byte: u8,
+    pub mcu: Option<McuFamily>,
+    /// The flash the stats check the fit in, and free space is found within
+    pub flash: Option<Flash>,
+    /// Where free space is found without a flash, by default the span of the data
+    pub device_start: Option<u32>,
+    pub device_size: Option<u32>,
+    pub sector_layout: SectorLayout,
+    /// The memory of an SVD file, data outside which the check lists
+    pub memory: Option<Vec<(u64, u64)>>,
+    /// How the files were loaded, which the check reads them again with
+    pub load_options: LoadOptions,
+    /// Text, or JSON or YAML of the stats and check on their own
+    pub format: OutputFormat,
+}
+
+/* A write to the output failed, e.g. the pipe it went to was closed */
+fn output_error(err: io::Error) -> Error {
+    Error::Render(format!("The output could not be written: {err}"))
+}
+
+/* Prints lines as they are, one to a line */
+fn write_lines(out: &mut impl Write, lines: impl IntoIterator<Item = String>) -> Result<(), Error> {
+    lines.into_iter().try_for_each(|line| writeln!(out, "{line}")).map_err(output_error)
+}
+
+/* Prints a heading with lines indented under it */
+fn write_under(out: &mut impl Write, heading: &str, lines: impl IntoIterator<Item = String>) -> Result<(), Error> {
+    write_lines(out, std::iter::once(heading.to_string()).chain(lines.into_iter().map(|line| format!("  {line}"))))
+}
+
+/* Prints a document as JSON or YAML, or nothing for text, which is printed as it goes */
+fn write_formatted(out: &mut impl Write, value: Value, format: OutputFormat) -> Result<(), Error> {
+    match value.formatted(format) {
+        Some(text) => write!(out, "{text}").map_err(output_error),
+        None => Ok(()),
+    }
+}
+
+/* Refuses the first file with a malformed record, a wrong checksum, an unknown record type or a missing or misplaced end of file */
+pub fn check_strict(files: &[String]) -> Result<(), Error> {
+    for path in files {
+        let mut check = hex_check::StrictCheck::default();
+        hex_loader::for_each_line(path, |line, text| check.line(line, text))?;
+        if let Some(issue) = check.finish() {
+            return Err(Error::Check(format!("{path} {issue}")));
+        }
+    }
+    Ok(())
+}
+
+/* The regions of a region file. A file which does not exist yet starts out empty, to be filled from the viewer */
+pub fn read_regions(regions_path: &str) -> Result<Vec<Region>, Error> {
+    if !Path::new(regions_path).exists() {
+        return Ok(Vec::new());
+    }
+    regions::parse_regions(&read_text(regions_path)?, regions::RegionFormat::from_path(regions_path))
+        .map_err(|reason| Error::parse(regions_path, reason))
+}
+
+/**
+ * Labels from the build, which unlike the regions are never saved back: the output sections of a linker map, the
+ * sections and the largest elf_symbols symbols of whichever files are ELF, and a PIC or AVR family's configuration, fuse
+ * and EEPROM windows.
+ */
+pub fn build_labels(map_path: Option<&str>, files: &[String], elf_symbols: usize, mcu: Option<McuFamily>) -> Result<Vec<Region>, Error> {
+    let mut labels = match map_path {
+        Some(map_path) => linker_map::parse_map(&read_text(map_path)?),
+        None => Vec::new(),
+    };
+    for elf_path in files {
+        if elf::is_elf_file(elf_path).map_err(|source| Error::io(elf_path, source))? {
+            let data = read_file(elf_path)?;
+            labels.extend(elf::elf_sections(&data).map_err(|reason| Error::parse(elf_path, reason))?);
+            labels.extend(elf::elf_symbols(&data, elf_symbols).map_err(|reason| Error::parse(elf_path, reason))?);
+        }
+    }
+    if let Some(family) = mcu {
+        labels.extend(family.regions());
+    }
+    Ok(labels)
+}
+
+/* The flash to fit the image in, from the device database with any addresses given overriding it */
+pub fn device_flash(device: Option<&str>, start: Option<u32>, size: Option<u32>) -> Result<Option<Flash>, Error> {
+    let device = device.map(devices::find_device).transpose().map_err(Error::Argument)?;
+    Ok(match (device, start, size) {
+        (Some(device), start, size) => Some(Flash {
+            name: device.name.to_string(),
+            start: start.unwrap_or(device.flash_start),
+            size: size.unwrap_or(device.flash_size),
+            sectors: device.sectors,
+        }),
+        (None, Some(start), Some(size)) => Some(Flash { name: "device".to_string(), start, size, sectors: &[] }),
+        _ => None,
+    })
+}
+
+/* Erase sectors follow the device's own layout where it is known, else are all sector_size */
+pub fn sector_layout(flash: Option<&Flash>, sector_size: u32) -> SectorLayout {
+    SectorLayout {
+        base: flash.map_or(0, |flash| flash.start as u64),
+        sectors: flash.map_or(&[], |flash| flash.sectors),
+        uniform: sector_size,
+    }
+}
+
+/* The memory an SVD file describes, its peripherals and the flash, which all data should fall in. The peripherals are added to labels */
+pub fn svd_memory(svd_path: &str, flash: Option<&Flash>, labels: &mut Vec<Region>) -> Result<Vec<(u64, u64)>, Error> {
+    let peripherals = svd::parse_svd(&read_text(svd_path)?).map_err(|reason| Error::parse(svd_path, reason))?;
+    let mut memory: Vec<(u64, u64)> = peripherals.iter()
+        .map(|peripheral| (peripheral.start as u64, peripheral.start as u64 + peripheral.size as u64))
+        .chain(flash.map(|flash| (flash.start as u64, flash.end())))
+        .collect();
+    memory.sort();
+    labels.extend(peripherals);
+    Ok(memory)
+}
+
+/* The padding byte a file uses most, None if it has none */
+pub fn padding_byte(path: &str) -> Result<Option<u8>, Error> {
+    let image = hex_loader::load_file(path, LoadOptions { store_values: true, blank_byte: None })?;
+    Ok(image.values.as_ref().and_then(|values| stats::detect_padding(&image.segment_map, values)).map(|(byte, _)| byte))
+}
+
+/* Labels for the signatures found in the data of files loaded with their values */
+pub fn signature_labels(images: &[(String, HexImage)]) -> Vec<Region> {
+    images.iter()
+        .filter_map(|(_, image)| image.values.as_ref().map(|values| signatures::find_signatures(&image.segment_map, values)))
+        .flatten()
+        .collect()
+}
+
+/* Loads every file, then warns about its unreadable lines, its end of file and any data outside the memory of an SVD file */
+pub fn load_images(files: &[String], load_options: LoadOptions, memory: Option<&[(u64, u64)]>) -> Result<Vec<(String, HexImage)>, Error> {
+    let images = files.iter()
+        .map(|path| hex_loader::load_file_with_progress(path, load_options).map(|image| (path.clone(), image)))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (path, image) in &images {
+        for error in &image.errors {
+            warn!("{path} {error}");
+        }
+        for issue in &image.issues {
+            warn!("{path} {issue}");
+        }
+        if let Some(memory) = memory {
+            let mut check = hex_check::OutsideMemory::new(memory);
+            hex_loader::for_each_line(path, |line, text| check.line(line, text))?;
+            for issue in check.finish() {
+                warn!("{path} {issue}");
+            }
+        }
+    }
+    Ok(images)
+}
+
+/* The occupied ranges of every file, which are all the budget, history, query and narration steps look at */
+pub fn file_ranges(images: &[(String, HexImage)]) -> Vec<(String, Vec<(u64, u64)>)> {
+    images.iter().map(|(path, image)| (path.clone(), occupied_ranges(&image.segment_map))).collect()
+}
+
+/* Streams every file into a range set for --sparse, keeping neither bitmaps nor values */
+pub fn load_sparse(files: &[String], blank_byte: Option<u8>) -> Result<Vec<(String, MemoryMap)>, Error> {
+    files.iter().map(|path| MemoryMap::from_file_sparse(path, blank_byte).map(|map| (path.clone(), map))).collect()
+}
+
+/* The problems the check finds in a file, in line order, including data outside the memory of an SVD file */
+pub fn check_issues(path: &str, load_options: LoadOptions, memory: Option<&[(u64, u64)]>) -> Result<Vec<hex_check::Issue>, Error> {
+    /* Both checks are fed the same pass over the file */
+    let mut check = hex_check::HexCheck::new(load_options);
+    let mut outside = memory.map(hex_check::OutsideMemory::new);
+    hex_loader::for_each_line(path, |line, text| {
+        check.line(line, text);
+        if let Some(outside) = outside.as_mut() {
+            outside.line(line, text);
+        }
+    })?;
+    let mut issues = check.finish();
+    if let Some(outside) = outside {
+        issues.extend(outside.finish());
+        issues.sort_by_key(|issue| issue.line);
+    }
+    Ok(issues)
+}
+
+/**
+ * Fails unless every file uses at most the budget, printing one budget line per file. A percentage is of the flash, or
+ * without one of device_size. With an mcu family only its program flash counts.
+ */
+pub fn check_max_used(out: &mut impl Write, files: &[(String, Vec<(u64, u64)>)], budget: Budget, flash: Option<&Flash>,
+                      device_size: Option<u32>, mcu: Option<McuFamily>) -> Result<(), Error> {
+    let limit = match (budget, flash, device_size) {
+        (Budget::Bytes(bytes), ..) => bytes as u64,
+        (Budget::Percent(percent), Some(flash), _) => (flash.size as f64 * percent / 100.0) as u64,
+        (Budget::Percent(percent), None, Some(size)) => (size as f64 * percent / 100.0) as u64,
+        (Budget::Percent(_), None, None) => return Err(Error::Argument("A --max-used percentage needs the flash size, give --device or --device-size".to_string())),
+    };
+    let mut over = Vec::new();
+    for (path, ranges) in files {
+        let used = match mcu {
+            Some(family) => family.program_ranges(ranges).iter().map(|(start, end)| end - start).sum(),
+            None => stats::OccupancyStats::from_ranges(ranges).bytes_used,
+        };
+        write_lines(out, [reports::budget_line(path, None, used, limit)])?;
+        if used > limit {
+            over.push(path.as_str());
+        }
+    }
+    match over.is_empty() {
+        true => Ok(()),
+        false => Err(Error::Check(format!("{} over the budget of {limit} bytes", over.join(", ")))),
+    }
+}
+
+/* Fails unless each file's data within each region with a budget fits it, printing one budget line per region and file */
+pub fn check_region_budgets(out: &mut impl Write, files: &[(String, Vec<(u64, u64)>)], labels: &[Region]) -> Result<(), Error> {
+    let budgeted: Vec<&Region> = labels.iter().filter(|region| region.budget.is_some()).collect();
+    if budgeted.is_empty() {
+        return Err(Error::Argument("--budgets needs a --regions file giving at least one region a budget".to_string()));
+    }
+    let mut over = Vec::new();
+    for (path, ranges) in files {
+        for region in &budgeted {
+            let limit = region.budget.unwrap_or(0) as u64;
+            let used: u64 = clip_ranges(ranges, region.start as u64, region.start as u64 + region.size as u64).iter()
+                .map(|(start, end)| end - start)
+                .sum();
+            write_lines(out, [reports::budget_line(path, Some(&region.name), used, limit)])?;
+            if used > limit {
+                over.push(format!("{} in {path}", region.name));
+            }
+        }
+    }
+    match over.is_empty() {
+        true => Ok(()),
+        false => Err(Error::Check(format!("{} over budget", over.join(", ")))),
+    }
+}
+
+/* Adds the usage of every file to the history file, creating it if need be, then prints each file's trend */
+pub fn record_history(out: &mut impl Write, files: &[(String, Vec<(u64, u64)>)], history_path: &str) -> Result<(), Error> {
+    let mut entries = match Path::new(history_path).exists() {
+        true => history::read_history(&read_text(history_path)?).map_err(|reason| Error::parse(history_path, reason))?,
+        false => Vec::new(),
+    };
+    for (path, ranges) in files {
+        entries.push(history::HistoryEntry::now(path, &stats::OccupancyStats::from_ranges(ranges)));
+    }
+    write_file(history_path, history::write_history(&entries))?;
+    for (path, _) in files {
+        write_lines(out, history::trend_lines(path, &entries))?;
+    }
+    Ok(())
+}
+
+/* The map of every page of a file holding data, a line of cells at a time, for the GIF and PDF */
+fn page_lines<'a>(segment_map: &'a SegmentMap, pages: &'a [u16], bytes_per_line: u16, width_symbols: u16) -> impl Iterator<Item = (u32, Vec<bool>)> + 'a {
+    let empty_page = vec![0u8; SEGMENT_BYTES as usize];
+    pages.iter().flat_map(move |page| {
+        let segment = segment_map.get(page).unwrap_or(&empty_page).clone();
+        (0..IHEX_SEGMENT_BYTES / bytes_per_line as u32).map(move |line_num| {
+            let addr = *page as u32 * IHEX_SEGMENT_BYTES + line_num * bytes_per_line as u32;
+            (addr, map_line_cells(&segment, line_num, bytes_per_line, width_symbols))
+        })
+    })
+}
+
+/* Writes every export asked for, of the first file but for the GIF, returning whether there were any */
+pub fn write_exports(images: &[(String, HexImage)], labels: &[Region], options: &ExportOptions) -> Result<bool, Error> {
+    let Some((file_path, image)) = images.first() else {
+        return Ok(false);
+    };
+    let segment_map = &image.segment_map;
+    let mut seg_idxs: Vec<u16> = segment_map.keys().cloned().collect();
+    seg_idxs.sort();
+    let (bytes_per_line, width_symbols) = (options.bytes_per_line, options.width_symbols);
+    let mut exported = false;
+    if let Some(ld_path) = &options.ld {
+        write_file(ld_path, exports::ld_memory_block(&occupied_ranges(segment_map), options.memory_end))?;
+        exported = true;
+    }
+    if let Some(bitmask_path) = &options.bitmask {
+        write_file(bitmask_path, exports::occupancy_bitmask(segment_map))?;
+        exported = true;
+    }
+    if let Some(regions_path) = &options.regions {
+        let detected = regions::regions_from_ranges(&occupied_ranges(segment_map));
+        write_file(regions_path, regions::write_regions(&detected, regions::RegionFormat::from_path(regions_path)))?;
+        exported = true;
+    }
+    if let Some(gdb_path) = &options.gdb {
+        let format = exports::ImageFormat::of_file(file_path)?;
+        write_file(gdb_path, exports::gdb_script(file_path, format, &occupied_ranges(segment_map), options.gdb_python))?;
+        exported = true;
+    }
+    let flash_scripts = [
+        (&options.openocd, exports::FlashTool::OpenOcd),
+        (&options.pyocd, exports::FlashTool::PyOcd),
+        (&options.jlink, exports::FlashTool::JLink),
+    ];
+    for (script_path, tool) in flash_scripts {
+        if let Some(script_path) = script_path {
+            let erase_spans = touched_sector_spans(&occupied_ranges(segment_map), options.sector_size);
+            let format = exports::ImageFormat::of_file(file_path)?;
+            write_file(script_path, exports::flash_script(tool, file_path, format, &erase_spans, options.sector_size))?;
+            exported = true;
+        }
+    }
+    if let Some(gaps_path) = &options.gaps_hex {
+        write_file(gaps_path, exports::gaps_hex(&occupied_ranges(segment_map), options.fill_byte).map_err(|err| Error::Render(err.to_string()))?)?;
+        exported = true;
+    }
+    if let Some(gif_path) = &options.gif {
+        /* Every frame covers the pages touched by any build so the frames line up */
+        let mut pages: Vec<u16> = images.iter().flat_map(|(_, image)| image.segment_map.keys().cloned()).collect();
+        pages.sort();
+        pages.dedup();
+        let frames: Vec<Vec<Vec<bool>>> = images.iter()
+            .map(|(_, image)| page_lines(&image.segment_map, &pages, bytes_per_line, width_symbols).map(|(_, cells)| cells).collect())
+            .collect();
+        write_file(gif_path, gif_export::animated_gif(&frames, 100))?;
+        exported = true;
+    }
+    if let Some(pdf_path) = &options.pdf {
+        let map_lines: Vec<pdf_export::ReportMapLine> = page_lines(segment_map, &seg_idxs, bytes_per_line, width_symbols)
+            .map(|(addr, cells)| pdf_export::ReportMapLine { addr, cells, label: regions::line_label(labels, addr, bytes_per_line as u32) })
+            .collect();
+        let stats = stats::OccupancyStats::from_map(segment_map);
+        write_file(pdf_path, pdf_export::pdf_report(file_path, &stats, &occupied_ranges(segment_map), labels, &map_lines, bytes_per_line))?;
+        exported = true;
+    }
+    Ok(exported)
+}
+
+/* Writes an image's data back out, as a raw binary from the first used byte for a .bin output with gaps filled with fill_byte, else as Intel HEX */
+pub fn convert(image: &HexImage, output: &str, fill_byte: u8) -> Result<(), Error> {
+    let segment_map = &image.segment_map;
+    let ranges = occupied_ranges(segment_map);
+    let values = image.values.as_ref().ok_or(Error::Render("The byte values were not kept".to_string()))?;
+    let contents = match Path::new(output).extension().is_some_and(|extension| extension.eq_ignore_ascii_case("bin")) {
+        true => {
+            let (start, end) = (ranges.first().map_or(0, |range| range.0), ranges.last().map_or(0, |range| range.1));
+            exports::range_bin(segment_map, values, start, end, fill_byte)
+        },
+        false => exports::range_hex(&ranges, segment_map, values, 0, 1 << 32).map_err(|err| Error::Render(err.to_string()))?.into_bytes(),
+    };
+    write_file(output, contents)
+}
+
+/* Lays each image's data over the ones before it into one Intel HEX, warning where a later file overwrites earlier data */
+pub fn merge(images: &[(String, HexImage)], output: &str) -> Result<(), Error> {
+    let (mut merged_map, mut merged_values) = (SegmentMap::new(), ValueMap::new());
+    for (path, image) in images {
+        let Some(values) = &image.values else { continue };
+        let ranges = occupied_ranges(&image.segment_map);
+        let merged_ranges = occupied_ranges(&merged_map);
+        for (start, end) in subtract_ranges(&ranges, &subtract_ranges(&ranges, &merged_ranges)) {
+            warn!("{path} overlaps the files before it at {start:#010x}-{:#010x} ({} bytes), its data is kept", end - 1, end - start);
+        }
+        overlay_image((&mut merged_map, &mut merged_values), (&image.segment_map, values));
+    }
+    let contents = exports::range_hex(&occupied_ranges(&merged_map), &merged_map, &merged_values, 0, 1 << 32)
+        .map_err(|err| Error::Render(err.to_string()))?;
+    write_file(output, contents)
+}
+
+/* Answers the [start, end) address queries for each file in turn, naming the records which wrote the data from the file itself */
+pub fn query(out: &mut impl Write, files: &[(String, Vec<(u64, u64)>)], addresses: &[(u32, u64)], labels: &[Region], format: OutputFormat) -> Result<(), Error> {
+    let formatted = format != OutputFormat::Text;
+    let mut answers = Vec::new();
+    for (path, ranges) in files {
+        /* One pass over the file finds the records of every query */
+        let mut searches: Vec<hex_loader::RecordSearch> = addresses.iter().map(|(start, end)| hex_loader::RecordSearch::new(*start, *end)).collect();
+        hex_loader::for_each_line(path, |line, text| searches.iter_mut().for_each(|search| search.line(line, text)))?;
+        let mut queries = Vec::new();
+        if !formatted {
+            write_lines(out, [path.clone()])?;
+        }
+        for ((start, end), search) in addresses.iter().zip(searches) {
+            let records = search.finish();
+            if formatted {
+                queries.push(reports::query_value(*start, *end, ranges, &records, labels));
+                continue;
+            }
+            write_lines(out, reports::query_lines(*start, *end, ranges, &records, labels).into_iter().map(|line| format!("  {line}")))?;
+        }
+        answers.push(Value::object([("file", path.as_str().into()), ("queries", Value::List(queries))]));
+    }
+    write_formatted(out, Value::List(answers), format)
+}
+
+/* The text counterpart of the overlay, from each file to the next */
+pub fn diff(out: &mut impl Write, images: &[(String, HexImage)], options: &DiffOptions) -> Result<(), Error> {
+    if images.len() < 2 {
+        return Err(Error::Argument(format!("--{} needs two files, give --file twice", if options.diff {"diff"} else {"delta"})));
+    }
+    let mut diffs = Vec::new();
+    for pair in images.windows(2) {
+        let ((old_path, old), (new_path, new)) = (&pair[0], &pair[1]);
+        let (old_ranges, new_ranges) = (occupied_ranges(&old.segment_map), occupied_ranges(&new.segment_map));
+        let changed = match (&old.values, &new.values) {
+            (Some(old_values), Some(new_values)) => Some(changed_ranges((&old.segment_map, old_values), (&new.segment_map, new_values))),
+            _ => None,
+        };
+        let (added, removed) = (subtract_ranges(&new_ranges, &old_ranges), subtract_ranges(&old_ranges, &new_ranges));
+        if options.format != OutputFormat::Text {
+            let diff = reports::diff_value(&added, &removed, changed.as_deref());
+            diffs.push(Value::object([("old", old_path.as_str().into()), ("new", new_path.as_str().into())]).with_fields(diff));
+            continue;
+        }
+        let mut lines = Vec::new();
+        if options.diff {
+            lines.extend(reports::diff_list(&added, &removed, changed.as_deref()));
+        }
+        if options.delta {
+            let differences: Vec<(u64, u64)> = added.iter().chain(&removed).chain(changed.iter().flatten()).cloned().collect();
+            lines.extend(reports::delta_plan(&differences, &new_ranges, &options.sector_layout));
+        }
+        write_under(out, &format!("{old_path} -> {new_path}"), lines)?;
+    }
+    write_formatted(out, Value::List(diffs), options.format)
+}
+
+/**
+ * What the reports of a file are made from: its occupied ranges, and the loaded image unless it was loaded with
+ * --sparse, in which case only the reports of the ranges and of the records themselves are made.
+ */
+struct ReportSource<'a> {
+    path: &'a str,
+    ranges: Vec<(u64, u64)>,
+    image: Option<&'a HexImage>,
+}
+
+/* The stats and check of every file as one JSON or YAML document */
+fn formatted_reports(out: &mut impl Write, sources: &[ReportSource], options: &ReportOptions) -> Result<(), Error> {
+    let mut documents = Vec::new();
+    for source in sources {
+        let path = source.path;
+        let mut report = Value::object([("file", path.into())]);
+        if options.stats {
+            let stats = stats::OccupancyStats::from_ranges(&source.ranges).to_value()
+                .with_fields(Value::object([("entry", source.image.and_then(|image| image.entry).into())]));
+            report = report.with_fields(Value::object([("stats", stats)]));
+        }
+        if options.check {
+            let issues = check_issues(path, options.load_options, options.memory.as_deref())?.iter().map(hex_check::Issue::to_value).collect();
+            report = report.with_fields(Value::object([("issues", Value::List(issues))]));
+        }
+        documents.push(report);
+    }
+    write_formatted(out, Value::List(documents), options.format)
+}
+
+/* The text reports of one file, in the order of the options */
+fn report_lines(source: &ReportSource, labels: &[Region], options: &ReportOptions) -> Result<Vec<String>, Error> {
+    let (path, ranges) = (source.path, &source.ranges);
+    let stats = stats::OccupancyStats::from_ranges(ranges);
+    let mut lines = Vec::new();
+    if options.stats {
+        lines.extend(stats.summary_lines());
+        if let Some(family) = options.mcu {
+            lines.extend(family.summary_lines(ranges));
+        }
+        if let Some(entry) = source.image.and_then(|image| image.entry) {
+            lines.push(format!("Entry point    {entry:#010x}"));
+        }
+        /* PIC and AVR parts have no Cortex-M vector table to decode */
+        if let Some(image) = source.image.filter(|_| options.mcu.is_none()) {
+            if let Some(table) = image.values.as_ref().and_then(|values| cortex_m::vector_table(&image.segment_map, values)) {
+                lines.extend(table.summary_lines(ranges, image.entry));
+            }
+        }
+        if let Some(flash) = &options.flash {
+            lines.extend(reports::fit_lines(ranges, flash));
+        }
+    }
+    if options.check {
+        lines.extend(reports::issue_list(&check_issues(path, options.load_options, options.memory.as_deref())?));
+    }
+    if options.order {
+        let mut search = hex_loader::RecordSearch::new(0, 1 << 32);
+        hex_loader::for_each_line(path, |line, text| search.line(line, text))?;
+        lines.extend(record_stats::RecordOrder::from_records(&search.finish()).summary_lines());
+    }
+    if options.records {
+        let mut counter = record_stats::RecordCounter::default();
+        hex_loader::for_each_line(path, |_, text| counter.line(text))?;
+        lines.extend(counter.finish().summary_lines());
+    }
+    if options.pages {
+        lines.extend(reports::page_table(ranges));
+    }
+    if options.sectors {
+        lines.extend(reports::sector_lines(ranges, &options.sector_layout));
+    }
+    if options.ranges {
+        lines.extend(reports::range_list(ranges));
+    }
+    let (start, end) = match &options.flash {
+        Some(flash) => (flash.start as u64, flash.end()),
+        None => {
+            let start = options.device_start.unwrap_or(stats.span_start) as u64;
+            (start, options.device_size.map_or(stats.span_end, |size| start + size as u64))
+        },
+    };
+    if let Some(count) = options.free {
+        lines.extend(reports::largest_free(ranges, start, end, count));
+    }
+    if options.gaps {
+        lines.extend(reports::gap_list(ranges, start, end, options.min_gap as u64));
+    }
+    /* The rest look at the data itself */
+    let Some(image) = source.image else {
+        return Ok(lines);
+    };
+    if let (true, Some(values)) = (options.checksum, &image.values) {
+        let start = options.checksum_start.unwrap_or(stats.span_start) as u64;
+        let end = options.checksum_size.map_or(stats.span_end, |size| start + size as u64);
+        if end > 1 << 32 {
+            return Err(Error::Argument(format!("The --checksum window {start:#010x}-{:#x} runs past the end of the 32 bit address space", end - 1)));
+        }
+        let (crc, sha) = checksums::image_checksums(&image.segment_map, values, start, end.max(start), options.fill_byte);
+        lines.extend(reports::checksum_lines(start, end.max(start), options.fill_byte, crc, &sha));
+    }
+    if let (Some(text), Some(values)) = (&options.find, &image.values) {
+        let found = pattern::find_pattern(&image.segment_map, values, &pattern::parse_pattern(text).map_err(Error::Argument)?);
+        lines.extend(reports::match_list(text, &found, labels));
+    }
+    if let (Some(min_len), Some(values)) = (options.strings, &image.values) {
+        lines.extend(reports::string_list(&pattern::find_strings(&image.segment_map, values, min_len), min_len));
+    }
+    if let (true, Some(values)) = (options.histogram, &image.values) {
+        lines.extend(reports::histogram_lines("Byte values", &stats::byte_histogram(&image.segment_map, values, 0, 1 << 32)));
+        for region in labels {
+            let end = region.start as u64 + region.size as u64;
+            let title = format!("Byte values of {} {:#010x}-{:#010x}", region.name, region.start, end.max(region.start as u64 + 1) - 1);
+            lines.extend(reports::histogram_lines(&title, &stats::byte_histogram(&image.segment_map, values, region.start, end)));
+        }
+    }
+    if let (Some(reference_path), Some(values)) = (&options.reference, &image.values) {
+        let reference = read_file(reference_path)?;
+        let base = options.base.unwrap_or(stats.span_start);
+        lines.extend(reports::reference_report(reference_path, &image.segment_map, values, &reference, base).map_err(Error::Argument)?);
+    }
+    if let (true, Some(values)) = (options.padding, &image.values) {
+        lines.extend(reports::padding_lines(stats::detect_padding(&image.segment_map, values), stats.bytes_used));
+    }
+    if let (true, Some(values)) = (options.compress, &image.values) {
+        let (sizes, total) = stats::range_compression(&image.segment_map, values);
+        lines.extend(reports::compression_list(&sizes, total));
+    }
+    if let (Some(page_size), Some(values)) = (options.page_crc, &image.values) {
+        let pages = checksums::page_crcs(ranges, &image.segment_map, values, page_size.max(1), options.fill_byte);
+        lines.extend(reports::page_crc_list(&pages, page_size.max(1), options.fill_byte));
+    }
+    if let (true, Some(values)) = (options.entropy, &image.values) {
+        lines.extend(reports::entropy_list(&stats::block_entropies(&image.segment_map, values, options.entropy_block.max(1))));
+    }
+    Ok(lines)
+}
+
+/* Prints the reports asked for under each file's name, or as one JSON or YAML document for all of them */
+pub fn print_reports(out: &mut impl Write, images: &[(String, HexImage)], labels: &[Region], options: &ReportOptions) -> Result<(), Error> {
+    let sources: Vec<ReportSource> = images.iter()
+        .map(|(path, image)| ReportSource { path, ranges: occupied_ranges(&image.segment_map), image: Some(image) })
+        .collect();
+    write_reports(out, &sources, labels, options)
+}
+
+/* Prints the reports of files loaded with --sparse, those of the occupied ranges and the records, as print_reports does */
+pub fn print_sparse_reports(out: &mut impl Write, maps: &[(String, MemoryMap)], labels: &[Region], options: &ReportOptions) -> Result<(), Error> {
+    let sources: Vec<ReportSource> = maps.iter()
+        .map(|(path, map)| ReportSource { path, ranges: map.ranges().collect(), image: None })
+        .collect();
+    write_reports(out, &sources, labels, options)
+}
+
+fn write_reports(out: &mut impl Write, sources: &[ReportSource], labels: &[Region], options: &ReportOptions) -> Result<(), Error> {
+    if options.format != OutputFormat::Text {
+        return formatted_reports(out, sources, options);
+    }
+    for source in sources {
+        let lines = report_lines(source, labels, options)?;
+        write_under(out, source.path, lines)?;
+    }
+    Ok(())
+}
+
+/* Describes the layout of every file in sentences for screen readers, plain lines being what they handle best */
+pub fn narrate_files(out: &mut impl Write, files: &[(String, Vec<(u64, u64)>)], labels: &[Region]) -> Result<(), Error> {
+    for (path, ranges) in files {
+        write_lines(out, narration::narrate(path, ranges, labels))?;
+    }
+    Ok(())
+}
+
+/* Prints every file's map one after the other through the pager, each under a summary line */
+#[cfg(feature = "tui")]
+pub fn page_maps(images: Vec<(String, HexImage)>, labels: &[Region], bytes_per_line: u16, width_symbols: u16, glyphs: crate::theme::Glyphs) -> Result<(), Error> {
+    let mut lines = Vec::new();
+    for (path, image) in images {
+        let stats = stats::OccupancyStats::from_map(&image.segment_map);
+        lines.push(format!("{path} | {} bytes used | {:.1}% of span | {} bytes/char",
+                           stats.bytes_used, stats.fill_percent(), bytes_per_line / width_symbols));
+        lines.extend(crate::viewer::map_text(image, labels, bytes_per_line, width_symbols, glyphs));
+    }
+    crate::pager::page(&lines).map_err(|err| Error::Render(format!("The map could not be paged: {err}")))
+}
+
+/* Writes the first screen of the interactive map as plain text */
+#[cfg(feature = "tui")]
+pub fn write_snapshot(snapshot_path: &str, images: Vec<(String, HexImage)>, labels: Vec<Region>, view_options: crate::viewer::ViewOptions) -> Result<(), Error> {
+    let viewer = crate::viewer::Viewer::new(images, labels, view_options);
+    write_file(snapshot_path, viewer.snapshot().join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::{check_max_used, file_ranges, Budget};
+    use crate::hex_loader::{load_hex, LoadOptions};
+
+    #[test]
+    fn test_check_max_used() -> Result<(),String> {
+        let image = load_hex(":0400000001020304F2\n:00000001FF\n", LoadOptions::default());
+        let images = file_ranges(&[("app.hex".to_string(), image)]);
+        let mut out = Vec::new();
+        check_max_used(&mut out, &images, Budget::Bytes(4), None, None, None).map_err(|e| e.to_string())?;
+        assert_eq!("budget result=pass used=4 limit=4 percent=100.0 file=app.hex\n", String::from_utf8_lossy(&out));
+        assert!(check_max_used(&mut Vec::new(), &images, Budget::Bytes(3), None, None, None).is_err());
+        assert!(check_max_used(&mut Vec::new(), &images, Budget::Percent(50.0), None, Some(8), None).is_ok());
+        assert!(check_max_used(&mut Vec::new(), &images, Budget::Percent(50.0), None, None, None).is_err());
+        Ok(())
+    }
+}
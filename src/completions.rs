@@ -0,0 +1,313 @@
+use clap::{ArgAction, Command, ValueHint};
+
+/* A shell the completions command writes a script for */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+/* What follows an option or fills a command's positional arguments */
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    None,
+    /// A value which may be left out, given as --option=value
+    Optional,
+    File,
+    Choices(Vec<String>),
+    Any,
+}
+
+/* One option as the scripts need it */
+struct Opt {
+    long: Option<String>,
+    short: Option<char>,
+    help: String,
+    value: Value,
+    repeats: bool,
+}
+
+/* The options and positional arguments of the top level or of one command */
+struct Level {
+    name: String,
+    help: String,
+    options: Vec<Opt>,
+    positional: Value,
+}
+
+/* The first sentence of a help text, without any example, for the short descriptions shells show */
+fn short_help(help: &str) -> String {
+    let sentence_end = help.match_indices(". ").map(|(idx, _)| idx).find(|idx| !help[..*idx].ends_with("e.g") && !help[..*idx].ends_with("i.e"));
+    let end = [sentence_end, help.find(", e.g.")].into_iter().flatten().min().unwrap_or(help.len());
+    help[..end].trim_end_matches('.').to_string()
+}
+
+fn arg_value(arg: &clap::Arg) -> Value {
+    let possible: Vec<String> = arg.get_possible_values().iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    match arg.get_num_args() {
+        _ if !arg.get_action().takes_values() => Value::None,
+        Some(range) if range.min_values() == 0 => Value::Optional,
+        _ if !possible.is_empty() => Value::Choices(possible),
+        _ if matches!(arg.get_value_hint(), ValueHint::FilePath | ValueHint::AnyPath) => Value::File,
+        _ => Value::Any,
+    }
+}
+
+fn level(command: &Command, name: &str) -> Level {
+    let visible = command.get_arguments().filter(|arg| !arg.is_hide_set());
+    let (positionals, options): (Vec<&clap::Arg>, Vec<&clap::Arg>) = visible.partition(|arg| arg.is_positional());
+    /* Completing files helps most wherever a command takes a file, so a file argument anywhere stands for all of them */
+    let values: Vec<Value> = positionals.iter().map(|arg| arg_value(arg)).collect();
+    let positional = values.iter().find(|value| **value == Value::File).or(values.first()).cloned().unwrap_or(Value::None);
+    Level {
+        name: name.to_string(),
+        help: short_help(&command.get_about().map(|about| about.to_string()).unwrap_or_default()),
+        options: options.iter()
+            .map(|arg| Opt {
+                long: arg.get_long().map(str::to_string),
+                short: arg.get_short(),
+                help: short_help(&arg.get_help().map(|help| help.to_string()).unwrap_or_default()),
+                value: arg_value(arg),
+                repeats: matches!(arg.get_action(), ArgAction::Append | ArgAction::Count),
+            })
+            .collect(),
+        positional,
+    }
+}
+
+/* The top level followed by every visible command */
+fn levels(command: &mut Command) -> Vec<Level> {
+    command.build();
+    let mut levels = vec![level(command, "")];
+    levels.extend(command.get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+        .map(|sub| level(sub, sub.get_name())));
+    levels
+}
+
+/* The ways of spelling an option, -f and --file */
+fn spellings(opt: &Opt) -> Vec<String> {
+    opt.short.map(|short| format!("-{short}")).into_iter().chain(opt.long.iter().map(|long| format!("--{long}"))).collect()
+}
+
+fn bash(bin: &str, levels: &[Level]) -> String {
+    let function = format!("_{}", bin.replace('-', "_"));
+    let commands: Vec<&str> = levels[1..].iter().map(|level| level.name.as_str()).collect();
+    let mut script = format!("{function}() {{\n    local cur prev command i\n    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n    command=\"\"\n");
+    script += &format!("    for ((i = 1; i < COMP_CWORD; i++)); do\n        case \"${{COMP_WORDS[i]}}\" in\n            {}) command=\"${{COMP_WORDS[i]}}\"; break ;;\n        esac\n    done\n", commands.join("|"));
+    /* The value of an option, whichever command it belongs to */
+    script += "    case \"$prev\" in\n";
+    for opt in levels.iter().flat_map(|level| &level.options) {
+        let words = spellings(opt).join("|");
+        match &opt.value {
+            Value::File => script += &format!("        {words}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n"),
+            Value::Choices(choices) => script += &format!("        {words}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n", choices.join(" ")),
+            Value::Any => script += &format!("        {words}) return ;;\n"),
+            Value::None | Value::Optional => {},
+        }
+    }
+    script += "    esac\n    case \"$command\" in\n";
+    for level in levels {
+        let mut words: Vec<String> = level.options.iter().flat_map(spellings).collect();
+        if level.name.is_empty() {
+            words.extend(commands.iter().map(|command| command.to_string()));
+        }
+        if let Value::Choices(choices) = &level.positional {
+            words.extend(choices.iter().cloned());
+        }
+        let files = if level.positional == Value::File {" $(compgen -f -- \"$cur\")"} else {""};
+        script += &format!("        \"{}\") COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"){files}) ;;\n", level.name, words.join(" "));
+    }
+    script += &format!("    esac\n}}\ncomplete -o filenames -F {function} {bin}\n");
+    script
+}
+
+/* Text inside a zsh single quoted _arguments spec, where brackets and colons have meanings */
+fn zsh_text(text: &str) -> String {
+    text.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:")
+}
+
+fn zsh_value(value: &Value) -> String {
+    match value {
+        Value::None => String::new(),
+        Value::Optional | Value::Any => ": :".to_string(),
+        Value::File => ":file:_files".to_string(),
+        Value::Choices(choices) => format!(":value:({})", choices.join(" ")),
+    }
+}
+
+fn zsh_specs(level: &Level) -> Vec<String> {
+    let mut specs = Vec::new();
+    for opt in &level.options {
+        let repeat = if opt.repeats {"*"} else {""};
+        let help = zsh_text(&opt.help);
+        let value = zsh_value(&opt.value);
+        let suffix = |long: bool| match (&opt.value, long) {
+            (Value::None, _) => "",
+            (Value::Optional, _) => "=-",
+            (_, true) => "=",
+            (_, false) => "+",
+        };
+        let names: Vec<String> = opt.short.map(|short| format!("-{short}{}", suffix(false))).into_iter()
+            .chain(opt.long.iter().map(|long| format!("--{long}{}", suffix(true))))
+            .collect();
+        match names.as_slice() {
+            [single] => specs.push(format!("'{repeat}{single}[{help}]{value}'")),
+            _ if opt.repeats => specs.push(format!("'*'{{{}}}'[{help}]{value}'", names.join(","))),
+            _ => specs.push(format!("{{{}}}'[{help}]{value}'", names.join(","))),
+        }
+    }
+    match &level.positional {
+        Value::None => {},
+        value => specs.push(format!("'*{}'", zsh_value(value))),
+    }
+    specs
+}
+
+fn zsh(bin: &str, levels: &[Level]) -> String {
+    let function = format!("_{}", bin.replace('-', "_"));
+    let mut script = format!("#compdef {bin}\n\n{function}() {{\n    local line state\n    _arguments -C \\\n");
+    for spec in zsh_specs(&levels[0]) {
+        script += &format!("        {spec} \\\n");
+    }
+    script += "        '1: :->command' \\\n        '*:: :->args'\n    case $state in\n        command)\n            _values 'command' \\\n";
+    let commands: Vec<String> = levels[1..].iter().map(|level| format!("                '{}[{}]'", level.name, zsh_text(&level.help))).collect();
+    script += &commands.join(" \\\n");
+    script += "\n            ;;\n        args)\n            case $line[1] in\n";
+    for level in &levels[1..] {
+        let specs = zsh_specs(level);
+        match specs.is_empty() {
+            true => script += &format!("                {}) ;;\n", level.name),
+            false => script += &format!("                {}) _arguments {} ;;\n", level.name, specs.join(" ")),
+        }
+    }
+    script += &format!("            esac\n            ;;\n    esac\n}}\n\n{function} \"$@\"\n");
+    script
+}
+
+/* Text inside a fish single quoted string */
+fn fish_text(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish(bin: &str, levels: &[Level]) -> String {
+    let mut script = format!("complete -c {bin} -f\n");
+    for level in levels {
+        let condition = match level.name.is_empty() {
+            true => "__fish_use_subcommand".to_string(),
+            false => format!("__fish_seen_subcommand_from {}", level.name),
+        };
+        if !level.name.is_empty() {
+            script += &format!("complete -c {bin} -n __fish_use_subcommand -a {} -d '{}'\n", level.name, fish_text(&level.help));
+        }
+        for opt in &level.options {
+            let mut line = format!("complete -c {bin} -n '{condition}'");
+            if let Some(short) = opt.short {
+                line += &format!(" -s {short}");
+            }
+            if let Some(long) = &opt.long {
+                line += &format!(" -l {long}");
+            }
+            match &opt.value {
+                Value::None | Value::Optional => {},
+                Value::File => line += " -r -F",
+                Value::Choices(choices) => line += &format!(" -r -a '{}'", choices.join(" ")),
+                Value::Any => line += " -r",
+            }
+            script += &format!("{line} -d '{}'\n", fish_text(&opt.help));
+        }
+        match &level.positional {
+            Value::File => script += &format!("complete -c {bin} -n '{condition}' -F\n"),
+            Value::Choices(choices) => script += &format!("complete -c {bin} -n '{condition}' -a '{}'\n", choices.join(" ")),
+            _ => {},
+        }
+    }
+    script
+}
+
+/* A PowerShell array literal of the words */
+fn powershell_array(words: &[String]) -> String {
+    format!("@({})", words.iter().map(|word| format!("'{}'", word.replace('\'', "''"))).collect::<Vec<_>>().join(", "))
+}
+
+fn powershell(bin: &str, levels: &[Level]) -> String {
+    let commands: Vec<String> = levels[1..].iter().map(|level| level.name.clone()).collect();
+    let mut script = format!("Register-ArgumentCompleter -Native -CommandName '{bin}' -ScriptBlock {{\n    param($wordToComplete, $commandAst, $cursorPosition)\n");
+    script += "    $words = @($commandAst.CommandElements | Select-Object -Skip 1 | ForEach-Object { $_.ToString() })\n";
+    script += "    $previous = if ($wordToComplete) { $words[-2] } else { $words[-1] }\n";
+    script += &format!("    $command = ''\n    foreach ($word in $words) {{ if ({} -contains $word) {{ $command = $word; break }} }}\n", powershell_array(&commands));
+    /* Returning nothing after an option taking a file leaves PowerShell to complete the path */
+    script += "    $candidates = switch -exact ($previous) {\n";
+    for opt in levels.iter().flat_map(|level| &level.options) {
+        let value = match &opt.value {
+            Value::File | Value::Any => "@()".to_string(),
+            Value::Choices(choices) => powershell_array(choices),
+            Value::None | Value::Optional => continue,
+        };
+        for spelling in spellings(opt) {
+            script += &format!("        '{spelling}' {{ {value}; break }}\n");
+        }
+    }
+    script += "        default {\n            switch ($command) {\n";
+    for level in levels {
+        let mut words: Vec<String> = level.options.iter().flat_map(spellings).collect();
+        if level.name.is_empty() {
+            words.extend(commands.iter().cloned());
+        }
+        if let Value::Choices(choices) = &level.positional {
+            words.extend(choices.iter().cloned());
+        }
+        script += &format!("                '{}' {{ {} }}\n", level.name, powershell_array(&words));
+    }
+    script += "            }\n        }\n    }\n";
+    script += "    $candidates | Where-Object { $_ -like \"$wordToComplete*\" } | ForEach-Object {\n        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n    }\n}\n";
+    script
+}
+
+/**
+ * A completion script for the shell, built from the options and commands of the command line so it never falls
+ * behind them. Options taking a path, those marked with a file value hint, complete file names.
+ */
+pub fn completion_script(command: &mut Command, shell: Shell) -> String {
+    let bin = command.get_name().to_string();
+    let levels = levels(command);
+    match shell {
+        Shell::Bash => bash(&bin, &levels),
+        Shell::Zsh => zsh(&bin, &levels),
+        Shell::Fish => fish(&bin, &levels),
+        Shell::Powershell => powershell(&bin, &levels),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, ArgAction, Command, ValueHint};
+    use crate::completions::{completion_script, Shell};
+
+    #[test]
+    fn test_completion_script() -> Result<(),String> {
+        let mut command = Command::new("tool")
+            .arg(Arg::new("file").short('f').long("file").action(ArgAction::Append).value_hint(ValueHint::FilePath).help("The file. More text"))
+            .arg(Arg::new("theme").long("theme").value_parser(["dark", "light"]))
+            .subcommand(Command::new("merge").about("Combine files, e.g. merge a b").arg(Arg::new("files").value_hint(ValueHint::FilePath)));
+        let bash = completion_script(&mut command, Shell::Bash);
+        assert!(bash.contains("-f|--file) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;"));
+        assert!(bash.contains("--theme) COMPREPLY=($(compgen -W \"dark light\" -- \"$cur\")); return ;;"));
+        assert!(bash.contains("\"merge\") COMPREPLY=($(compgen -W \"-h --help\" -- \"$cur\") $(compgen -f -- \"$cur\")) ;;"));
+        assert!(bash.ends_with("complete -o filenames -F _tool tool\n"));
+        let zsh = completion_script(&mut command, Shell::Zsh);
+        assert!(zsh.contains("'*'{-f+,--file=}'[The file]:file:_files'"));
+        assert!(zsh.contains("'merge[Combine files]'"));
+        let fish = completion_script(&mut command, Shell::Fish);
+        assert!(fish.contains("complete -c tool -n '__fish_use_subcommand' -s f -l file -r -F -d 'The file'"));
+        assert!(fish.contains("complete -c tool -n '__fish_seen_subcommand_from merge' -F"));
+        let powershell = completion_script(&mut command, Shell::Powershell);
+        assert!(powershell.contains("'--theme' { @('dark', 'light'); break }"));
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+/* Where the defaults are read from: $XDG_CONFIG_HOME/ihex-visualize/config.toml, else under ~/.config */
+pub fn config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").filter(|dir| !dir.is_empty()).map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("ihex-visualize").join("config.toml"))
+}
+
+/* The prefix of the environment variables giving options, e.g. IHEX_VIZ_LINE_WIDTH for --line-width */
+pub const ENV_PREFIX: &str = "IHEX_VIZ_";
+
+/* The variable giving an option */
+pub fn env_var_name(option: &str) -> String {
+    format!("{ENV_PREFIX}{}", option.to_uppercase().replace('-', "_"))
+}
+
+/* The settings the environment gives for the options named, in the same form as parse_config's. Empty variables are unset */
+pub fn env_settings<'a>(options: impl IntoIterator<Item = &'a str>) -> Vec<(String, String)> {
+    options.into_iter()
+        .filter_map(|option| std::env::var(env_var_name(option)).ok().filter(|value| !value.is_empty()).map(|value| (option.to_string(), value)))
+        .collect()
+}
+
+/* A line with any # comment taken off, leaving a # inside quotes alone */
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (idx, chr) in line.char_indices() {
+        match (chr, quote) {
+            ('"' | '\'', None) => quote = Some(chr),
+            (chr, Some(open)) if chr == open => quote = None,
+            ('#', None) => return &line[..idx],
+            _ => {},
+        }
+    }
+    line
+}
+
+/**
+ * Reads the settings of a config file as (option, value) pairs in file order. Only flat TOML is understood: one
+ * key = value per line and # comments. Keys are the long options without the dashes, either with dashes or
+ * underscores, and a quoted value has its quotes taken off.
+ */
+pub fn parse_config(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut settings = Vec::new();
+    for (line_idx, raw_line) in contents.lines().enumerate() {
+        let line = strip_comment(raw_line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(format!("Line {}: tables are not supported, give every option at the top level", line_idx + 1));
+        }
+        let (key, value) = line.split_once('=').ok_or(format!("Line {}: expected a key and value", line_idx + 1))?;
+        let value = value.trim();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value.strip_prefix(quote).and_then(|rest| rest.strip_suffix(quote))
+                .ok_or(format!("Line {}: unterminated string", line_idx + 1))?,
+            _ => value,
+        };
+        settings.push((key.trim().replace('_', "-"), value.to_string()));
+    }
+    Ok(settings)
+}
+
+/* The settings as command line arguments, true giving a flag and anything else, false included, the option's value */
+pub fn config_args(settings: &[(String, String)]) -> Vec<String> {
+    settings.iter()
+        .map(|(key, value)| match value.as_str() {
+            "true" => format!("--{key}"),
+            value => format!("--{key}={value}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{config_args, env_var_name, parse_config};
+
+    #[test]
+    fn test_parse_config() -> Result<(),String> {
+        let contents = "# My usual setup\nline_width = 0x400\ndisplay-width = 0   # fit the terminal\ntheme = \"solarized\"\nvalues = true\nwatch = false\nregions = 'map #2.toml'\n";
+        let settings = parse_config(contents)?;
+        assert_eq!(("line-width".to_string(), "0x400".to_string()), settings[0]);
+        assert_eq!(vec!["--line-width=0x400", "--display-width=0", "--theme=solarized", "--values", "--watch=false", "--regions=map #2.toml"], config_args(&settings));
+        assert!(parse_config("[viewer]\ntheme = \"solarized\"\n").is_err());
+        assert!(parse_config("theme = \"solarized\n").is_err());
+        assert!(parse_config("theme\n").is_err());
+        assert_eq!("IHEX_VIZ_LINE_WIDTH", env_var_name("line-width"));
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{byte_value, occupied_ranges, SegmentMap, ValueMap};
+
+/* Where Cortex-M parts map their flash and boot from: address 0, Atmel SAM, STM32 over ITCM, and STM32 over AXI */
+const FLASH_BASES: [u32; 4] = [0x0000_0000, 0x0040_0000, 0x0020_0000, 0x0800_0000];
+
+/* The SRAM region of the Armv7-M and Armv8-M memory maps, where the stack lives */
+const SRAM_START: u32 = 0x2000_0000;
+const SRAM_END: u32 = 0x4000_0000;
+
+/* The first two words of a Cortex-M vector table, which the core loads on reset */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VectorTable {
+    pub base: u32,
+    pub initial_sp: u32,
+    pub reset: u32,
+}
+
+/* A little endian word, if all four bytes hold data */
+fn word_at(segment_map: &SegmentMap, values: &ValueMap, addr: u32) -> Option<u32> {
+    let mut bytes = [0u8; 4];
+    for (idx, byte) in bytes.iter_mut().enumerate() {
+        *byte = byte_value(segment_map, values, addr.checked_add(idx as u32)?)?;
+    }
+    Some(u32::from_le_bytes(bytes))
+}
+
+/* The vector table at the start of the image, if the image starts at a usual Cortex-M flash base */
+pub fn vector_table(segment_map: &SegmentMap, values: &ValueMap) -> Option<VectorTable> {
+    let base = occupied_ranges(segment_map).first()?.0 as u32;
+    if !FLASH_BASES.contains(&base) {
+        return None;
+    }
+    Some(VectorTable {
+        base,
+        initial_sp: word_at(segment_map, values, base)?,
+        reset: word_at(segment_map, values, base + 4)?,
+    })
+}
+
+impl VectorTable {
+    /* What looks wrong about the initial stack pointer, if anything */
+    fn sp_problem(&self) -> Option<&'static str> {
+        if !(SRAM_START..=SRAM_END).contains(&self.initial_sp) {
+            Some("outside SRAM")
+        } else if !self.initial_sp.is_multiple_of(8) {
+            Some("not 8 byte aligned")
+        } else {
+            None
+        }
+    }
+
+    /* What looks wrong about the reset vector, given the occupied ranges of the image, if anything */
+    fn reset_problem(&self, ranges: &[(u64, u64)]) -> Option<&'static str> {
+        let target = self.reset & !1;
+        if self.reset & 1 == 0 {
+            Some("Thumb bit clear, the core would fault")
+        } else if !ranges.iter().any(|(start, end)| (*start..*end).contains(&(target as u64))) {
+            Some("points outside the image")
+        } else {
+            None
+        }
+    }
+
+    /* The table as "name  value" lines to follow the --stats ones, noting anything which looks wrong */
+    pub fn summary_lines(&self, ranges: &[(u64, u64)], entry: Option<u32>) -> Vec<String> {
+        let sp_note = self.sp_problem().map_or("in SRAM".to_string(), |problem| format!("suspicious, {problem}"));
+        let mut reset_note = self.reset_problem(ranges).map_or("inside the image".to_string(), |problem| format!("suspicious, {problem}"));
+        if entry.is_some_and(|entry| entry != self.reset) {
+            reset_note.push_str(", not the entry point");
+        }
+        vec![
+            format!("Vector table   {:#010x}", self.base),
+            format!("Initial SP     {:#010x} ({sp_note})", self.initial_sp),
+            format!("Reset vector   {:#010x} ({reset_note})", self.reset),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cortex_m::vector_table;
+    use crate::hex_loader::{load_hex, LoadOptions};
+
+    #[test]
+    fn test_vector_table() -> Result<(),String> {
+        let options = LoadOptions { store_values: true, blank_byte: None };
+        /* SP 0x20005000, reset 0x08000009, then code up to 0x0800000f */
+        let image = load_hex(":020000040800F2\n:10000000005000200900000800000000000000006F\n:00000001FF\n", options);
+        let table = vector_table(&image.segment_map, image.values.as_ref().ok_or("values were not stored")?).ok_or("no vector table")?;
+        assert_eq!(vec![
+            "Vector table   0x08000000",
+            "Initial SP     0x20005000 (in SRAM)",
+            "Reset vector   0x08000009 (inside the image)",
+        ], table.summary_lines(&[(0x0800_0000, 0x0800_0010)], None));
+        assert_eq!(vec![
+            "Vector table   0x08000000",
+            "Initial SP     0x20005000 (in SRAM)",
+            "Reset vector   0x08000009 (suspicious, points outside the image, not the entry point)",
+        ], table.summary_lines(&[(0x0800_0000, 0x0800_0008)], Some(0x0800_0101)));
+
+        /* An image starting anywhere else is not taken for a vector table */
+        let image = load_hex(":020000040801F1\n:10000000005000200900000800000000000000006F\n:00000001FF\n", options);
+        assert_eq!(None, vector_table(&image.segment_map, image.values.as_ref().ok_or("values were not stored")?));
+        Ok(())
+    }
+}
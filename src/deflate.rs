@@ -0,0 +1,147 @@
+/* How far back matches are looked for, and the longest and shortest match DEFLATE can express */
+const WINDOW: usize = 32 * 1024;
+const MAX_MATCH: usize = 258;
+const MIN_MATCH: usize = 3;
+/* How many earlier positions with the same three bytes are tried, trading ratio for speed */
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/* Packs bits least significant first, as DEFLATE streams are laid out */
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= value << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /* Huffman codes go out most significant bit first */
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push(self.bit_buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/* Writes a literal/length symbol with the fixed Huffman code of RFC 1951 3.2.6 */
+fn write_symbol(writer: &mut BitWriter, symbol: u16) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol as u32, 8),
+        144..=255 => writer.write_code(0x190 + (symbol as u32 - 144), 9),
+        256..=279 => writer.write_code(symbol as u32 - 256, 7),
+        _ => writer.write_code(0xC0 + (symbol as u32 - 280), 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|base| *base as usize <= length).unwrap_or(0);
+    write_symbol(writer, 257 + code as u16);
+    writer.write_bits((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+    let code = DISTANCE_BASE.iter().rposition(|base| *base as usize <= distance).unwrap_or(0);
+    writer.write_code(code as u32, 5);
+    writer.write_bits((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+}
+
+fn hash3(data: &[u8], pos: usize) -> usize {
+    let key = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+    (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/* Adds pos to the chain of earlier positions starting with the same three bytes */
+fn insert(data: &[u8], pos: usize, head: &mut [usize], previous: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let hash = hash3(data, pos);
+        previous[pos] = head[hash];
+        head[hash] = pos;
+    }
+}
+
+/**
+ * Compresses data into a raw DEFLATE stream (RFC 1951) in one block with the fixed Huffman codes, finding repeats
+ * with a hash chain over the last 32K. It comes out a little bigger than zlib's output, which also builds codes fitted
+ * to the data, but close enough to tell how well something compresses.
+ */
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter { bytes: Vec::with_capacity(data.len() / 2), bit_buffer: 0, bit_count: 0 };
+    /* The final block flag, then block type 1 for the fixed codes */
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; data.len()];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash3(data, pos)];
+            let mut tries = 0;
+            while candidate != usize::MAX && pos - candidate <= WINDOW && tries < MAX_CHAIN {
+                let limit = MAX_MATCH.min(data.len() - pos);
+                let length = (0..limit).take_while(|idx| data[candidate + idx] == data[pos + idx]).count();
+                if length > best_length {
+                    (best_length, best_distance) = (length, pos - candidate);
+                    if length == limit {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                tries += 1;
+            }
+        }
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+            for skipped in pos..pos + best_length {
+                insert(data, skipped, &mut head, &mut previous);
+            }
+            pos += best_length;
+        } else {
+            write_symbol(&mut writer, data[pos] as u16);
+            insert(data, pos, &mut head, &mut previous);
+            pos += 1;
+        }
+    }
+    write_symbol(&mut writer, 256);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deflate::deflate;
+
+    #[test]
+    fn test_deflate() -> Result<(),String> {
+        /* An empty stream is the final fixed block holding just the end of block symbol */
+        assert_eq!(vec![0x03, 0x00], deflate(&[]));
+        /* As zlib.compress(b"a", -1) gives without its header: the literal then the end of block */
+        assert_eq!(vec![0x4B, 0x04, 0x00], deflate(b"a"));
+        /* A literal then one match at distance 1 covering the rest */
+        assert_eq!(vec![0x4B, 0x84, 0x03, 0x00], deflate(b"aaaaaaaaaa"));
+        /* Repetitive data shrinks a lot */
+        assert!(deflate(&[0xFF; 4096]).len() < 40);
+        /* Data with no repeats grows, as the fixed codes spend 9 bits on each byte from 0x90 up */
+        let no_repeats: Vec<u8> = (0..=255).collect();
+        assert_eq!(272, deflate(&no_repeats).len());
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+/* A microcontroller's internal flash, as far as fitting an image into it and erasing it goes */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    pub name: &'static str,
+    pub flash_start: u32,
+    pub flash_size: u32,
+    /// The erase sectors (or pages) from the start of the flash, as runs of (count, size)
+    pub sectors: &'static [(u32, u32)],
+}
+
+const K: u32 = 1024;
+const M: u32 = 1024 * 1024;
+
+/* The STM32F4 parts start with small sectors for a bootloader and settings, then go on in 128K ones */
+const STM32F4_512K: &[(u32, u32)] = &[(4, 16 * K), (1, 64 * K), (3, 128 * K)];
+const STM32F4_1M: &[(u32, u32)] = &[(4, 16 * K), (1, 64 * K), (7, 128 * K)];
+
+/* Common parts by their flash. Where a family comes in several flash sizes the part number names the size */
+pub const DEVICES: &[Device] = &[
+    Device { name: "atmega328p", flash_start: 0x0, flash_size: 32 * K, sectors: &[(256, 128)] },
+    Device { name: "atmega2560", flash_start: 0x0, flash_size: 256 * K, sectors: &[(1024, 256)] },
+    Device { name: "atsamd21g18", flash_start: 0x0, flash_size: 256 * K, sectors: &[(1024, 256)] },
+    Device { name: "atsamd51j19", flash_start: 0x0, flash_size: 512 * K, sectors: &[(64, 8 * K)] },
+    Device { name: "lpc1768", flash_start: 0x0, flash_size: 512 * K, sectors: &[(16, 4 * K), (14, 32 * K)] },
+    Device { name: "nrf51822", flash_start: 0x0, flash_size: 256 * K, sectors: &[(256, K)] },
+    Device { name: "nrf52832", flash_start: 0x0, flash_size: 512 * K, sectors: &[(128, 4 * K)] },
+    Device { name: "nrf52833", flash_start: 0x0, flash_size: 512 * K, sectors: &[(128, 4 * K)] },
+    Device { name: "nrf52840", flash_start: 0x0, flash_size: M, sectors: &[(256, 4 * K)] },
+    /* The flash of the Raspberry Pi Pico, which is external and mapped for execute in place */
+    Device { name: "rp2040", flash_start: 0x1000_0000, flash_size: 2 * M, sectors: &[(512, 4 * K)] },
+    Device { name: "stm32f030f4", flash_start: 0x0800_0000, flash_size: 16 * K, sectors: &[(16, K)] },
+    Device { name: "stm32f103c8", flash_start: 0x0800_0000, flash_size: 64 * K, sectors: &[(64, K)] },
+    Device { name: "stm32f103rb", flash_start: 0x0800_0000, flash_size: 128 * K, sectors: &[(128, K)] },
+    Device { name: "stm32f303cc", flash_start: 0x0800_0000, flash_size: 256 * K, sectors: &[(128, 2 * K)] },
+    Device { name: "stm32f401re", flash_start: 0x0800_0000, flash_size: 512 * K, sectors: STM32F4_512K },
+    Device { name: "stm32f405", flash_start: 0x0800_0000, flash_size: M, sectors: STM32F4_1M },
+    Device { name: "stm32f407", flash_start: 0x0800_0000, flash_size: M, sectors: STM32F4_1M },
+    Device { name: "stm32f411re", flash_start: 0x0800_0000, flash_size: 512 * K, sectors: STM32F4_512K },
+    Device { name: "stm32f446re", flash_start: 0x0800_0000, flash_size: 512 * K, sectors: STM32F4_512K },
+    Device { name: "stm32g071rb", flash_start: 0x0800_0000, flash_size: 128 * K, sectors: &[(64, 2 * K)] },
+    Device { name: "stm32h743", flash_start: 0x0800_0000, flash_size: 2 * M, sectors: &[(16, 128 * K)] },
+    Device { name: "stm32l073rz", flash_start: 0x0800_0000, flash_size: 192 * K, sectors: &[(1536, 128)] },
+    Device { name: "stm32l432kc", flash_start: 0x0800_0000, flash_size: 256 * K, sectors: &[(128, 2 * K)] },
+    Device { name: "stm32l476rg", flash_start: 0x0800_0000, flash_size: M, sectors: &[(512, 2 * K)] },
+];
+
+/* Looks a device up by name, ignoring case */
+pub fn find_device(name: &str) -> Result<&'static Device, String> {
+    DEVICES.iter()
+        .find(|device| device.name.eq_ignore_ascii_case(name.trim()))
+        .ok_or_else(|| format!("Unknown device {name}, known devices are {}", DEVICES.iter().map(|device| device.name).collect::<Vec<_>>().join(", ")))
+}
+
+/* The flash an image is meant to fit in, from --device or given outright */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flash {
+    /// The device name, or just "device" when only the addresses were given
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+    /// The device's erase sectors as runs of (count, size), empty when they are not known
+    pub sectors: &'static [(u32, u32)],
+}
+
+impl Flash {
+    /* One past the last address, which can be past the 32 bit space */
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.size as u64
+    }
+}
+
+/**
+ * Where the erase sectors of a flash lie. The listed runs of (count, size) follow on from base, and everywhere else,
+ * including past the listed sectors, sectors are uniform ones of the given size aligned to it.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectorLayout {
+    pub base: u64,
+    pub sectors: &'static [(u32, u32)],
+    pub uniform: u32,
+}
+
+impl SectorLayout {
+    /* The [start, end) of the sector holding an address */
+    pub fn sector_at(&self, addr: u64) -> (u64, u64) {
+        let mut start = self.base;
+        if addr >= start {
+            for (count, size) in self.sectors {
+                let run_end = start + *count as u64 * *size as u64;
+                if addr < run_end {
+                    let first = start + (addr - start) / *size as u64 * *size as u64;
+                    return (first, first + *size as u64);
+                }
+                start = run_end;
+            }
+        }
+        let uniform = self.uniform.max(1) as u64;
+        let first = addr / uniform * uniform;
+        (first, first + uniform)
+    }
+
+    /* Every sector holding any of the sorted ranges, once each in address order */
+    pub fn sectors_of(&self, ranges: &[(u64, u64)]) -> Vec<(u64, u64)> {
+        let mut sectors: Vec<(u64, u64)> = Vec::new();
+        for (start, end) in ranges {
+            let last = self.sector_at(*end - 1);
+            let mut sector = self.sector_at(*start);
+            loop {
+                if sectors.last().is_none_or(|previous| previous.1 <= sector.0) {
+                    sectors.push(sector);
+                }
+                if sector == last {
+                    break;
+                }
+                sector = self.sector_at(sector.1);
+            }
+        }
+        sectors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::devices::{find_device, SectorLayout, DEVICES, STM32F4_1M};
+
+    #[test]
+    fn test_find_device() -> Result<(),String> {
+        let device = find_device("STM32F405")?;
+        assert_eq!((0x0800_0000, 0x10_0000), (device.flash_start, device.flash_size));
+        assert!(find_device("stm32").is_err_and(|e| e.contains("stm32f405")));
+        /* Every listed sector layout covers exactly the flash */
+        for device in DEVICES {
+            assert_eq!(device.flash_size as u64, device.sectors.iter().map(|(count, size)| *count as u64 * *size as u64).sum::<u64>(), "{}", device.name);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sector_at() -> Result<(),String> {
+        let layout = SectorLayout { base: 0x0800_0000, sectors: STM32F4_1M, uniform: 0x1000 };
+        assert_eq!((0x0800_4000, 0x0800_8000), layout.sector_at(0x0800_4001));
+        assert_eq!((0x0801_0000, 0x0802_0000), layout.sector_at(0x0801_ffff));
+        assert_eq!((0x0802_0000, 0x0804_0000), layout.sector_at(0x0802_0000));
+        /* Before and after the listed sectors the uniform size takes over */
+        assert_eq!((0x07ff_f000, 0x0800_0000), layout.sector_at(0x07ff_ffff));
+        assert_eq!((0x0810_0000, 0x0810_1000), layout.sector_at(0x0810_0010));
+        Ok(())
+    }
+}
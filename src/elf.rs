@@ -0,0 +1,322 @@
+use ihex::Record;
+use std::fs::File;
+use std::io::{self, Read};
+use crate::exports::hex_records;
+use crate::regions::Region;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u64 = 0x2;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/* Whether a file is an ELF rather than an Intel HEX */
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(ELF_MAGIC)
+}
+
+/* The same for a file on disk, looking at just its first bytes */
+pub fn is_elf_file(path: &str) -> io::Result<bool> {
+    let mut magic = [0; 4];
+    let read = File::open(path)?.read(&mut magic)?;
+    Ok(is_elf(&magic[..read]))
+}
+
+/* A loadable segment: where it runs, where it is stored in flash and where its contents are in the file */
+struct Segment {
+    vaddr: u64,
+    paddr: u64,
+    memsz: u64,
+    offset: u64,
+    filesz: u64,
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+/**
+ * Just enough of an ELF file, 32 or 64 bit and either byte order, to find what ends up in flash and the names of its
+ * sections and symbols. Reads past the end of the file come back as errors rather than panics.
+ */
+struct Elf<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    little_endian: bool,
+}
+
+impl<'a> Elf<'a> {
+    fn new(data: &'a [u8]) -> Result<Elf<'a>, String> {
+        if !is_elf(data) || data.len() < 0x34 {
+            return Err("Not an ELF file".to_string());
+        }
+        Ok(Elf { data, is_64: data[4] == 2, little_endian: data[5] != 2 })
+    }
+
+    fn bytes(&self, at: u64, len: usize) -> Result<&'a [u8], String> {
+        let start = usize::try_from(at).map_err(|e| e.to_string())?;
+        self.data.get(start..start.saturating_add(len)).ok_or(format!("The ELF file ends before offset {at:#x}"))
+    }
+
+    fn uint(&self, at: u64, len: usize) -> Result<u64, String> {
+        let bytes = self.bytes(at, len)?;
+        let fold = |value: u64, byte: &u8| value << 8 | *byte as u64;
+        Ok(if self.little_endian {bytes.iter().rev().fold(0, fold)} else {bytes.iter().fold(0, fold)})
+    }
+
+    fn u16(&self, at: u64) -> Result<u64, String> {
+        self.uint(at, 2)
+    }
+
+    fn u32(&self, at: u64) -> Result<u64, String> {
+        self.uint(at, 4)
+    }
+
+    /* An address or offset sized field, 4 bytes in 32 bit files and 8 in 64 bit ones */
+    fn word(&self, at: u64) -> Result<u64, String> {
+        self.uint(at, if self.is_64 {8} else {4})
+    }
+
+    fn entry(&self) -> Result<u64, String> {
+        self.word(0x18)
+    }
+
+    fn segments(&self) -> Result<Vec<Segment>, String> {
+        let (table, entry_size, count) = if self.is_64 {
+            (self.word(0x20)?, self.u16(0x36)?, self.u16(0x38)?)
+        } else {
+            (self.word(0x1C)?, self.u16(0x2A)?, self.u16(0x2C)?)
+        };
+        let mut segments = Vec::new();
+        for i in 0..count {
+            let at = table + i * entry_size;
+            if self.u32(at)? != PT_LOAD as u64 {
+                continue;
+            }
+            segments.push(if self.is_64 {
+                Segment { offset: self.word(at + 0x8)?, vaddr: self.word(at + 0x10)?, paddr: self.word(at + 0x18)?, filesz: self.word(at + 0x20)?, memsz: self.word(at + 0x28)? }
+            } else {
+                Segment { offset: self.word(at + 0x4)?, vaddr: self.word(at + 0x8)?, paddr: self.word(at + 0xC)?, filesz: self.word(at + 0x10)?, memsz: self.word(at + 0x14)? }
+            });
+        }
+        Ok(segments)
+    }
+
+    fn sections(&self) -> Result<Vec<Section>, String> {
+        let (table, entry_size, count) = if self.is_64 {
+            (self.word(0x28)?, self.u16(0x3A)?, self.u16(0x3C)?)
+        } else {
+            (self.word(0x20)?, self.u16(0x2E)?, self.u16(0x30)?)
+        };
+        let mut sections = Vec::new();
+        for i in 0..count {
+            let at = table + i * entry_size;
+            sections.push(if self.is_64 {
+                Section {
+                    name: self.u32(at)? as u32, kind: self.u32(at + 0x4)? as u32, flags: self.word(at + 0x8)?, addr: self.word(at + 0x10)?,
+                    offset: self.word(at + 0x18)?, size: self.word(at + 0x20)?, link: self.u32(at + 0x28)? as u32,
+                }
+            } else {
+                Section {
+                    name: self.u32(at)? as u32, kind: self.u32(at + 0x4)? as u32, flags: self.u32(at + 0x8)?, addr: self.u32(at + 0xC)?,
+                    offset: self.u32(at + 0x10)?, size: self.u32(at + 0x14)?, link: self.u32(at + 0x18)? as u32,
+                }
+            });
+        }
+        Ok(sections)
+    }
+
+    /* The NUL terminated string at an offset into a string table section */
+    fn string(&self, table: &Section, at: u32) -> Result<String, String> {
+        let start = table.offset + at as u64;
+        let available = table.size.saturating_sub(at as u64) as usize;
+        let bytes = self.bytes(start, available)?;
+        let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+
+    fn section_names(&self) -> Result<u64, String> {
+        self.u16(if self.is_64 {0x3E} else {0x32})
+    }
+}
+
+/* Where something running at addr is stored, for data copied from flash to RAM at startup. None if no segment holds it */
+fn load_address(segments: &[Segment], addr: u64) -> Option<u64> {
+    segments.iter()
+        .find(|segment| segment.vaddr <= addr && addr < segment.vaddr + segment.memsz.max(1))
+        .map(|segment| segment.paddr + (addr - segment.vaddr))
+}
+
+/* A region at the load address of something, named "<name> load image" when that is not where it runs */
+fn placed_region(segments: &[Segment], name: &str, addr: u64, size: u64) -> Option<Region> {
+    let load = load_address(segments, addr).unwrap_or(addr);
+    let name = if load == addr {name.to_string()} else {format!("{name} load image")};
+    Some(Region { name, start: u32::try_from(load).ok()?, size: u32::try_from(size).ok()?, budget: None })
+}
+
+/**
+ * The sections of an ELF file which take up space in the image, at the address they are stored at. As with the
+ * linker map, initialized data copied to RAM shows at its load image in flash.
+ */
+pub fn elf_sections(data: &[u8]) -> Result<Vec<Region>, String> {
+    let elf = Elf::new(data)?;
+    let segments = elf.segments()?;
+    let sections = elf.sections()?;
+    let Some(names) = sections.get(elf.section_names()? as usize) else {
+        return Ok(Vec::new());
+    };
+    let mut regions = Vec::new();
+    for section in &sections {
+        if section.flags & SHF_ALLOC == 0 || section.kind == SHT_NOBITS || section.size == 0 {
+            continue;
+        }
+        regions.extend(placed_region(&segments, &elf.string(names, section.name)?, section.addr, section.size));
+    }
+    regions.sort_by_key(|region| region.start);
+    Ok(regions)
+}
+
+/* The count largest functions and data objects of an ELF file, biggest first, at the address they are stored at */
+pub fn elf_symbols(data: &[u8], count: usize) -> Result<Vec<Region>, String> {
+    let elf = Elf::new(data)?;
+    let segments = elf.segments()?;
+    let sections = elf.sections()?;
+    let mut symbols = Vec::new();
+    for table in sections.iter().filter(|section| section.kind == SHT_SYMTAB) {
+        let Some(strings) = sections.get(table.link as usize) else {
+            continue;
+        };
+        let entry_size = if elf.is_64 {24} else {16};
+        for at in (table.offset..table.offset + table.size).step_by(entry_size) {
+            let (name, info, addr, size) = if elf.is_64 {
+                (elf.u32(at)?, elf.uint(at + 4, 1)?, elf.word(at + 8)?, elf.word(at + 16)?)
+            } else {
+                (elf.u32(at)?, elf.uint(at + 12, 1)?, elf.u32(at + 4)?, elf.u32(at + 8)?)
+            };
+            let kind = (info & 0xF) as u8;
+            if size == 0 || (kind != STT_FUNC && kind != STT_OBJECT) {
+                continue;
+            }
+            /* The lowest bit of a function address marks Thumb code on ARM rather than being part of the address */
+            let addr = if kind == STT_FUNC {addr & !1} else {addr};
+            symbols.push((size, elf.string(strings, name as u32)?, addr));
+        }
+    }
+    symbols.sort_by(|a, b| b.0.cmp(&a.0).then(a.2.cmp(&b.2)));
+    Ok(symbols.into_iter()
+        .take(count)
+        .filter_map(|(size, name, addr)| placed_region(&segments, &name, addr, size))
+        .collect())
+}
+
+/**
+ * Converts what an ELF file puts in flash to Intel HEX, so it can be loaded like any hex file: the contents of every
+ * loadable segment at its load address, then the entry point.
+ */
+pub fn elf_to_hex(data: &[u8]) -> Result<String, String> {
+    let elf = Elf::new(data)?;
+    let mut segments: Vec<Segment> = elf.segments()?.into_iter().filter(|segment| segment.filesz > 0).collect();
+    segments.sort_by_key(|segment| segment.paddr);
+    let mut ranges = Vec::new();
+    for segment in &segments {
+        let end = segment.paddr + segment.filesz;
+        if end > 1 << 32 {
+            return Err(format!("A segment at {:#x} is outside the 32 bit space of Intel HEX", segment.paddr));
+        }
+        elf.bytes(segment.offset, segment.filesz as usize)?;
+        ranges.push((segment.paddr, end));
+    }
+    let byte_at = |addr: u32| {
+        segments.iter()
+            .find(|segment| segment.paddr <= addr as u64 && (addr as u64) < segment.paddr + segment.filesz)
+            .map_or(0, |segment| data[(segment.offset + addr as u64 - segment.paddr) as usize])
+    };
+    let mut records = hex_records(&ranges, byte_at);
+    if let Ok(entry) = u32::try_from(elf.entry()?) {
+        records.insert(records.len() - 1, Record::StartLinearAddress(entry));
+    }
+    ihex::create_object_file_representation(&records).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::elf::{elf_sections, elf_symbols, elf_to_hex};
+    use crate::hex_loader::{load_hex, LoadOptions};
+    use crate::occupied_ranges;
+    use crate::regions::Region;
+
+    /* Appends little endian fields of the given sizes */
+    fn push_fields(out: &mut Vec<u8>, fields: &[(u64, usize)]) {
+        for (value, len) in fields {
+            out.extend_from_slice(&value.to_le_bytes()[..*len]);
+        }
+    }
+
+    /**
+     * A 32 bit little endian ELF of 8 bytes of code at 0x08000000 and 4 bytes of data running at 0x20000000 but stored
+     * right after the code, with a symbol for each.
+     */
+    fn test_elf() -> Vec<u8> {
+        let names = b"\0.text\0.data\0.bss\0.symtab\0.strtab\0.shstrtab\0";
+        let strings = b"\0main\0counter\0";
+        let mut elf = b"\x7fELF\x01\x01\x01".to_vec();
+        elf.resize(0x18, 0);
+        /* Entry, program headers at 0x34, section headers at 0x140, sizes and counts, section names in section 6 */
+        push_fields(&mut elf, &[(0x0800_0001, 4), (0x34, 4), (0x140, 4), (0, 4), (0x34, 2), (0x20, 2), (2, 2), (0x28, 2), (7, 2), (6, 2)]);
+        for (offset, vaddr, paddr, size) in [(0xA0, 0x0800_0000u64, 0x0800_0000u64, 8u64), (0xA8, 0x2000_0000, 0x0800_0008, 4)] {
+            push_fields(&mut elf, &[(1, 4), (offset, 4), (vaddr, 4), (paddr, 4), (size, 4), (size, 4), (7, 4), (4, 4)]);
+        }
+        elf.resize(0xA0, 0);
+        elf.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        /* Symbols: the null one, main as a 8 byte Thumb function and counter as a 4 byte object */
+        elf.resize(0xC0, 0);
+        push_fields(&mut elf, &[(1, 4), (0x0800_0001, 4), (8, 4), (0x12, 1), (0, 1), (1, 2)]);
+        push_fields(&mut elf, &[(6, 4), (0x2000_0000, 4), (4, 4), (0x11, 1), (0, 1), (2, 2)]);
+        let strings_at = elf.len() as u64;
+        elf.extend_from_slice(strings);
+        let names_at = elf.len() as u64;
+        elf.extend_from_slice(names);
+        elf.resize(0x140, 0);
+        /* Sections: null, .text, .data, .bss, .symtab, .strtab, .shstrtab */
+        let sections = [
+            (0, 0, 0, 0, 0, 0, 0),
+            (1, 1, 6, 0x0800_0000, 0xA0, 8, 0),
+            (7, 1, 3, 0x2000_0000, 0xA8, 4, 0),
+            (13, 8, 3, 0x2000_0004, 0xAC, 0x40, 0),
+            (18, 2, 0, 0, 0xB0, 0x30, 5),
+            (26, 3, 0, 0, strings_at, strings.len() as u64, 0),
+            (34, 3, 0, 0, names_at, names.len() as u64, 0),
+        ];
+        for (name, kind, flags, addr, offset, size, link) in sections {
+            push_fields(&mut elf, &[(name, 4), (kind, 4), (flags, 4), (addr, 4), (offset, 4), (size, 4), (link, 4), (0, 4), (0, 4), (0, 4)]);
+        }
+        elf
+    }
+
+    #[test]
+    fn test_elf_sections() -> Result<(),String> {
+        assert_eq!(vec![
+            Region { name: ".text".to_string(), start: 0x0800_0000, size: 8, budget: None },
+            Region { name: ".data load image".to_string(), start: 0x0800_0008, size: 4, budget: None },
+        ], elf_sections(&test_elf())?);
+        assert_eq!(vec![Region { name: "main".to_string(), start: 0x0800_0000, size: 8, budget: None }], elf_symbols(&test_elf(), 1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_elf_to_hex() -> Result<(),String> {
+        let image = load_hex(&elf_to_hex(&test_elf())?, LoadOptions { store_values: true, blank_byte: None });
+        assert_eq!(vec![(0x0800_0000, 0x0800_000C)], occupied_ranges(&image.segment_map));
+        assert_eq!(Some(0x0800_0001), image.entry);
+        assert!(image.errors.is_empty() && image.issues.is_empty());
+        Ok(())
+    }
+}
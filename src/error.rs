@@ -0,0 +1,76 @@
+use std::fmt;
+use std::fs;
+use std::io;
+
+/* Everything which stops a run, each shown as one line saying what it was about */
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed
+    Io { path: String, source: io::Error },
+    /// A file could not be read as what it was given as, e.g. a hex, ELF, region or SVD file
+    Parse { path: String, reason: String },
+    /// Showing or writing out the map failed, e.g. the terminal went away or an export could not be encoded
+    Render(String),
+    /// The arguments ask for something which cannot be done, e.g. --diff with one file
+    Argument(String),
+    /// A check the arguments asked for failed, e.g. --strict on a damaged file or a budget
+    Check(String),
+}
+
+impl Error {
+    pub fn io(path: &str, source: io::Error) -> Error {
+        Error::Io { path: path.to_string(), source }
+    }
+
+    pub fn parse(path: &str, reason: impl fmt::Display) -> Error {
+        Error::Parse { path: path.to_string(), reason: reason.to_string() }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{path}: {source}"),
+            Error::Parse { path, reason } => write!(f, "{path}: {reason}"),
+            Error::Render(reason) | Error::Argument(reason) | Error::Check(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/* fs::read, naming the file in the error */
+pub fn read_file(path: &str) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|source| Error::io(path, source))
+}
+
+/* fs::read_to_string, naming the file in the error */
+pub fn read_text(path: &str) -> Result<String, Error> {
+    fs::read_to_string(path).map_err(|source| Error::io(path, source))
+}
+
+/* fs::write, naming the file in the error */
+pub fn write_file(path: &str, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+    fs::write(path, contents).map_err(|source| Error::io(path, source))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{read_text, Error};
+
+    #[test]
+    fn test_error_text() -> Result<(),String> {
+        assert_eq!("regions.toml: Line 3: expected a key and value", Error::parse("regions.toml", "Line 3: expected a key and value").to_string());
+        let missing = read_text("/nonexistent/app.hex").err().ok_or("A missing file reads")?;
+        assert!(missing.to_string().starts_with("/nonexistent/app.hex: "));
+        assert!(std::error::Error::source(&missing).is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use std::fmt::Write;
+use ihex::Record;
+use crate::elf;
+use crate::error::Error;
+use crate::structured::json_string;
+use crate::{byte_value, clip_ranges, SegmentMap, ValueMap, SEGMENT_BYTES, IHEX_SEGMENT_BYTES};
+
+/* Bytes per data record when writing hex files, matching what most toolchains emit */
+const HEX_RECORD_BYTES: u32 = 16;
+
+/* Identifies a raw occupancy bitmask file and its layout version */
+pub const BITMASK_MAGIC: &[u8; 8] = b"IHXMAP01";
+
+/**
+ * Builds a GNU ld MEMORY block describing every occupied range followed by the free gaps between them. The final free
+ * region runs from the end of the data to memory_end, the end of the device flash, and is left out without one.
+ */
+pub fn ld_memory_block(ranges: &[(u64, u64)], memory_end: Option<u64>) -> String {
+    let mut out = String::new();
+    writeln!(out, "/* Generated by ihex-visualize from the occupied ranges of the hex file */").unwrap();
+    writeln!(out, "MEMORY").unwrap();
+    writeln!(out, "{{").unwrap();
+
+    for (i, (start, end)) in ranges.iter().enumerate() {
+        writeln!(out, "    USED{i} (rx) : ORIGIN = {start:#010x}, LENGTH = {:#x}", end - start).unwrap();
+    }
+
+    /* Gaps are everything between the end of one used range and the start of the next */
+    let mut free_idx = 0;
+    let gap_starts = ranges.iter().map(|r| r.1);
+    let gap_ends = ranges.iter().skip(1).map(|r| r.0).chain(memory_end);
+    for (start, end) in gap_starts.zip(gap_ends) {
+        if end > start {
+            writeln!(out, "    FREE{free_idx} (rwx) : ORIGIN = {start:#010x}, LENGTH = {:#x}", end - start).unwrap();
+            free_idx += 1;
+        }
+    }
+
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/**
+ * Serializes the occupancy map so external tools can do coverage math without re-parsing the hex.
+ *
+ * Layout (all integers little endian):
+ *   8 bytes  magic "IHXMAP01"
+ *   4 bytes  number of pages that follow
+ *   per page, in ascending page order:
+ *     2 bytes     page index (the upper 16 bits of the address)
+ *     8192 bytes  bitmap, one bit per address. Bit 7 (MSB) of map byte 0 is the first address of the page
+ */
+pub fn occupancy_bitmask(segment_map: &SegmentMap) -> Vec<u8> {
+    let mut pages: Vec<u16> = segment_map.keys().cloned().collect();
+    pages.sort();
+
+    let mut out = Vec::with_capacity(12 + pages.len() * (2 + SEGMENT_BYTES as usize));
+    out.extend_from_slice(BITMASK_MAGIC);
+    out.extend_from_slice(&(pages.len() as u32).to_le_bytes());
+    for page in pages {
+        out.extend_from_slice(&page.to_le_bytes());
+        out.extend_from_slice(&segment_map[&page]);
+    }
+    out
+}
+
+/* What an image file given to GDB or a flash tool is, which decides how it is told to load the file */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Hex,
+    Elf,
+}
+
+impl ImageFormat {
+    /* An ELF file by its magic, anything else taken for Intel HEX */
+    pub fn of_file(path: &str) -> Result<ImageFormat, Error> {
+        match elf::is_elf_file(path).map_err(|source| Error::io(path, source))? {
+            true => Ok(ImageFormat::Elf),
+            false => Ok(ImageFormat::Hex),
+        }
+    }
+}
+
+/* A path as a double quoted argument, which GDB and the flash tools take with spaces in it */
+fn quoted_path(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/**
+ * Builds a GDB script which loads the image onto the target and checks what was written. An ELF file is loaded and
+ * compared section by section with GDB's own load and compare-sections. A hex file has no sections, so only its occupied
+ * ranges are restored, then with python each range is read back and compared with the hex file by a short Python block
+ * which raises an error, failing the script, at the first byte which differs. GDB builds without Python leave it out.
+ */
+pub fn gdb_script(image_path: &str, format: ImageFormat, ranges: &[(u64, u64)], python: bool) -> String {
+    let mut out = String::new();
+    writeln!(out, "# Generated by ihex-visualize from the occupied ranges of {image_path}").unwrap();
+    writeln!(out, "set confirm off").unwrap();
+    writeln!(out).unwrap();
+
+    if format == ImageFormat::Elf {
+        writeln!(out, "# Load the sections of the ELF file, then compare each on the target with the file").unwrap();
+        writeln!(out, "file {}", quoted_path(image_path)).unwrap();
+        writeln!(out, "load").unwrap();
+        writeln!(out, "compare-sections -r").unwrap();
+        return out;
+    }
+
+    writeln!(out, "# Load: restore <file> <bias> <start> <end> only writes the given window of the file").unwrap();
+    for (start, end) in ranges {
+        writeln!(out, "restore {} 0 {start:#010x} {end:#010x}", quoted_path(image_path)).unwrap();
+    }
+    if !python {
+        return out;
+    }
+    writeln!(out).unwrap();
+
+    writeln!(out, "# Verify: compare each range on the target with {image_path}, failing at the first difference").unwrap();
+    writeln!(out, "python").unwrap();
+    out.push_str(GDB_VERIFY_READER);
+    writeln!(out, "expected = ihex_bytes({})", json_string(image_path)).unwrap();
+    let range_list: Vec<String> = ranges.iter().map(|(start, end)| format!("({start:#010x}, {end:#010x})")).collect();
+    writeln!(out, "for start, end in [{}]:", range_list.join(", ")).unwrap();
+    writeln!(out, "    actual = bytes(gdb.selected_inferior().read_memory(start, end - start))").unwrap();
+    writeln!(out, "    for offset, byte in enumerate(actual):").unwrap();
+    writeln!(out, "        if expected.get(start + offset, byte) != byte:").unwrap();
+    writeln!(out, "            raise gdb.GdbError(\"Verify failed at %#010x: read %#04x, expected %#04x\" % (start + offset, byte, expected[start + offset]))").unwrap();
+    writeln!(out, "print(\"Verified {} ranges\")", ranges.len()).unwrap();
+    writeln!(out, "end").unwrap();
+    out
+}
+
+/* The start of the verify block of gdb_script: a reader for the data records of a hex file, by address */
+const GDB_VERIFY_READER: &str = "\
+import gdb
+def ihex_bytes(path):
+    data, base = {}, 0
+    for line in open(path):
+        line = line.strip()
+        if not line.startswith(':'):
+            continue
+        record = bytes.fromhex(line[1:])
+        count, offset, kind = record[0], (record[1] << 8) | record[2], record[3]
+        if kind == 0:
+            for idx, byte in enumerate(record[4:4 + count]):
+                data[(base + offset + idx) & 0xFFFFFFFF] = byte
+        elif kind == 2:
+            base = ((record[4] << 8) | record[5]) << 4
+        elif kind == 4:
+            base = ((record[4] << 8) | record[5]) << 16
+    return data
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashTool {
+    OpenOcd,
+    PyOcd,
+    JLink,
+}
+
+/**
+ * Builds a flash tool command script which erases only the sectors holding data, then programs and verifies the image.
+ * erase_spans are sector aligned [start, end) ranges as produced by touched_sector_spans. The J-Link script is for
+ * JLinkExe -device <part> -if SWD -CommanderScript, which connects without asking with the part given.
+ */
+pub fn flash_script(tool: FlashTool, image_path: &str, format: ImageFormat, erase_spans: &[(u64, u64)], sector_size: u32) -> String {
+    let mut out = String::new();
+    /* J-Link command files have no comment syntax */
+    if tool != FlashTool::JLink {
+        writeln!(out, "# Generated by ihex-visualize from the occupied ranges of {image_path} with {sector_size:#x} byte sectors").unwrap();
+    }
+    match tool {
+        FlashTool::OpenOcd => {
+            writeln!(out, "init").unwrap();
+            writeln!(out, "reset halt").unwrap();
+            for (start, end) in erase_spans {
+                writeln!(out, "flash erase_address {start:#010x} {:#x}", end - start).unwrap();
+            }
+            let (path, kind) = (format!("{{{image_path}}}"), if format == ImageFormat::Elf {"elf"} else {"ihex"});
+            writeln!(out, "flash write_image {path} 0 {kind}").unwrap();
+            writeln!(out, "verify_image {path} 0 {kind}").unwrap();
+            writeln!(out, "reset run").unwrap();
+            writeln!(out, "shutdown").unwrap();
+        },
+        FlashTool::PyOcd => {
+            writeln!(out, "halt").unwrap();
+            for (start, end) in erase_spans {
+                writeln!(out, "erase {start:#010x} {}", (end - start) / sector_size as u64).unwrap();
+            }
+            writeln!(out, "load {}", quoted_path(image_path)).unwrap();
+            writeln!(out, "reset").unwrap();
+        },
+        FlashTool::JLink => {
+            writeln!(out, "connect").unwrap();
+            writeln!(out, "r").unwrap();
+            for (start, end) in erase_spans {
+                writeln!(out, "erase {start:#010x} {end:#010x}").unwrap();
+            }
+            /* Every byte loadfile writes is read back and compared, failing the script at the first difference */
+            writeln!(out, "exec SetVerifyDownload = 7").unwrap();
+            writeln!(out, "loadfile {}", quoted_path(image_path)).unwrap();
+            writeln!(out, "r").unwrap();
+            writeln!(out, "g").unwrap();
+            writeln!(out, "exit").unwrap();
+        },
+    }
+    out
+}
+
+/**
+ * Builds the Intel HEX records covering the given ranges, taking each byte's value from byte_at.
+ * An extended linear address record is emitted whenever the upper 16 bits change, and data records never cross a
+ * 64kb page so no record relies on offset wrap-around.
+ */
+pub fn hex_records(ranges: &[(u64, u64)], byte_at: impl Fn(u32) -> u8) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut current_page: Option<u16> = None;
+    for &(start, end) in ranges {
+        let mut addr = start;
+        while addr < end {
+            let page = (addr >> 16) as u16;
+            if current_page != Some(page) {
+                records.push(Record::ExtendedLinearAddress(page));
+                current_page = Some(page);
+            }
+            let page_end = (page as u64 + 1) * IHEX_SEGMENT_BYTES as u64;
+            let chunk_end = (addr + HEX_RECORD_BYTES as u64).min(end).min(page_end);
+            records.push(Record::Data {
+                offset: (addr & 0xFFFF) as u16,
+                value: (addr..chunk_end).map(|a| byte_at(a as u32)).collect(),
+            });
+            addr = chunk_end;
+        }
+    }
+    records.push(Record::EndOfFile);
+    records
+}
+
+/**
+ * Builds an Intel HEX file filling every unoccupied address between the first and last occupied byte with fill_byte.
+ * Loading it alongside the original image pads or blanks exactly the holes.
+ */
+pub fn gaps_hex(ranges: &[(u64, u64)], fill_byte: u8) -> Result<String, ihex::WriterError> {
+    let gaps: Vec<(u64, u64)> = ranges.windows(2)
+        .map(|pair| (pair[0].1, pair[1].0))
+        .collect();
+    ihex::create_object_file_representation(&hex_records(&gaps, |_| fill_byte))
+}
+
+/* An Intel HEX of just the occupied bytes within [start, end), with their values */
+pub fn range_hex(ranges: &[(u64, u64)], segment_map: &SegmentMap, values: &ValueMap, start: u64, end: u64) -> Result<String, ihex::WriterError> {
+    let clipped = clip_ranges(ranges, start, end);
+    ihex::create_object_file_representation(&hex_records(&clipped, |addr| byte_value(segment_map, values, addr).unwrap_or(0)))
+}
+
+/* A flat binary image of [start, end), with unoccupied bytes set to fill_byte */
+pub fn range_bin(segment_map: &SegmentMap, values: &ValueMap, start: u64, end: u64, fill_byte: u8) -> Vec<u8> {
+    (start..end).map(|addr| byte_value(segment_map, values, addr as u32).unwrap_or(fill_byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ihex::Record;
+    use crate::exports::{flash_script, gdb_script, hex_records, ld_memory_block, FlashTool, ImageFormat};
+
+    #[test]
+    fn test_hex_records() -> Result<(),String> {
+        /* Crosses a page boundary mid-record and ends on a partial record */
+        let records = hex_records(&[(0xFFF8, 0x1000A)], |addr| addr as u8);
+        assert_eq!(vec![
+            Record::ExtendedLinearAddress(0),
+            Record::Data { offset: 0xFFF8, value: (0xF8..=0xFF).collect() },
+            Record::ExtendedLinearAddress(1),
+            Record::Data { offset: 0, value: (0x00..0x0A).collect() },
+            Record::EndOfFile,
+        ], records);
+
+        assert_eq!(vec![Record::EndOfFile], hex_records(&[], |_| 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gdb_script() -> Result<(),String> {
+        let script = gdb_script("app.hex", ImageFormat::Hex, &[(0x0800_0000, 0x0800_0100)], true);
+        assert!(script.contains("restore \"app.hex\" 0 0x08000000 0x08000100\n"));
+        /* Verifying compares with the hex file rather than only reading back */
+        assert!(script.contains("\npython\n") && script.ends_with("\nend\n"));
+        assert!(script.contains("expected = ihex_bytes(\"app.hex\")\nfor start, end in [(0x08000000, 0x08000100)]:\n"));
+        assert!(script.contains("raise gdb.GdbError("));
+        /* Without Python only the load is left, and an ELF file is checked by GDB itself */
+        let script = gdb_script("my app.hex", ImageFormat::Hex, &[(0x0800_0000, 0x0800_0100)], false);
+        assert!(script.ends_with("restore \"my app.hex\" 0 0x08000000 0x08000100\n"));
+        let script = gdb_script("app.elf", ImageFormat::Elf, &[(0x0800_0000, 0x0800_0100)], true);
+        assert!(script.ends_with("file \"app.elf\"\nload\ncompare-sections -r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_flash_script() -> Result<(),String> {
+        let script = flash_script(FlashTool::JLink, "app.hex", ImageFormat::Hex, &[(0x0800_0000, 0x0800_1000)], 0x1000);
+        assert_eq!("connect\nr\nerase 0x08000000 0x08001000\nexec SetVerifyDownload = 7\nloadfile \"app.hex\"\nr\ng\nexit\n", script);
+        let script = flash_script(FlashTool::OpenOcd, "app.elf", ImageFormat::Elf, &[(0x0800_0000, 0x0800_1000)], 0x1000);
+        assert!(script.contains("flash write_image {app.elf} 0 elf\nverify_image {app.elf} 0 elf\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ld_memory_block() -> Result<(),String> {
+        let block = ld_memory_block(&[(0x1000, 0x1800), (0x2000, 0x2100)], Some(0x10000));
+        assert!(block.contains("USED1 (rx) : ORIGIN = 0x00002000, LENGTH = 0x100"));
+        assert!(block.contains("FREE0 (rwx) : ORIGIN = 0x00001800, LENGTH = 0x800"));
+        assert!(block.contains("FREE1 (rwx) : ORIGIN = 0x00002100, LENGTH = 0xdf00"));
+        assert!(!block.contains("FREE2"));
+        assert!(!ld_memory_block(&[(0x1000, 0x1800), (0x2000, 0x2100)], None).contains("FREE1"));
+
+        Ok(())
+    }
+}
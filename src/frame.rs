@@ -0,0 +1,114 @@
+use crossterm::style::{self, ContentStyle, StyledContent};
+use crossterm::{cursor, queue};
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/**
+ * A screen's worth of styled characters, drawn into in memory and then written out. Comparing against the frame
+ * before lets only the rows which changed go to the terminal, so nothing flickers. Everything past the edges is
+ * clipped.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    width: u16,
+    height: u16,
+    rows: Vec<Vec<(char, ContentStyle)>>,
+    column: u16,
+    row: u16,
+}
+
+impl Frame {
+    /* A blank frame of the given size */
+    pub fn new(width: u16, height: u16) -> Frame {
+        Frame {
+            width,
+            height,
+            rows: vec![vec![(' ', ContentStyle::new()); width as usize]; height as usize],
+            column: 0,
+            row: 0,
+        }
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    pub fn move_to(&mut self, column: u16, row: u16) {
+        (self.column, self.row) = (column, row);
+    }
+
+    pub fn move_to_column(&mut self, column: u16) {
+        self.column = column;
+    }
+
+    /* Puts text down in the default style from the cursor onwards, moving the cursor past it */
+    pub fn print(&mut self, text: &str) {
+        self.put(text, ContentStyle::new());
+    }
+
+    pub fn print_styled<D: Display>(&mut self, content: StyledContent<D>) {
+        self.put(&content.content().to_string(), *content.style());
+    }
+
+    fn put(&mut self, text: &str, text_style: ContentStyle) {
+        for chr in text.chars() {
+            if let Some(cell) = self.rows.get_mut(self.row as usize).and_then(|row| row.get_mut(self.column as usize)) {
+                *cell = (chr, text_style);
+            }
+            self.column = self.column.saturating_add(1);
+        }
+    }
+
+    /**
+     * Writes out the rows which differ from the frame shown before, or every row without one (or after a resize).
+     * Runs of characters sharing a style go out in one print.
+     */
+    pub fn write_changes(&self, out: &mut impl Write, previous: Option<&Frame>) -> io::Result<()> {
+        let previous = previous.filter(|previous| previous.size() == self.size());
+        for (row_idx, row) in self.rows.iter().enumerate() {
+            if previous.is_some_and(|previous| previous.rows[row_idx] == *row) {
+                continue;
+            }
+            queue!(out, cursor::MoveTo(0, row_idx as u16))?;
+            for run in row.chunk_by(|a, b| a.1 == b.1) {
+                let text: String = run.iter().map(|(chr, _)| chr).collect();
+                queue!(out, style::PrintStyledContent(run[0].1.apply(text)))?;
+            }
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::frame::Frame;
+
+    #[test]
+    fn test_write_changes() -> Result<(),String> {
+        let mut before = Frame::new(8, 3);
+        before.move_to(0, 1);
+        before.print("abc");
+
+        /* Nothing changed, nothing written */
+        let mut out = Vec::new();
+        before.clone().write_changes(&mut out, Some(&before)).map_err(|e| e.to_string())?;
+        assert!(out.is_empty());
+
+        /* Only the changed row is written, clipped to the width */
+        let mut after = before.clone();
+        after.move_to(6, 2);
+        after.print("xyz");
+        let mut out = Vec::new();
+        after.write_changes(&mut out, Some(&before)).map_err(|e| e.to_string())?;
+        let out = String::from_utf8(out).map_err(|e| e.to_string())?;
+        assert!(out.contains("\x1b[3;1H") && out.contains("xy") && !out.contains('z'));
+        assert!(!out.contains("\x1b[1;1H") && !out.contains("\x1b[2;1H"));
+
+        /* Without a frame before, or after a resize, every row is written */
+        let mut out = Vec::new();
+        after.write_changes(&mut out, Some(&Frame::new(9, 3))).map_err(|e| e.to_string())?;
+        let out = String::from_utf8(out).map_err(|e| e.to_string())?;
+        assert!(out.contains("\x1b[1;1H") && out.contains("\x1b[2;1H") && out.contains("\x1b[3;1H"));
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+/* Palette index of each pixel kind. The palette is padded to 4 entries since GIF needs at least a 2 bit code size */
+const PX_BACKGROUND: u8 = 0;
+const PX_BLANK: u8 = 1;
+const PX_DATA: u8 = 2;
+const PALETTE: [[u8; 3]; 4] = [
+    [0x10, 0x10, 0x10],
+    [0x40, 0x40, 0x48],
+    [0x30, 0xC0, 0x60],
+    [0xFF, 0xFF, 0xFF],
+];
+const LZW_MIN_CODE_SIZE: u8 = 2;
+/* Each map cell is drawn as a block of this many pixels, with a one pixel gap between lines */
+const CELL_PX_W: usize = 2;
+const CELL_PX_H: usize = 3;
+
+/**
+ * A bit-packing writer for the LZW code stream. GIF packs codes least significant bit first.
+ */
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u8) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bytes.push((self.acc & 0xFF) as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push((self.acc & 0xFF) as u8);
+        }
+        self.bytes
+    }
+}
+
+/* Standard GIF variable width LZW, resetting the dictionary when it fills the 12 bit code space */
+fn lzw_encode(pixels: &[u8]) -> Vec<u8> {
+    let clear_code: u16 = 1 << LZW_MIN_CODE_SIZE;
+    let eoi_code: u16 = clear_code + 1;
+    let mut writer = BitWriter { bytes: Vec::new(), acc: 0, bits: 0 };
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = eoi_code + 1;
+    let mut width = LZW_MIN_CODE_SIZE + 1;
+
+    writer.write(clear_code, width);
+    let mut prefix: Option<u16> = None;
+    for px in pixels {
+        let current = match prefix {
+            None => {
+                prefix = Some(*px as u16);
+                continue;
+            },
+            Some(current) => current,
+        };
+        if let Some(code) = dict.get(&(current, *px)) {
+            prefix = Some(*code);
+            continue;
+        }
+
+        writer.write(current, width);
+        if next_code < 4096 {
+            dict.insert((current, *px), next_code);
+            /* The decoder widens one code later than the encoder adds the entry */
+            if next_code == (1 << width) && width < 12 {
+                width += 1;
+            }
+            next_code += 1;
+        } else {
+            writer.write(clear_code, width);
+            dict.clear();
+            next_code = eoi_code + 1;
+            width = LZW_MIN_CODE_SIZE + 1;
+        }
+        prefix = Some(*px as u16);
+    }
+    if let Some(current) = prefix {
+        writer.write(current, width);
+    }
+    writer.write(eoi_code, width);
+    writer.finish()
+}
+
+/* Scales a grid of map cells to pixels, padding shorter frames with background */
+fn frame_pixels(frame: &[Vec<bool>], width_px: usize, height_px: usize) -> Vec<u8> {
+    let mut pixels = vec![PX_BACKGROUND; width_px * height_px];
+    for (line_idx, line) in frame.iter().enumerate() {
+        for (cell_idx, cell) in line.iter().enumerate() {
+            let px = if *cell {PX_DATA} else {PX_BLANK};
+            for y in 0..CELL_PX_H - 1 {
+                let row_start = (line_idx * CELL_PX_H + y) * width_px + cell_idx * CELL_PX_W;
+                pixels[row_start..row_start + CELL_PX_W].fill(px);
+            }
+        }
+    }
+    pixels
+}
+
+/**
+ * Encodes one occupancy map per frame into a looping animated GIF. Each frame is a grid of map lines, each line a
+ * list of cells (set or blank) the same as the terminal rendering. delay_cs is the time per frame in 1/100 s.
+ */
+pub fn animated_gif(frames: &[Vec<Vec<bool>>], delay_cs: u16) -> Vec<u8> {
+    let cells_w = frames.iter().flatten().map(|line| line.len()).max().unwrap_or(0);
+    let lines_h = frames.iter().map(|frame| frame.len()).max().unwrap_or(0);
+    let width_px = (cells_w * CELL_PX_W).max(1);
+    let height_px = (lines_h * CELL_PX_H).max(1);
+
+    let mut out = Vec::new();
+    /* Header and logical screen descriptor with a 4 entry global color table */
+    out.extend_from_slice(b"GIF89a");
+    out.extend_from_slice(&(width_px as u16).to_le_bytes());
+    out.extend_from_slice(&(height_px as u16).to_le_bytes());
+    out.extend_from_slice(&[0b1000_0001, PX_BACKGROUND, 0]);
+    for color in PALETTE {
+        out.extend_from_slice(&color);
+    }
+
+    /* NETSCAPE2.0 application extension, loop forever */
+    out.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+    out.extend_from_slice(b"NETSCAPE2.0");
+    out.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+
+    for frame in frames {
+        /* Graphic control extension carrying the frame delay */
+        out.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+        out.extend_from_slice(&delay_cs.to_le_bytes());
+        out.extend_from_slice(&[0x00, 0x00]);
+
+        /* Image descriptor covering the whole screen, no local color table */
+        out.push(0x2C);
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        out.extend_from_slice(&(width_px as u16).to_le_bytes());
+        out.extend_from_slice(&(height_px as u16).to_le_bytes());
+        out.push(0);
+
+        /* Image data in sub-blocks of at most 255 bytes */
+        out.push(LZW_MIN_CODE_SIZE);
+        for block in lzw_encode(&frame_pixels(frame, width_px, height_px)).chunks(255) {
+            out.push(block.len() as u8);
+            out.extend_from_slice(block);
+        }
+        out.push(0);
+    }
+
+    out.push(0x3B);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gif_export::{animated_gif, lzw_encode};
+
+    /* A straightforward GIF LZW decoder to check the encoder against */
+    fn lzw_decode(data: &[u8]) -> Vec<u8> {
+        let (clear, eoi) = (4u16, 5u16);
+        let mut dict: Vec<Vec<u8>> = Vec::new();
+        let mut width = 3;
+        let mut out = Vec::new();
+        let mut prev: Option<Vec<u8>> = None;
+        let (mut acc, mut bits, mut pos) = (0u32, 0u8, 0usize);
+        loop {
+            while bits < width {
+                acc |= (data[pos] as u32) << bits;
+                pos += 1;
+                bits += 8;
+            }
+            let code = (acc & ((1 << width) - 1)) as u16;
+            acc >>= width;
+            bits -= width;
+
+            if code == clear {
+                dict = (0..4).map(|i| vec![i]).collect();
+                dict.push(Vec::new());
+                dict.push(Vec::new());
+                width = 3;
+                prev = None;
+                continue;
+            }
+            if code == eoi {
+                return out;
+            }
+            let entry = if (code as usize) < dict.len() {
+                dict[code as usize].clone()
+            } else {
+                let mut p = prev.clone().unwrap();
+                p.push(p[0]);
+                p
+            };
+            out.extend_from_slice(&entry);
+            if let Some(mut p) = prev {
+                p.push(entry[0]);
+                dict.push(p);
+                if dict.len() == (1 << width) && width < 12 {
+                    width += 1;
+                }
+            }
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() -> Result<(),String> {
+        let pixels: Vec<u8> = (0..20000u32).map(|i| ((i * 7 / 13) % 3) as u8).collect();
+        assert_eq!(pixels, lzw_decode(&lzw_encode(&pixels)));
+
+        let repetitive = vec![1u8; 5000];
+        assert_eq!(repetitive, lzw_decode(&lzw_encode(&repetitive)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_animated_gif_framing() -> Result<(),String> {
+        let frames = vec![vec![vec![true, false]], vec![vec![false, false], vec![true, true]]];
+        let gif = animated_gif(&frames, 50);
+        assert_eq!(b"GIF89a", &gif[..6]);
+        /* 2 cells wide by the tallest frame's 2 lines */
+        assert_eq!([4, 0, 6, 0], gif[6..10]);
+        assert_eq!(Some(&0x3B), gif.last());
+        assert_eq!(2, gif.windows(3).filter(|w| w == &[0x21, 0xF9, 0x04]).count());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use crate::error::{write_file, Error};
+
+/* The contents of a file as of a git revision, read with the git command line from the repository around the current directory */
+pub fn show_file(revision: &str, file_path: &str) -> Result<Vec<u8>, String> {
+    /* A ./ prefix makes git take the path from the current directory rather than the top of the repository */
+    let spec = if Path::new(file_path).is_absolute() {format!("{revision}:{file_path}")} else {format!("{revision}:./{file_path}")};
+    let output = Command::new("git").args(["show", &spec]).output()
+        .map_err(|err| format!("Could not run git: {err}"))?;
+    if !output.status.success() {
+        return Err(format!("git show {spec} failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+/* A revision made safe to put in a file name, e.g. origin/main to origin_main */
+fn revision_name(revision: &str) -> String {
+    revision.chars().map(|chr| if chr.is_ascii_alphanumeric() || "~^.-_".contains(chr) {chr} else {'_'}).collect()
+}
+
+/**
+ * Writes a file as of each revision to a directory of its own under the system temporary directory, named after the
+ * file and the revision (firmware@HEAD~1.hex), and returns their paths in the order of the revisions. Everything else
+ * then loads them like any other file.
+ */
+pub fn extract_revisions(revisions: &[&str], file_path: &str) -> Result<Vec<String>, Error> {
+    let dir = env::temp_dir().join(format!("ihex-visualize-{}", std::process::id()));
+    fs::create_dir_all(&dir).map_err(|source| Error::io(&dir.to_string_lossy(), source))?;
+    let path = Path::new(file_path);
+    let stem = path.file_stem().map_or("file".into(), |stem| stem.to_string_lossy());
+    let extension = path.extension().map_or(String::new(), |extension| format!(".{}", extension.to_string_lossy()));
+    let mut paths = Vec::new();
+    for revision in revisions {
+        let revision_path = dir.join(format!("{stem}@{}{extension}", revision_name(revision))).to_string_lossy().to_string();
+        write_file(&revision_path, show_file(revision, file_path).map_err(Error::Argument)?)?;
+        paths.push(revision_path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::git::revision_name;
+
+    #[test]
+    fn test_revision_name() -> Result<(),String> {
+        assert_eq!("HEAD~1", revision_name("HEAD~1"));
+        assert_eq!("origin_main", revision_name("origin/main"));
+        assert_eq!("v1.2.0", revision_name("v1.2.0"));
+        Ok(())
+    }
+}
@@ -0,0 +1,494 @@
+use ihex::{ReaderError, Record};
+use std::collections::HashMap;
+use std::fmt;
+use crate::hex_loader::{LoadOptions, RecordBase};
+use crate::structured::Value;
+use crate::{free_ranges, IHEX_SEGMENT_BYTES};
+
+/* Something wrong with a hex file which loading quietly gets past */
+#[derive(Debug, Clone, PartialEq)]
+pub enum IssueKind {
+    /// A data record sets bytes which earlier records (on the given lines) already set. Later data wins when loading
+    Overlap { first: u32, last: u32, bytes: u32, earlier_lines: Vec<usize> },
+    /// Overlapping data which changes the values earlier records (on the given lines) set, at the [start, end) runs
+    Conflict { runs: Vec<(u32, u32)>, earlier_lines: Vec<usize> },
+    /// The checksum at the end of the record is not the one its bytes add up to
+    Checksum { in_file: u8, expected: u8 },
+    /// The line cannot be read as a record at all, e.g. it is cut short, not hex or of a type that does not exist
+    Unreadable { reason: String, text: String },
+    /// The file does not end with an end of file record, so it may have been cut short
+    MissingEof,
+    /// Records follow the end of file record on the given line and are never loaded
+    AfterEof { eof_line: usize, records: usize },
+    /// An end of file record after the first one, on the given line
+    ExtraEof { eof_line: usize },
+    /// A data record with bytes outside all of the memory described, e.g. by an SVD file
+    OutsideMemory { first: u32, last: u32, bytes: u32 },
+}
+
+/* Joins addresses into [start, end) runs of consecutive ones */
+fn address_runs(addresses: &[u32]) -> Vec<(u32, u32)> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for addr in addresses {
+        match runs.last_mut() {
+            Some(run) if run.1 == *addr => run.1 += 1,
+            _ => runs.push((*addr, addr + 1)),
+        }
+    }
+    runs
+}
+
+fn line_list(lines: &[usize]) -> String {
+    lines.iter().map(|line| line.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/* An issue and the line of the file it was found on, counting from 1 */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub line: usize,
+    pub kind: IssueKind,
+}
+
+impl IssueKind {
+    /* The kind as a short name for machine readable output, e.g. overlap */
+    pub fn name(&self) -> &'static str {
+        match self {
+            IssueKind::Overlap { .. } => "overlap",
+            IssueKind::Conflict { .. } => "conflict",
+            IssueKind::Checksum { .. } => "checksum",
+            IssueKind::Unreadable { .. } => "unreadable",
+            IssueKind::MissingEof => "missing-eof",
+            IssueKind::AfterEof { .. } => "after-eof",
+            IssueKind::ExtraEof { .. } => "extra-eof",
+            IssueKind::OutsideMemory { .. } => "outside-memory",
+        }
+    }
+}
+
+impl Issue {
+    /* The issue for --format json and yaml: the line, the kind's name and the message the text output gives */
+    pub fn to_value(&self) -> Value {
+        Value::object([("line", self.line.into()), ("kind", self.kind.name().into()), ("message", self.kind.to_string().into())])
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IssueKind::Overlap { first, last, bytes, earlier_lines } => {
+                write!(f, "{first:#010x}-{last:#010x} ({bytes} bytes) already set by line {}", line_list(earlier_lines))
+            },
+            IssueKind::Conflict { runs, earlier_lines } => {
+                let bytes: u32 = runs.iter().map(|(start, end)| end - start).sum();
+                let runs: Vec<String> = runs.iter()
+                    .map(|(start, end)| if end - start == 1 {format!("{start:#010x}")} else {format!("{start:#010x}-{:#010x}", end - 1)})
+                    .collect();
+                let plural = if bytes == 1 {"byte differs"} else {"bytes differ"};
+                write!(f, "{bytes} {plural} from line {}: {}", line_list(earlier_lines), runs.join(", "))
+            },
+            IssueKind::Checksum { in_file, expected } => write!(f, "checksum is {in_file:02X}, expected {expected:02X}"),
+            IssueKind::Unreadable { reason, text } => write!(f, "{reason}: {text}"),
+            IssueKind::MissingEof => write!(f, "no end of file record, the file may be cut short"),
+            IssueKind::AfterEof { eof_line, records } => {
+                let plural = if *records == 1 {"record follows"} else {"records follow"};
+                write!(f, "{records} {plural} the end of file record on line {eof_line} and will not load")
+            },
+            IssueKind::ExtraEof { eof_line } => write!(f, "another end of file record, the first is on line {eof_line}"),
+            IssueKind::OutsideMemory { first, last, bytes } => {
+                write!(f, "{first:#010x}-{last:#010x} ({bytes} {}) outside the described memory", if *bytes == 1 {"byte"} else {"bytes"})
+            },
+        }
+    }
+}
+
+/**
+ * Reads records from the lines of a file fed a line at a time, as ihex's reader does with the whole text: empty lines
+ * give no record, bad records are read past and nothing after the end of file record is read.
+ */
+#[derive(Debug, Default)]
+pub struct RecordLines {
+    ended: bool,
+}
+
+impl RecordLines {
+    /* The record on a line, without its line ending, or None for a line which holds none */
+    pub fn record(&mut self, text: &str) -> Option<Result<Record, ReaderError>> {
+        if self.ended || text.is_empty() {
+            return None;
+        }
+        let record = Record::from_record_string(text);
+        self.ended = matches!(record, Ok(Record::EndOfFile));
+        Some(record)
+    }
+}
+
+/* Pairs each record of a file up with its line number and text, reading past bad records up to the end of file record */
+pub fn numbered_records(file_contents: &str) -> impl Iterator<Item = (usize, &str, Result<Record, ReaderError>)> + '_ {
+    let mut records = RecordLines::default();
+    file_contents.lines()
+        .enumerate()
+        .filter_map(move |(idx, text)| records.record(text).map(|record| (idx + 1, text, record)))
+}
+
+/* An error reading a record as an issue */
+fn record_issue(line: usize, text: &str, error: ReaderError) -> Issue {
+    let kind = match error {
+        /* The reader names the checksum it worked out first */
+        ReaderError::ChecksumMismatch(expected, in_file) => IssueKind::Checksum { in_file, expected },
+        error => IssueKind::Unreadable { reason: error.to_string(), text: text.to_string() },
+    };
+    Issue { line, kind }
+}
+
+/**
+ * Looks for a missing end of file record, records after it and repeated ones, fed a line at a time so a file being
+ * streamed can be checked as it goes. Only the record types are looked at, so this is cheap enough for every load.
+ */
+#[derive(Debug, Default)]
+pub struct EofCheck {
+    issues: Vec<Issue>,
+    eof_line: Option<usize>,
+    /// The first line after the end of file record and how many records follow it
+    after: Option<(usize, usize)>,
+    last_line: usize,
+}
+
+impl EofCheck {
+    /* Takes the text of a line, counting from 1 */
+    pub fn line(&mut self, line: usize, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        self.last_line = line;
+        let is_eof = text.starts_with(':') && text.get(7..9) == Some("01");
+        match (self.eof_line, is_eof) {
+            (None, true) => self.eof_line = Some(line),
+            (Some(first), true) => self.issues.push(Issue { line, kind: IssueKind::ExtraEof { eof_line: first } }),
+            (Some(_), false) => self.after.get_or_insert((line, 0)).1 += 1,
+            (None, false) => {},
+        }
+    }
+
+    /* The issues once every line has been seen, in line order */
+    pub fn finish(mut self) -> Vec<Issue> {
+        if let (Some(eof_line), Some((line, records))) = (self.eof_line, self.after) {
+            self.issues.push(Issue { line, kind: IssueKind::AfterEof { eof_line, records } });
+            self.issues.sort_by_key(|issue| issue.line);
+        }
+        if self.eof_line.is_none() && self.last_line != 0 {
+            self.issues.push(Issue { line: self.last_line, kind: IssueKind::MissingEof });
+        }
+        self.issues
+    }
+}
+
+/* The end of file issues of a whole file, see EofCheck */
+pub fn eof_issues(file_contents: &str) -> Vec<Issue> {
+    let mut check = EofCheck::default();
+    for (idx, text) in file_contents.lines().enumerate() {
+        check.line(idx + 1, text);
+    }
+    check.finish()
+}
+
+/**
+ * The first record which cannot be read or has a wrong checksum, for refusing a damaged file outright. Unknown record
+ * types count as unreadable.
+ */
+pub fn first_bad_record(file_contents: &str) -> Option<Issue> {
+    numbered_records(file_contents).find_map(|(line, text, record)| record.err().map(|error| record_issue(line, text, error)))
+}
+
+/**
+ * The first problem strict mode refuses a file for, fed a line at a time: a record first_bad_record would find, or an
+ * end of file issue, whichever comes first in the file.
+ */
+#[derive(Debug, Default)]
+pub struct StrictCheck {
+    records: RecordLines,
+    eof_check: EofCheck,
+    first_bad: Option<Issue>,
+}
+
+impl StrictCheck {
+    /* Takes the text of a line, counting from 1 */
+    pub fn line(&mut self, line: usize, text: &str) {
+        self.eof_check.line(line, text);
+        if let Some(Err(error)) = self.records.record(text) {
+            self.first_bad.get_or_insert_with(|| record_issue(line, text, error));
+        }
+    }
+
+    /* The first problem once every line has been seen, if there is one */
+    pub fn finish(self) -> Option<Issue> {
+        self.first_bad.into_iter().chain(self.eof_check.finish()).min_by_key(|issue| issue.line)
+    }
+}
+
+/**
+ * Finds every data record putting bytes outside the given memory, a sorted list of [start, end) ranges which may
+ * overlap, with the span and number of bytes outside. Fed a line at a time like EofCheck.
+ */
+#[derive(Debug)]
+pub struct OutsideMemory<'a> {
+    memory: &'a [(u64, u64)],
+    records: RecordLines,
+    base: RecordBase,
+    issues: Vec<Issue>,
+}
+
+impl<'a> OutsideMemory<'a> {
+    pub fn new(memory: &'a [(u64, u64)]) -> OutsideMemory<'a> {
+        OutsideMemory { memory, records: RecordLines::default(), base: RecordBase::default(), issues: Vec::new() }
+    }
+
+    /* Takes the text of a line, counting from 1 */
+    pub fn line(&mut self, line: usize, text: &str) {
+        let Some(Ok(record)) = self.records.record(text) else {
+            return;
+        };
+        if self.base.update(&record) {
+            return;
+        }
+        let Record::Data { offset, value } = record else {
+            return;
+        };
+        let (page, start) = self.base.locate(offset);
+        let page_base = page as u64 * IHEX_SEGMENT_BYTES as u64;
+        /* Data running past the end of the page wraps to its start, as fill_bytes does */
+        let end = start as u64 + value.len() as u64;
+        let parts = [(start as u64, end.min(IHEX_SEGMENT_BYTES as u64)), (0, end.saturating_sub(IHEX_SEGMENT_BYTES as u64))];
+        let outside: Vec<(u64, u64)> = parts.iter()
+            .filter(|(part_start, part_end)| part_start < part_end)
+            .flat_map(|(part_start, part_end)| free_ranges(self.memory, page_base + part_start, page_base + part_end))
+            .collect();
+        if let (Some(first), Some(last)) = (outside.first(), outside.last()) {
+            let bytes = outside.iter().map(|(start, end)| end - start).sum::<u64>() as u32;
+            self.issues.push(Issue { line, kind: IssueKind::OutsideMemory { first: first.0 as u32, last: (last.1 - 1) as u32, bytes } });
+        }
+    }
+
+    /* The issues once every line has been seen, in line order */
+    pub fn finish(self) -> Vec<Issue> {
+        self.issues
+    }
+}
+
+/* The records of a whole file outside the given memory, see OutsideMemory */
+pub fn outside_memory(file_contents: &str, memory: &[(u64, u64)]) -> Vec<Issue> {
+    let mut check = OutsideMemory::new(memory);
+    for (idx, text) in file_contents.lines().enumerate() {
+        check.line(idx + 1, text);
+    }
+    check.finish()
+}
+
+/**
+ * Reads a hex file the way the loader does, looking for problems along the way: lines which cannot be read as records
+ * and records with a wrong checksum, which are read past to find every one, a missing or misplaced end of file record, and data records writing over bytes which earlier records set, which usually
+ * means a broken image generator. With store_values, overlapping data which
+ * repeats the same bytes is told apart from data which changes them, listing the changed addresses.
+ */
+pub fn check_hex(file_contents: &str, options: LoadOptions) -> Vec<Issue> {
+    let mut check = HexCheck::new(options);
+    for (idx, text) in file_contents.lines().enumerate() {
+        check.line(idx + 1, text);
+    }
+    check.finish()
+}
+
+/* The checks of check_hex, fed a line at a time like EofCheck so a file can be checked as it is streamed */
+#[derive(Debug)]
+pub struct HexCheck {
+    options: LoadOptions,
+    records: RecordLines,
+    eof_check: EofCheck,
+    base: RecordBase,
+    /// The line which last set each byte, 0 for none yet, kept per 64kb page
+    setters: HashMap<u16, Vec<u32>>,
+    values: HashMap<u16, Vec<u8>>,
+    issues: Vec<Issue>,
+}
+
+impl HexCheck {
+    pub fn new(options: LoadOptions) -> HexCheck {
+        HexCheck {
+            options,
+            records: RecordLines::default(),
+            eof_check: EofCheck::default(),
+            base: RecordBase::default(),
+            setters: HashMap::new(),
+            values: HashMap::new(),
+            issues: Vec::new(),
+        }
+    }
+
+    /* Takes the text of a line, counting from 1 */
+    pub fn line(&mut self, line: usize, text: &str) {
+        self.eof_check.line(line, text);
+        let record = match self.records.record(text) {
+            Some(Ok(record)) => record,
+            Some(Err(error)) => {
+                self.issues.push(record_issue(line, text, error));
+                return;
+            },
+            None => return,
+        };
+        if self.base.update(&record) {
+            return;
+        }
+        let Record::Data { offset, value } = record else {
+            return;
+        };
+        let (page, start) = self.base.locate(offset);
+        let page_setters = self.setters.entry(page).or_insert_with(|| vec![0; IHEX_SEGMENT_BYTES as usize]);
+        let mut page_values = self.options.store_values.then(|| self.values.entry(page).or_insert_with(|| vec![0; IHEX_SEGMENT_BYTES as usize]));
+        let page_base = page as u32 * IHEX_SEGMENT_BYTES;
+        let mut overlapped = Vec::new();
+        let mut earlier_lines = Vec::new();
+        let mut changed = Vec::new();
+        let mut changed_lines = Vec::new();
+        for (i, byte) in value.iter().enumerate() {
+            /* Data running past the end of the page wraps to its start, as fill_bytes does */
+            let offset = start.wrapping_add(i as u16) as usize;
+            let earlier_line = page_setters[offset] as usize;
+            if let Some(page_values) = page_values.as_mut() {
+                if earlier_line != 0 && page_values[offset] != *byte {
+                    changed.push(page_base + offset as u32);
+                    changed_lines.push(earlier_line);
+                }
+                page_values[offset] = *byte;
+            }
+            if earlier_line != 0 {
+                overlapped.push(offset as u32);
+                earlier_lines.push(earlier_line);
+            }
+            page_setters[offset] = line as u32;
+        }
+        changed_lines.sort();
+        changed_lines.dedup();
+        earlier_lines.sort();
+        earlier_lines.dedup();
+        if !changed.is_empty() {
+            self.issues.push(Issue { line, kind: IssueKind::Conflict { runs: address_runs(&changed), earlier_lines: changed_lines } });
+        } else if let (Some(first), Some(last)) = (overlapped.first(), overlapped.last()) {
+            self.issues.push(Issue {
+                line,
+                kind: IssueKind::Overlap { first: page_base + first, last: page_base + last, bytes: overlapped.len() as u32, earlier_lines },
+            });
+        }
+    }
+
+    /* The issues once every line has been seen, in line order */
+    pub fn finish(mut self) -> Vec<Issue> {
+        self.issues.extend(self.eof_check.finish());
+        self.issues.sort_by_key(|issue| issue.line);
+        self.issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hex_check::{check_hex, eof_issues, first_bad_record, outside_memory, Issue, IssueKind, StrictCheck};
+    use crate::hex_loader::LoadOptions;
+
+    #[test]
+    fn test_check_overlaps() -> Result<(),String> {
+        /* Line 3 writes over the end of line 1, line 4 over both */
+        let contents = ":0400000001020304F2\n\n:0400020003050607E5\n:0400010001020304F1\n:00000001FF\n";
+        let issues = check_hex(contents, LoadOptions::default());
+        assert_eq!(vec![
+            Issue { line: 3, kind: IssueKind::Overlap { first: 0x2, last: 0x3, bytes: 2, earlier_lines: vec![1] } },
+            Issue { line: 4, kind: IssueKind::Overlap { first: 0x1, last: 0x4, bytes: 4, earlier_lines: vec![1, 3] } },
+        ], issues);
+        assert_eq!("line 4: 0x00000001-0x00000004 (4 bytes) already set by line 1, 3", issues[1].to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_conflicts() -> Result<(),String> {
+        /* Line 2 repeats 0x2 but changes 0x3, line 3 repeats line 2 exactly, line 4 changes 0x1 and 0x4 only */
+        let contents = ":0400000001020304F2\n:0400020003050607E5\n:0400020003050607E5\n:0400010009030507E3\n:00000001FF\n";
+        let issues = check_hex(contents, LoadOptions { store_values: true, blank_byte: None });
+        assert_eq!(vec![
+            Issue { line: 2, kind: IssueKind::Conflict { runs: vec![(0x3, 0x4)], earlier_lines: vec![1] } },
+            Issue { line: 3, kind: IssueKind::Overlap { first: 0x2, last: 0x5, bytes: 4, earlier_lines: vec![2] } },
+            Issue { line: 4, kind: IssueKind::Conflict { runs: vec![(0x1, 0x2), (0x4, 0x5)], earlier_lines: vec![1, 3] } },
+        ], issues);
+        assert_eq!("line 4: 2 bytes differ from line 1, 3: 0x00000001, 0x00000004", issues[2].to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_checksums() -> Result<(),String> {
+        /* Every bad checksum is found, not just the first */
+        let contents = ":0400000001020304F3\n:0400020003050607E5\n:00000001FE\n";
+        let issues = check_hex(contents, LoadOptions::default());
+        assert_eq!(vec![
+            Issue { line: 1, kind: IssueKind::Checksum { in_file: 0xF3, expected: 0xF2 } },
+            Issue { line: 3, kind: IssueKind::Checksum { in_file: 0xFE, expected: 0xFF } },
+        ], issues);
+        assert_eq!("line 1: checksum is F3, expected F2", issues[0].to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_unreadable() -> Result<(),String> {
+        /* A line which is not a record at all is listed with its text, and the records around it still checked */
+        let contents = ":0400000001020304F2\nnot a record\n:0400020003050607E4\n:00000001FF\n";
+        let issues = check_hex(contents, LoadOptions::default());
+        assert_eq!(vec![2, 3], issues.iter().map(|issue| issue.line).collect::<Vec<_>>());
+        assert!(matches!(&issues[0].kind, IssueKind::Unreadable { text, .. } if text == "not a record"));
+        assert!(issues[0].to_string().ends_with(": not a record"));
+        assert!(matches!(issues[1].kind, IssueKind::Checksum { .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_bad_record() -> Result<(),String> {
+        assert_eq!(None, first_bad_record(":0400000001020304F2\n:00000001FF\n"));
+        let bad_type = first_bad_record(":0400000001020304F2\n\n:00000009F7\n:0400000001020304F3\n");
+        assert_eq!(Some(3), bad_type.as_ref().map(|issue| issue.line));
+        assert!(bad_type.is_some_and(|issue| matches!(issue.kind, IssueKind::Unreadable { .. })));
+        /* Strict mode stops at whichever comes first, here a second end of file record before a record it never reads */
+        let mut check = StrictCheck::default();
+        for (idx, text) in ":0400000001020304F2\n:00000001FF\n:00000001FF\nnot a record\n".lines().enumerate() {
+            check.line(idx + 1, text);
+        }
+        assert_eq!(Some(Issue { line: 3, kind: IssueKind::ExtraEof { eof_line: 2 } }), check.finish());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eof_issues() -> Result<(),String> {
+        assert!(eof_issues(":0400000001020304F2\n:00000001FF\n\n").is_empty());
+        assert_eq!(vec![Issue { line: 2, kind: IssueKind::MissingEof }], eof_issues(":0400000001020304F2\n:0400100001020304E2\n\n"));
+        /* Everything after the first end of file record counts, extra end of file records are pointed out on their own */
+        let issues = eof_issues(":00000001FF\n:0400000001020304F2\n:00000001FF\n:0400100001020304E2\n");
+        assert_eq!(vec![
+            Issue { line: 2, kind: IssueKind::AfterEof { eof_line: 1, records: 2 } },
+            Issue { line: 3, kind: IssueKind::ExtraEof { eof_line: 1 } },
+        ], issues);
+        assert_eq!("line 2: 2 records follow the end of file record on line 1 and will not load", issues[0].to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_outside_memory() -> Result<(),String> {
+        /* Line 1 fits, line 2 runs 2 bytes past the first block, line 3 is entirely between the blocks */
+        let contents = ":0400000001020304F2\n:0400020003050607E5\n:0400080001020304EA\n:00000001FF\n";
+        let memory = [(0x0, 0x4), (0x2, 0x4), (0x10, 0x20)];
+        assert_eq!(vec![
+            Issue { line: 2, kind: IssueKind::OutsideMemory { first: 0x4, last: 0x5, bytes: 2 } },
+            Issue { line: 3, kind: IssueKind::OutsideMemory { first: 0x8, last: 0xB, bytes: 4 } },
+        ], outside_memory(contents, &memory));
+        Ok(())
+    }
+}
@@ -0,0 +1,522 @@
+use ihex::{Record, ReaderError};
+use std::convert::Infallible;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::elf;
+use crate::error::{read_file, Error};
+use crate::hex_check::{EofCheck, Issue, RecordLines};
+use crate::progress::Progress;
+use crate::{fill_bytes, SegmentMap, ValueMap, IHEX_SEGMENT_BYTES, SEGMENT_BYTES};
+
+/* Choices about how much of the hex file to keep while loading */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadOptions {
+    /// Keep the data byte values as well as which addresses are set
+    pub store_values: bool,
+    /// Data equal to this value is erased flash written out explicitly, and counts as empty
+    pub blank_byte: Option<u8>,
+}
+
+/* A line of the hex file which could not be read as a record and was left out of the map */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Line number in the file, counting from 1
+    pub line: usize,
+    pub text: String,
+    pub reason: ReaderError,
+}
+
+impl ParseError {
+    /* Why the line could not be read. The reader's own text for checksums names the one it worked out as the invalid one */
+    pub fn reason_text(&self) -> String {
+        match self.reason {
+            ReaderError::ChecksumMismatch(expected, in_file) => format!("checksum is {in_file:02X}, expected {expected:02X}"),
+            ref reason => reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}: {}", self.line, self.reason_text(), self.text)
+    }
+}
+
+/* Everything kept from one hex file */
+pub struct HexImage {
+    pub segment_map: SegmentMap,
+    /// Present when loaded with store_values
+    pub values: Option<ValueMap>,
+    /// Lines which could not be read, in file order
+    pub errors: Vec<ParseError>,
+    /// Problems with the end of file record, which loading gets past
+    pub issues: Vec<Issue>,
+    /// Where execution starts, from the last start linear or start segment address record
+    pub entry: Option<u32>,
+}
+
+/**
+ * The extended address in effect while reading records. Only one of the two kinds is ever set at a time.
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RecordBase {
+    ela_addr: u16,
+    esx_addr: u16,
+}
+
+impl RecordBase {
+    /* Takes in an extended address record, returning false for every other kind of record */
+    pub fn update(&mut self, record: &Record) -> bool {
+        match record {
+            Record::ExtendedSegmentAddress(addr) => { self.esx_addr = *addr; self.ela_addr = 0; },
+            Record::ExtendedLinearAddress(addr)  => { self.esx_addr = 0; self.ela_addr = *addr; },
+            _ => return false,
+        }
+        true
+    }
+
+    /* The page a data record at this offset lands in, and where in the page it starts. ESX can offset in or between pages. */
+    pub fn locate(&self, offset: u16) -> (u16, u16) {
+        if self.esx_addr != 0 {
+            ((self.esx_addr & 0xF000)>>12, offset.wrapping_add(self.esx_addr.wrapping_mul(16)))
+        } else {
+            (self.ela_addr, offset)
+        }
+    }
+}
+
+/* A data record of a hex file and where its bytes landed */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceRecord {
+    /// Line number in the file, counting from 1
+    pub line: usize,
+    pub page: u16,
+    /// Offset of the first byte in the page. The data wraps to the start of the page if it runs past the end
+    pub start: u16,
+    pub len: u16,
+    /// The line and record of the extended address in effect, if any
+    pub base: Option<(usize, Record)>,
+}
+
+impl SourceRecord {
+    /* The address of the first byte */
+    pub fn addr(&self) -> u32 {
+        self.page as u32 * IHEX_SEGMENT_BYTES + self.start as u32
+    }
+}
+
+/**
+ * Every data record that put bytes into [start, end), in file order. The file is read the same way load_hex reads it
+ * so the records match what the map shows, including those overwritten by later records.
+ */
+pub fn records_in(file_contents: &str, start: u32, end: u64) -> Vec<SourceRecord> {
+    let mut search = RecordSearch::new(start, end);
+    for (idx, text) in file_contents.lines().enumerate() {
+        search.line(idx + 1, text);
+    }
+    search.finish()
+}
+
+/* The search of records_in, fed a line at a time so a file can be searched as it is streamed */
+#[derive(Debug)]
+pub struct RecordSearch {
+    start: u32,
+    end: u64,
+    records: RecordLines,
+    base: RecordBase,
+    base_record: Option<(usize, Record)>,
+    found: Vec<SourceRecord>,
+}
+
+impl RecordSearch {
+    pub fn new(start: u32, end: u64) -> RecordSearch {
+        RecordSearch { start, end, records: RecordLines::default(), base: RecordBase::default(), base_record: None, found: Vec::new() }
+    }
+
+    /* Takes the text of a line, counting from 1. Bad lines are read past as load_hex does, so the records after them are still found */
+    pub fn line(&mut self, line: usize, text: &str) {
+        let Some(Ok(record)) = self.records.record(text) else {
+            return;
+        };
+        if self.base.update(&record) {
+            self.base_record = Some((line, record));
+            return;
+        }
+        if let Record::Data { offset, value } = record {
+            let (page, page_start) = self.base.locate(offset);
+            let page_base = page as u32 * IHEX_SEGMENT_BYTES;
+            let touches = (0..value.len() as u16)
+                .map(|i| page_base + page_start.wrapping_add(i) as u32)
+                .any(|addr| self.start <= addr && (addr as u64) < self.end);
+            if touches {
+                self.found.push(SourceRecord { line, page, start: page_start, len: value.len() as u16, base: self.base_record.clone() });
+            }
+        }
+    }
+
+    /* The records found once every line has been seen, in file order */
+    pub fn finish(self) -> Vec<SourceRecord> {
+        self.found
+    }
+}
+
+/* Copies record data into a value page, wrapping at the end of the page the same way fill_bytes does */
+fn store_values(page_values: &mut [u8], start: u16, data: &[u8]) {
+    for (i, byte) in data.iter().enumerate() {
+        page_values[start.wrapping_add(i as u16) as usize] = *byte;
+    }
+}
+
+/* The [offset, length) runs of data which are not the blank byte */
+pub fn non_blank_runs(data: &[u8], blank: u8) -> Vec<(u16, u16)> {
+    let mut runs: Vec<(u16, u16)> = Vec::new();
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == blank {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((run_start, run_len)) if *run_start + *run_len == i as u16 => *run_len += 1,
+            _ => runs.push((i as u16, 1)),
+        }
+    }
+    runs
+}
+
+/* Builds the occupancy map (and optionally the values) of every data record in the hex file */
+pub fn load_hex(file_contents: &str, options: LoadOptions) -> HexImage {
+    load_hex_reporting(file_contents, options, |_, _, _| {})
+}
+
+/* How many lines are parsed between progress reports */
+const PROGRESS_LINES: usize = 0x10000;
+
+/**
+ * Loads the same as load_hex, calling progress every so often with how many bytes of the file and how many lines
+ * have been processed, and the image loaded so far.
+ */
+pub fn load_hex_reporting(file_contents: &str, options: LoadOptions, progress: impl FnMut(usize, usize, &HexImage)) -> HexImage {
+    let mut rest = file_contents;
+    let next_line = |text: &mut String| {
+        let len = rest.find('\n').map_or(rest.len(), |idx| idx + 1);
+        text.push_str(&rest[..len]);
+        rest = &rest[len..];
+        Ok::<usize, Infallible>(len)
+    };
+    let Ok(image) = load_lines(next_line, options, progress);
+    image
+}
+
+/* The text of a line as read with its line ending, without it */
+fn line_without_ending(line: &str) -> &str {
+    line.strip_suffix('\n').map_or(line, |line| line.strip_suffix('\r').unwrap_or(line))
+}
+
+/**
+ * Loads the same as load_hex_reporting, reading the file a line at a time so only the map is kept and never the text,
+ * which lets hex files of gigabytes load in the memory of their map. Fails only if reading fails, e.g. the file is not
+ * text.
+ */
+pub fn load_hex_stream(mut reader: impl BufRead, options: LoadOptions, progress: impl FnMut(usize, usize, &HexImage)) -> io::Result<HexImage> {
+    load_lines(|text: &mut String| reader.read_line(text), options, progress)
+}
+
+/* Loads from whatever next_line adds to the end of the string it is given, a line with its ending at a time, until it adds nothing */
+fn load_lines<E>(mut next_line: impl FnMut(&mut String) -> Result<usize, E>, options: LoadOptions, mut progress: impl FnMut(usize, usize, &HexImage)) -> Result<HexImage, E> {
+    /* A counter must be kept between rows to indicate address offsets */
+    let mut base = RecordBase::default();
+
+    /* Store a map of every byte in the hex file, 0 if unset and 1 if set
+       8kb (mapping 64kb) segments are added on-demand to minimize memory usage */
+    let mut image = HexImage {
+        segment_map: SegmentMap::new(),
+        values: if options.store_values {Some(ValueMap::new())} else {None},
+        errors: Vec::new(),
+        issues: Vec::new(),
+        entry: None,
+    };
+
+    /* Empty lines are skipped and bad records read past so the rest of the file still shows. Records after the end of
+       file record are not loaded, but every line goes through the end of file check */
+    let mut eof_check = EofCheck::default();
+    let mut text = String::new();
+    let (mut done, mut line_idx, mut line_count) = (0, 0, 0);
+    let mut ended = false;
+    loop {
+        text.clear();
+        let read = next_line(&mut text)?;
+        if read == 0 {
+            break;
+        }
+        let line_text = line_without_ending(&text);
+        line_idx += 1;
+        eof_check.line(line_idx, line_text);
+        let line_start = done;
+        done += read;
+        if ended || line_text.is_empty() {
+            continue;
+        }
+        if line_count % PROGRESS_LINES == 0 {
+            progress(line_start, line_count, &image);
+        }
+        line_count += 1;
+        /* Other types are not useful for this analysis. Invalid lines are kept aside to report */
+        match Record::from_record_string(line_text) {
+            Ok(Record::Data { offset, value }) => {
+                /* Determine wich part of the segment map we need to access */
+                let (page, start) = base.locate(offset);
+
+                /* Find the segment or create it if it doesn't exist. */
+                let segment = image.segment_map.entry(page).or_default();
+                segment.resize(SEGMENT_BYTES as usize, 0);
+
+                /* Fill the proper bits in this segment, leaving out runs of the blank byte */
+                match options.blank_byte {
+                    Some(blank) => {
+                        for (run_start, run_len) in non_blank_runs(&value, blank) {
+                            fill_bytes(segment, start.wrapping_add(run_start), run_len);
+                        }
+                    },
+                    None => fill_bytes(segment, start, value.len() as u16),
+                }
+
+                if let Some(values) = image.values.as_mut() {
+                    let page_values = values.entry(page).or_insert_with(|| vec![0; IHEX_SEGMENT_BYTES as usize]);
+                    store_values(page_values, start, &value);
+                }
+            },
+            Ok(Record::StartLinearAddress(addr)) => image.entry = Some(addr),
+            /* The 8086 style CS:IP pair of real mode, 16 bytes per segment */
+            Ok(Record::StartSegmentAddress { cs, ip }) => image.entry = Some(cs as u32 * 16 + ip as u32),
+            Ok(Record::EndOfFile) => {
+                log::debug!("Line {line_idx}: end of file, any records after it are not loaded");
+                ended = true;
+            },
+            Ok(record) => {
+                if base.update(&record) {
+                    let (page, start) = base.locate(0);
+                    log::debug!("Line {line_idx}: data records now start from {:#010x}", page as u32 * IHEX_SEGMENT_BYTES + start as u32);
+                }
+            },
+            Err(reason) => {
+                let error = ParseError { line: line_idx, text: line_text.to_string(), reason };
+                log::debug!("Left out {error}");
+                image.errors.push(error);
+            },
+        }
+    }
+    image.issues = eof_check.finish();
+    Ok(image)
+}
+
+/* Reads a hex file from disk. An ELF file is converted to Intel HEX holding what it puts in flash */
+pub fn read_hex_file(file_path: &str) -> Result<String, Error> {
+    let data = read_file(file_path)?;
+    if elf::is_elf(&data) {
+        return elf::elf_to_hex(&data).map_err(|reason| Error::parse(file_path, reason));
+    }
+    String::from_utf8(data).map_err(|_| Error::parse(file_path, "not a hex file, it is not text"))
+}
+
+/* A hex file opened for load_hex_stream, and its size in bytes */
+pub struct HexReader {
+    pub reader: Box<dyn BufRead + Send>,
+    pub size: usize,
+}
+
+/**
+ * Opens a hex file to stream from. An ELF file is converted to Intel HEX in memory the same as read_hex_file does, as
+ * only the parts it puts in flash are kept and those are rarely big.
+ */
+pub fn open_hex_file(file_path: &str) -> Result<HexReader, Error> {
+    let file = File::open(file_path).map_err(|source| Error::io(file_path, source))?;
+    let size = file.metadata().map_err(|source| Error::io(file_path, source))?.len() as usize;
+    let mut reader = BufReader::new(file);
+    if elf::is_elf(reader.fill_buf().map_err(|source| Error::io(file_path, source))?) {
+        let file_contents = read_hex_file(file_path)?;
+        return Ok(HexReader { size: file_contents.len(), reader: Box::new(Cursor::new(file_contents.into_bytes())) });
+    }
+    Ok(HexReader { reader: Box::new(reader), size })
+}
+
+/**
+ * Streams the lines of a hex file from disk to each_line, numbered from 1 and without their line endings, for the
+ * checks which look at the records themselves. An ELF file is converted as open_hex_file does.
+ */
+pub fn for_each_line(file_path: &str, mut each_line: impl FnMut(usize, &str)) -> Result<(), Error> {
+    let mut hex = open_hex_file(file_path)?;
+    let mut text = String::new();
+    let mut line = 0;
+    loop {
+        text.clear();
+        if hex.reader.read_line(&mut text).map_err(|source| Error::io(file_path, source))? == 0 {
+            return Ok(());
+        }
+        line += 1;
+        each_line(line, line_without_ending(&text));
+    }
+}
+
+/* Streams a hex file from disk and loads it */
+pub fn load_file(file_path: &str, options: LoadOptions) -> Result<HexImage, Error> {
+    let hex = open_hex_file(file_path)?;
+    load_hex_stream(hex.reader, options, |_, _, _| {}).map_err(|source| Error::io(file_path, source))
+}
+
+/* Loads a hex file the same as load_file, with a progress bar on the terminal for files that take a while */
+pub fn load_file_with_progress(file_path: &str, options: LoadOptions) -> Result<HexImage, Error> {
+    let file_name = Path::new(file_path).file_name().map_or(file_path.into(), |name| name.to_string_lossy());
+    let hex = open_hex_file(file_path)?;
+    let mut progress = Progress::new(&file_name, hex.size);
+    let image = load_hex_stream(hex.reader, options, |done, lines, _| progress.update(done, lines))
+        .map_err(|source| Error::io(file_path, source))?;
+    progress.finish();
+    Ok(image)
+}
+
+/* How often a background load sends what it has so far */
+const BACKGROUND_INTERVAL: Duration = Duration::from_millis(200);
+
+/* One page as loaded so far: its occupancy bits and, if stored, its values */
+pub struct LoadedPage {
+    pub page: u16,
+    pub segment: Vec<u8>,
+    pub values: Option<Vec<u8>>,
+}
+
+/* What a background load reports, in order. Loaded is always last */
+pub enum LoadEvent {
+    /// Bytes of the file processed so far and the file size
+    Progress(usize, usize),
+    /// Pages which changed since they were last sent
+    Pages(Vec<LoadedPage>),
+    Loaded(HexImage),
+    /// Reading the file failed part way, and why. Nothing follows
+    Failed(String),
+}
+
+/**
+ * Parses a hex file on a worker thread. Every so often the pages that changed since the last report are sent, so the
+ * map can fill in while the rest of the file loads, and the finished image comes last.
+ */
+pub fn load_in_background(hex: HexReader, options: LoadOptions) -> Receiver<LoadEvent> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let total = hex.size;
+        let mut sent = SegmentMap::new();
+        let mut next_report = Instant::now();
+        let loaded = load_hex_stream(hex.reader, options, |done, _, image| {
+            if Instant::now() < next_report {
+                return;
+            }
+            next_report = Instant::now() + BACKGROUND_INTERVAL;
+            /* Comparing the occupancy bits against the last copy sent is much cheaper than sending every page */
+            let pages: Vec<LoadedPage> = image.segment_map.iter()
+                .filter(|(page, segment)| sent.get(page) != Some(segment))
+                .map(|(page, segment)| LoadedPage {
+                    page: *page,
+                    segment: segment.clone(),
+                    values: image.values.as_ref().and_then(|values| values.get(page).cloned()),
+                })
+                .collect();
+            for loaded in &pages {
+                sent.insert(loaded.page, loaded.segment.clone());
+            }
+            /* The viewer going away just means nobody is waiting for the rest */
+            let _ = sender.send(LoadEvent::Progress(done, total));
+            if !pages.is_empty() {
+                let _ = sender.send(LoadEvent::Pages(pages));
+            }
+        });
+        let _ = sender.send(match loaded {
+            Ok(image) => LoadEvent::Loaded(image),
+            Err(err) => LoadEvent::Failed(err.to_string()),
+        });
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hex_check::IssueKind;
+    use crate::hex_loader::{for_each_line, load_hex, load_hex_stream, records_in, LoadOptions, ParseError, RecordSearch};
+    use crate::occupied_ranges;
+    use ihex::{ReaderError, Record};
+
+    #[test]
+    fn test_records_in() -> Result<(),String> {
+        /* Two records overlapping at 0x08000008, with an empty line before the second */
+        let file_contents = ":020000040800F2\n:10000000000102030405060708090A0B0C0D0E0F78\n\n:040008000102030CE2\n:00000001FF\n";
+        let found = records_in(file_contents, 0x0800_0008, 0x0800_000C);
+        assert_eq!(vec![2, 4], found.iter().map(|record| record.line).collect::<Vec<_>>());
+        assert_eq!(0x0800_0000, found[0].addr());
+        assert_eq!(0x0800_0008, found[1].addr());
+        assert_eq!(4, found[1].len);
+        assert_eq!(Some((1, Record::ExtendedLinearAddress(0x0800))), found[1].base);
+        assert!(records_in(file_contents, 0x0800_0010, 0x0800_0020).is_empty());
+        /* A bad checksum on line 2 does not hide the records after it */
+        let damaged = ":0400000001020304F2\n:0400100001020304F3\n:0400200001020304D2\n:00000001FF\n";
+        assert_eq!(vec![1, 3], records_in(damaged, 0, 1 << 32).iter().map(|record| record.line).collect::<Vec<_>>());
+        /* The last byte of the address space is found like any other */
+        let top = ":02000004FFFFFC\n:04FFFC0001020304F7\n:00000001FF\n";
+        assert_eq!(1, records_in(top, 0xFFFF_FFFF, 1 << 32).len());
+        /* Streamed from disk with CRLF endings, the same records are found */
+        let path = std::env::temp_dir().join(format!("ihex-visualize-records-{}.hex", std::process::id()));
+        std::fs::write(&path, file_contents.replace('\n', "\r\n")).map_err(|e| e.to_string())?;
+        let mut search = RecordSearch::new(0x0800_0008, 0x0800_000C);
+        let streamed = for_each_line(&path.to_string_lossy(), |line, text| search.line(line, text));
+        std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+        streamed.map_err(|e| e.to_string())?;
+        assert_eq!(found, search.finish());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_errors() -> Result<(),String> {
+        /* The bad checksum on line 2 is reported and loading carries on past it */
+        let image = load_hex(":0400000001020304F2\n:0400100001020304F3\n\n:0400200001020304D2\n:00000001FF\n", LoadOptions::default());
+        assert_eq!(vec![
+            ParseError { line: 2, text: ":0400100001020304F3".to_string(), reason: ReaderError::ChecksumMismatch(0xE2, 0xF3) },
+        ], image.errors);
+        assert_eq!("line 2: checksum is F3, expected E2: :0400100001020304F3", image.errors[0].to_string());
+        assert_eq!(vec![(0x0, 0x4), (0x20, 0x24)], occupied_ranges(&image.segment_map));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_hex_stream() -> Result<(),String> {
+        /* Windows line endings, and a record after the end of file record which is not loaded */
+        let contents = ":0400000001020304F2\r\n:00000001FF\r\n:0400200001020304D2\r\n";
+        let image = load_hex_stream(contents.as_bytes(), LoadOptions::default(), |_, _, _| {}).map_err(|err| err.to_string())?;
+        assert!(image.errors.is_empty());
+        assert_eq!(vec![(0x0, 0x4)], occupied_ranges(&image.segment_map));
+        assert_eq!(vec![IssueKind::AfterEof { eof_line: 2, records: 1 }], image.issues.into_iter().map(|issue| issue.kind).collect::<Vec<_>>());
+        /* Bytes which are not text stop the load */
+        assert!(load_hex_stream(&b":04000000\xFF\n"[..], LoadOptions::default(), |_, _, _| {}).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry() -> Result<(),String> {
+        assert_eq!(Some(0x0800_01C1), load_hex(":04000005080001C12D\n:00000001FF\n", LoadOptions::default()).entry);
+        assert_eq!(Some(0x12340 + 0x100), load_hex(":0400000312340100B2\n:00000001FF\n", LoadOptions::default()).entry);
+        assert_eq!(None, load_hex(":00000001FF\n", LoadOptions::default()).entry);
+        Ok(())
+    }
+
+    #[test]
+    fn test_blank_byte() -> Result<(),String> {
+        /* The erased 0xFF bytes of the first record and the whole second record count as empty */
+        let contents = ":08000000FFFF0102FFFF03FFF7
+:04001000FFFFFFFFF0
+:00000001FF
+";
+        let image = load_hex(contents, LoadOptions { store_values: false, blank_byte: Some(0xFF) });
+        assert_eq!(vec![(0x2, 0x4), (0x6, 0x7)], occupied_ranges(&image.segment_map));
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::stats::OccupancyStats;
+use crate::structured::json_string;
+
+/* One run's occupancy of one file, as kept in a --history file */
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub file: String,
+    pub bytes_used: u64,
+    pub span_start: u32,
+    pub span_end: u64,
+    pub ranges: usize,
+    pub pages: usize,
+}
+
+impl HistoryEntry {
+    /* The entry for a file loaded now */
+    pub fn now(file: &str, stats: &OccupancyStats) -> HistoryEntry {
+        HistoryEntry {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+            file: file.to_string(),
+            bytes_used: stats.bytes_used,
+            span_start: stats.span_start,
+            span_end: stats.span_end,
+            ranges: stats.regions,
+            pages: stats.pages,
+        }
+    }
+
+    /* The entry as one JSON object on one line */
+    fn to_json(&self) -> String {
+        format!("{{\"time\": {}, \"file\": {}, \"bytes_used\": {}, \"span_start\": {}, \"span_end\": {}, \"ranges\": {}, \"pages\": {}}}",
+                self.time, json_string(&self.file), self.bytes_used, self.span_start, self.span_end, self.ranges, self.pages)
+    }
+
+    /* An entry from the fields of a JSON object, if it has them all */
+    fn from_fields(fields: &[(String, String)]) -> Option<HistoryEntry> {
+        let field = |name: &str| fields.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+        Some(HistoryEntry {
+            time: field("time")?.parse().ok()?,
+            file: field("file")?.to_string(),
+            bytes_used: field("bytes_used")?.parse().ok()?,
+            span_start: field("span_start")?.parse().ok()?,
+            span_end: field("span_end")?.parse().ok()?,
+            ranges: field("ranges")?.parse().ok()?,
+            pages: field("pages")?.parse().ok()?,
+        })
+    }
+}
+
+/**
+ * Reads the history as written by write_history: a JSON array of flat objects holding strings and numbers. This is
+ * all the history needs, so nested values are not understood. Strings come back unescaped, numbers as their text.
+ */
+pub fn read_history(contents: &str) -> Result<Vec<HistoryEntry>, String> {
+    let mut entries = Vec::new();
+    let mut chars = contents.trim().strip_prefix('[').and_then(|rest| rest.strip_suffix(']'))
+        .ok_or("A history file holds a JSON array")?
+        .chars()
+        .peekable();
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut key: Option<String> = None;
+    while let Some(chr) = chars.next() {
+        match chr {
+            '"' => {
+                let mut text = String::new();
+                while let Some(chr) = chars.next() {
+                    match chr {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('u') => {
+                                let code: String = chars.by_ref().take(4).collect();
+                                text.extend(u32::from_str_radix(&code, 16).ok().and_then(char::from_u32));
+                            },
+                            Some(chr) => text.push(chr),
+                            None => {},
+                        },
+                        chr => text.push(chr),
+                    }
+                }
+                match key.take() {
+                    Some(name) => fields.push((name, text)),
+                    None => key = Some(text),
+                }
+            },
+            '0'..='9' | '-' => {
+                let mut number = chr.to_string();
+                while let Some(digit) = chars.next_if(|chr| chr.is_ascii_digit() || *chr == '.') {
+                    number.push(digit);
+                }
+                fields.push((key.take().ok_or(format!("The number {number} has no name"))?, number));
+            },
+            '}' => {
+                entries.push(HistoryEntry::from_fields(&fields).ok_or("A history entry is missing fields")?);
+                fields.clear();
+            },
+            _ => {},
+        }
+    }
+    Ok(entries)
+}
+
+/* The history as a JSON array with one entry per line, so it diffs well under version control */
+pub fn write_history(entries: &[HistoryEntry]) -> String {
+    let lines: Vec<String> = entries.iter().map(|entry| format!("  {}", entry.to_json())).collect();
+    format!("[\n{}\n]\n", lines.join(",\n"))
+}
+
+/* The date and time of seconds since the Unix epoch in UTC, as 2024-01-31 09:05 */
+fn date_text(time: u64) -> String {
+    /* Days to a civil date, after Howard Hinnant's days_from_civil inverse */
+    let days = (time / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {month_index + 3} else {month_index - 9};
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}", time % 86400 / 3600, time % 3600 / 60)
+}
+
+/* How wide the bar of the biggest build is */
+const TREND_BAR: usize = 30;
+
+/**
+ * The builds of one file in the history, oldest first, with their usage, the change from the build before and a bar
+ * scaled to the biggest one, so a creep stands out.
+ */
+pub fn trend_lines(file: &str, entries: &[HistoryEntry]) -> Vec<String> {
+    let builds: Vec<&HistoryEntry> = entries.iter().filter(|entry| entry.file == file).collect();
+    let mut lines = vec![format!("Usage history of {file}: {} builds", builds.len())];
+    let max = builds.iter().map(|entry| entry.bytes_used).max().unwrap_or(0).max(1);
+    let mut previous: Option<u64> = None;
+    for entry in builds {
+        let change = previous.map_or("".to_string(), |previous| format!("{:+}", entry.bytes_used as i64 - previous as i64));
+        let bar = "█".repeat((entry.bytes_used as usize * TREND_BAR).div_ceil(max as usize));
+        lines.push(format!("  {}  {:>10} bytes  {change:>9}  {bar}", date_text(entry.time), entry.bytes_used));
+        previous = Some(entry.bytes_used);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::{date_text, read_history, write_history, HistoryEntry};
+
+    #[test]
+    fn test_history() -> Result<(),String> {
+        let entries = vec![
+            HistoryEntry { time: 1_700_000_000, file: "build/app.hex".to_string(), bytes_used: 8512, span_start: 0x0800_0000, span_end: 0x0801_f040, ranges: 3, pages: 2 },
+            HistoryEntry { time: 1_700_086_400, file: "C:\\fw \"new\".hex".to_string(), bytes_used: 9000, span_start: 0, span_end: 9000, ranges: 1, pages: 1 },
+        ];
+        let written = write_history(&entries);
+        assert!(written.starts_with("[\n  {\"time\": 1700000000, \"file\": \"build/app.hex\", \"bytes_used\": 8512,"));
+        assert_eq!(entries, read_history(&written)?);
+        assert_eq!(Vec::<HistoryEntry>::new(), read_history("[]")?);
+        assert!(read_history("{}").is_err());
+        assert_eq!("2023-11-14 22:13", date_text(1_700_000_000));
+        assert_eq!("2024-02-29 00:00", date_text(1_709_164_800));
+        Ok(())
+    }
+}
@@ -1,6 +1,19 @@
+use std::collections::HashMap;
+
 pub const SEGMENT_BYTES: u16 = 8192;
 pub const IHEX_SEGMENT_BYTES: u32 = 0x10000;
 
+/* Every touched 64kb page of the hex file, keyed by the upper 16 bits of the address */
+pub type SegmentMap = HashMap<u16, Vec<u8>>;
+
+/* The data byte of every address in each touched page (64kb per page), keyed the same as SegmentMap */
+pub type ValueMap = HashMap<u16, Vec<u8>>;
+
+/* The end of a page, as the exclusive end of a [start, end) range. 64 bit so the top page ends at 0x1_0000_0000 */
+pub fn page_end(page: u16) -> u64 {
+    (page as u64 + 1) * IHEX_SEGMENT_BYTES as u64
+}
+
 fn ibyte_to_mapbyte(ibyte: u16) -> (usize,u8) {
     ((ibyte / 8) as usize, (ibyte % 8) as u8)
 }
@@ -29,7 +42,7 @@ pub fn end_mask(bits: u8) -> u8 {
     if bits > 8 {
         0xFF
     } else {
-        (((0xFF as u16) << (8-bits)) & 0xFF) as u8
+        ((0xFF_u16 << (8-bits)) & 0xFF) as u8
     }
 }
 
@@ -69,7 +82,7 @@ pub fn fill_bytes(map: &mut Vec<u8>, start: u16, len: u16) {
 
 }
 
-pub fn is_seg_range_set(segment: &Vec<u8>, start: u16, len: u16) -> bool {
+pub fn is_seg_range_set(segment: &[u8], start: u16, len: u16) -> bool {
     // Convert the ihex byte range to bit ranges on the segment map
     let remainder = ((start as i32) + (len as i32) - 0x10000).max(0) as u16;
     let len = len - remainder;
@@ -95,9 +108,250 @@ pub fn is_seg_range_set(segment: &Vec<u8>, start: u16, len: u16) -> bool {
     false
 }
 
+pub fn is_byte_set(segment: &[u8], byte: u16) -> bool {
+    let (target_byte, target_bit) = ibyte_to_mapbyte(byte);
+    segment[target_byte] & bit_msk(target_bit) != 0
+}
+
+/* Counts how many bytes of the range are set. Like is_seg_range_set the range must not run past the segment */
+pub fn count_set_bytes(segment: &[u8], start: u16, len: u32) -> u32 {
+    (start as u32..start as u32 + len)
+        .filter(|byte| is_byte_set(segment, *byte as u16))
+        .count() as u32
+}
+
+/**
+ * Collapses a segment map into a sorted list of contiguous occupied [start, end) address ranges.
+ * Ranges which touch across a 64kb page boundary are merged into one. Ends are 64 bit so a range holding the last byte
+ * of the address space ends at 0x1_0000_0000.
+ */
+pub fn occupied_ranges(segment_map: &SegmentMap) -> Vec<(u64, u64)> {
+    let mut pages: Vec<u16> = segment_map.keys().cloned().collect();
+    pages.sort();
+
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    let mut open_start: Option<u64> = None;
+    let mut last_end: u64 = 0;
+    for page in pages {
+        let segment = &segment_map[&page];
+        let page_base = page as u64 * IHEX_SEGMENT_BYTES as u64;
+
+        /* A range left open at the end of the previous page only continues if this page directly follows it */
+        if let Some(start) = open_start {
+            if page_base != last_end {
+                ranges.push((start, last_end));
+                open_start = None;
+            }
+        }
+
+        for (map_idx, map_byte) in segment.iter().enumerate() {
+            /* Whole map bytes can be skipped when they don't change the state of the current range */
+            if (*map_byte == 0 && open_start.is_none()) || (*map_byte == 0xFF && open_start.is_some()) {
+                continue;
+            }
+            for bit in 0..8u8 {
+                let addr = page_base + (map_idx as u64) * 8 + bit as u64;
+                let set = map_byte & bit_msk(bit) != 0;
+                match (set, open_start) {
+                    (true, None) => open_start = Some(addr),
+                    (false, Some(start)) => {
+                        ranges.push((start, addr));
+                        open_start = None;
+                    },
+                    _ => {},
+                }
+            }
+        }
+        last_end = page_end(page);
+    }
+
+    if let Some(start) = open_start {
+        ranges.push((start, last_end));
+    }
+    ranges
+}
+
+/**
+ * Coalesces the sectors touched by any occupied range into [start, end) spans of whole sectors.
+ * A sector only partially covered by data still has to be erased in full, so each span is sector aligned.
+ */
+pub fn touched_sector_spans(ranges: &[(u64, u64)], sector_size: u32) -> Vec<(u64, u64)> {
+    let mut spans: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in ranges {
+        let first = (start / sector_size as u64) * sector_size as u64;
+        let last = end.div_ceil(sector_size as u64) * sector_size as u64;
+        match spans.last_mut() {
+            Some(span) if span.1 >= first => span.1 = span.1.max(last),
+            _ => spans.push((first, last)),
+        }
+    }
+    spans
+}
+
+/* The parts of sorted [start, end) ranges which fall inside [start, end) */
+pub fn clip_ranges(ranges: &[(u64, u64)], start: u64, end: u64) -> Vec<(u64, u64)> {
+    ranges.iter()
+        .map(|(range_start, range_end)| (*range_start.max(&start), *range_end.min(&end)))
+        .filter(|(range_start, range_end)| range_start < range_end)
+        .collect()
+}
+
+/* The [start, end) gaps between sorted occupied ranges within [start, end) */
+pub fn free_ranges(ranges: &[(u64, u64)], start: u64, end: u64) -> Vec<(u64, u64)> {
+    let mut gaps = Vec::new();
+    let mut gap_start = start;
+    for &(range_start, range_end) in ranges {
+        if range_start > gap_start && gap_start < end {
+            gaps.push((gap_start, range_start.min(end)));
+        }
+        gap_start = gap_start.max(range_end);
+    }
+    if gap_start < end {
+        gaps.push((gap_start, end));
+    }
+    gaps
+}
+
+/* The parts of sorted ranges a which are not in sorted ranges b */
+pub fn subtract_ranges(a: &[(u64, u64)], b: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    a.iter().flat_map(|(start, end)| free_ranges(b, *start, *end)).collect()
+}
+
+/**
+ * The ranges holding data in both images whose values differ, in address order. Both value maps must come from the
+ * same segment maps the ranges were taken from.
+ */
+pub fn changed_ranges(old: (&SegmentMap, &ValueMap), new: (&SegmentMap, &ValueMap)) -> Vec<(u64, u64)> {
+    let common = subtract_ranges(&occupied_ranges(old.0), &subtract_ranges(&occupied_ranges(old.0), &occupied_ranges(new.0)));
+    let mut changed: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in common {
+        for addr in start..end {
+            if byte_value(old.0, old.1, addr as u32) == byte_value(new.0, new.1, addr as u32) {
+                continue;
+            }
+            match changed.last_mut() {
+                Some(range) if range.1 == addr => range.1 = addr + 1,
+                _ => changed.push((addr, addr + 1)),
+            }
+        }
+    }
+    changed
+}
+
+/* The ranges where the image holds data differing from a reference binary loaded at base, in address order */
+pub fn reference_mismatches(segment_map: &SegmentMap, values: &ValueMap, reference: &[u8], base: u32) -> Vec<(u64, u64)> {
+    let end = base as u64 + reference.len() as u64;
+    let mut mismatches: Vec<(u64, u64)> = Vec::new();
+    for (start, end) in clip_ranges(&occupied_ranges(segment_map), base as u64, end) {
+        for addr in start..end {
+            if byte_value(segment_map, values, addr as u32) == Some(reference[(addr - base as u64) as usize]) {
+                continue;
+            }
+            match mismatches.last_mut() {
+                Some(range) if range.1 == addr => range.1 = addr + 1,
+                _ => mismatches.push((addr, addr + 1)),
+            }
+        }
+    }
+    mismatches
+}
+
+/**
+ * Writes the data of one image over another, as loading its file after the other's would, so the later data wins
+ * where both hold some. Both value maps must come from the segment maps given with them.
+ */
+pub fn overlay_image(into: (&mut SegmentMap, &mut ValueMap), from: (&SegmentMap, &ValueMap)) {
+    for (page, segment) in from.0 {
+        let into_values = into.1.entry(*page).or_insert_with(|| vec![0; IHEX_SEGMENT_BYTES as usize]);
+        if let Some(page_values) = from.1.get(page) {
+            for offset in (0..IHEX_SEGMENT_BYTES as usize).filter(|offset| is_byte_set(segment, *offset as u16)) {
+                into_values[offset] = page_values[offset];
+            }
+        }
+        let into_segment = into.0.entry(*page).or_insert_with(|| vec![0; SEGMENT_BYTES as usize]);
+        for (into_byte, byte) in into_segment.iter_mut().zip(segment) {
+            *into_byte |= byte;
+        }
+    }
+}
+
+/* The value stored at an address, or None if the address holds no data */
+pub fn byte_value(segment_map: &SegmentMap, values: &ValueMap, addr: u32) -> Option<u8> {
+    let page = (addr / IHEX_SEGMENT_BYTES) as u16;
+    let offset = (addr % IHEX_SEGMENT_BYTES) as u16;
+    if !segment_map.get(&page).is_some_and(|segment| is_byte_set(segment, offset)) {
+        return None;
+    }
+    values.get(&page).map(|page_values| page_values[offset as usize])
+}
+
+/* One line of the map: where it starts, how many bytes it covers and the state of each character */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapRow {
+    pub addr: u32,
+    pub len: u32,
+    pub cells: Vec<bool>,
+}
+
+/**
+ * The lines of the map, as the viewer shows them. Pages in shared_pages get a full set of lines, each run of other
+ * pages before the last shared one collapses to a single blank line, however much of the address space it covers. A
+ * map without pages is one blank line.
+ */
+pub fn map_rows(segment_map: &SegmentMap, bytes_per_line: u16, width_symbols: u16, shared_pages: &[u16]) -> Vec<MapRow> {
+    let lines_per_page = IHEX_SEGMENT_BYTES / bytes_per_line as u32;
+    let mut rows = Vec::new();
+    let mut next_page: u32 = 0;
+    for &page in shared_pages {
+        let page_base = page as u32 * IHEX_SEGMENT_BYTES;
+        if page as u32 > next_page {
+            rows.push(MapRow {
+                addr: next_page * IHEX_SEGMENT_BYTES,
+                len: (page as u32 - next_page) * IHEX_SEGMENT_BYTES,
+                cells: vec![false; width_symbols as usize],
+            });
+        }
+        next_page = page as u32 + 1;
+        for line_num in 0..lines_per_page {
+            let cells = match segment_map.get(&page) {
+                Some(segment) => map_line_cells(segment, line_num, bytes_per_line, width_symbols),
+                None => vec![false; width_symbols as usize],
+            };
+            rows.push(MapRow {
+                addr: page_base + line_num * bytes_per_line as u32,
+                len: bytes_per_line as u32,
+                cells,
+            });
+        }
+    }
+    if rows.is_empty() {
+        rows.push(MapRow { addr: 0, len: IHEX_SEGMENT_BYTES, cells: vec![false; width_symbols as usize] });
+    }
+    rows
+}
+
+/**
+ * Computes one rendered line of a page: each character is set if any byte it represents is set.
+ * The last character of the line also covers the remainder when the line does not divide evenly.
+ */
+pub fn map_line_cells(segment: &[u8], line_num: u32, bytes_per_line: u16, width_symbols: u16) -> Vec<bool> {
+    let bytes_per_char = bytes_per_line / width_symbols;
+    let bytes_per_char_rem = bytes_per_line % width_symbols;
+    let mut line_data: Vec<bool> = Vec::with_capacity(width_symbols as usize);
+    for chr in 0..width_symbols {
+        // The requested number of bytes plus the remainder at the end if asked for a nondivisible combination
+        let is_last = chr==width_symbols-1;
+        let num_bytes = bytes_per_char+{if is_last {bytes_per_char_rem} else {0}};
+        // The offset in the segment
+        let ihex_start_byte = bytes_per_line * line_num as u16 + chr * bytes_per_char;
+        line_data.push(is_seg_range_set(segment, ihex_start_byte, num_bytes));
+    }
+    line_data
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{ihex_storage_utils::{ibyte_to_mapbyte, get_pad_counts}, start_mask, end_mask, fill_bytes, is_seg_range_set};
+    use crate::{ihex_storage_utils::{ibyte_to_mapbyte, get_pad_counts}, start_mask, end_mask, fill_bytes, is_seg_range_set, occupied_ranges, touched_sector_spans, count_set_bytes, clip_ranges, free_ranges, subtract_ranges, changed_ranges, reference_mismatches, overlay_image, byte_value, SegmentMap, ValueMap, SEGMENT_BYTES, IHEX_SEGMENT_BYTES};
 
     #[test]
     fn test_ibyte_to_mapbyte() -> Result<(),String> {
@@ -178,9 +432,9 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::identity_op)]
     fn test_fill_bytes() -> Result<(),String> {
-        let mut test_vec: Vec<u8> = Vec::new();
-        test_vec.resize(8192, 0);
+        let mut test_vec: Vec<u8> = vec![0; 8192];
         /* Keep everything in the first 128 bytes for readability. Test as many edge cases as posible since off-by-one style errors have been common */
         let expected_vec: Vec<u8> = [
             0xFF,0xFF,0xFF,0,0,0,0,0, //line 0, byte 0, len 24
@@ -254,4 +508,145 @@ mod tests {
         Ok(())
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_count_set_bytes() -> Result<(),String> {
+        let mut segment = vec![0; SEGMENT_BYTES as usize];
+        fill_bytes(&mut segment, 6, 12);
+        assert_eq!(12, count_set_bytes(&segment, 0, 0x10000));
+        assert_eq!(2, count_set_bytes(&segment, 0, 8));
+        assert_eq!(0, count_set_bytes(&segment, 18, 100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_occupied_ranges() -> Result<(),String> {
+        let mut segment_map = SegmentMap::new();
+        let mut page0 = vec![0; SEGMENT_BYTES as usize];
+        fill_bytes(&mut page0, 0, 24);
+        fill_bytes(&mut page0, 100, 1);
+        /* Runs to the end of the page so it merges with page 1 */
+        fill_bytes(&mut page0, 0xFFF0, 16);
+        let mut page1 = vec![0; SEGMENT_BYTES as usize];
+        fill_bytes(&mut page1, 0, 4);
+        /* Page 3 is not adjacent to page 1 and must start a new range */
+        let mut page3 = vec![0; SEGMENT_BYTES as usize];
+        fill_bytes(&mut page3, 0xFFFE, 2);
+        segment_map.insert(0, page0);
+        segment_map.insert(1, page1);
+        segment_map.insert(3, page3.clone());
+
+        assert_eq!(vec![(0, 24), (100, 101), (0xFFF0, 0x10004), (0x3FFFE, 0x40000)], occupied_ranges(&segment_map));
+        assert_eq!(Vec::<(u64, u64)>::new(), occupied_ranges(&SegmentMap::new()));
+
+        /* The top page ends past the last address rather than a byte short of it */
+        segment_map.insert(0xFFFF, page3);
+        assert_eq!(Some(&(0xFFFF_FFFE, 0x1_0000_0000)), occupied_ranges(&segment_map).last());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_touched_sector_spans() -> Result<(),String> {
+        /* Partial sectors round out, neighbouring ranges sharing or abutting a sector merge */
+        assert_eq!(vec![(0, 0x2000)], touched_sector_spans(&[(0x10, 0x20), (0xFF0, 0x1004)], 0x1000));
+        assert_eq!(vec![(0, 0x1000), (0x3000, 0x5000)], touched_sector_spans(&[(0, 0x1000), (0x3FFF, 0x4001)], 0x1000));
+        /* Sectors to the very top of the address space don't overflow */
+        assert_eq!(vec![(0xFFFF_F000, 0x1_0000_0000)], touched_sector_spans(&[(0xFFFF_FFF0, 0xFFFF_FFFF)], 0x1000));
+        assert_eq!(Vec::<(u64, u64)>::new(), touched_sector_spans(&[], 0x1000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clip_ranges() -> Result<(),String> {
+        let ranges = [(0x100, 0x200), (0x300, 0x400), (0x500, 0x600)];
+        assert_eq!(vec![(0x180, 0x200), (0x300, 0x340)], clip_ranges(&ranges, 0x180, 0x340));
+        assert_eq!(vec![(0x300, 0x400)], clip_ranges(&ranges, 0x200, 0x500));
+        assert!(clip_ranges(&ranges, 0x200, 0x300).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_free_ranges() -> Result<(),String> {
+        let ranges = [(0x100, 0x200), (0x300, 0x400)];
+        assert_eq!(vec![(0x200, 0x300)], free_ranges(&ranges, 0x100, 0x400));
+        assert_eq!(vec![(0x0, 0x100), (0x200, 0x300), (0x400, 0x1000)], free_ranges(&ranges, 0x0, 0x1000));
+        /* A window starting or ending inside data only counts what is free within it */
+        assert_eq!(vec![(0x200, 0x280)], free_ranges(&ranges, 0x180, 0x280));
+        assert_eq!(vec![(0xFFFF_0000, 0x1_0000_0000)], free_ranges(&ranges, 0xFFFF_0000, 0x1_0000_0000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_subtract_ranges() -> Result<(),String> {
+        assert_eq!(vec![(0x100, 0x180), (0x380, 0x400)], subtract_ranges(&[(0x100, 0x200), (0x300, 0x400)], &[(0x180, 0x380)]));
+        assert_eq!(vec![(0x100, 0x200)], subtract_ranges(&[(0x100, 0x200)], &[]));
+        assert!(subtract_ranges(&[(0x100, 0x200)], &[(0x0, 0x1000)]).is_empty());
+
+        Ok(())
+    }
+
+    /**
+     * Two images in page 1: the old one holds 0x10000-0x10008 and the new one 0x10004-0x1000c, all 0xAA but for 0x55 at
+     * 0x10005, 0x10006 and 0x10009 in the new one.
+     */
+    fn old_and_new() -> ((SegmentMap, ValueMap), (SegmentMap, ValueMap)) {
+        let mut old_page = vec![0; SEGMENT_BYTES as usize];
+        fill_bytes(&mut old_page, 0, 8);
+        let mut new_page = vec![0; SEGMENT_BYTES as usize];
+        fill_bytes(&mut new_page, 4, 8);
+        let old_values = ValueMap::from([(1, vec![0xAA; IHEX_SEGMENT_BYTES as usize])]);
+        let mut new_values = old_values.clone();
+        for offset in [5, 6, 9] {
+            new_values.get_mut(&1).unwrap()[offset] = 0x55;
+        }
+        ((SegmentMap::from([(1, old_page)]), old_values), (SegmentMap::from([(1, new_page)]), new_values))
+    }
+
+    #[test]
+    fn test_changed_ranges() -> Result<(),String> {
+        let ((old_map, old_values), (new_map, new_values)) = old_and_new();
+        /* Byte 9 differs too but only the new image holds data there */
+        assert_eq!(vec![(0x10005, 0x10007)], changed_ranges((&old_map, &old_values), (&new_map, &new_values)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reference_mismatches() -> Result<(),String> {
+        let (_, (new_map, new_values)) = old_and_new();
+        /* Against a reference of 0x10002-0x1000a, which differs at 0x10004 and matches the new value at 0x10009 */
+        let mut reference = vec![0xAA; 9];
+        reference[2] = 0x00;
+        reference[7] = 0x55;
+        assert_eq!(vec![(0x10004, 0x10007)], reference_mismatches(&new_map, &new_values, &reference, 0x10002));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay_image() -> Result<(),String> {
+        let ((mut merged_map, mut merged_values), (new_map, new_values)) = old_and_new();
+        /* Overlaying the new image on the old keeps the old data only where the new has none */
+        overlay_image((&mut merged_map, &mut merged_values), (&new_map, &new_values));
+        assert_eq!(vec![(0x10000, 0x1000c)], occupied_ranges(&merged_map));
+        assert_eq!(Some(0x55), byte_value(&merged_map, &merged_values, 0x10005));
+        assert_eq!(Some(0xAA), byte_value(&merged_map, &merged_values, 0x10000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_byte_value() -> Result<(),String> {
+        let (_, (new_map, new_values)) = old_and_new();
+        assert_eq!(Some(0x55), byte_value(&new_map, &new_values, 0x10009));
+        /* Values outside the data are not there, even where the value map holds something */
+        assert_eq!(None, byte_value(&new_map, &new_values, 0x10000));
+        assert_eq!(None, byte_value(&new_map, &new_values, 0x20000));
+
+        Ok(())
+    }
+}
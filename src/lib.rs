@@ -0,0 +1,51 @@
+/*
+ * The occupancy maps, reports and rendering behind ihex-visualize, for use from other build tooling. MemoryMap is the
+ * place to start; the modules below are what the command line is built from.
+ */
+pub mod error;
+pub mod config;
+pub mod completions;
+pub mod man_page;
+pub mod ihex_storage_utils;
+pub mod memory_map;
+pub mod range_set;
+pub mod exports;
+pub mod regions;
+pub mod gif_export;
+pub mod pdf_export;
+pub mod stats;
+pub mod structured;
+#[cfg(feature = "tui")]
+pub mod viewer;
+pub mod hex_loader;
+#[cfg(feature = "tui")]
+pub mod theme;
+#[cfg(feature = "tui")]
+pub mod pager;
+pub mod clipboard;
+pub mod progress;
+#[cfg(feature = "tui")]
+pub mod frame;
+pub mod narration;
+pub mod reports;
+pub mod hex_check;
+pub mod devices;
+pub mod linker_map;
+pub mod elf;
+pub mod svd;
+pub mod checksums;
+pub mod cortex_m;
+pub mod pattern;
+pub mod deflate;
+pub mod signatures;
+pub mod record_stats;
+pub mod history;
+pub mod git;
+pub mod mcu;
+pub mod wasm;
+pub mod server;
+pub mod logging;
+pub mod commands;
+pub use crate::ihex_storage_utils::{*};
+pub use crate::error::Error;
+pub use crate::memory_map::MemoryMap;
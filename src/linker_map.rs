@@ -0,0 +1,99 @@
+use crate::regions::Region;
+
+/* Output sections which never make it into the image, listed by ld at address 0 */
+const UNLOADED_SECTIONS: [&str; 5] = [".debug", ".comment", ".ARM.attributes", ".stab", ".note.gnu"];
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text.strip_prefix("0x")?, 16).ok()
+}
+
+/**
+ * Reads where each output section was placed from a GNU ld map file (-Map=app.map), as regions named after the
+ * sections. Sections with an initial value copied to RAM at startup, such as .data, are placed at their load address
+ * where the hex file holds them, named e.g. ".data load image". Empty sections and debug information are left out.
+ *
+ *   .text           0x08000000     0x1234
+ *   .data           0x20000000       0x40 load address 0x08001234
+ *   .a_long_section_name
+ *                   0x08001274       0x10
+ */
+pub fn parse_map(contents: &str) -> Vec<Region> {
+    let mut sections = Vec::new();
+    /* Placements only follow this heading, the memory configuration and discarded sections come before it */
+    let Some((_, memory_map)) = contents.split_once("Linker script and memory map") else {
+        return sections;
+    };
+    let mut pending_name: Option<&str> = None;
+    for line in memory_map.lines() {
+        let starts_section = line.starts_with('.') || line.starts_with(|chr: char| chr.is_ascii_alphabetic());
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, placement) = match (starts_section, pending_name.take()) {
+            /* A section name too long for its column is followed by its placement on the next line */
+            (true, _) if words.len() == 1 => {
+                pending_name = Some(words[0]);
+                continue;
+            },
+            (true, _) => (words[0], &words[1..]),
+            (false, Some(name)) if line.starts_with(' ') => (name, &words[..]),
+            _ => continue,
+        };
+        if UNLOADED_SECTIONS.iter().any(|prefix| name.starts_with(prefix)) {
+            continue;
+        }
+        let (Some(addr), Some(size)) = (placement.first().and_then(|word| parse_hex(word)), placement.get(1).and_then(|word| parse_hex(word))) else {
+            continue;
+        };
+        if size == 0 {
+            continue;
+        }
+        match placement[2..] {
+            ["load", "address", load_addr, ..] => if let Some(load_addr) = parse_hex(load_addr) {
+                sections.push(Region { name: format!("{name} load image"), start: load_addr, size, budget: None });
+            },
+            _ => sections.push(Region { name: name.to_string(), start: addr, size, budget: None }),
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::linker_map::parse_map;
+    use crate::regions::Region;
+
+    #[test]
+    fn test_parse_map() -> Result<(),String> {
+        let contents = "\
+Memory Configuration
+
+Name             Origin             Length             Attributes
+FLASH            0x08000000         0x00100000         xr
+
+Linker script and memory map
+
+.isr_vector     0x08000000      0x188
+ *(.isr_vector)
+ .isr_vector    0x08000000      0x188 startup.o
+
+.text           0x08000188     0x1000
+ *(.text)
+ .text          0x08000188      0x800 main.o
+                0x08000188                main
+
+.ARM.extab
+                0x08001188       0x20
+.bss            0x20000040      0x200
+.empty          0x08001188        0x0
+.data           0x20000000       0x40 load address 0x080011a8
+.debug_info     0x00000000     0x4000
+";
+        assert_eq!(vec![
+            Region { name: ".isr_vector".to_string(), start: 0x0800_0000, size: 0x188, budget: None },
+            Region { name: ".text".to_string(), start: 0x0800_0188, size: 0x1000, budget: None },
+            Region { name: ".ARM.extab".to_string(), start: 0x0800_1188, size: 0x20, budget: None },
+            Region { name: ".bss".to_string(), start: 0x2000_0040, size: 0x200, budget: None },
+            Region { name: ".data load image".to_string(), start: 0x0800_11a8, size: 0x40, budget: None },
+        ], parse_map(contents));
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{LevelFilter, Log, Metadata, Record};
+use crate::error::Error;
+
+/* The crate's own name as log targets start with it, which module names are given under */
+const CRATE_TARGET: &str = "ihex_visualize";
+
+/* How much to log: a level for everything, and levels for modules overriding it */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLevels {
+    pub default: LevelFilter,
+    /// Full module paths, e.g. ihex_visualize::hex_loader, longest first so the first match is the closest
+    pub modules: Vec<(String, LevelFilter)>,
+}
+
+/**
+ * Reads the levels from a comma separated list of a level for everything and module=level pairs, e.g.
+ * warn,hex_loader=debug. Modules are named within the crate; the crate's name on its own covers the whole of it. A
+ * list without a level for everything keeps the default given.
+ */
+pub fn parse_log_levels(spec: &str, default: LevelFilter) -> Result<LogLevels, String> {
+    let mut levels = LogLevels { default, modules: Vec::new() };
+    for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let parse_level = |level: &str| level.trim().parse::<LevelFilter>()
+            .map_err(|_| format!("'{}' is not a log level, expected off, error, warn, info, debug or trace", level.trim()));
+        match item.split_once('=') {
+            Some((module, level)) => {
+                let module = module.trim();
+                let target = match module == CRATE_TARGET || module.starts_with(&format!("{CRATE_TARGET}::")) {
+                    true => module.to_string(),
+                    false => format!("{CRATE_TARGET}::{module}"),
+                };
+                levels.modules.push((target, parse_level(level)?));
+            },
+            None => levels.default = parse_level(item)?,
+        }
+    }
+    levels.modules.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+    Ok(levels)
+}
+
+impl LogLevels {
+    /* The level for a log target, from the closest module given */
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules.iter()
+            .find(|(module, _)| target == module || target.starts_with(&format!("{module}::")))
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /* The most verbose of the levels, past which nothing is logged at all */
+    pub fn max(&self) -> LevelFilter {
+        self.modules.iter().map(|(_, level)| *level).chain([self.default]).max().unwrap_or(LevelFilter::Off)
+    }
+}
+
+/* A UTC time as 2023-08-30T12:34:56.789Z, the calendar worked out from the days since 1970 */
+fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {month_index + 3} else {month_index - 9};
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+    format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+            secs / 3600 % 24, secs / 60 % 60, secs % 60, since_epoch.subsec_millis())
+}
+
+/* Writes the log to a file rather than the terminal, in the same layout as on the terminal */
+struct FileLogger {
+    levels: LogLevels,
+    file: Mutex<File>,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.levels.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("{} {:<5} [{}] {}\n", utc_timestamp(SystemTime::now()), record.level(), record.target(), record.args());
+        if let Ok(mut file) = self.file.lock() {
+            /* A log which cannot be written has nowhere to say so */
+            let _ = file.write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/* Sends the log to the end of a file, creating it if needed, so it stays out of the way of the map on the terminal */
+pub fn init_file_logger(path: &str, levels: LogLevels) -> Result<(), Error> {
+    let file = OpenOptions::new().create(true).append(true).open(path).map_err(|source| Error::io(path, source))?;
+    let max = levels.max();
+    /* This only fails with a logger already set, which then keeps logging */
+    if log::set_boxed_logger(Box::new(FileLogger { levels, file: Mutex::new(file) })).is_ok() {
+        log::set_max_level(max);
+    }
+    Ok(())
+}
+
+/* Sends the log to a file when given one, else to the terminal */
+#[cfg(feature = "tui")]
+pub fn init_logger(log_file: Option<&str>, levels: LogLevels) -> Result<(), Error> {
+    if let Some(path) = log_file {
+        return init_file_logger(path, levels);
+    }
+    let logger = levels.modules.iter()
+        .fold(simple_logger::SimpleLogger::new().with_level(levels.default), |logger, (module, level)| logger.with_module_level(module, *level));
+    /* This only fails with a logger already set, which then keeps logging */
+    let _ = logger.init();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use log::LevelFilter;
+    use crate::logging::{parse_log_levels, utc_timestamp};
+
+    #[test]
+    fn test_log_levels() -> Result<(),String> {
+        let levels = parse_log_levels("error, hex_loader=debug,ihex_visualize::server=info", LevelFilter::Warn)?;
+        assert_eq!(LevelFilter::Error, levels.default);
+        assert_eq!(LevelFilter::Debug, levels.level_for("ihex_visualize::hex_loader"));
+        assert_eq!(LevelFilter::Error, levels.level_for("ihex_visualize::hex_loader_extra"));
+        assert_eq!(LevelFilter::Info, levels.level_for("ihex_visualize::server"));
+        assert_eq!(LevelFilter::Debug, levels.max());
+        assert_eq!(LevelFilter::Warn, parse_log_levels("", LevelFilter::Warn)?.default);
+        assert!(parse_log_levels("hex_loader=loud", LevelFilter::Warn).is_err());
+        assert_eq!("2024-02-29T23:59:59.250Z", utc_timestamp(UNIX_EPOCH + Duration::from_millis(1709251199250)));
+        Ok(())
+    }
+}
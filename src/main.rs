@@ -1,97 +1,606 @@
-use ihex::{Record,Reader};
-use log::{debug, warn};
-use std::collections::HashMap;
-use std::fs;
-use std::error::Error;
-use clap::Parser;
+use log::warn;
+use std::ffi::OsString;
+use std::process::ExitCode;
+use clap::builder::BoolishValueParser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueHint};
 use clap_num::maybe_hex;
-use crossterm::{cursor, queue, style, execute, terminal,};
-use std::io::{stdin, stdout, Read, Write};
-mod ihex_storage_utils;
-pub use crate::ihex_storage_utils::{*};
+use ihex_visualize::*;
+use ihex_visualize::commands::Budget;
+use ihex_visualize::error::read_text;
 
-const CHR_BLANK: char = '░';
-const CHR_DATA: char  = '▓';
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Open the interactive map of the files, the same as giving them with --file and no command, e.g. view boot.hex app.hex
+    View {
+        /// The hex files, shown as tabs in order after any given with --file
+        #[arg(value_hint = ValueHint::FilePath)]
+        files: Vec<String>,
+    },
+    /// Print the bytes used, span, fill, ranges, pages and any Cortex-M vector table of every file, the same as --stats, e.g. stats app.hex
+    Stats {
+        #[arg(value_hint = ValueHint::FilePath)]
+        files: Vec<String>,
+    },
+    /// List the ranges added, removed and, with --values, changed from each file to the next, the same as --diff, e.g. diff old.hex new.hex
+    Diff {
+        #[arg(value_hint = ValueHint::FilePath)]
+        files: Vec<String>,
+    },
+    /// List the problems in the records of every file, such as data written over earlier data, with line numbers, the same as --check, e.g. lint app.hex
+    Lint {
+        #[arg(value_hint = ValueHint::FilePath)]
+        files: Vec<String>,
+    },
+    /// Write a hex or ELF file out as Intel HEX, or as a raw binary from the first used byte for a .bin output with gaps filled with --fill-byte, e.g. convert app.elf app.bin
+    Convert {
+        #[arg(value_hint = ValueHint::FilePath)]
+        input: String,
+        #[arg(value_hint = ValueHint::FilePath)]
+        output: String,
+    },
+    /// Combine the data of several files into one Intel HEX, later files winning where they overlap, e.g. merge boot.hex app.hex -o combined.hex
+    Merge {
+        #[arg(required = true, value_hint = ValueHint::FilePath)]
+        files: Vec<String>,
+        /// Where to write the combined hex file
+        #[arg(short, long, value_hint = ValueHint::FilePath)]
+        output: String,
+    },
+    /// Host a web page showing the map of the files, read again on every refresh so it follows rebuilds, or of a file dropped on the page, e.g. serve --port 8080 app.hex
+    Serve {
+        #[arg(value_hint = ValueHint::FilePath)]
+        files: Vec<String>,
+        /// The port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// The address to listen on. 0.0.0.0 shares the page with the rest of the network
+        #[arg(long, default_value = "127.0.0.1")]
+        address: String,
+    },
+    /// Print a completion script for the shell, e.g. completions bash > /etc/bash_completion.d/ihex-visualize
+    Completions {
+        shell: completions::Shell,
+    },
+    /// Compare a hex file between two git revisions, e.g. git-diff HEAD~1 HEAD -- firmware.hex, overlaid on the map or with --diff and the other options
+    GitDiff {
+        /// The older revision
+        old: String,
+        /// The newer revision
+        new: String,
+        /// The hex file, relative to the current directory
+        #[arg(last = true, required = true, value_hint = ValueHint::FilePath)]
+        file: String,
+    },
+    /// Say whether addresses or ranges (0x0800F000, 0x08000000-0x08000fff) hold data, the range or gap they are in and the lines which wrote them, e.g. -f app.hex query 0x0800F000
+    Query {
+        #[arg(required = true, value_parser = parse_query)]
+        addresses: Vec<(u32, u64)>,
+    },
+}
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true, args_override_self = true)]
 struct Args {
-    /// The Intel Hex file to process
-    #[arg(short, long)]
-    file: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The Intel Hex file to process. Repeat to give several builds in order, shown as tabs (Tab / 1-9 switch)
+    #[arg(short, long, required = true, value_hint = ValueHint::FilePath)]
+    file: Vec<String>,
+
+    /// Keep the data byte values as well as occupancy, enabling value inspection and analyses
+    #[arg(long, global = true, default_value_t = false)]
+    values: bool,
+
+    /// Keep the occupancy as a set of ranges rather than a bitmap per 64kb page, for files touching a few bytes across the address space. Only the reports of the ranges and records work with it
+    #[arg(long, global = true, default_value_t = false)]
+    sparse: bool,
 
     /// How many bytes each line represents (base 10 or hex)
-    #[arg(short, long, value_parser=maybe_hex::<u16>, default_value_t = 0x1000)]
+    #[arg(short, long, global = true, value_parser=maybe_hex::<u16>, default_value_t = 0x1000)]
     line_width: u16,
 
-    /// How many characters should be generated per line (base 10 or hex)
-    #[arg(short, long, value_parser=maybe_hex::<u16>, default_value_t = 128)]
+    /// How many characters should be generated per line (base 10 or hex). 0 fits the terminal width
+    #[arg(short, long, global = true, value_parser=maybe_hex::<u16>, default_value_t = 128)]
     display_width: u16,
 
+    /// Write a GNU ld MEMORY block of the used and free regions to this path and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_ld: Option<String>,
+
+    /// Write the raw bit-per-byte occupancy map of every touched page to this path and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_bitmask: Option<String>,
+
+    /// Write the occupied ranges as a region file (.toml or .yaml) to this path and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_regions: Option<String>,
+
+    /// Write a GDB script which loads and reads back exactly the occupied ranges to this path and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_gdb: Option<String>,
+
+    /// Leave the Python comparison out of the --export-gdb script of a hex file, for a GDB built without Python
+    #[arg(long, global = true, default_value_t = false)]
+    no_gdb_python: bool,
+
+    /// Write an OpenOCD command script which erases the touched sectors and flashes the image to this path and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_openocd: Option<String>,
+
+    /// Write a pyOCD commander script which erases the touched sectors and flashes the image to this path and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_pyocd: Option<String>,
+
+    /// Write a J-Link Commander script which erases the touched sectors and flashes the image to this path and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_jlink: Option<String>,
+
+    /// Write an Intel HEX covering only the unoccupied addresses within the image, filled with --fill-byte, and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_gaps_hex: Option<String>,
+
+    /// Treat data equal to this byte (e.g. 0xFF for erased flash) as empty, on the map and in every report (base 10 or hex), or auto for the padding byte the first file uses most
+    #[arg(long, global = true, value_parser=parse_blank_byte)]
+    blank_byte: Option<BlankByte>,
+
+    /// The byte value used to fill gaps, also in binary selections saved from the viewer (base 10 or hex)
+    #[arg(long, global = true, value_parser=maybe_hex::<u8>, default_value_t = 0xFF)]
+    fill_byte: u8,
+
+    /// Write an animated GIF with one frame per --file, in order, and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_gif: Option<String>,
+
+    /// Write a PDF report with statistics, ranges, regions and the rendered map, and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    export_pdf: Option<String>,
+
+    /// Flash sector size used for erase planning (base 10 or hex, K/M suffixes allowed)
+    #[arg(long, global = true, value_parser=parse_sector_size, default_value = "4K")]
+    sector_size: u32,
+
+    /// Reload the files whenever they change on disk, keeping the scroll position
+    #[arg(long, global = true, default_value_t = false)]
+    watch: bool,
+
+    /// Print the map to the normal screen a page at a time instead of opening the interactive view
+    #[arg(long, global = true, default_value_t = false)]
+    pager: bool,
+
+    /// Write the first screen of the interactive map as plain text to this path and exit
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    snapshot: Option<String>,
+
+    /// Print the bytes used, span, fill, number of ranges, pages touched and any Cortex-M vector table of every file and exit
+    #[arg(long, global = true, default_value_t = false)]
+    stats: bool,
+
+    /// Check the records for problems such as data written over earlier data, list them with line numbers and exit
+    #[arg(long, global = true, default_value_t = false)]
+    check: bool,
+
+    /// Compare each --file with the next, listing the ranges added, removed and, with --values, changed, and exit
+    #[arg(long, global = true, default_value_t = false)]
+    diff: bool,
+
+    /// Print one row per 64K page holding data, with the bytes used, the fill and the first and last used address, and exit
+    #[arg(long, global = true, default_value_t = false)]
+    pages: bool,
+
+    /// Count the sectors to erase for the image, by the --device sector layout or --sector-size, flag ranges straddling sectors awkwardly, and exit
+    #[arg(long, global = true, default_value_t = false)]
+    sectors: bool,
+
+    /// Compare each --file with the next and list the sectors to erase and write again to update one to the other, by the same sectors as --sectors, and exit. Implies --values
+    #[arg(long, global = true, default_value_t = false)]
+    delta: bool,
+
+    /// List every occupied range exactly, with its length, and exit
+    #[arg(long, global = true, default_value_t = false)]
+    ranges: bool,
+
+    /// List the N (default 5) largest free ranges within the image, or within the device memory if given, and exit
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "5")]
+    free: Option<usize>,
+
+    /// List every free range between the data, or within the device memory if given, in address order and exit
+    #[arg(long, global = true, default_value_t = false)]
+    gaps: bool,
+
+    /// Leave free ranges smaller than this out of --gaps (base 10 or hex, K/M suffixes allowed)
+    #[arg(long, global = true, value_parser=parse_size, default_value = "0")]
+    min_gap: u32,
+
+    /// A GNU ld map file of the build, whose output sections label the map alongside any --regions
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    map: Option<String>,
+
+    /// An ELF file of the build, whose sections label the map alongside any --regions. An ELF given as --file is used the same way
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    elf: Option<String>,
+
+    /// Also label the N (default 10) largest functions and data objects of the ELF files
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "10")]
+    elf_symbols: Option<usize>,
+
+    /// A CMSIS-SVD file of the part, whose peripherals label the map. Data outside them and the --device flash is warned about
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    svd: Option<String>,
+
+    /// The part the image is for, e.g. stm32f405, giving the flash to check the image fits in and to mark on the map
+    #[arg(long, global = true)]
+    device: Option<String>,
+
+    /// Where the device's memory starts, for reports about free space (base 10 or hex). Defaults to the start of the --device flash or of the image
+    #[arg(long, global = true, value_parser=maybe_hex::<u32>)]
+    device_start: Option<u32>,
+
+    /// How big the device's memory is, for reports about free space (base 10 or hex, K/M suffixes allowed). Defaults to the --device flash or the end of the image
+    #[arg(long, global = true, visible_alias = "flash-size", value_parser=parse_size)]
+    device_size: Option<u32>,
+
+    /// Describe the memory layout in sentences for screen readers, listing every occupied range, instead of drawing the map
+    #[arg(long, global = true, default_value_t = false)]
+    accessible: bool,
+
+    /// Colors of the interactive map, t cycles through them while viewing
+    #[arg(long, global = true, value_enum, default_value_t = theme::ThemeName::Default)]
+    theme: theme::ThemeName,
+
+    /// The map's characters for occupied, empty and partly occupied cells, three characters in that order, e.g. "#.+"
+    #[arg(long, global = true, default_value = "▓░▒")]
+    glyphs: theme::Glyphs,
+
+    /// Key bindings of the interactive map. vim adds hjkl, gg/G, Ctrl-d/Ctrl-u and N to the default keys
+    #[arg(long, global = true, value_enum, default_value_t = viewer::Keymap::Default)]
+    keymap: viewer::Keymap,
+
+    /// A region file (.toml or .yaml) whose names label the map. Regions named in the viewer are saved to it, creating it if need be
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    regions: Option<String>,
+
+    /// Print the regions of high entropy (likely compressed or encrypted), low entropy (likely fill) and those between instead of the map. Implies --values
+    #[arg(long, global = true, default_value_t = false)]
+    entropy: bool,
+
+    /// The block size --entropy measures in (base 10 or hex, K/M suffixes allowed)
+    #[arg(long, global = true, value_parser=parse_size, default_value = "1K")]
+    entropy_block: u32,
+
+    /// Fail unless every file uses at most this many bytes (base 10 or hex, K/M suffixes allowed) or this percentage of the device flash (e.g. 90%), printing one budget line per file
+    #[arg(long, global = true, value_parser=parse_budget)]
+    max_used: Option<Budget>,
+
+    /// List every address where the data holds a pattern, hex bytes such as DEADBEEF or text such as "v1.2", and exit. Implies --values
+    #[arg(long, global = true)]
+    find: Option<String>,
+
+    /// List the printable ASCII strings of N (default 4) characters or more in the data with their addresses, and exit. Implies --values
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "4")]
+    strings: Option<usize>,
+
+    /// Fail unless every region of the --regions file which has a budget holds at most that many bytes of data, printing one budget line per region and file
+    #[arg(long, global = true, default_value_t = false)]
+    budgets: bool,
+
+    /// Add this run's usage of every file to a JSON history file, creating it if need be, and print the usage over all the builds in it
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    history: Option<String>,
+
+    /// Label the configuration, fuse and EEPROM windows of this microcontroller family and leave them out of the program flash --stats and --max-used count
+    #[arg(long, global = true, value_enum)]
+    mcu: Option<mcu::McuFamily>,
+
+    /// Print whether the data records come in increasing address order, how often they jump back and the largest jump, as some flash programmers need them in order
+    #[arg(long, global = true, default_value_t = false)]
+    order: bool,
+
+    /// Print how many records of each type the file has, how long its data records are and how often the extended address changes, to debug the tool which wrote it
+    #[arg(long, global = true, default_value_t = false)]
+    records: bool,
+
+    /// Label the UF2 blocks, MCUboot image headers and trailers, FAT boot sectors and ELF headers found in the data on the map
+    #[arg(long, global = true, default_value_t = false)]
+    signatures: bool,
+
+    /// Print the padding byte the data uses most, 0xFF or 0x00, and how many bytes are padding and how many real data. Implies --values
+    #[arg(long, global = true, default_value_t = false)]
+    padding: bool,
+
+    /// Compare the data with a raw binary (e.g. app.bin from objcopy) loaded at --base, listing the bytes either one lacks and those which differ. Implies --values
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    reference: Option<String>,
+
+    /// Where the --reference binary is loaded (base 10 or hex). Defaults to the first used byte
+    #[arg(long, global = true, value_parser=maybe_hex::<u32>)]
+    base: Option<u32>,
+
+    /// Print how well each occupied range compresses with DEFLATE, to estimate OTA package sizes and spot data which is compressed already. Implies --values
+    #[arg(long, global = true, default_value_t = false)]
+    compress: bool,
+
+    /// Print a histogram of the byte values, of the whole image and of each region, to spot padding, text and random data. Implies --values
+    #[arg(long, global = true, default_value_t = false)]
+    histogram: bool,
+
+    /// Print the CRC-32 and SHA-256 of the flat image, gaps filled with --fill-byte, to fingerprint a release. Implies --values
+    #[arg(long, global = true, default_value_t = false)]
+    checksum: bool,
+
+    /// Where the --checksum image starts (base 10 or hex). Defaults to the first used byte
+    #[arg(long, global = true, value_parser=maybe_hex::<u32>)]
+    checksum_start: Option<u32>,
+
+    /// How many bytes the --checksum image covers (base 10 or hex, K/M suffixes allowed). Defaults to the end of the data
+    #[arg(long, global = true, value_parser=parse_size)]
+    checksum_size: Option<u32>,
+
+    /// Print the CRC-32 of every 64K page (--page-crc 4K for other sizes) holding data, gaps filled with --fill-byte, to compare with a bootloader's verification. Implies --values
+    #[arg(long, global = true, value_parser=parse_size, num_args = 0..=1, default_missing_value = "64K")]
+    page_crc: Option<u32>,
+
+    /// Print stats, diff, query, lint and --check as JSON or YAML for scripts instead of text
+    #[arg(long, global = true, value_enum, default_value_t = structured::OutputFormat::Text)]
+    format: structured::OutputFormat,
+
+    /// Refuse any file with a malformed record, a wrong checksum or an unknown record type, exiting with an error
+    #[arg(long, global = true, default_value_t = false)]
+    strict: bool,
+
+    /// Print a man page in roff for packaging, e.g. --generate-man > ihex-visualize.1
+    #[arg(long, hide = true, exclusive = true, default_value_t = false)]
+    generate_man: bool,
+
+    /// Ignore the config file (~/.config/ihex-visualize/config.toml) for this run
+    #[arg(long, global = true, default_value_t = false)]
+    no_config: bool,
+
     // Enable debug output
-    #[arg(long, default_value_t = false)]
+    #[arg(long, global = true, default_value_t = false)]
     debug: bool,
+
+    /// Log levels, for everything and for modules, e.g. debug or warn,hex_loader=debug. Levels are off, error, warn, info, debug and trace
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// Append the log to this file instead of showing it on the terminal
+    #[arg(long, global = true, value_hint = ValueHint::FilePath)]
+    log_file: Option<String>,
+}
+
+impl Args {
+    /* Whether anything is to be written or printed, rather than showing the interactive map */
+    fn wants_output(&self) -> bool {
+        self.wants_export() || self.pager || self.snapshot.is_some() || self.accessible || self.diff || self.delta || self.max_used.is_some() || self.budgets || self.history.is_some() || matches!(self.command, Some(Command::Query { .. } | Command::Convert { .. } | Command::Merge { .. })) || self.wants_report()
+    }
+
+    /* Whether everything asked for works from the occupied ranges and the records alone, which is all --sparse keeps */
+    fn ranges_suffice(&self) -> bool {
+        let needs_data = self.wants_export() || self.pager || self.snapshot.is_some() || self.diff || self.delta || self.signatures
+            || self.entropy || self.find.is_some() || self.strings.is_some() || self.histogram || self.reference.is_some()
+            || self.compress || self.padding || self.checksum || self.page_crc.is_some() || matches!(self.blank_byte, Some(BlankByte::Auto))
+            || matches!(self.command, Some(Command::Convert { .. } | Command::Merge { .. }));
+        self.wants_output() && !needs_data
+    }
+}
+
+impl Args {
+    /* Whether any of the --export options was given */
+    fn wants_export(&self) -> bool {
+        let exports = [
+            &self.export_ld, &self.export_bitmask, &self.export_regions, &self.export_gdb, &self.export_openocd,
+            &self.export_pyocd, &self.export_jlink, &self.export_gaps_hex, &self.export_gif, &self.export_pdf,
+        ];
+        exports.iter().any(|export| export.is_some())
+    }
+
+    /* Whether any of the per file text reports was asked for */
+    fn wants_report(&self) -> bool {
+        self.stats || self.check || self.wants_text_report()
+    }
+
+    /* Whether any report --format does not cover was asked for, which would be mixed in with the JSON or YAML */
+    fn wants_text_report(&self) -> bool {
+        self.order || self.records || self.pages || self.sectors || self.ranges || self.free.is_some() || self.gaps || self.entropy || self.find.is_some() || self.strings.is_some() || self.histogram || self.reference.is_some() || self.compress || self.padding || self.checksum || self.page_crc.is_some()
+    }
+}
+
+/* Parses a byte count such as 4096, 0x1000 or 4K */
+fn parse_size(s: &str) -> Result<u32, String> {
+    let (digits, multiplier) = match s.chars().last() {
+        Some('k') | Some('K') => (&s[..s.len()-1], 1024),
+        Some('m') | Some('M') => (&s[..s.len()-1], 1024 * 1024),
+        _ => (s, 1),
+    };
+    let value = maybe_hex::<u32>(digits)?;
+    value.checked_mul(multiplier).ok_or(format!("{s} does not fit in 32 bits"))
 }
 
-fn print_map_line(line: &Vec<bool>) {
-    let mut line_str = String::with_capacity(line.len());
-    for i in  line.into_iter() {line_str.push(if *i==false {CHR_BLANK} else {CHR_DATA})};
-
-    queue!(
-        stdout(),
-        /* Move past the last column */
-        cursor::MoveToColumn(10),
-        style::Print(format!("{line_str}")),
-        cursor::MoveToNextLine(1),
-    ).expect("Couldnt output line");
-}
-
-fn fill_map_addrs(start_xy: (u16, u16), lines: u32, bracket_width: u8, hex_width: u8, line_size: u16, initial_offset: u16) {
-    let mut stdout = stdout();
-    queue!(stdout, cursor::SavePosition).expect("Couldnt save cursor");
-    for i in 0..lines {
-        let addr = i * line_size as u32 + (if i==0 {initial_offset as u32} else {0});
-        queue!(
-            stdout,
-            cursor::MoveTo(start_xy.0, start_xy.1 + i as u16),
-            /* Print a hex value of the desired length for the address */
-            style::Print(format!(
-                "{:<bracket_width$}",
-                format!(
-                    "{:#0hex_width$x}",
-                    addr,
-                    hex_width=hex_width as usize),
-                bracket_width=bracket_width as usize
-            )),
-        ).expect("Couldnt output line");
-    }
-    queue!(stdout, cursor::RestorePosition).expect("Couldnt reset cursor");
-}
-
-fn pause() {
-    let mut stdout = stdout();
-    queue!(
-        stdout,
-        style::Print("Press Enter to exit"),
-        cursor::MoveToNextLine(2)
-    ).expect("Could not output");
-    stdout.flush().unwrap();
-    stdin().read(&mut [0]).unwrap();
-}
-
-fn main() -> Result<(), Box<dyn Error>> {
-    /* Get the hex file object */
-    let args = Args::parse();
+/* Parses a sector size the same as parse_size, refusing 0 */
+fn parse_sector_size(s: &str) -> Result<u32, String> {
+    match parse_size(s)? {
+        0 => Err("a sector has to be at least 1 byte".to_string()),
+        size => Ok(size),
+    }
+}
+
+/* Parses an address, or an inclusive range of them such as 0x08000000-0x08000fff, into [start, end) */
+fn parse_query(s: &str) -> Result<(u32, u64), String> {
+    match s.split_once('-') {
+        Some((start, last)) => {
+            let (start, last) = (maybe_hex::<u32>(start.trim())?, maybe_hex::<u32>(last.trim())?);
+            if last < start {
+                return Err(format!("{s} ends before it starts"));
+            }
+            Ok((start, last as u64 + 1))
+        },
+        None => maybe_hex::<u32>(s.trim()).map(|addr| (addr, addr as u64 + 1)),
+    }
+}
+
+/* The byte --blank-byte treats as empty, given or found in the data */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlankByte {
+    Byte(u8),
+    Auto,
+}
+
+/* Parses a blank byte such as 0xFF or 255, or auto */
+fn parse_blank_byte(s: &str) -> Result<BlankByte, String> {
+    match s {
+        "auto" => Ok(BlankByte::Auto),
+        _ => maybe_hex::<u8>(s).map(BlankByte::Byte).map_err(|err| err.to_string()),
+    }
+}
+
+/* Parses a budget such as 480K, 0x78000 or 90% */
+fn parse_budget(s: &str) -> Result<Budget, String> {
+    match s.strip_suffix('%') {
+        Some(percent) => match percent.trim().parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(Budget::Percent(percent)),
+            _ => Err(format!("{s} is not a percentage from 0 to 100")),
+        },
+        None => parse_size(s).map(Budget::Bytes),
+    }
+}
+
+/**
+ * The command line as it is parsed. Flags also take =true or =false, so one the config file turns on can be turned off
+ * for a run, e.g. --watch=false.
+ */
+fn cli_command() -> clap::Command {
+    Args::command().mut_args(|arg| match arg.get_action() {
+        ArgAction::SetTrue if arg.get_id() != "generate_man" => arg.action(ArgAction::Set)
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("true")
+            .value_parser(BoolishValueParser::new()),
+        _ => arg,
+    })
+}
+
+/**
+ * The command line with the settings of the config file and the IHEX_VIZ_ variables added ahead of it. Every option
+ * comes from the command line, else the environment, else the config file, whole from the first of those giving it, so
+ * a file given on the command line is the only file rather than one more after those of the environment and config.
+ */
+fn with_settings(args: Vec<OsString>, config: &[(String, String)], env: &[(String, String)]) -> Vec<OsString> {
+    let command = cli_command();
+    /* Help, version and mistakes come out when the full command line is parsed */
+    let Ok(given) = command.clone().ignore_errors(true).try_get_matches_from(&args) else {
+        return args;
+    };
+    let on_command_line = |key: &str| command.get_arguments()
+        .find(|arg| arg.get_long() == Some(key))
+        .is_some_and(|arg| given.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine));
+    /* Files after a command are given on the command line as much as with --file */
+    let files_after_command = given.subcommand().is_some_and(|(_, command)| command.ids()
+        .any(|id| ["files", "input", "file"].contains(&id.as_str()) && command.value_source(id.as_str()) == Some(ValueSource::CommandLine)));
+    let on_command_line = |key: &str| on_command_line(key) || (key == "file" && files_after_command);
+    let settings: Vec<(String, String)> = config.iter()
+        .filter(|(key, _)| !env.iter().any(|(env_key, _)| env_key == key))
+        .chain(env)
+        .filter(|(key, _)| !on_command_line(key))
+        .cloned()
+        .collect();
+    let mut args = args;
+    let given = args.split_off(args.len().min(1));
+    args.into_iter().chain(config::config_args(&settings).into_iter().map(OsString::from)).chain(given).collect()
+}
+
+/* The command line with the settings of the config file and the IHEX_VIZ_ variables, as with_settings merges them */
+fn args_with_config() -> Result<Vec<OsString>, Error> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    /* --generate-man goes alone, so takes no settings */
+    if args.iter().any(|arg| arg == "--generate-man") {
+        return Ok(args);
+    }
+    let command = cli_command();
+    let env_settings = config::env_settings(command.get_arguments().filter_map(|arg| arg.get_long()).filter(|long| *long != "generate-man"));
+    let no_config = args.iter().any(|arg| arg == "--no-config" || arg == "--no-config=true") || env_settings.iter().any(|(key, value)| key == "no-config" && value == "true");
+    let settings = match config::config_path() {
+        Some(path) if path.exists() && !no_config => {
+            let config_path = path.display().to_string();
+            let settings = config::parse_config(&read_text(&config_path)?).map_err(|reason| Error::parse(&config_path, reason))?;
+            if let Some((key, _)) = settings.iter().find(|(key, _)| !command.get_arguments().any(|arg| arg.get_long() == Some(key.as_str()))) {
+                return Err(Error::parse(&config_path, format!("'{key}' is not an option")));
+            }
+            settings
+        },
+        _ => Vec::new(),
+    };
+    Ok(with_settings(args, &settings, &env_settings))
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+fn run() -> Result<(), Error> {
+    /* The command line with the config file and environment merged in, then the steps it asks for from commands */
+    let mut args = Args::from_arg_matches(&cli_command().get_matches_from(args_with_config()?)).unwrap_or_else(|err| err.exit());
+    if args.generate_man {
+        print!("{}", man_page::man_page(&mut cli_command()));
+        return Ok(());
+    }
+    if let Some(Command::Completions { shell }) = &args.command {
+        print!("{}", completions::completion_script(&mut cli_command(), *shell));
+        return Ok(());
+    }
+    /* The commands stand for the flags they grew out of, their files joining any given with --file */
+    match &args.command {
+        Some(Command::View { files } | Command::Merge { files, .. } | Command::Serve { files, .. }) => args.file.extend(files.iter().cloned()),
+        Some(Command::Stats { files }) => {
+            args.file.extend(files.iter().cloned());
+            args.stats = true;
+        },
+        Some(Command::Diff { files }) => {
+            args.file.extend(files.iter().cloned());
+            args.diff = true;
+        },
+        Some(Command::Lint { files }) => {
+            args.file.extend(files.iter().cloned());
+            args.check = true;
+        },
+        Some(Command::Convert { input, .. }) => args.file.push(input.clone()),
+        /* git-diff loads both revisions of the file as if they had been given with --file */
+        Some(Command::GitDiff { old, new, file }) => args.file = git::extract_revisions(&[old, new], file)?,
+        Some(Command::Query { .. } | Command::Completions { .. }) | None => {},
+    }
+    /* A server can start without files, to map the ones dropped on its page */
+    if args.file.is_empty() && !matches!(args.command, Some(Command::Serve { .. })) {
+        return Err(Error::Argument("Give the hex file with --file or after the command".to_string()));
+    }
+    /* Anything else printed would end up in the middle of the JSON or YAML */
+    let formatted = args.format != structured::OutputFormat::Text;
+    let formattable = args.stats || args.check || args.diff || matches!(args.command, Some(Command::Query { .. }));
+    let unformattable = args.wants_export() || args.wants_text_report() || args.pager || args.snapshot.is_some() || args.accessible || args.delta
+        || args.max_used.is_some() || args.budgets || args.history.is_some() || matches!(args.command, Some(Command::Convert { .. } | Command::Merge { .. }));
+    if formatted && (!formattable || unformattable) {
+        return Err(Error::Argument("--format json and yaml are for stats, diff, query, lint and --check on their own".to_string()));
+    }
+    if matches!(args.command, Some(Command::Convert { .. })) && args.file.len() > 1 {
+        return Err(Error::Argument("convert writes out one file, give it without --file".to_string()));
+    }
     let is_debug = args.debug;
-    let width_symbols = args.display_width;
+    let width_symbols = if args.display_width == 0 {viewer::fit_width(args.line_width)} else {args.display_width};
     let bytes_per_line = args.line_width ;
     let bytes_per_char = bytes_per_line / width_symbols;
     let bytes_per_char_rem = args.line_width % width_symbols;
-    let map_start_xy: (u16, u16) = (0, 2);
 
     /* Init logging */
-    let log_level = if is_debug {log::Level::Debug} else {log::Level::Warn};
-    simple_logger::init_with_level(log_level).unwrap();
+    let log_level = if is_debug {log::LevelFilter::Debug} else {log::LevelFilter::Warn};
+    let log_levels = logging::parse_log_levels(args.log_level.as_deref().unwrap_or(""), log_level)
+        .map_err(|reason| Error::Argument(format!("--log-level: {reason}")))?;
+    logging::init_logger(args.log_file.as_deref(), log_levels)?;
 
     if bytes_per_char_rem > 0 {
         warn!("The requested line width of {bytes_per_line} cannot be divided evenly across {width_symbols} \
@@ -100,120 +609,217 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     // TODO Support multiple segments per line
-    if IHEX_SEGMENT_BYTES % bytes_per_line as u32 != 0 {
+    if !IHEX_SEGMENT_BYTES.is_multiple_of(bytes_per_line as u32) {
         warn!("Segments of {IHEX_SEGMENT_BYTES} cannot be evenly represented in {bytes_per_line} byte lines. Insufficient lines will be 0-filled.")
     }
 
-    /* A counter must be kept between rows to indicate address offsets. Only one of these will ever be set at a time.gitf */
-    let mut ihex_ela_addr: u16 = 0;
-    let mut ihex_esx_addr: u16 = 0;
-
-    /* Store a map of every byte in the hex file, 0 if unset and 1 if set
-       8kb (mapping 64kb) segments are added on-demand to minimize memory usage */
-    let mut segment_map: HashMap<u16, Vec<u8>> = HashMap::new();
-
-    /* Get the hex file contents as a (ihex) Reader object */
-    let file_path = args.file.expect("Could not get file arg");
-    let file_contents = fs::read_to_string(file_path).expect("Could not read file");
-    let ihex_obj = Reader::new(&file_contents).into_iter();
-
-    for line in ihex_obj {
-       match line {
-        Ok(v) => match v {
-            Record::Data { offset, value } => {
-                /* Determine wich part of the segment map we need to access. ESX can offset in or between pages. */
-                let (page, esx_offset) = if ihex_esx_addr != 0 {
-                    ((ihex_esx_addr & 0xF000)>>12 as u16, ihex_esx_addr*16)
-                } else {
-                    (ihex_ela_addr, 0)
-                };
-
-                /* Find the segment or create it if it doesn't exist. */
-                segment_map.entry(page)
-                    .or_default()
-                    .resize(SEGMENT_BYTES as usize, 0);
-
-                /* Fill the proper bits in this segment */
-                fill_bytes(
-                    segment_map.get_mut(&page).expect("Could not find EXS"),
-                    offset + esx_offset,
-                    value.len() as u16);
-            },
-            Record::ExtendedSegmentAddress(addr) => { ihex_esx_addr = addr; ihex_ela_addr = 0; },
-            Record::ExtendedLinearAddress(addr)  => { ihex_esx_addr = 0; ihex_ela_addr = addr; },
-            _ => {}, /* Other types not useful for this analysis */
+    if let Some(Command::Serve { port, address, .. }) = &args.command {
+        println!("Serving the map on http://{address}:{port}/");
+        let options = server::ServeOptions { files: args.file.clone(), bytes_per_line, width_symbols };
+        return server::serve(address, *port, options);
+    }
+
+    if args.sparse && !args.ranges_suffice() {
+        return Err(Error::Argument("--sparse keeps only the occupied ranges, give it with --stats, --check, --order, --records, --pages, --sectors, \
+                                    --ranges, --free, --gaps, --max-used, --budgets, --history, --accessible or query".to_string()));
+    }
+
+    /* Strict mode turns away damaged files before anything is shown or written */
+    if args.strict {
+        commands::check_strict(&args.file)?;
+    }
+
+    /* Region labels are saved back from the viewer, the build's labels and those found in the data never are */
+    let labels = args.regions.as_deref().map(commands::read_regions).transpose()?.unwrap_or_default();
+    let elf_files: Vec<String> = args.elf.iter().chain(&args.file).cloned().collect();
+    let mut annotations = commands::build_labels(args.map.as_deref(), &elf_files, args.elf_symbols.unwrap_or(0), args.mcu)?;
+    let flash = commands::device_flash(args.device.as_deref(), args.device_start, args.device_size)?;
+    let sector_layout = commands::sector_layout(flash.as_ref(), args.sector_size);
+    let memory = args.svd.as_deref().map(|svd_path| commands::svd_memory(svd_path, flash.as_ref(), &mut annotations)).transpose()?;
+    let blank_byte = match (args.blank_byte, args.file.first()) {
+        (Some(BlankByte::Byte(byte)), _) => Some(byte),
+        (Some(BlankByte::Auto), Some(path)) => commands::padding_byte(path)?,
+        _ => None,
+    };
+    /* Show the map interactively unless asked for output. The files are parsed while the map is already up */
+    let rewrites = matches!(args.command, Some(Command::Convert { .. } | Command::Merge { .. }));
+    let load_options = hex_loader::LoadOptions { store_values: args.values || args.signatures || rewrites || args.delta || args.stats || args.entropy || args.find.is_some() || args.strings.is_some() || args.histogram || args.reference.is_some() || args.compress || args.padding || args.checksum || args.page_crc.is_some(), blank_byte };
+    let mut view_options = viewer::ViewOptions {
+        bytes_per_line,
+        width_symbols,
+        auto_width: args.display_width == 0,
+        fill_byte: args.fill_byte,
+        theme: args.theme,
+        glyphs: args.glyphs,
+        keymap: args.keymap,
+        watch: if args.watch {Some(load_options)} else {None},
+        regions_path: args.regions.clone(),
+        flash: flash.clone(),
+        annotations: annotations.clone(),
+        overlay: matches!(args.command, Some(Command::GitDiff { .. })),
+    };
+    if !args.wants_output() {
+        /* Signatures are found in the data, so the files are loaded before the map comes up and only once */
+        if args.signatures {
+            let images = commands::load_images(&args.file, load_options, memory.as_deref())?;
+            view_options.annotations.extend(commands::signature_labels(&images));
+            return viewer::Viewer::new(images, labels, view_options).run()
+                .map_err(|err| Error::Render(format!("The terminal could not show the map: {err}")));
         }
-        Err(_) => {},
-       }
+        let files = args.file.into_iter()
+            .map(|path| hex_loader::open_hex_file(&path).map(|hex| (path, hex)))
+            .collect::<Result<Vec<_>, _>>()?;
+        viewer::Viewer::load_in_background(files, labels, view_options, load_options).run()
+            .map_err(|err| Error::Render(format!("The terminal could not show the map: {err}")))?;
+        return Ok(());
     }
 
-    /* Process the keys in order */
-    let mut seg_idxs: Vec<u16> = segment_map
-        .keys()
-        .cloned()
-        .collect();
-    seg_idxs.sort();
-
-    /* The segment vector stores one byte per bit, so whatever the client is asked for should be divided by 8 */
-    let last_seg_idx = *seg_idxs.last().expect("Could not get last segment");
-
-    /* Fill in the address data */
-    let max_addr: u32 = (last_seg_idx as u32 + 1) * (SEGMENT_BYTES as u32) * 8 - 1;
-    let hex_width = (std::format!("{:#01x}", max_addr as u32).len() & 0xFF) as u8;
-    let lines_per_seg = IHEX_SEGMENT_BYTES / bytes_per_line as u32;
-    let lines_total = (max_addr + 1) / bytes_per_line as u32;
-
-    /* Write the data onto an alternatie screen */
-    execute!(stdout(), terminal::EnterAlternateScreen)?;
-    queue!(
-        stdout(),
-        cursor::MoveTo(0, 0),
-        style::Print(format!("Printing out segment map with bytes_per_line={bytes_per_line} bytes_per_char={bytes_per_char} hex_width={hex_width} lines_per_seg={lines_per_seg} lines_total={lines_total}")),
-        cursor::MoveToNextLine(2)
-    )?;
-    stdout().flush().expect("Could not flush");
-
-    // Fill in the addresses on the left
-    fill_map_addrs(map_start_xy, lines_total, 10, hex_width, bytes_per_line, 0);
-
-    /* Print the actual map */
-    for seg_idx in 0..last_seg_idx+1 {
-        match segment_map.get(&seg_idx) {
-            Some(segment) => {
-                for line_num in 0..lines_per_seg {
-                    let mut line_data: Vec<bool> = Vec::new();
-    
-                    for chr in 0..width_symbols {
-                        // The requested number of bytes plus the remainder at the end if asked for a nondivisible combination
-                        let is_last = chr==width_symbols-1;
-                        let num_bytes = bytes_per_char+{if is_last {bytes_per_char_rem} else {0}};
-                        // The offset in the segment
-                        let ihex_start_byte = bytes_per_line * line_num as u16 + chr * bytes_per_char;
-                        let res = is_seg_range_set(
-                            &segment,
-                            ihex_start_byte,
-                            num_bytes
-                        );
-                        line_data.push(res);
-                        if res {
-                            //println!("is_last={is_last} num_bytes={num_bytes} ihex_start_byte={ihex_start_byte} res={res}");
-                        }
-                    }
-    
-                    print_map_line(&line_data);
-                }
-            },
-            None => {
-                let mut line_data: Vec<bool> = Vec::new();
-                line_data.resize(width_symbols as usize, false);
-                print_map_line(&line_data);
-            },
+    /* A sparse load keeps only range sets, and leaves nothing for the steps working from the images to do */
+    let (images, sparse_maps) = match args.sparse {
+        true => (Vec::new(), Some(commands::load_sparse(&args.file, blank_byte)?)),
+        false => (commands::load_images(&args.file, load_options, memory.as_deref())?, None),
+    };
+    let files = match &sparse_maps {
+        Some(maps) => maps.iter().map(|(path, map)| (path.clone(), map.ranges().collect())).collect(),
+        None => commands::file_ranges(&images),
+    };
+    if args.signatures {
+        let found = commands::signature_labels(&images);
+        annotations.extend(found.iter().cloned());
+        view_options.annotations.extend(found);
+    }
+    /* Printed and exported maps have nothing to save, so the build's labels go in with the regions */
+    let all_labels: Vec<regions::Region> = labels.iter().chain(&annotations).cloned().collect();
+    let mut out = std::io::stdout();
+
+    /* Budgets and the history come before anything else is written, so a build over budget fails however it was asked for */
+    if let Some(budget) = args.max_used {
+        commands::check_max_used(&mut out, &files, budget, flash.as_ref(), args.device_size, args.mcu)?;
+    }
+    if args.budgets {
+        commands::check_region_budgets(&mut out, &files, &labels)?;
+    }
+    if let Some(history_path) = &args.history {
+        commands::record_history(&mut out, &files, history_path)?;
+    }
+
+    let export_options = commands::ExportOptions {
+        ld: args.export_ld.clone(),
+        bitmask: args.export_bitmask.clone(),
+        regions: args.export_regions.clone(),
+        gdb: args.export_gdb.clone(),
+        gdb_python: !args.no_gdb_python,
+        openocd: args.export_openocd.clone(),
+        pyocd: args.export_pyocd.clone(),
+        jlink: args.export_jlink.clone(),
+        gaps_hex: args.export_gaps_hex.clone(),
+        gif: args.export_gif.clone(),
+        pdf: args.export_pdf.clone(),
+        memory_end: flash.as_ref().map(devices::Flash::end),
+        sector_size: args.sector_size,
+        fill_byte: args.fill_byte,
+        bytes_per_line,
+        width_symbols,
+    };
+    if commands::write_exports(&images, &all_labels, &export_options)? {
+        return Ok(());
+    }
+
+    match &args.command {
+        Some(Command::Convert { output, .. }) => return commands::convert(&images[0].1, output, args.fill_byte),
+        Some(Command::Merge { output, .. }) => return commands::merge(&images, output),
+        Some(Command::Query { addresses }) => return commands::query(&mut out, &files, addresses, &all_labels, args.format),
+        _ => {},
+    }
+
+    if args.diff || args.delta {
+        let options = commands::DiffOptions { diff: args.diff, delta: args.delta, sector_layout, format: args.format };
+        return commands::diff(&mut out, &images, &options);
+    }
+
+    if args.wants_report() {
+        let options = commands::ReportOptions {
+            stats: args.stats,
+            check: args.check,
+            order: args.order,
+            records: args.records,
+            pages: args.pages,
+            sectors: args.sectors,
+            ranges: args.ranges,
+            free: args.free,
+            gaps: args.gaps,
+            min_gap: args.min_gap,
+            checksum: args.checksum,
+            checksum_start: args.checksum_start,
+            checksum_size: args.checksum_size,
+            find: args.find.clone(),
+            strings: args.strings,
+            histogram: args.histogram,
+            reference: args.reference.clone(),
+            base: args.base,
+            padding: args.padding,
+            compress: args.compress,
+            page_crc: args.page_crc,
+            entropy: args.entropy,
+            entropy_block: args.entropy_block,
+            fill_byte: args.fill_byte,
+            mcu: args.mcu,
+            flash,
+            device_start: args.device_start,
+            device_size: args.device_size,
+            sector_layout,
+            memory,
+            load_options,
+            format: args.format,
+        };
+        return match &sparse_maps {
+            Some(maps) => commands::print_sparse_reports(&mut out, maps, &all_labels, &options),
+            None => commands::print_reports(&mut out, &images, &all_labels, &options),
         };
     }
-    //println!("{:?}",segment_map.get(&0).expect("Could not get segment 0"));
 
-    /* Pause and exit */
-    pause();
-    execute!(stdout(), terminal::LeaveAlternateScreen)?;
+    if args.accessible {
+        return commands::narrate_files(&mut out, &files, &all_labels);
+    }
+    if args.pager {
+        return commands::page_maps(images, &all_labels, bytes_per_line, width_symbols, args.glyphs);
+    }
+    /* Only the snapshot of the first screen is left */
+    if let Some(snapshot_path) = &args.snapshot {
+        commands::write_snapshot(snapshot_path, images, labels, view_options)?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use clap::FromArgMatches;
+    use ihex_visualize::config;
+    use crate::{cli_command, with_settings, Args};
+
+    /* The command line parsed with the config settings and those of the environment merged in, as run parses it */
+    fn parse(given: &[&str], config: &[(&str, &str)], env: &[(String, String)]) -> Result<Args, String> {
+        let args = std::iter::once("ihex-visualize").chain(given.iter().copied()).map(OsString::from).collect();
+        let config: Vec<(String, String)> = config.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let matches = cli_command().try_get_matches_from(with_settings(args, &config, env)).map_err(|err| err.to_string())?;
+        Args::from_arg_matches(&matches).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_with_settings() -> Result<(),String> {
+        let settings = [("file", "config.hex"), ("values", "true"), ("line-width", "0x400")];
+        let args = parse(&[], &settings, &[])?;
+        assert_eq!((vec!["config.hex".to_string()], true, 0x400), (args.file, args.values, args.line_width));
+        let args = parse(&["-f", "cli.hex", "--values=false"], &settings, &[])?;
+        assert_eq!((vec!["cli.hex".to_string()], false, 0x400), (args.file, args.values, args.line_width));
+        assert_eq!(Vec::<String>::new(), parse(&["stats", "cli.hex"], &settings, &[])?.file);
+
+        /* The environment replaces the config file, and the command line both */
+        std::env::set_var(config::env_var_name("file"), "env.hex");
+        let env = config::env_settings(["file"]);
+        assert_eq!(vec!["env.hex".to_string()], parse(&[], &settings, &env)?.file);
+        assert_eq!(vec!["cli.hex".to_string()], parse(&["--file", "cli.hex"], &settings, &env)?.file);
+        Ok(())
+    }
+}
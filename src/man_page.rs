@@ -0,0 +1,133 @@
+use clap::{Arg, Command};
+
+/* Text safe to put in roff, with the backslashes and hyphens escaped and no line starting as a request */
+fn roff_text(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let escaped = line.replace('\\', "\\e").replace('-', "\\-");
+            match escaped.starts_with('.') || escaped.starts_with('\'') {
+                true => format!("\\&{escaped}"),
+                false => escaped,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/* How an argument is written, e.g. \fB\-f\fR, \fB\-\-file\fR \fIFILE\fR */
+fn arg_heading(arg: &Arg) -> String {
+    let value_name = match arg.get_value_names() {
+        Some(names) => names.iter().map(|name| name.to_string()).collect::<Vec<_>>().join(" "),
+        None => arg.get_id().as_str().to_uppercase(),
+    };
+    if arg.is_positional() {
+        return format!("\\fI{}\\fR", roff_text(&value_name));
+    }
+    let names: Vec<String> = arg.get_short().map(|short| format!("\\fB\\-{short}\\fR")).into_iter()
+        .chain(arg.get_long().map(|long| format!("\\fB\\-\\-{}\\fR", roff_text(long))))
+        .collect();
+    match arg.get_action().takes_values() {
+        true if arg.get_num_args().is_some_and(|range| range.min_values() == 0) => format!("{} [\\fI{}\\fR]", names.join(", "), roff_text(&value_name)),
+        true => format!("{} \\fI{}\\fR", names.join(", "), roff_text(&value_name)),
+        false => names.join(", "),
+    }
+}
+
+/* An argument as a tagged paragraph: how it is written, then its help with any choices and default */
+fn arg_paragraph(arg: &Arg) -> String {
+    let mut help = arg.get_long_help().or(arg.get_help()).map(|help| help.to_string()).unwrap_or_default();
+    let choices: Vec<String> = arg.get_possible_values().iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if !choices.is_empty() {
+        help += &format!(" [possible values: {}]", choices.join(", "));
+    }
+    let defaults: Vec<String> = arg.get_default_values().iter().map(|value| value.to_string_lossy().to_string()).collect();
+    if arg.get_action().takes_values() && !defaults.is_empty() {
+        help += &format!(" [default: {}]", defaults.join(", "));
+    }
+    format!(".TP\n{}\n{}\n", arg_heading(arg), roff_text(help.trim()))
+}
+
+/**
+ * A man page for section 1 in roff, built from the options and commands of the command line so it never falls behind
+ * them. Hidden options and commands are left out.
+ */
+pub fn man_page(command: &mut Command) -> String {
+    command.build();
+    let name = command.get_name().to_string();
+    let about = command.get_long_about().or(command.get_about()).map(|about| about.to_string()).unwrap_or_default();
+    /* One line of the synopsis for each way of running it */
+    let usage: Vec<String> = command.render_usage().to_string().lines()
+        .map(|line| format!("\\fB{}\\fR", roff_text(line.trim().trim_start_matches("Usage: "))))
+        .collect();
+
+    let title = format!("{name} {}", command.get_version().unwrap_or(""));
+    let mut page = format!(".TH {} 1 \"\" \"{}\"\n", roff_text(&name.to_uppercase()), roff_text(title.trim_end()));
+    page += &format!(".SH NAME\n{}", roff_text(&name));
+    if let Some(summary) = about.lines().next().filter(|summary| !summary.is_empty()) {
+        page += &format!(" \\- {}", roff_text(summary));
+    }
+    page += &format!("\n.SH SYNOPSIS\n{}\n", usage.join("\n.br\n"));
+    if !about.is_empty() {
+        page += &format!(".SH DESCRIPTION\n{}\n", roff_text(&about));
+    }
+
+    let visible: Vec<&Arg> = command.get_arguments().filter(|arg| !arg.is_hide_set()).collect();
+    if visible.iter().any(|arg| !arg.is_positional()) {
+        page += ".SH OPTIONS\n";
+        for arg in visible.iter().filter(|arg| !arg.is_positional()) {
+            page += &arg_paragraph(arg);
+        }
+    }
+
+    let subcommands: Vec<&Command> = command.get_subcommands().filter(|sub| !sub.is_hide_set() && sub.get_name() != "help").collect();
+    if !subcommands.is_empty() {
+        page += ".SH COMMANDS\n";
+        for sub in subcommands {
+            let sub_about = sub.get_long_about().or(sub.get_about()).map(|about| about.to_string()).unwrap_or_default();
+            page += &format!(".TP\n\\fB{}\\fR\n{}\n", roff_text(sub.get_name()), roff_text(&sub_about));
+            /* The command's own arguments are indented under it */
+            let args: Vec<&Arg> = sub.get_arguments().filter(|arg| !arg.is_hide_set() && arg.get_id() != "help").collect();
+            if !args.is_empty() {
+                page += ".RS\n";
+                for arg in args {
+                    page += &arg_paragraph(arg);
+                }
+                page += ".RE\n";
+            }
+        }
+    }
+    if let Some(version) = command.get_version() {
+        page += &format!(".SH VERSION\nv{}\n", roff_text(version));
+    }
+    if let Some(author) = command.get_author().filter(|author| !author.is_empty()) {
+        page += &format!(".SH AUTHORS\n{}\n", roff_text(author));
+    }
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, ArgAction, Command};
+    use crate::man_page::man_page;
+
+    #[test]
+    fn test_man_page() -> Result<(),String> {
+        let mut command = Command::new("my-tool").version("1.2.0").about("Shows files")
+            .arg(Arg::new("file").short('f').long("file").action(ArgAction::Append).value_name("FILE").help("The file to show, e.g. a.hex"))
+            .arg(Arg::new("theme").long("theme").value_parser(["dark", "light"]).default_value("dark"))
+            .arg(Arg::new("secret").long("secret").hide(true))
+            .subcommand(Command::new("merge").about(".hex files into one").arg(Arg::new("files").value_name("FILES")));
+        let page = man_page(&mut command);
+        assert!(page.starts_with(".TH MY\\-TOOL 1 \"\" \"my\\-tool 1.2.0\"\n.SH NAME\nmy\\-tool \\- Shows files\n"));
+        assert!(page.contains(".TP\n\\fB\\-f\\fR, \\fB\\-\\-file\\fR \\fIFILE\\fR\nThe file to show, e.g. a.hex\n"));
+        assert!(page.contains("[possible values: dark, light] [default: dark]"));
+        assert!(!page.contains("secret"));
+        /* A line of help starting with a dot is not taken for a request */
+        assert!(page.contains(".TP\n\\fBmerge\\fR\n\\&.hex files into one\n.RS\n.TP\n\\fIFILES\\fR\n"));
+        assert!(page.ends_with(".SH VERSION\nv1.2.0\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use crate::regions::Region;
+use crate::{clip_ranges, subtract_ranges};
+
+/* A microcontroller family whose hex files keep configuration, fuses or EEPROM at conventional addresses past the program flash */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum McuFamily {
+    /// Mid-range PIC16, e.g. PIC16F877A
+    Pic16,
+    /// Enhanced mid-range PIC16F1, e.g. PIC16F1847
+    Pic16f1,
+    Pic18,
+    /// PIC24 and dsPIC
+    Pic24,
+    /// AVR as avr-objcopy writes it, e.g. ATmega328P
+    Avr,
+}
+
+/* A window of the address space of a hex file which does not hold program code, as [start, end) */
+struct Window {
+    name: &'static str,
+    start: u32,
+    end: u32,
+}
+
+/* PIC hex files give byte addresses, twice the word address the datasheets use, so 0x2007 is at 0x400E */
+const PIC16_WINDOWS: [Window; 3] = [
+    Window { name: "User ID", start: 0x4000, end: 0x4008 },
+    Window { name: "Config words", start: 0x400E, end: 0x4012 },
+    Window { name: "EEPROM", start: 0x4200, end: 0x4400 },
+];
+
+const PIC16F1_WINDOWS: [Window; 3] = [
+    Window { name: "User ID", start: 0x1_0000, end: 0x1_0008 },
+    Window { name: "Config words", start: 0x1_000E, end: 0x1_0018 },
+    Window { name: "EEPROM", start: 0x1_E000, end: 0x1_E200 },
+];
+
+/* PIC18 addresses are byte addresses already. Q series parts moved the EEPROM from 0xF00000 to 0x310000 */
+const PIC18_WINDOWS: [Window; 4] = [
+    Window { name: "User ID", start: 0x20_0000, end: 0x20_0008 },
+    Window { name: "Config words", start: 0x30_0000, end: 0x30_0010 },
+    Window { name: "EEPROM", start: 0x31_0000, end: 0x31_0400 },
+    Window { name: "EEPROM", start: 0xF0_0000, end: 0xF0_0400 },
+];
+
+const PIC24_WINDOWS: [Window; 1] = [
+    Window { name: "Config words", start: 0x1F0_0000, end: 0x1F0_0030 },
+];
+
+/* The sections avr-libc gives the memories other than flash, each 64K apart */
+const AVR_WINDOWS: [Window; 5] = [
+    Window { name: "EEPROM", start: 0x81_0000, end: 0x82_0000 },
+    Window { name: "Fuses", start: 0x82_0000, end: 0x83_0000 },
+    Window { name: "Lock bits", start: 0x83_0000, end: 0x84_0000 },
+    Window { name: "Signature", start: 0x84_0000, end: 0x85_0000 },
+    Window { name: "User signatures", start: 0x85_0000, end: 0x86_0000 },
+];
+
+impl McuFamily {
+    fn windows(self) -> &'static [Window] {
+        match self {
+            McuFamily::Pic16 => &PIC16_WINDOWS,
+            McuFamily::Pic16f1 => &PIC16F1_WINDOWS,
+            McuFamily::Pic18 => &PIC18_WINDOWS,
+            McuFamily::Pic24 => &PIC24_WINDOWS,
+            McuFamily::Avr => &AVR_WINDOWS,
+        }
+    }
+
+    /* The configuration, fuse and EEPROM windows as regions labelling the map */
+    pub fn regions(self) -> Vec<Region> {
+        self.windows().iter()
+            .map(|window| Region { name: window.name.to_string(), start: window.start, size: window.end - window.start, budget: None })
+            .collect()
+    }
+
+    /* The occupied ranges with the windows taken out, leaving the program flash */
+    pub fn program_ranges(self, ranges: &[(u64, u64)]) -> Vec<(u64, u64)> {
+        let windows: Vec<(u64, u64)> = self.windows().iter().map(|window| (window.start as u64, window.end as u64)).collect();
+        subtract_ranges(ranges, &windows)
+    }
+
+    /**
+     * Program flash and window usage as "name  value" lines to follow the --stats ones, the windows holding data each
+     * on a line of their own.
+     */
+    pub fn summary_lines(self, ranges: &[(u64, u64)]) -> Vec<String> {
+        let bytes = |ranges: &[(u64, u64)]| ranges.iter().map(|(start, end)| end - start).sum::<u64>();
+        let mut lines = vec![format!("Program flash  {} bytes", bytes(&self.program_ranges(ranges)))];
+        for window in self.windows() {
+            let used = bytes(&clip_ranges(ranges, window.start as u64, window.end as u64));
+            if used > 0 {
+                lines.push(format!("{:<15}{used} bytes at {:#010x}-{:#010x}", window.name, window.start, window.end - 1));
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mcu::McuFamily;
+
+    #[test]
+    fn test_program_ranges() -> Result<(),String> {
+        /* Code, then the PIC18 config words and EEPROM data */
+        let ranges = [(0x0, 0x800), (0x30_0000, 0x30_000E), (0xF0_0000, 0xF0_0010)];
+        assert_eq!(vec![(0x0, 0x800)], McuFamily::Pic18.program_ranges(&ranges));
+        assert_eq!(vec![
+            "Program flash  2048 bytes",
+            "Config words   14 bytes at 0x00300000-0x0030000f",
+            "EEPROM         16 bytes at 0x00f00000-0x00f003ff",
+        ], McuFamily::Pic18.summary_lines(&ranges));
+        /* AVR leaves the same ranges alone */
+        assert_eq!(ranges.to_vec(), McuFamily::Avr.program_ranges(&ranges));
+        Ok(())
+    }
+}
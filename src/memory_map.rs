@@ -0,0 +1,390 @@
+use ihex::Record;
+use crate::error::Error;
+use crate::hex_check::RecordLines;
+use crate::hex_loader::{self, load_hex, HexImage, LoadOptions, RecordBase};
+use std::ops::Range;
+use crate::range_set::RangeSet;
+#[cfg(feature = "tui")]
+use crate::{regions::Region, theme::Glyphs, viewer::map_text};
+use crate::{count_set_bytes, fill_bytes, is_byte_set, is_seg_range_set, SegmentMap, IHEX_SEGMENT_BYTES, SEGMENT_BYTES};
+
+/**
+ * Which addresses of a 32 bit address space hold data, for tooling which wants the occupancy without the command line.
+ * Built from a hex file or by inserting ranges, then queried by address or range.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryMap {
+    storage: Storage,
+}
+
+/**
+ * How a MemoryMap keeps the occupancy. The bitmaps are what the viewer and reports work from, an 8kb bitmap for every
+ * touched 64kb page. The range set suits files touching a few bytes here and there across the address space, which
+ * would otherwise allocate a bitmap for every one of those pages.
+ */
+#[derive(Debug, Clone, PartialEq)]
+enum Storage {
+    Bitmap(SegmentMap),
+    Ranges(RangeSet),
+}
+
+impl Default for MemoryMap {
+    fn default() -> MemoryMap {
+        MemoryMap { storage: Storage::Bitmap(SegmentMap::new()) }
+    }
+}
+
+/* Reads records into a range set as load_hex reads them into bitmaps, fed a line at a time */
+struct SparseLoader {
+    map: MemoryMap,
+    records: RecordLines,
+    base: RecordBase,
+    blank_byte: Option<u8>,
+}
+
+impl SparseLoader {
+    fn new(blank_byte: Option<u8>) -> SparseLoader {
+        SparseLoader { map: MemoryMap::sparse(), records: RecordLines::default(), base: RecordBase::default(), blank_byte }
+    }
+
+    fn line(&mut self, text: &str) {
+        let Some(Ok(record)) = self.records.record(text) else {
+            return;
+        };
+        if self.base.update(&record) {
+            return;
+        }
+        let Record::Data { offset, value } = record else {
+            return;
+        };
+        let (page, start) = self.base.locate(offset);
+        let runs = match self.blank_byte {
+            Some(blank) => hex_loader::non_blank_runs(&value, blank),
+            None => vec![(0, value.len() as u16)],
+        };
+        for (run_start, run_len) in runs {
+            /* A run going past the end of its page wraps to the start of it, as in fill_bytes */
+            let run_start = start.wrapping_add(run_start) as u32;
+            let first = (run_len as u32).min(IHEX_SEGMENT_BYTES - run_start);
+            self.map.insert(page as u32 * IHEX_SEGMENT_BYTES + run_start, first);
+            self.map.insert(page as u32 * IHEX_SEGMENT_BYTES, run_len as u32 - first);
+        }
+    }
+}
+
+/* The pieces of [start, end) falling in each 64kb page, as the page, the offset into it and the length */
+fn page_pieces(start: u32, end: u64) -> impl Iterator<Item = (u16, u16, u32)> {
+    let mut addr = start as u64;
+    std::iter::from_fn(move || {
+        if addr >= end {
+            return None;
+        }
+        let page_end = (addr / IHEX_SEGMENT_BYTES as u64 + 1) * IHEX_SEGMENT_BYTES as u64;
+        let piece = ((addr >> 16) as u16, addr as u16, (page_end.min(end) - addr) as u32);
+        addr = page_end.min(end);
+        Some(piece)
+    })
+}
+
+/* The runs of set bits in one page's bitmap as [start, end) addresses, skipping whole map bytes where it can */
+fn page_runs(page: u16, segment: &[u8]) -> impl Iterator<Item = Range<u64>> + '_ {
+    let base = page as u64 * IHEX_SEGMENT_BYTES as u64;
+    let bits = segment.len() as u32 * 8;
+    let mut bit: u32 = 0;
+    let step = move |bit: u32, whole: u8| if bit.is_multiple_of(8) && segment[bit as usize / 8] == whole {8} else {1};
+    std::iter::from_fn(move || {
+        while bit < bits && !is_byte_set(segment, bit as u16) {
+            bit += step(bit, 0);
+        }
+        if bit >= bits {
+            return None;
+        }
+        let start = bit;
+        while bit < bits && is_byte_set(segment, bit as u16) {
+            bit += step(bit, 0xFF);
+        }
+        Some(base + start as u64..base + bit as u64)
+    })
+}
+
+/* Sets [start, end) in the bitmaps, adding pages as needed */
+fn fill_range(segment_map: &mut SegmentMap, start: u32, end: u64) {
+    for (page, offset, len) in page_pieces(start, end) {
+        let segment = segment_map.entry(page).or_insert_with(|| vec![0; SEGMENT_BYTES as usize]);
+        /* fill_bytes takes a 16 bit length, so a whole page goes in two halves */
+        let half = len.min(0x8000);
+        fill_bytes(segment, offset, half as u16);
+        if len > half {
+            fill_bytes(segment, offset + half as u16, (len - half) as u16);
+        }
+    }
+}
+
+impl MemoryMap {
+    /* An empty map kept as per page bitmaps */
+    pub fn new() -> MemoryMap {
+        MemoryMap::default()
+    }
+
+    /* An empty map kept as a range set, for sparse data */
+    pub fn sparse() -> MemoryMap {
+        MemoryMap { storage: Storage::Ranges(RangeSet::new()) }
+    }
+
+    /* The occupancy of the data records of a hex file */
+    pub fn from_hex(file_contents: &str) -> MemoryMap {
+        MemoryMap::from_image(load_hex(file_contents, LoadOptions::default()))
+    }
+
+    /* The same as from_hex, kept as a range set without allocating any bitmaps */
+    pub fn from_hex_sparse(file_contents: &str) -> MemoryMap {
+        let mut loader = SparseLoader::new(None);
+        for text in file_contents.lines() {
+            loader.line(text);
+        }
+        loader.map
+    }
+
+    /**
+     * Streams a hex file from disk into a range set a line at a time, so neither the text nor any bitmaps are held.
+     * Data equal to blank_byte counts as empty, as it does when loading with it.
+     */
+    pub fn from_file_sparse(file_path: &str, blank_byte: Option<u8>) -> Result<MemoryMap, Error> {
+        let mut loader = SparseLoader::new(blank_byte);
+        hex_loader::for_each_line(file_path, |_, text| loader.line(text))?;
+        Ok(loader.map)
+    }
+
+    pub fn from_image(image: HexImage) -> MemoryMap {
+        MemoryMap { storage: Storage::Bitmap(image.segment_map) }
+    }
+
+    /* Whether the map is kept as a range set */
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.storage, Storage::Ranges(_))
+    }
+
+    /* Marks len bytes from start as holding data. Anything past the top of the address space is left out */
+    pub fn insert(&mut self, start: u32, len: u32) {
+        let end = (start as u64 + len as u64).min(1 << 32);
+        match &mut self.storage {
+            Storage::Bitmap(segment_map) => fill_range(segment_map, start, end),
+            Storage::Ranges(set) => set.insert(start as u64, end),
+        }
+    }
+
+    /* Whether the byte at addr holds data */
+    pub fn contains(&self, addr: u32) -> bool {
+        match &self.storage {
+            Storage::Bitmap(segment_map) => segment_map.get(&((addr >> 16) as u16)).is_some_and(|segment| is_byte_set(segment, addr as u16)),
+            Storage::Ranges(set) => set.contains(addr as u64),
+        }
+    }
+
+    /* Whether any byte of [start, end) holds data. The end is 64 bit so a query can take in the top byte */
+    pub fn overlaps(&self, start: u32, end: u64) -> bool {
+        let segment_map = match &self.storage {
+            Storage::Bitmap(segment_map) => segment_map,
+            Storage::Ranges(set) => return set.overlaps(start as u64, end),
+        };
+        page_pieces(start, end).any(|(page, offset, len)| {
+            segment_map.get(&page).is_some_and(|segment| {
+                let half = len.min(0x8000);
+                is_seg_range_set(segment, offset, half as u16) || (len > half && is_seg_range_set(segment, offset + half as u16, (len - half) as u16))
+            })
+        })
+    }
+
+    /* How many bytes of [start, end) hold data */
+    pub fn bytes_in(&self, start: u32, end: u64) -> u64 {
+        match &self.storage {
+            Storage::Bitmap(segment_map) => page_pieces(start, end)
+                .filter_map(|(page, offset, len)| segment_map.get(&page).map(|segment| count_set_bytes(segment, offset, len) as u64))
+                .sum(),
+            Storage::Ranges(set) => set.count_in(start as u64, end),
+        }
+    }
+
+    /* How many bytes hold data in all */
+    pub fn bytes_used(&self) -> u64 {
+        self.occupied_ranges().map(|range| range.end - range.start).sum()
+    }
+
+    /* The occupied_ranges collected up front, as [start, end) pairs */
+    pub fn ranges(&self) -> impl Iterator<Item = (u64, u64)> {
+        self.occupied_ranges().map(|range| (range.start, range.end)).collect::<Vec<_>>().into_iter()
+    }
+
+    /**
+     * The occupied [start, end) ranges in address order, worked out as they are iterated. Ranges touching across a page
+     * boundary come out as one, and the ends are 64 bit so data in the top byte of the address space is kept.
+     */
+    pub fn occupied_ranges(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+        let ranges: Box<dyn Iterator<Item = Range<u64>> + '_> = match &self.storage {
+            Storage::Bitmap(segment_map) => {
+                let mut pages: Vec<u16> = segment_map.keys().copied().collect();
+                pages.sort_unstable();
+                let mut runs = pages.into_iter().flat_map(|page| page_runs(page, &segment_map[&page])).peekable();
+                Box::new(std::iter::from_fn(move || {
+                    let mut range = runs.next()?;
+                    while let Some(next) = runs.next_if(|next| next.start == range.end) {
+                        range.end = next.end;
+                    }
+                    Some(range)
+                }))
+            },
+            /* A range set never has two ranges touching */
+            Storage::Ranges(set) => Box::new(set.iter().map(|(start, end)| start..end)),
+        };
+        ranges
+    }
+
+    /* The map as the interactive view draws it, as plain text lines with the regions labelled */
+    #[cfg(feature = "tui")]
+    pub fn render(&self, labels: &[Region], bytes_per_line: u16, width_symbols: u16) -> Vec<String> {
+        let image = HexImage { segment_map: self.segment_map(), values: None, errors: Vec::new(), issues: Vec::new(), entry: None };
+        map_text(image, labels, bytes_per_line, width_symbols, Glyphs::default())
+    }
+
+    /* The per page bitmaps, for the functions of ihex_storage_utils. A range set is turned into bitmaps of the pages it touches */
+    pub fn segment_map(&self) -> SegmentMap {
+        match &self.storage {
+            Storage::Bitmap(segment_map) => segment_map.clone(),
+            Storage::Ranges(set) => {
+                let mut segment_map = SegmentMap::new();
+                for (start, end) in set.iter() {
+                    fill_range(&mut segment_map, start as u32, end);
+                }
+                segment_map
+            },
+        }
+    }
+}
+
+/**
+ * With the serde feature a map is written as {"sparse": bool, "ranges": [[start, end], ...]}, the occupied ranges rather
+ * than the bitmaps so a cached map stays small, and read back into the same kind of storage.
+ */
+#[cfg(feature = "serde")]
+impl serde::Serialize for MemoryMap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let ranges: Vec<(u64, u64)> = self.ranges().collect();
+        let mut map = serializer.serialize_struct("MemoryMap", 2)?;
+        map.serialize_field("sparse", &self.is_sparse())?;
+        map.serialize_field("ranges", &ranges)?;
+        map.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for MemoryMap {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<MemoryMap, D::Error> {
+        use serde::de::{self, MapAccess, SeqAccess, Visitor};
+
+        struct MapVisitor;
+
+        /* The map from what was read, refusing ranges past the top of the address space */
+        fn build<E: de::Error>(sparse: bool, ranges: RangeSet) -> Result<MemoryMap, E> {
+            let mut map = if sparse {MemoryMap::sparse()} else {MemoryMap::new()};
+            for (start, end) in ranges.iter() {
+                if end > 1 << 32 {
+                    return Err(E::custom(format!("range {start:#x}-{end:#x} runs past the 32 bit address space")));
+                }
+                /* The whole address space is one byte too long for insert, so goes in two parts */
+                let first = (end - start).min(u32::MAX as u64);
+                map.insert(start as u32, first as u32);
+                map.insert((start + first) as u32, (end - start - first) as u32);
+            }
+            Ok(map)
+        }
+
+        impl<'de> Visitor<'de> for MapVisitor {
+            type Value = MemoryMap;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a memory map with sparse and ranges")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MemoryMap, A::Error> {
+                let sparse = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let ranges = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                build(sparse, ranges)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut fields: A) -> Result<MemoryMap, A::Error> {
+                let (mut sparse, mut ranges) = (None, None);
+                while let Some(key) = fields.next_key::<String>()? {
+                    match key.as_str() {
+                        "sparse" => sparse = Some(fields.next_value()?),
+                        "ranges" => ranges = Some(fields.next_value()?),
+                        _ => return Err(de::Error::unknown_field(&key, FIELDS)),
+                    }
+                }
+                build(sparse.unwrap_or(false), ranges.ok_or_else(|| de::Error::missing_field("ranges"))?)
+            }
+        }
+
+        const FIELDS: &[&str] = &["sparse", "ranges"];
+        deserializer.deserialize_struct("MemoryMap", FIELDS, MapVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hex_loader::{load_hex, LoadOptions};
+    use crate::memory_map::{MemoryMap, SparseLoader};
+
+    #[test]
+    fn test_memory_map() -> Result<(),String> {
+        let mut map = MemoryMap::new();
+        map.insert(0x100, 0x10);
+        /* Across a page boundary, and a whole page */
+        map.insert(0x1_fff8, 0x10);
+        map.insert(0x5_0000, 0x1_0000);
+        assert_eq!(vec![(0x100, 0x110), (0x1_fff8, 0x2_0008), (0x5_0000, 0x6_0000)], map.ranges().collect::<Vec<_>>());
+        assert!(map.contains(0x10f) && !map.contains(0x110) && map.contains(0x2_0000));
+        assert!(map.overlaps(0x0, 0x101) && !map.overlaps(0x110, 0x1_fff8));
+        assert_eq!(0x10 + 0x10 + 0x1_0000, map.bytes_used());
+        assert_eq!(8, map.bytes_in(0x2_0000, 0x3_0000));
+        assert_eq!(vec![(0x0, 0x4)], MemoryMap::from_hex(":0400000001020304F2\n:00000001FF\n").ranges().collect::<Vec<_>>());
+        map.insert(0xFFFF_FFF0, 0x10);
+        assert_eq!(vec![0x100..0x110, 0x1_fff8..0x2_0008, 0x5_0000..0x6_0000, 0xFFFF_FFF0..0x1_0000_0000], map.occupied_ranges().collect::<Vec<_>>());
+        assert_eq!(Some((0xFFFF_FFF0, 0x1_0000_0000)), map.ranges().last());
+        assert_eq!(0x10 + 0x10 + 0x1_0000 + 0x10, map.bytes_used());
+        assert!(map.contains(0xFFFF_FFFF) && map.overlaps(0xFFFF_FFFF, 1 << 32));
+        assert_eq!(0x10, map.bytes_in(0xFFFF_0000, 1 << 32));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparse_memory_map() -> Result<(),String> {
+        /* The same inserts answer the same with either storage */
+        let mut bitmap = MemoryMap::new();
+        let mut sparse = MemoryMap::sparse();
+        for (start, len) in [(0x100, 0x10), (0x1_fff8, 0x10), (0xF000_0000, 0x4), (0xF000_0004, 0x4)] {
+            bitmap.insert(start, len);
+            sparse.insert(start, len);
+        }
+        assert_eq!(bitmap.ranges().collect::<Vec<_>>(), sparse.ranges().collect::<Vec<_>>());
+        assert_eq!(bitmap.occupied_ranges().collect::<Vec<_>>(), sparse.occupied_ranges().collect::<Vec<_>>());
+        assert_eq!(bitmap.bytes_in(0x108, 0x2_0000), sparse.bytes_in(0x108, 0x2_0000));
+        assert_eq!(bitmap.segment_map(), sparse.segment_map());
+        assert!(sparse.contains(0xF000_0007) && !sparse.contains(0xF000_0008) && sparse.overlaps(0x0, 0x101));
+        sparse.insert(0xFFFF_FFFF, 1);
+        assert!(sparse.overlaps(0xFFFF_FFFF, 1 << 32) && sparse.bytes_in(0xF000_0000, 1 << 32) == 9);
+        /* A record wrapping at the end of its page */
+        let contents = ":020000040001F9\n:04FFFE0001020304FB\n:00000001FF\n";
+        assert_eq!(MemoryMap::from_hex(contents).ranges().collect::<Vec<_>>(), MemoryMap::from_hex_sparse(contents).ranges().collect::<Vec<_>>());
+        let damaged = ":0400000001020304F2\n:0400100001020304F3\n:0400200001020304D2\n:00000001FF\n";
+        assert_eq!(MemoryMap::from_hex(damaged).ranges().collect::<Vec<_>>(), MemoryMap::from_hex_sparse(damaged).ranges().collect::<Vec<_>>());
+        /* The blank byte leaves out the same bytes as loading with it */
+        let padded = ":0400000001FFFF04F9\n:00000001FF\n";
+        let mut loader = SparseLoader::new(Some(0xFF));
+        padded.lines().for_each(|text| loader.line(text));
+        let blanked = load_hex(padded, LoadOptions { store_values: false, blank_byte: Some(0xFF) });
+        assert_eq!(vec![(0x0, 0x1), (0x3, 0x4)], loader.map.ranges().collect::<Vec<_>>());
+        assert_eq!(MemoryMap::from_image(blanked), MemoryMap::from_hex(":0100000001FE\n:0100030004F8\n:00000001FF\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use crate::regions::Region;
+
+/* A byte count as it reads out loud, e.g. "1 byte", "512 bytes" or "3.5 kilobytes" */
+pub fn spoken_size(bytes: u64) -> String {
+    let (number, unit) = match bytes {
+        0..=1023 => (bytes.to_string(), "byte"),
+        1024..=0xFFFFF => (spoken_number(bytes as f64 / 1024.0), "kilobyte"),
+        _ => (spoken_number(bytes as f64 / (1024.0 * 1024.0)), "megabyte"),
+    };
+    if number == "1" {format!("1 {unit}")} else {format!("{number} {unit}s")}
+}
+
+/* One decimal place, left off when it is zero so "4 kilobytes" does not read as "4.0" */
+fn spoken_number(value: f64) -> String {
+    let rounded = format!("{value:.1}");
+    rounded.strip_suffix(".0").map_or(rounded.clone(), str::to_string)
+}
+
+/**
+ * Describes the memory layout of a file in sentences, one per line, for screen readers and braille displays instead
+ * of the block character map: a summary, then every occupied range in address order with its size and the regions
+ * it falls in, with the gap to the next one in between. Addresses are read out in full, without symbols or ranges
+ * written as dashes.
+ */
+pub fn narrate(file_path: &str, ranges: &[(u64, u64)], regions: &[Region]) -> Vec<String> {
+    let count = ranges.len();
+    let (Some(first), Some(last)) = (ranges.first(), ranges.last()) else {
+        return vec![format!("{file_path} holds no data.")];
+    };
+    let bytes_used: u64 = ranges.iter().map(|(start, end)| end - start).sum();
+    let fill_percent = bytes_used as f64 * 100.0 / (last.1 - first.0) as f64;
+    let mut lines = vec![format!(
+        "{file_path} holds {} of data in {count} {}, from {:#010x} to {:#010x}, {} percent of that span.",
+        spoken_size(bytes_used), if count == 1 {"range"} else {"ranges"}, first.0, last.1 - 1, spoken_number(fill_percent),
+    )];
+    for (i, (start, end)) in ranges.iter().enumerate() {
+        if i > 0 {
+            lines.push(format!("Gap of {}.", spoken_size(start - ranges[i - 1].1)));
+        }
+        let names: Vec<&str> = regions.iter()
+            .filter(|region| (region.start as u64) < *end && region.start as u64 + region.size as u64 > *start)
+            .map(|region| region.name.as_str())
+            .collect();
+        let within = if names.is_empty() {String::new()} else {format!(" In {}.", names.join(", "))};
+        lines.push(format!(
+            "Range {} of {count}: {start:#010x} to {:#010x}, {}.{within}",
+            i + 1, end - 1, spoken_size(end - start),
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::narration::{narrate, spoken_size};
+    use crate::regions::Region;
+
+    #[test]
+    fn test_spoken_size() -> Result<(),String> {
+        assert_eq!("1 byte", spoken_size(1));
+        assert_eq!("512 bytes", spoken_size(512));
+        assert_eq!("1 kilobyte", spoken_size(1024));
+        assert_eq!("4 kilobytes", spoken_size(4096));
+        assert_eq!("3.5 kilobytes", spoken_size(3584));
+        assert_eq!("1.5 megabytes", spoken_size(3 * 512 * 1024));
+        Ok(())
+    }
+
+    #[test]
+    fn test_narrate() -> Result<(),String> {
+        let regions = vec![Region { name: "boot".to_string(), start: 0x0, size: 0x100, budget: None }];
+        let lines = narrate("a.hex", &[(0x0, 0x100), (0x200, 0x300)], &regions);
+        assert_eq!(vec![
+            "a.hex holds 512 bytes of data in 2 ranges, from 0x00000000 to 0x000002ff, 66.7 percent of that span.",
+            "Range 1 of 2: 0x00000000 to 0x000000ff, 256 bytes. In boot.",
+            "Gap of 256 bytes.",
+            "Range 2 of 2: 0x00000200 to 0x000002ff, 256 bytes.",
+        ], lines);
+        assert_eq!(vec!["b.hex holds no data."], narrate("b.hex", &[], &[]));
+        Ok(())
+    }
+}
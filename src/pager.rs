@@ -0,0 +1,38 @@
+use crossterm::terminal;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/**
+ * Prints lines to the normal screen so they stay in the scrollback after exit. On a terminal it stops after every
+ * screenful and waits for Enter (or q and Enter to stop), reading whole lines so it also works where raw mode and
+ * the alternate screen don't, e.g. dumb SSH sessions. Anywhere else, such as a pipe, everything is printed at once.
+ */
+pub fn page(lines: &[String]) -> io::Result<()> {
+    /* A reader such as head closing the pipe early is a normal way for paging to end */
+    match write_pages(lines) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+fn write_pages(lines: &[String]) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let interactive = io::stdout().is_terminal() && io::stdin().is_terminal();
+    /* One line of the screen is kept for the prompt */
+    let page_lines = terminal::size().map_or(24, |(_, height)| height as usize).saturating_sub(1).max(1);
+    let mut input = String::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        writeln!(stdout, "{line}")?;
+        let page_done = (i + 1) % page_lines == 0 && i + 1 < lines.len();
+        if interactive && page_done {
+            write!(stdout, "-- {}/{} lines, Enter for more, q to quit --", i + 1, lines.len())?;
+            stdout.flush()?;
+            input.clear();
+            io::stdin().lock().read_line(&mut input)?;
+            if input.trim() == "q" {
+                break;
+            }
+        }
+    }
+    stdout.flush()
+}
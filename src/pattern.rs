@@ -0,0 +1,98 @@
+use crate::{byte_value, occupied_ranges, SegmentMap, ValueMap};
+
+/**
+ * Parses a search pattern. Pairs of hex digits, optionally after 0x and separated by spaces, are bytes, e.g. DEADBEEF
+ * or "de ad be ef". Anything else is ASCII text, and text in quotes is always text, e.g. "CAFE".
+ */
+pub fn parse_pattern(text: &str) -> Result<Vec<u8>, String> {
+    if let Some(quoted) = text.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        return Ok(quoted.as_bytes().to_vec());
+    }
+    let digits: String = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text).split_whitespace().collect();
+    if !digits.is_empty() && digits.len().is_multiple_of(2) && digits.chars().all(|chr| chr.is_ascii_hexdigit()) {
+        return Ok((0..digits.len()).step_by(2).map(|idx| u8::from_str_radix(&digits[idx..idx + 2], 16).unwrap_or(0)).collect());
+    }
+    if text.is_empty() {
+        return Err("An empty pattern matches everywhere".to_string());
+    }
+    Ok(text.as_bytes().to_vec())
+}
+
+/* Every [start, end) where the data holds the pattern, in address order. Matches may overlap but never span a gap */
+pub fn find_pattern(segment_map: &SegmentMap, values: &ValueMap, pattern: &[u8]) -> Vec<(u64, u64)> {
+    let mut matches = Vec::new();
+    if pattern.is_empty() {
+        return matches;
+    }
+    for (start, end) in occupied_ranges(segment_map) {
+        let bytes: Vec<u8> = (start..end).map(|addr| byte_value(segment_map, values, addr as u32).unwrap_or(0)).collect();
+        matches.extend(bytes.windows(pattern.len())
+            .enumerate()
+            .filter(|(_, window)| *window == pattern)
+            .map(|(offset, _)| (start + offset as u64, start + (offset + pattern.len()) as u64)));
+    }
+    matches
+}
+
+/* Whether a byte is printable ASCII text, counting tabs but not other control characters */
+pub fn is_printable(byte: u8) -> bool {
+    byte == b'\t' || (0x20..0x7F).contains(&byte)
+}
+
+/**
+ * Every run of at least min_len printable bytes in the data, as its address and text in address order, like the
+ * strings tool but knowing where each one sits. A gap in the data ends a string.
+ */
+pub fn find_strings(segment_map: &SegmentMap, values: &ValueMap, min_len: usize) -> Vec<(u32, String)> {
+    let mut strings = Vec::new();
+    for (start, end) in occupied_ranges(segment_map) {
+        let mut text_start = start as u32;
+        let mut text = String::new();
+        for addr in start..=end {
+            match (addr < end).then(|| byte_value(segment_map, values, addr as u32).unwrap_or(0)) {
+                Some(byte) if is_printable(byte) => {
+                    if text.is_empty() {
+                        text_start = addr as u32;
+                    }
+                    text.push(byte as char);
+                },
+                _ if text.len() >= min_len.max(1) => strings.push((text_start, std::mem::take(&mut text))),
+                _ => text.clear(),
+            }
+        }
+    }
+    strings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hex_loader::{load_hex, LoadOptions};
+    use crate::pattern::{find_pattern, find_strings, parse_pattern};
+
+    #[test]
+    fn test_find_pattern() -> Result<(),String> {
+        assert_eq!(vec![0xDE, 0xAD, 0xBE, 0xEF], parse_pattern("DEADBEEF")?);
+        assert_eq!(vec![0xDE, 0xAD], parse_pattern("0xde ad")?);
+        assert_eq!(b"CAFE".to_vec(), parse_pattern("\"CAFE\"")?);
+        assert_eq!(b"Hello".to_vec(), parse_pattern("Hello")?);
+        assert!(parse_pattern("").is_err());
+
+        /* 0x0-0x5 is 00 FF 00 FF 00 FF, 0x10-0x11 is 00 FF after a gap */
+        let image = load_hex(":0600000000FF00FF00FFFD\n:0200100000FFEF\n:00000001FF\n", LoadOptions { store_values: true, blank_byte: None });
+        let values = image.values.ok_or("values were not stored")?;
+        assert_eq!(vec![(0x0, 0x3), (0x2, 0x5)], find_pattern(&image.segment_map, &values, &[0x00, 0xFF, 0x00]));
+        assert_eq!(vec![(0x1, 0x2), (0x3, 0x4), (0x5, 0x6), (0x11, 0x12)], find_pattern(&image.segment_map, &values, &[0xFF]));
+        /* The gap between 0x5 and 0x10 breaks the match */
+        assert!(find_pattern(&image.segment_map, &values, &[0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00]).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_strings() -> Result<(),String> {
+        /* "Hi" is too short, "Hello" is cut off from "ab" by a NUL and "xyz" runs up to the end of the data */
+        let image = load_hex(":0F00000048690048656C6C6F0061620A78797A14\n:00000001FF\n", LoadOptions { store_values: true, blank_byte: None });
+        let values = image.values.ok_or("values were not stored")?;
+        assert_eq!(vec![(0x3, "Hello".to_string()), (0xC, "xyz".to_string())], find_strings(&image.segment_map, &values, 3));
+        Ok(())
+    }
+}
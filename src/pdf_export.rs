@@ -0,0 +1,229 @@
+use std::fmt::Write;
+use crate::regions::Region;
+use crate::stats::OccupancyStats;
+
+/* A4 portrait in points */
+const PAGE_W: f32 = 595.0;
+const PAGE_H: f32 = 842.0;
+const MARGIN: f32 = 40.0;
+const TEXT_SIZE: f32 = 9.0;
+const TEXT_LEADING: f32 = 12.0;
+/* Map layout: an address gutter, the cells, then room for region labels */
+const MAP_GUTTER_W: f32 = 58.0;
+const MAP_CELLS_W: f32 = 380.0;
+const MAP_ROW_H: f32 = 5.0;
+const MAP_TEXT_SIZE: f32 = 4.5;
+
+/* One rendered line of the map, as drawn in the terminal */
+pub struct ReportMapLine {
+    pub addr: u32,
+    pub cells: Vec<bool>,
+    pub label: Option<String>,
+}
+
+/* Escapes a string for use inside a PDF literal string */
+fn pdf_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            },
+            /* The standard fonts only cover Latin-1 via WinAnsi, anything else becomes a placeholder */
+            c if c.is_ascii() && !c.is_ascii_control() => out.push(c),
+            _ => out.push('?'),
+        }
+    }
+    out
+}
+
+/**
+ * Lays out page content streams top to bottom, starting a new page whenever the next item would not fit.
+ */
+struct PageBuilder {
+    /// The pages before the one being laid out
+    pages: Vec<String>,
+    page: String,
+    y: f32,
+}
+
+impl PageBuilder {
+    fn new() -> PageBuilder {
+        PageBuilder { pages: Vec::new(), page: String::new(), y: PAGE_H - MARGIN }
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(std::mem::take(&mut self.page));
+        self.y = PAGE_H - MARGIN;
+    }
+
+    /* Every page, the one being laid out last */
+    fn finish(mut self) -> Vec<String> {
+        self.pages.push(self.page);
+        self.pages
+    }
+
+    /* Makes room for an item of the given height, returning the baseline to draw it at */
+    fn reserve(&mut self, height: f32) -> f32 {
+        if self.y - height < MARGIN {
+            self.new_page();
+        }
+        self.y -= height;
+        self.y
+    }
+
+    fn current(&mut self) -> &mut String {
+        &mut self.page
+    }
+
+    fn text_at(&mut self, x: f32, y: f32, size: f32, bold: bool, text: &str) {
+        let font = if bold {"F2"} else {"F1"};
+        writeln!(self.current(), "BT /{font} {size:.1} Tf {x:.2} {y:.2} Td ({}) Tj ET", pdf_string(text)).unwrap();
+    }
+
+    fn line(&mut self, text: &str, size: f32, bold: bool) {
+        let y = self.reserve(TEXT_LEADING * size / TEXT_SIZE);
+        self.text_at(MARGIN, y, size, bold, text);
+    }
+
+    fn heading(&mut self, text: &str) {
+        self.reserve(TEXT_LEADING / 2.0);
+        self.line(text, TEXT_SIZE * 1.4, true);
+        self.reserve(TEXT_LEADING / 3.0);
+    }
+
+    /* A table of left-aligned columns at fixed x offsets */
+    fn table(&mut self, headers: &[&str], col_x: &[f32], rows: &[Vec<String>]) {
+        let y = self.reserve(TEXT_LEADING);
+        for (header, x) in headers.iter().zip(col_x) {
+            self.text_at(MARGIN + x, y, TEXT_SIZE, true, header);
+        }
+        for row in rows {
+            let y = self.reserve(TEXT_LEADING);
+            for (cell, x) in row.iter().zip(col_x) {
+                self.text_at(MARGIN + x, y, TEXT_SIZE, false, cell);
+            }
+        }
+    }
+
+    fn map_line(&mut self, line: &ReportMapLine, cell_w: f32) {
+        let y = self.reserve(MAP_ROW_H);
+        self.text_at(MARGIN, y + 0.5, MAP_TEXT_SIZE, false, &format!("{:#010x}", line.addr));
+
+        /* Runs of equal cells are drawn as one rectangle to keep the file small */
+        let mut run_start = 0;
+        for idx in 1..=line.cells.len() {
+            if idx == line.cells.len() || line.cells[idx] != line.cells[run_start] {
+                let color = if line.cells[run_start] {"0.19 0.63 0.35"} else {"0.85 0.85 0.87"};
+                let x = MARGIN + MAP_GUTTER_W + run_start as f32 * cell_w;
+                let w = (idx - run_start) as f32 * cell_w;
+                writeln!(self.current(), "{color} rg {x:.2} {y:.2} {w:.2} {:.2} re f", MAP_ROW_H - 1.0).unwrap();
+                run_start = idx;
+            }
+        }
+        writeln!(self.current(), "0 g").unwrap();
+
+        if let Some(label) = &line.label {
+            self.text_at(MARGIN + MAP_GUTTER_W + MAP_CELLS_W + 4.0, y + 0.5, MAP_TEXT_SIZE, false, label);
+        }
+    }
+}
+
+/* Assembles the page content streams into a complete PDF using the built-in Helvetica fonts */
+fn assemble(pages: &[String]) -> Vec<u8> {
+    /* Objects 1-4 are the catalog, page tree and fonts, then a page and content stream object per page */
+    let mut objects: Vec<String> = Vec::new();
+    objects.push("<< /Type /Catalog /Pages 2 0 R >>".to_string());
+    let kids: Vec<String> = (0..pages.len()).map(|i| format!("{} 0 R", 5 + i * 2)).collect();
+    objects.push(format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()));
+    objects.push("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string());
+    objects.push("<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>".to_string());
+    for (i, content) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_W} {PAGE_H}] /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+            6 + i * 2));
+        objects.push(format!("<< /Length {} >>\nstream\n{content}endstream", content.len()));
+    }
+
+    let mut out = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        write!(out, "{} 0 obj\n{object}\nendobj\n", i + 1).unwrap();
+    }
+    let xref_offset = out.len();
+    write!(out, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).unwrap();
+    for offset in offsets {
+        writeln!(out, "{offset:010} 00000 n ").unwrap();
+    }
+    write!(out, "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n", objects.len() + 1).unwrap();
+    out.into_bytes()
+}
+
+/**
+ * Builds a paginated PDF report: a summary with the occupancy statistics, the occupied ranges and any region
+ * annotations, followed by the rendered map.
+ */
+pub fn pdf_report(file_path: &str, stats: &OccupancyStats, ranges: &[(u64, u64)], regions: &[Region], map: &[ReportMapLine], bytes_per_line: u16) -> Vec<u8> {
+    let mut builder = PageBuilder::new();
+    builder.line(&format!("Memory map of {file_path}"), TEXT_SIZE * 1.8, true);
+
+    builder.heading("Statistics");
+    let summary = vec![
+        vec!["Bytes used".to_string(), format!("{} ({:#x})", stats.bytes_used, stats.bytes_used)],
+        vec!["Address span".to_string(), format!("{:#010x} - {:#010x} ({} bytes)", stats.span_start, stats.span_end, stats.span())],
+        vec!["Fill of span".to_string(), format!("{:.2}%", stats.fill_percent())],
+        vec!["Occupied ranges".to_string(), stats.regions.to_string()],
+        vec!["64kb pages touched".to_string(), stats.pages.to_string()],
+    ];
+    builder.table(&["Statistic", "Value"], &[0.0, 150.0], &summary);
+
+    builder.heading("Occupied ranges");
+    let range_rows: Vec<Vec<String>> = ranges.iter()
+        .map(|(start, end)| vec![format!("{start:#010x}"), format!("{end:#010x}"), format!("{:#x}", end - start)])
+        .collect();
+    builder.table(&["Start", "End", "Size"], &[0.0, 100.0, 200.0], &range_rows);
+
+    if !regions.is_empty() {
+        builder.heading("Regions");
+        let region_rows: Vec<Vec<String>> = regions.iter()
+            .map(|r| vec![r.name.clone(), format!("{:#010x}", r.start), format!("{:#x}", r.size)])
+            .collect();
+        builder.table(&["Name", "Start", "Size"], &[0.0, 150.0, 250.0], &region_rows);
+    }
+
+    builder.new_page();
+    builder.heading(&format!("Map ({bytes_per_line:#x} bytes per line)"));
+    let cells_per_line = map.iter().map(|line| line.cells.len()).max().unwrap_or(1).max(1);
+    let cell_w = MAP_CELLS_W / cells_per_line as f32;
+    for line in map {
+        builder.map_line(line, cell_w);
+    }
+
+    assemble(&builder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pdf_export::{pdf_string, assemble};
+
+    #[test]
+    fn test_pdf_string() -> Result<(),String> {
+        assert_eq!("a\\(b\\)c\\\\", pdf_string("a(b)c\\"));
+        assert_eq!("x?y", pdf_string("x\u{2593}y"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_xref_offsets() -> Result<(),String> {
+        let pdf = String::from_utf8(assemble(&["0 g\n".to_string(), "1 g\n".to_string()])).unwrap();
+        /* Every xref entry must point at the start of its object */
+        let xref = &pdf[pdf.find("xref\n").unwrap()..];
+        for (i, entry) in xref.lines().skip(3).take(8).enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj", i + 1)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+const BAR_W: usize = 30;
+/* Redrawing more often than this only slows the parsing down */
+const DRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/**
+ * A one line progress bar on stderr for loading a file, e.g.
+ * "Loading big.hex [#########.....................]  31% 1234567 lines". It only shows up once loading takes a
+ * noticeable time, and never when stderr is not a terminal, so logs and pipes stay clean.
+ */
+pub struct Progress {
+    label: String,
+    total: usize,
+    enabled: bool,
+    next_draw: Instant,
+    drawn: bool,
+}
+
+impl Progress {
+    pub fn new(label: &str, total: usize) -> Progress {
+        Progress {
+            label: label.to_string(),
+            total,
+            enabled: io::stderr().is_terminal(),
+            next_draw: Instant::now() + DRAW_INTERVAL,
+            drawn: false,
+        }
+    }
+
+    /* Reports how many bytes and lines have been processed, drawing the bar if it is due */
+    pub fn update(&mut self, done: usize, lines: usize) {
+        if !self.enabled || Instant::now() < self.next_draw {
+            return;
+        }
+        self.next_draw = Instant::now() + DRAW_INTERVAL;
+        self.drawn = true;
+        let percent = (done.min(self.total) * 100).checked_div(self.total).unwrap_or(100);
+        let filled = percent * BAR_W / 100;
+        /* A failed write to stderr is not worth stopping the load for */
+        let _ = write!(
+            io::stderr(),
+            "\rLoading {} [{}{}] {percent:>3}% {lines} lines",
+            self.label, "#".repeat(filled), ".".repeat(BAR_W - filled),
+        );
+    }
+
+    /* Clears the bar, if one was drawn, so whatever comes next starts on a clean line */
+    pub fn finish(&mut self) {
+        if std::mem::take(&mut self.drawn) {
+            let _ = write!(io::stderr(), "\r{}\r", " ".repeat(self.label.chars().count() + BAR_W + 40));
+        }
+    }
+}
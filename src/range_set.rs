@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/**
+ * A set of addresses kept as sorted, disjoint [start, end) ranges, merged as they are inserted so no two touch. Memory
+ * grows with the number of ranges rather than the pages they are spread over, and lookups are O(log n) in the number
+ * of ranges. Ends are 64 bit so a range can run to the very top of the address space.
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RangeSet {
+    /// End of each range, keyed by its start
+    ranges: BTreeMap<u64, u64>,
+}
+
+impl RangeSet {
+    pub fn new() -> RangeSet {
+        RangeSet::default()
+    }
+
+    /* Adds [start, end), merging it with every range it overlaps or touches */
+    pub fn insert(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        let (mut start, mut end) = (start, end);
+        /* Only the range starting at or before start can reach into it from below, the others start inside it */
+        let merged: Vec<(u64, u64)> = self.ranges.range(..=end).rev()
+            .take_while(|(_, range_end)| **range_end >= start)
+            .map(|(range_start, range_end)| (*range_start, *range_end))
+            .collect();
+        for (range_start, range_end) in merged {
+            self.ranges.remove(&range_start);
+            start = start.min(range_start);
+            end = end.max(range_end);
+        }
+        self.ranges.insert(start, end);
+    }
+
+    /* The range holding addr, if any */
+    fn range_at(&self, addr: u64) -> Option<(u64, u64)> {
+        self.ranges.range(..=addr).next_back()
+            .filter(|(_, end)| **end > addr)
+            .map(|(start, end)| (*start, *end))
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.range_at(addr).is_some()
+    }
+
+    /* Whether any address of [start, end) is in the set */
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        start < end && self.ranges.range(..end).next_back().is_some_and(|(_, range_end)| *range_end > start)
+    }
+
+    /* How many addresses of [start, end) are in the set */
+    pub fn count_in(&self, start: u64, end: u64) -> u64 {
+        let first = self.range_at(start).map_or(start, |(range_start, _)| range_start);
+        self.ranges.range((Bound::Included(first), Bound::Excluded(end.max(first))))
+            .map(|(range_start, range_end)| range_end.min(&end).saturating_sub(*range_start.max(&start)))
+            .sum()
+    }
+
+    /* The ranges in address order */
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.ranges.iter().map(|(start, end)| (*start, *end))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+/* With the serde feature a set is a list of [start, end) pairs in address order, e.g. [[4096, 8192]] in JSON */
+#[cfg(feature = "serde")]
+impl serde::Serialize for RangeSet {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/* Pairs may come in any order, touching or overlapping, and are merged as they would be inserted */
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for RangeSet {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<RangeSet, D::Error> {
+        let mut set = RangeSet::new();
+        for (start, end) in Vec::<(u64, u64)>::deserialize(deserializer)? {
+            if start > end {
+                return Err(serde::de::Error::custom(format!("range {start:#x}-{end:#x} ends before it starts")));
+            }
+            set.insert(start, end);
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::range_set::RangeSet;
+
+    #[test]
+    fn test_range_set() -> Result<(),String> {
+        let mut set = RangeSet::new();
+        set.insert(0x10, 0x20);
+        set.insert(0x40, 0x50);
+        set.insert(0xFFFF_FFF0, 0x1_0000_0000);
+        /* Touching the first, then bridging the first two */
+        set.insert(0x20, 0x28);
+        assert_eq!(vec![(0x10, 0x28), (0x40, 0x50), (0xFFFF_FFF0, 0x1_0000_0000)], set.iter().collect::<Vec<_>>());
+        set.insert(0x18, 0x44);
+        assert_eq!(vec![(0x10, 0x50), (0xFFFF_FFF0, 0x1_0000_0000)], set.iter().collect::<Vec<_>>());
+        assert!(set.contains(0x10) && !set.contains(0x50) && set.contains(0xFFFF_FFFF));
+        assert!(set.overlaps(0x0, 0x11) && !set.overlaps(0x50, 0xFFFF_FFF0) && !set.overlaps(0x20, 0x20));
+        assert_eq!(0x8 + 0x10, set.count_in(0x48, 0x1_0000_0000));
+        assert_eq!(0x10, set.count_in(0x0, 0x20));
+        assert_eq!(0, set.count_in(0x60, 0x70));
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_range_set_deserialize() -> Result<(),String> {
+        use serde::de::{value::Error, Deserialize, IntoDeserializer};
+        let pairs: Vec<Vec<u64>> = vec![vec![0x40, 0x50], vec![0x10, 0x20], vec![0x20, 0x28]];
+        let set = RangeSet::deserialize(pairs.into_deserializer()).map_err(|error: Error| error.to_string())?;
+        assert_eq!(vec![(0x10, 0x28), (0x40, 0x50)], set.iter().collect::<Vec<_>>());
+        let backwards: Vec<Vec<u64>> = vec![vec![0x50, 0x40]];
+        assert!(RangeSet::deserialize(backwards.into_deserializer()).map_err(|error: Error| error.to_string()).is_err());
+        Ok(())
+    }
+}
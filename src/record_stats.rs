@@ -0,0 +1,185 @@
+use ihex::Record;
+use std::collections::BTreeMap;
+use crate::hex_check::RecordLines;
+use crate::hex_loader::SourceRecord;
+
+/* How the data records of a file are ordered. Some flash programmers only take them in increasing address order */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordOrder {
+    /// Number of data records
+    pub records: usize,
+    /// Number of records starting below the end of the record before them
+    pub backward_jumps: usize,
+    /// The line of the biggest backward jump, where the record before it ended and where it starts
+    pub largest_jump: Option<(usize, u64, u32)>,
+}
+
+impl RecordOrder {
+    /* Goes through the data records, from hex_loader::records_in, in file order */
+    pub fn from_records(records: &[SourceRecord]) -> RecordOrder {
+        let mut order = RecordOrder { records: records.len(), backward_jumps: 0, largest_jump: None };
+        let mut previous_end = None;
+        for record in records {
+            let start = record.addr();
+            if let Some(end) = previous_end.filter(|end| (start as u64) < *end) {
+                order.backward_jumps += 1;
+                if order.largest_jump.is_none_or(|(_, from, to)| end - start as u64 > from - to as u64) {
+                    order.largest_jump = Some((record.line, end, start));
+                }
+            }
+            previous_end = Some(start as u64 + record.len as u64);
+        }
+        order
+    }
+
+    /* The order as aligned "name  value" lines, like --stats prints */
+    pub fn summary_lines(&self) -> Vec<String> {
+        let order = match self.backward_jumps {
+            0 => "increasing".to_string(),
+            1 => "1 backward jump".to_string(),
+            jumps => format!("{jumps} backward jumps"),
+        };
+        let mut lines = vec![
+            format!("Data records   {}", self.records),
+            format!("Record order   {order}"),
+        ];
+        if let Some((line, from, to)) = self.largest_jump {
+            lines.push(format!("Largest jump   line {line} goes back {} bytes, from {from:#010x} to {to:#010x}", from - to as u64));
+        }
+        lines
+    }
+}
+
+/* What a file is made of, record by record, which tells a lot about the tool which wrote it */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordStats {
+    pub data: usize,
+    pub end_of_file: usize,
+    pub extended_segment: usize,
+    pub start_segment: usize,
+    pub extended_linear: usize,
+    pub start_linear: usize,
+    /// Records which cannot be read, e.g. cut short or with a wrong checksum
+    pub unreadable: usize,
+    /// Extended address records changing the address in effect, rather than repeating it
+    pub linear_switches: usize,
+    pub segment_switches: usize,
+    /// How many data records carry each number of bytes
+    pub data_lengths: BTreeMap<usize, usize>,
+}
+
+impl RecordStats {
+    /* Counts every record of the file, reading past bad ones and stopping at the end of file record as loading does */
+    pub fn from_contents(file_contents: &str) -> RecordStats {
+        let mut counter = RecordCounter::default();
+        for text in file_contents.lines() {
+            counter.line(text);
+        }
+        counter.finish()
+    }
+
+    /* The counts as aligned "name  value" lines, leaving out record types the file does not use */
+    pub fn summary_lines(&self) -> Vec<String> {
+        let counts = [
+            ("Data", self.data),
+            ("End of file", self.end_of_file),
+            ("Ext. segment", self.extended_segment),
+            ("Start segment", self.start_segment),
+            ("Ext. linear", self.extended_linear),
+            ("Start linear", self.start_linear),
+            ("Unreadable", self.unreadable),
+        ];
+        let mut lines: Vec<String> = counts.iter()
+            .filter(|(name, count)| *count > 0 || *name == "Data")
+            .map(|(name, count)| format!("{name:<15}{count}"))
+            .collect();
+        lines.push(format!("Base switches  {} linear, {} segment", self.linear_switches, self.segment_switches));
+        /* The most common lengths first, which is usually the one the generator writes with a few shorter ones at the ends of ranges */
+        let mut lengths: Vec<(&usize, &usize)> = self.data_lengths.iter().collect();
+        lengths.sort_by_key(|(length, count)| (std::cmp::Reverse(**count), **length));
+        let lengths: Vec<String> = lengths.iter().map(|(length, count)| format!("{length} bytes x {count}")).collect();
+        lines.push(format!("Data lengths   {}", if lengths.is_empty() {"none".to_string()} else {lengths.join(", ")}));
+        lines
+    }
+}
+
+/* The counting of RecordStats::from_contents, fed a line at a time so a file can be counted as it is streamed */
+#[derive(Debug, Default)]
+pub struct RecordCounter {
+    stats: RecordStats,
+    records: RecordLines,
+    linear: u16,
+    segment: u16,
+}
+
+impl RecordCounter {
+    /* Takes the text of a line, without its line ending */
+    pub fn line(&mut self, text: &str) {
+        let Some(record) = self.records.record(text) else {
+            return;
+        };
+        let stats = &mut self.stats;
+        match record {
+            Ok(Record::Data { value, .. }) => {
+                stats.data += 1;
+                *stats.data_lengths.entry(value.len()).or_default() += 1;
+            },
+            Ok(Record::EndOfFile) => stats.end_of_file += 1,
+            Ok(Record::ExtendedSegmentAddress(addr)) => {
+                stats.extended_segment += 1;
+                stats.segment_switches += (addr != self.segment) as usize;
+                (self.segment, self.linear) = (addr, 0);
+            },
+            Ok(Record::StartSegmentAddress { .. }) => stats.start_segment += 1,
+            Ok(Record::ExtendedLinearAddress(addr)) => {
+                stats.extended_linear += 1;
+                stats.linear_switches += (addr != self.linear) as usize;
+                (self.linear, self.segment) = (addr, 0);
+            },
+            Ok(Record::StartLinearAddress(_)) => stats.start_linear += 1,
+            Err(_) => stats.unreadable += 1,
+        }
+    }
+
+    /* The counts once every line has been seen */
+    pub fn finish(self) -> RecordStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hex_loader::records_in;
+    use crate::record_stats::{RecordOrder, RecordStats};
+
+    #[test]
+    fn test_record_order() -> Result<(),String> {
+        /* 0x10-0x13, back to 0x0 on line 2, up to 0x20 and back to 0x1e on line 4, which overlaps */
+        let contents = ":0400100001020304E2\n:0400000001020304F2\n:0400200001020304D2\n:04001E0001020304D4\n:00000001FF\n";
+        let order = RecordOrder::from_records(&records_in(contents, 0, 1 << 32));
+        assert_eq!(vec![
+            "Data records   4",
+            "Record order   2 backward jumps",
+            "Largest jump   line 2 goes back 20 bytes, from 0x00000014 to 0x00000000",
+        ], order.summary_lines());
+        let contents = ":0400000001020304F2\n:0400100001020304E2\n:00000001FF\n";
+        assert_eq!(0, RecordOrder::from_records(&records_in(contents, 0, 1 << 32)).backward_jumps);
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_stats() -> Result<(),String> {
+        /* Two linear bases, the second repeated, and a record with a bad checksum */
+        let contents = ":020000040800F2\n:0400000001020304F2\n:020000040801F1\n:020000040801F1\n:0400100001020304E2\n\
+                        :020020000102DB\n:0400300001020304FF\n:00000001FF\n";
+        assert_eq!(vec![
+            "Data           3",
+            "End of file    1",
+            "Ext. linear    3",
+            "Unreadable     1",
+            "Base switches  2 linear, 0 segment",
+            "Data lengths   4 bytes x 2, 2 bytes x 1",
+        ], RecordStats::from_contents(contents).summary_lines());
+        Ok(())
+    }
+}
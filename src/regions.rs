@@ -0,0 +1,252 @@
+use std::fmt::Write;
+
+/**
+ * A named address range loaded from (or saved to) a region file. Region files label parts of the map and come in
+ * two equivalent flavours, picked by file extension:
+ *
+ * TOML (.toml)              YAML (.yaml / .yml)
+ *   [[region]]                region:
+ *   name = "bootloader"         - name: bootloader
+ *   start = 0x08000000            start: 0x08000000
+ *   size = 0x8000                 size: 0x8000
+ *   budget = 24K                  budget: 24K
+ *
+ * The budget is optional and caps how many bytes of the region may hold data, for --budgets to check.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub start: u32,
+    pub size: u32,
+    pub budget: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionFormat {
+    Toml,
+    Yaml,
+}
+
+impl RegionFormat {
+    pub fn from_path(path: &str) -> RegionFormat {
+        if path.ends_with(".yaml") || path.ends_with(".yml") {RegionFormat::Yaml} else {RegionFormat::Toml}
+    }
+}
+
+/* Names each occupied range so the generated file can be loaded straight back in and renamed by hand */
+pub fn regions_from_ranges(ranges: &[(u64, u64)]) -> Vec<Region> {
+    ranges.iter()
+        .enumerate()
+        .map(|(i, (start, end))| Region { name: format!("used{i}"), start: *start as u32, size: (end - start) as u32, budget: None })
+        .collect()
+}
+
+/* A name as a double quoted string, which TOML and YAML read the same, with its quotes and backslashes escaped */
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn write_regions(regions: &[Region], format: RegionFormat) -> String {
+    let mut out = String::new();
+    match format {
+        RegionFormat::Toml => {
+            for region in regions {
+                writeln!(out, "[[region]]").unwrap();
+                writeln!(out, "name = {}", quote(&region.name)).unwrap();
+                writeln!(out, "start = {:#010x}", region.start).unwrap();
+                writeln!(out, "size = {:#x}", region.size).unwrap();
+                if let Some(budget) = region.budget {
+                    writeln!(out, "budget = {budget:#x}").unwrap();
+                }
+                writeln!(out).unwrap();
+            }
+        },
+        RegionFormat::Yaml => {
+            writeln!(out, "region:").unwrap();
+            for region in regions {
+                writeln!(out, "  - name: {}", quote(&region.name)).unwrap();
+                writeln!(out, "    start: {:#010x}", region.start).unwrap();
+                writeln!(out, "    size: {:#x}", region.size).unwrap();
+                if let Some(budget) = region.budget {
+                    writeln!(out, "    budget: {budget:#x}").unwrap();
+                }
+            }
+        },
+    }
+    out
+}
+
+/* The names of every region which starts within the given line, if any */
+pub fn line_label(regions: &[Region], line_start: u32, line_len: u32) -> Option<String> {
+    let names: Vec<&str> = regions.iter()
+        .filter(|r| r.start >= line_start && r.start - line_start < line_len)
+        .map(|r| r.name.as_str())
+        .collect();
+    if names.is_empty() {None} else {Some(names.join(", "))}
+}
+
+/* A decimal or 0x prefixed hex number, which may end in K or M for a size */
+fn parse_number(value: &str) -> Result<u32, String> {
+    let value = value.trim().replace('_', "");
+    let (digits, multiplier) = match value.strip_suffix(['k', 'K']) {
+        Some(digits) => (digits, 1024),
+        None => value.strip_suffix(['m', 'M']).map_or((value.as_str(), 1), |digits| (digits, 1024 * 1024)),
+    };
+    let parsed = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => digits.parse::<u32>(),
+    };
+    parsed.map_err(|e| format!("Invalid number '{value}': {e}"))?
+        .checked_mul(multiplier)
+        .ok_or(format!("Invalid number '{value}': too large"))
+}
+
+/* The fields of a region as they are read, checked once the region is complete */
+#[derive(Default)]
+struct RegionFields {
+    name: Option<String>,
+    start: Option<u32>,
+    size: Option<u32>,
+    budget: Option<u32>,
+}
+
+/* A string value: double quoted with backslash escapes, single quoted as it is, or bare */
+fn parse_string(value: &str) -> String {
+    let value = value.trim();
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut text = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(escaped) => text.push(escaped),
+                    None => break,
+                },
+                c => text.push(c),
+            }
+        }
+        return text;
+    }
+    match value.strip_prefix('\'') {
+        Some(rest) => rest.split('\'').next().unwrap_or("").to_string(),
+        None => value.to_string(),
+    }
+}
+
+/* A line without its comment, a # outside any quoted string */
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            },
+            (Some(open), c) if c == open && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..idx],
+            _ => {},
+        }
+        escaped = false;
+    }
+    line
+}
+
+/**
+ * Reads a region file in either format. Only the subset of TOML/YAML produced by write_regions is understood: one
+ * key/value pair per line, # comments, and a new region started by [[region]] or a "- " list item.
+ */
+pub fn parse_regions(contents: &str, format: RegionFormat) -> Result<Vec<Region>, String> {
+    /* Fields are collected as they are seen and validated once the region is complete */
+    let mut partial: Vec<RegionFields> = Vec::new();
+
+    for (line_idx, raw_line) in contents.lines().enumerate() {
+        let line = strip_comment(raw_line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let key_value = match format {
+            RegionFormat::Toml => {
+                if line == "[[region]]" {
+                    partial.push(RegionFields::default());
+                    continue;
+                }
+                line.split_once('=')
+            },
+            RegionFormat::Yaml => {
+                if line == "region:" {
+                    continue;
+                }
+                let line = match line.strip_prefix("- ") {
+                    Some(rest) => {
+                        partial.push(RegionFields::default());
+                        rest
+                    },
+                    None => line,
+                };
+                line.split_once(':')
+            },
+        };
+
+        let (key, value) = key_value.ok_or(format!("Line {}: expected a key and value", line_idx + 1))?;
+        let current = partial.last_mut().ok_or(format!("Line {}: value outside of a region", line_idx + 1))?;
+        match key.trim() {
+            "name" => current.name = Some(parse_string(value)),
+            "start" => current.start = Some(parse_number(value).map_err(|e| format!("Line {}: {e}", line_idx + 1))?),
+            "size" => current.size = Some(parse_number(value).map_err(|e| format!("Line {}: {e}", line_idx + 1))?),
+            "budget" => current.budget = Some(parse_number(value).map_err(|e| format!("Line {}: {e}", line_idx + 1))?),
+            other => return Err(format!("Line {}: unknown key '{other}'", line_idx + 1)),
+        }
+    }
+
+    partial.into_iter()
+        .enumerate()
+        .map(|(i, fields)| match fields {
+            RegionFields { name: Some(name), start: Some(start), size: Some(size), budget } => Ok(Region { name, start, size, budget }),
+            _ => Err(format!("Region {i} needs a name, start and size")),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regions::{Region, RegionFormat, parse_regions, regions_from_ranges, write_regions};
+
+    #[test]
+    fn test_round_trip() -> Result<(),String> {
+        let mut regions = regions_from_ranges(&[(0x08000000, 0x08002000), (0x08008000, 0x08008100)]);
+        assert_eq!(Region { name: "used1".to_string(), start: 0x08008000, size: 0x100, budget: None }, regions[1]);
+        regions[0].budget = Some(0x1800);
+
+        for format in [RegionFormat::Toml, RegionFormat::Yaml] {
+            assert_eq!(regions, parse_regions(&write_regions(&regions, format), format)?);
+        }
+
+        /* Names typed in the viewer can hold anything, including what the formats treat specially */
+        let named = vec![Region { name: "boot #2 \"v1\" C:\\fw: 'x'".to_string(), start: 0x08000000, size: 0x100, budget: None }];
+        for format in [RegionFormat::Toml, RegionFormat::Yaml] {
+            assert_eq!(named, parse_regions(&write_regions(&named, format), format)?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_hand_edited() -> Result<(),String> {
+        let toml = "# Bootloader first\n[[region]]\nname = 'boot'  # renamed\nstart = 0x0800_0000\nsize = 32768\n";
+        assert_eq!(vec![Region { name: "boot".to_string(), start: 0x08000000, size: 0x8000, budget: None }], parse_regions(toml, RegionFormat::Toml)?);
+
+        let yaml = "region:\n  - name: app\n    start: 0x08008000\n    size: 448K\n    budget: 400K\n";
+        assert_eq!(vec![Region { name: "app".to_string(), start: 0x08008000, size: 0x70000, budget: Some(0x64000) }], parse_regions(yaml, RegionFormat::Yaml)?);
+
+        assert!(parse_regions("[[region]]\nname = \"x\"\n", RegionFormat::Toml).is_err());
+        assert!(parse_regions("region:\n  - name: x\n    colour: red\n", RegionFormat::Yaml).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,717 @@
+use crate::devices::{Flash, SectorLayout};
+use crate::{clip_ranges, free_ranges, occupied_ranges, reference_mismatches, subtract_ranges, SegmentMap, ValueMap, IHEX_SEGMENT_BYTES};
+use crate::hex_loader::SourceRecord;
+use crate::hex_check::Issue;
+use crate::regions::Region;
+use crate::stats::PADDING_RUN;
+use crate::structured::{range_value, ranges_value, Value};
+
+/* An inclusive address span as reports print it, e.g. 0x08000000-0x08003fff */
+fn span_text(start: u64, end: u64) -> String {
+    format!("{start:#010x}-{:#010x}", end - 1)
+}
+
+/**
+ * The largest free ranges within [start, end), biggest first (ties in address order), under a heading naming the
+ * window they were searched in.
+ */
+pub fn largest_free(ranges: &[(u64, u64)], start: u64, end: u64, count: usize) -> Vec<String> {
+    let mut gaps = free_ranges(ranges, start, end);
+    gaps.sort_by_key(|(gap_start, gap_end)| (std::cmp::Reverse(gap_end - gap_start), *gap_start));
+    let mut lines = vec![format!("Largest free ranges of {}", span_text(start, end.max(start + 1)))];
+    if gaps.is_empty() {
+        lines.push("  None, everything is used".to_string());
+    }
+    lines.extend(gaps.iter()
+        .take(count)
+        .map(|(gap_start, gap_end)| format!("  {}  {} bytes", span_text(*gap_start, *gap_end), gap_end - gap_start)));
+    lines
+}
+
+/* Every occupied range exactly, byte for byte rather than at the granularity of the map, in address order */
+pub fn range_list(ranges: &[(u64, u64)]) -> Vec<String> {
+    let mut lines = vec![format!("Occupied ranges: {}", ranges.len())];
+    lines.extend(ranges.iter().map(|(start, end)| format!("  {}  {} bytes", span_text(*start, *end), end - start)));
+    lines
+}
+
+/* The problems found in a file, one per line */
+pub fn issue_list(issues: &[Issue]) -> Vec<String> {
+    let mut lines = vec![format!("Problems: {}", issues.len())];
+    lines.extend(issues.iter().map(|issue| format!("  {issue}")));
+    lines
+}
+
+/* Every free range within [start, end) of at least min_size bytes, in address order */
+pub fn gap_list(ranges: &[(u64, u64)], start: u64, end: u64, min_size: u64) -> Vec<String> {
+    let gaps: Vec<(u64, u64)> = free_ranges(ranges, start, end).into_iter()
+        .filter(|(gap_start, gap_end)| gap_end - gap_start >= min_size)
+        .collect();
+    let bigger = if min_size > 1 {format!(" of {min_size} bytes or more")} else {String::new()};
+    let mut lines = vec![format!("Free ranges{bigger} in {}: {}", span_text(start, end.max(start + 1)), gaps.len())];
+    lines.extend(gaps.iter().map(|(gap_start, gap_end)| format!("  {}  {} bytes", span_text(*gap_start, *gap_end), gap_end - gap_start)));
+    lines
+}
+
+/* Whether the data fits in the flash and how much room is left, as "name  value" lines to follow the --stats ones */
+pub fn fit_lines(ranges: &[(u64, u64)], flash: &Flash) -> Vec<String> {
+    let (start, end) = (flash.start as u64, flash.end());
+    let mut inside = 0;
+    let mut outside = 0;
+    let mut first_outside = None;
+    for &(range_start, range_end) in ranges {
+        let overlap = range_end.min(end).saturating_sub(range_start.max(start));
+        inside += overlap;
+        outside += range_end - range_start - overlap;
+        if overlap < range_end - range_start && first_outside.is_none() {
+            first_outside = Some(if range_start < start {range_start} else {range_start.max(end)});
+        }
+    }
+    let fits = match first_outside {
+        None => {
+            let free = flash.size as u64 - inside;
+            format!("yes, {free} bytes ({:.1}%) free", free as f64 * 100.0 / (flash.size as f64).max(1.0))
+        },
+        Some(first) => format!("no, {outside} bytes outside, the first at {first:#010x}"),
+    };
+    vec![
+        format!("Flash          {} {} ({} bytes)", flash.name, span_text(start, end.max(start + 1)), flash.size),
+        format!("Fits           {fits}"),
+    ]
+}
+
+/* A titled list of ranges with their total size, one range per line */
+fn titled_ranges(title: &str, ranges: &[(u64, u64)]) -> Vec<String> {
+    let bytes: u64 = ranges.iter().map(|(start, end)| end - start).sum();
+    let mut lines = vec![format!("{title}: {} ranges, {bytes} bytes", ranges.len())];
+    lines.extend(ranges.iter().map(|(start, end)| format!("  {}  {} bytes", span_text(*start, *end), end - start)));
+    lines
+}
+
+/**
+ * What changed from one image to the next: the ranges only the new one holds, those only the old one held and, when
+ * the values were kept, those holding different data in both.
+ */
+pub fn diff_list(added: &[(u64, u64)], removed: &[(u64, u64)], changed: Option<&[(u64, u64)]>) -> Vec<String> {
+    let mut lines = titled_ranges("Added", added);
+    lines.extend(titled_ranges("Removed", removed));
+    match changed {
+        Some(changed) => lines.extend(titled_ranges("Changed", changed)),
+        None => lines.push("Changed: not compared, pass --values to compare the data".to_string()),
+    }
+    lines
+}
+
+/* The same as diff_list for --format json and yaml, with changed null when the values were not compared */
+pub fn diff_value(added: &[(u64, u64)], removed: &[(u64, u64)], changed: Option<&[(u64, u64)]>) -> Value {
+    Value::object([("added", ranges_value(added)), ("removed", ranges_value(removed)), ("changed", changed.map(ranges_value).into())])
+}
+
+/**
+ * How an image covers a reference binary loaded at [start, end): the parts of the reference it leaves out, the data it
+ * holds outside the reference and, with the values kept, the data differing from the reference.
+ */
+pub fn reference_lines(reference: &str, start: u64, end: u64, missing: &[(u64, u64)], extra: &[(u64, u64)], differing: &[(u64, u64)]) -> Vec<String> {
+    let covered = if end > start {span_text(start, end)} else {"nothing".to_string()};
+    let mut lines = vec![format!("Reference {reference} at {covered} ({} bytes)", end - start)];
+    if missing.is_empty() && extra.is_empty() && differing.is_empty() {
+        lines.push("The image matches the reference byte for byte".to_string());
+        return lines;
+    }
+    lines.extend(titled_ranges("Missing from the image", missing));
+    lines.extend(titled_ranges("Only in the image", extra));
+    lines.extend(titled_ranges("Different from the reference", differing));
+    lines
+}
+
+/**
+ * Compares an image with a flat binary placed at base, as reference_lines reports it. A reference running past the end
+ * of the 32 bit address space cannot be placed, and is an error.
+ */
+pub fn reference_report(reference: &str, segment_map: &SegmentMap, values: &ValueMap, data: &[u8], base: u32) -> Result<Vec<String>, String> {
+    let end = base as u64 + data.len() as u64;
+    if end > 1 << 32 {
+        return Err(format!("{reference} at {base:#010x} runs past the end of the 32 bit address space ({} bytes)", data.len()));
+    }
+    let ranges = occupied_ranges(segment_map);
+    let missing = free_ranges(&ranges, base as u64, end);
+    let extra = subtract_ranges(&ranges, &[(base as u64, end)]);
+    let differing = reference_mismatches(segment_map, values, data, base);
+    Ok(reference_lines(reference, base as u64, end, &missing, &extra, &differing))
+}
+
+/**
+ * The outcome of the --max-used check for one file, or the --budgets check for one region of it, as one line of
+ * key=value pairs for scripts to pick up. The path comes last so any spaces in it stay in its value.
+ *
+ *   budget result=pass used=12288 limit=491520 percent=2.5 file=build/app.hex
+ *   budget result=fail region=bootloader used=33024 limit=32768 percent=100.8 file=build/app.hex
+ */
+pub fn budget_line(path: &str, region: Option<&str>, used: u64, limit: u64) -> String {
+    let result = if used > limit {"fail"} else {"pass"};
+    let region = region.map_or(String::new(), |name| format!(" region={}", name.replace(' ', "_")));
+    let percent = used as f64 * 100.0 / (limit as f64).max(1.0);
+    format!("budget result={result}{region} used={used} limit={limit} percent={percent:.1} file={path}")
+}
+
+/* The fingerprints of the flat image of [start, end), as "name  value" lines under a heading saying what they cover */
+pub fn checksum_lines(start: u64, end: u64, fill_byte: u8, crc: u32, sha: &[u8; 32]) -> Vec<String> {
+    let covered = if end > start {span_text(start, end)} else {"nothing".to_string()};
+    vec![
+        format!("Checksums of {covered} ({} bytes), gaps filled with {fill_byte:#04x}", end - start),
+        format!("  CRC-32         {crc:#010x}"),
+        format!("  SHA-256        {}", sha.iter().map(|byte| format!("{byte:02x}")).collect::<String>()),
+    ]
+}
+
+/* One line per page from checksums::page_crcs, for comparing with what a bootloader logs */
+pub fn page_crc_list(pages: &[(u64, u64, u32)], page_size: u32, fill_byte: u8) -> Vec<String> {
+    let mut lines = vec![format!("Page CRC-32s of {page_size} byte pages, gaps filled with {fill_byte:#04x}: {}", pages.len())];
+    lines.extend(pages.iter().map(|(start, end, crc)| format!("  {}  {crc:#010x}", span_text(*start, *end))));
+    lines
+}
+
+/**
+ * What programming the image costs in erases: the sectors holding data, joined into spans, and against how many bytes
+ * of data. Then the ranges which straddle a sector boundary although they would fit in one sector, and the sectors
+ * holding the end of one range and the start of the next, which cannot be erased to update one without the other.
+ */
+pub fn sector_lines(ranges: &[(u64, u64)], layout: &SectorLayout) -> Vec<String> {
+    let sectors = layout.sectors_of(ranges);
+    /* The ranges with the sectors they start and end in */
+    let range_sectors: Vec<_> = ranges.iter()
+        .map(|(start, end)| (*start, *end, layout.sector_at(*start), layout.sector_at(*end - 1)))
+        .collect();
+    let erase_bytes: u64 = sectors.iter().map(|(start, end)| end - start).sum();
+    let data_bytes: u64 = ranges.iter().map(|(start, end)| end - start).sum();
+    let mut spans: Vec<(u64, u64, usize)> = Vec::new();
+    for (start, end) in &sectors {
+        match spans.last_mut() {
+            Some(span) if span.1 == *start => (span.1, span.2) = (*end, span.2 + 1),
+            _ => spans.push((*start, *end, 1)),
+        }
+    }
+    let mut lines = vec![format!("Sectors to erase: {}, {erase_bytes} bytes for {data_bytes} bytes of data", sectors.len())];
+    lines.extend(spans.iter().map(|(start, end, count)| {
+        format!("  {}  {count} {}", span_text(*start, *end), if *count == 1 {"sector"} else {"sectors"})
+    }));
+
+    let straddling: Vec<String> = range_sectors.iter()
+        .filter(|(start, end, first, last)| first != last && end - start <= first.1 - first.0)
+        .map(|(start, end, _, _)| format!("  {}  {} bytes over 2 sectors, would fit in 1 if aligned", span_text(*start, *end), end - start))
+        .collect();
+    lines.push(format!("Straddling ranges: {}", straddling.len()));
+    lines.extend(straddling);
+
+    let shared: Vec<String> = range_sectors.windows(2)
+        .filter(|pair| pair[0].3 == pair[1].2)
+        .map(|pair| format!("  {}  shared by {} and {}", span_text(pair[0].3.0, pair[0].3.1),
+                            span_text(pair[0].0, pair[0].1), span_text(pair[1].0, pair[1].1)))
+        .collect();
+    lines.push(format!("Shared sectors: {}", shared.len()));
+    lines.extend(shared);
+    lines
+}
+
+/**
+ * The sectors to erase and program again to update a device from one image to the next, from the ranges which differ
+ * between them. Sectors where the new image holds no data only need erasing.
+ */
+pub fn delta_plan(differences: &[(u64, u64)], new_ranges: &[(u64, u64)], layout: &SectorLayout) -> Vec<String> {
+    let mut differences = differences.to_vec();
+    differences.sort();
+    let sectors = layout.sectors_of(&differences);
+    let rewrite_bytes: u64 = sectors.iter().map(|(start, end)| end - start).sum();
+    let new_sectors = layout.sectors_of(new_ranges).len();
+    let mut lines = vec![format!("Sectors to update: {}, {rewrite_bytes} bytes (the new image fills {new_sectors})", sectors.len())];
+    lines.extend(sectors.iter().map(|(start, end)| {
+        let holds_data = new_ranges.iter().any(|(range_start, range_end)| range_start < end && range_end > start);
+        format!("  {}  {}", span_text(*start, *end), if holds_data {"erase and write"} else {"erase only"})
+    }));
+    lines
+}
+
+/**
+ * One row per 64K page holding data: its number, the bytes used and how much of the page that is, and the first and
+ * last used address, showing at a glance which banks of a multi-bank part are populated.
+ */
+pub fn page_table(ranges: &[(u64, u64)]) -> Vec<String> {
+    let page_bytes = IHEX_SEGMENT_BYTES as u64;
+    /* Page, bytes used, first and last used address */
+    let mut pages: Vec<(u64, u64, u64, u64)> = Vec::new();
+    for &(mut start, end) in ranges {
+        while start < end {
+            let page = start / page_bytes;
+            let piece_end = end.min((page + 1) * page_bytes);
+            match pages.last_mut() {
+                Some(last) if last.0 == page => (last.1, last.3) = (last.1 + piece_end - start, piece_end - 1),
+                _ => pages.push((page, piece_end - start, start, piece_end - 1)),
+            }
+            start = piece_end;
+        }
+    }
+    let mut lines = vec![
+        format!("Pages: {}", pages.len()),
+        "  Page    Used   Fill    First       Last".to_string(),
+    ];
+    lines.extend(pages.iter().map(|(page, used, first, last)| {
+        format!("  {page:#06x}  {used:>5}  {:>5.1}%  {first:#010x}  {last:#010x}", *used as f64 * 100.0 / page_bytes as f64)
+    }));
+    lines
+}
+
+/* Where a pattern was found, one match per line with the region it falls in if any */
+pub fn match_list(text: &str, found: &[(u64, u64)], labels: &[Region]) -> Vec<String> {
+    let mut lines = vec![format!("Matches of {text}: {}", found.len())];
+    lines.extend(found.iter().map(|(start, end)| {
+        let region = labels.iter().find(|region| region.start as u64 <= *start && *start < region.start as u64 + region.size as u64);
+        let line = format!("  {}", span_text(*start, *end));
+        region.map_or(line.clone(), |region| format!("{line}  in {}", region.name))
+    }));
+    lines
+}
+
+/* Line numbers as a short list, or as the first and last when there are many */
+fn record_lines_text(records: &[SourceRecord]) -> String {
+    let lines: Vec<String> = records.iter().map(|record| record.line.to_string()).collect();
+    match lines.len() {
+        1 => format!("line {}", lines[0]),
+        2..=5 => format!("lines {}", lines.join(", ")),
+        count => format!("{count} records, lines {} to {}", lines[0], lines[count - 1]),
+    }
+}
+
+/* The names of the regions holding all of [start, end) */
+fn region_names(labels: &[Region], start: u32, end: u64) -> Vec<&str> {
+    labels.iter()
+        .filter(|region| region.start as u64 <= start as u64 && end <= region.start as u64 + region.size as u64)
+        .map(|region| region.name.as_str())
+        .collect()
+}
+
+/**
+ * The same answer as query_lines for --format json and yaml: the occupied ranges within [start, end), the occupied
+ * range or free gap holding start, the regions holding it all and the lines of the records which wrote it.
+ */
+pub fn query_value(start: u32, end: u64, ranges: &[(u64, u64)], records: &[SourceRecord], labels: &[Region]) -> Value {
+    let clipped = clip_ranges(ranges, start as u64, end);
+    let used: u64 = clipped.iter().map(|(start, end)| end - start).sum();
+    let in_range = ranges.iter()
+        .find(|(range_start, range_end)| (*range_start..*range_end).contains(&(start as u64)))
+        .map(|(range_start, range_end)| range_value(*range_start, *range_end));
+    let in_gap = free_ranges(ranges, 0, 1 << 32).into_iter()
+        .find(|(gap_start, gap_end)| (*gap_start..*gap_end).contains(&(start as u64)))
+        .map(|(gap_start, gap_end)| range_value(gap_start, gap_end));
+    Value::object([
+        ("start", start.into()),
+        ("end", end.into()),
+        ("occupied_bytes", used.into()),
+        ("ranges", ranges_value(&clipped)),
+        ("in_range", in_range.into()),
+        ("in_gap", in_gap.into()),
+        ("regions", region_names(labels, start, end).into()),
+        ("lines", records.iter().map(|record| record.line).collect::<Vec<_>>().into()),
+    ])
+}
+
+/**
+ * Answers a query about [start, end): whether it holds data, the range or gap it falls in, any region containing it
+ * and the records which wrote it (from hex_loader::records_in), last writer last.
+ */
+pub fn query_lines(start: u32, end: u64, ranges: &[(u64, u64)], records: &[SourceRecord], labels: &[Region]) -> Vec<String> {
+    let clipped = clip_ranges(ranges, start as u64, end);
+    let used: u64 = clipped.iter().map(|(start, end)| end - start).sum();
+    let mut line = if end - start as u64 == 1 {
+        let text = format!("{start:#010x}");
+        match ranges.iter().find(|(range_start, range_end)| (*range_start..*range_end).contains(&(start as u64))) {
+            Some((range_start, range_end)) => format!("{text}  occupied, in the range {} ({} bytes)", span_text(*range_start, *range_end), range_end - range_start),
+            None => match free_ranges(ranges, 0, 1 << 32).into_iter().find(|(gap_start, gap_end)| (*gap_start..*gap_end).contains(&(start as u64))) {
+                _ if ranges.is_empty() => format!("{text}  free, the file holds no data"),
+                Some((0, _)) => format!("{text}  free, before the data"),
+                Some((_, 0x1_0000_0000)) => format!("{text}  free, after the data"),
+                Some((gap_start, gap_end)) => format!("{text}  free, in the gap {} ({} bytes)", span_text(gap_start, gap_end), gap_end - gap_start),
+                None => format!("{text}  free"),
+            },
+        }
+    } else {
+        let count = if clipped.len() == 1 {"1 range".to_string()} else {format!("{} ranges", clipped.len())};
+        format!("{}  {used} of {} bytes occupied, in {count}", span_text(start as u64, end), end - start as u64)
+    };
+    let names = region_names(labels, start, end);
+    if !names.is_empty() {
+        line.push_str(&format!(", in {}", names.join(", ")));
+    }
+    let mut lines = vec![line];
+    match records {
+        [] => {},
+        [.., last] if end - start as u64 == 1 && records.len() > 1 => {
+            lines.push(format!("  Written by line {}, overwriting {}", last.line, record_lines_text(&records[..records.len() - 1])));
+        },
+        _ => lines.push(format!("  Written by {}", record_lines_text(records))),
+    }
+    lines
+}
+
+/* The strings found in the data, one per line with its address, quoted and escaped as Rust would */
+pub fn string_list(strings: &[(u32, String)], min_len: usize) -> Vec<String> {
+    let mut lines = vec![format!("Strings of {min_len} characters or more: {}", strings.len())];
+    lines.extend(strings.iter().map(|(addr, text)| format!("  {addr:#010x}  {text:?}")));
+    lines
+}
+
+/* Shades for how often a byte value occurs, on a log scale so rare values still show next to the fill byte */
+const HISTOGRAM_LEVELS: [char; 9] = ['·', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/**
+ * A 256 bucket histogram of byte values as a 16 by 16 grid, rows by high nibble and columns by low nibble, after a line
+ * summing it up and one with the most common values, or just the first line when there are no bytes. Padding shows as one dark cell, text as a dark band from 0x20 to
+ * 0x7e and compressed or random data as an even grid.
+ */
+pub fn histogram_lines(title: &str, counts: &[u64; 256]) -> Vec<String> {
+    let total: u64 = counts.iter().sum();
+    let distinct = counts.iter().filter(|count| **count > 0).count();
+    let printable: u64 = counts[0x20..0x7F].iter().sum();
+    let percent = |count: u64| count as f64 * 100.0 / (total as f64).max(1.0);
+    let mut lines = vec![format!("{title}: {total} bytes, {distinct} distinct values, {:.1}% printable ASCII", percent(printable))];
+    if total == 0 {
+        return lines;
+    }
+    let mut common: Vec<usize> = (0..256).filter(|value| counts[*value] > 0).collect();
+    common.sort_by_key(|value| (std::cmp::Reverse(counts[*value]), *value));
+    let top: Vec<String> = common.iter().take(5).map(|value| format!("{value:#04x} {:.1}%", percent(counts[*value]))).collect();
+    lines.push(format!("  Most common  {}", top.join(", ")));
+    let max = counts.iter().max().cloned().unwrap_or(0);
+    lines.push(format!("        {}", (0..16).map(|low| format!("{low:x}")).collect::<Vec<_>>().join(" ")));
+    for high in 0..16 {
+        let cells: Vec<String> = counts[high * 16..high * 16 + 16].iter()
+            .map(|count| match *count {
+                0 => HISTOGRAM_LEVELS[0],
+                _ if max <= 1 => HISTOGRAM_LEVELS[8],
+                count => HISTOGRAM_LEVELS[1 + ((count as f64).ln() * 7.0 / (max as f64).ln()).round() as usize],
+            }.to_string())
+            .collect();
+        lines.push(format!("  {:#04x}  {}", high * 16, cells.join(" ")));
+    }
+    lines
+}
+
+/* How much of the data is padding, from stats::detect_padding, and how much is left as real contents */
+pub fn padding_lines(padding: Option<(u8, u64)>, bytes_used: u64) -> Vec<String> {
+    let percent = |bytes: u64| bytes as f64 * 100.0 / (bytes_used as f64).max(1.0);
+    let (byte_text, padding) = match padding {
+        Some((0xFF, bytes)) => ("0xff (erased flash)".to_string(), bytes),
+        Some((byte, bytes)) => (format!("{byte:#04x}"), bytes),
+        None => ("none found".to_string(), 0),
+    };
+    vec![
+        format!("Padding byte   {byte_text}"),
+        format!("Padding        {padding} bytes in runs of {} or more ({:.1}%)", PADDING_RUN, percent(padding)),
+        format!("Data           {} bytes ({:.1}%)", bytes_used - padding, percent(bytes_used - padding)),
+    ]
+}
+
+/* Compressed to this share of its size or more, data was most likely compressed or encrypted already */
+const INCOMPRESSIBLE: f64 = 0.95;
+
+/* How well each occupied range compresses, from stats::range_compression, after a line for the whole image */
+pub fn compression_list(sizes: &[(u64, u64, usize)], total: usize) -> Vec<String> {
+    let ratio = |bytes: u64, compressed: usize| compressed as f64 / (bytes as f64).max(1.0);
+    let bytes: u64 = sizes.iter().map(|(start, end, _)| end - start).sum();
+    let mut lines = vec![format!("Compressed with DEFLATE: {bytes} bytes to {total} ({:.1}%)", ratio(bytes, total) * 100.0)];
+    lines.extend(sizes.iter().map(|(start, end, compressed)| {
+        let ratio = ratio(end - start, *compressed);
+        let line = format!("  {}  {} bytes to {compressed} ({:.1}%)", span_text(*start, *end), end - start, ratio * 100.0);
+        if ratio >= INCOMPRESSIBLE {format!("{line}  already compressed or encrypted")} else {line}
+    }));
+    lines
+}
+
+/* Entropy in bits per byte at or above which data looks compressed or encrypted, and below which it looks like fill */
+const HIGH_ENTROPY: f64 = 7.0;
+const LOW_ENTROPY: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntropyClass {
+    High,
+    Medium,
+    Low,
+}
+
+impl EntropyClass {
+    fn of(entropy: f64) -> EntropyClass {
+        if entropy >= HIGH_ENTROPY {
+            EntropyClass::High
+        } else if entropy < LOW_ENTROPY {
+            EntropyClass::Low
+        } else {
+            EntropyClass::Medium
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            EntropyClass::High => "high, likely compressed or encrypted",
+            EntropyClass::Medium => "medium, likely code or data",
+            EntropyClass::Low => "low, likely padding or tables",
+        }
+    }
+}
+
+/**
+ * Sorts the blocks from stats::block_entropies into high, medium and low entropy, joining neighbouring blocks of the
+ * same kind into one region with their average entropy, in address order.
+ */
+pub fn entropy_list(blocks: &[(u64, u64, f64)]) -> Vec<String> {
+    /* [start, end), the sum of entropy times bytes and the kind of each region */
+    let mut regions: Vec<(u64, u64, f64, EntropyClass)> = Vec::new();
+    for (start, end, entropy) in blocks {
+        let class = EntropyClass::of(*entropy);
+        let weighted = entropy * (end - start) as f64;
+        match regions.last_mut() {
+            Some(region) if region.1 == *start && region.3 == class => (region.1, region.2) = (*end, region.2 + weighted),
+            _ => regions.push((*start, *end, weighted, class)),
+        }
+    }
+    let count = |class| regions.iter().filter(|region| region.3 == class).count();
+    let mut lines = vec![format!("Entropy regions: {} ({} high, {} low)", regions.len(), count(EntropyClass::High), count(EntropyClass::Low))];
+    lines.extend(regions.iter().map(|(start, end, weighted, class)| {
+        let bytes = end - start;
+        format!("  {}  {bytes} bytes  {:.2} bits per byte  {}", span_text(*start, *end), weighted / bytes as f64, class.label())
+    }));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::devices::{Flash, SectorLayout};
+    use crate::hex_loader::records_in;
+    use crate::regions::Region;
+    use crate::reports::{budget_line, checksum_lines, delta_plan, histogram_lines, match_list, page_table, query_lines, sector_lines, string_list, page_crc_list, diff_list, entropy_list, fit_lines, gap_list, largest_free, range_list, reference_report};
+    use crate::hex_loader::{load_hex, LoadOptions};
+
+    #[test]
+    fn test_largest_free() -> Result<(),String> {
+        let ranges = [(0x100, 0x200), (0x300, 0x400), (0x480, 0x500)];
+        assert_eq!(vec![
+            "Largest free ranges of 0x00000000-0x00000fff",
+            "  0x00000500-0x00000fff  2816 bytes",
+            "  0x00000000-0x000000ff  256 bytes",
+        ], largest_free(&ranges, 0x0, 0x1000, 2));
+        assert_eq!(vec![
+            "Largest free ranges of 0x00000100-0x000001ff",
+            "  None, everything is used",
+        ], largest_free(&ranges, 0x100, 0x200, 5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_list() -> Result<(),String> {
+        assert_eq!(vec![
+            "Occupied ranges: 2",
+            "  0x00000100-0x000001ff  256 bytes",
+            "  0x00000300-0x00000301  2 bytes",
+        ], range_list(&[(0x100, 0x200), (0x300, 0x302)]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_gap_list() -> Result<(),String> {
+        let ranges = [(0x100, 0x200), (0x300, 0x400), (0x480, 0x500)];
+        assert_eq!(vec![
+            "Free ranges in 0x00000100-0x000004ff: 2",
+            "  0x00000200-0x000002ff  256 bytes",
+            "  0x00000400-0x0000047f  128 bytes",
+        ], gap_list(&ranges, 0x100, 0x500, 0));
+        assert_eq!(vec![
+            "Free ranges of 256 bytes or more in 0x00000100-0x000004ff: 1",
+            "  0x00000200-0x000002ff  256 bytes",
+        ], gap_list(&ranges, 0x100, 0x500, 0x100));
+        Ok(())
+    }
+
+    #[test]
+    fn test_entropy_list() -> Result<(),String> {
+        /* The first two blocks join up, the gap before 0x400 keeps the last low block apart */
+        let blocks = [(0x0, 0x100, 7.5), (0x100, 0x200, 7.9), (0x200, 0x300, 5.0), (0x300, 0x380, 0.0), (0x400, 0x500, 1.0)];
+        assert_eq!(vec![
+            "Entropy regions: 4 (1 high, 2 low)",
+            "  0x00000000-0x000001ff  512 bytes  7.70 bits per byte  high, likely compressed or encrypted",
+            "  0x00000200-0x000002ff  256 bytes  5.00 bits per byte  medium, likely code or data",
+            "  0x00000300-0x0000037f  128 bytes  0.00 bits per byte  low, likely padding or tables",
+            "  0x00000400-0x000004ff  256 bytes  1.00 bits per byte  low, likely padding or tables",
+        ], entropy_list(&blocks));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fit_lines() -> Result<(),String> {
+        let flash = Flash { name: "part".to_string(), start: 0x1000, size: 0x1000, sectors: &[] };
+        assert_eq!(vec![
+            "Flash          part 0x00001000-0x00001fff (4096 bytes)",
+            "Fits           yes, 3840 bytes (93.8%) free",
+        ], fit_lines(&[(0x1000, 0x1080), (0x1f80, 0x2000)], &flash));
+        assert_eq!("Fits           no, 48 bytes outside, the first at 0x00002000", fit_lines(&[(0x1f00, 0x2010), (0x3000, 0x3020)], &flash)[1]);
+        assert_eq!("Fits           no, 16 bytes outside, the first at 0x00000ff0", fit_lines(&[(0xff0, 0x1010)], &flash)[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_list() -> Result<(),String> {
+        assert_eq!(vec![
+            "Added: 2 ranges, 272 bytes",
+            "  0x00000100-0x000001ff  256 bytes",
+            "  0x00000300-0x0000030f  16 bytes",
+            "Removed: 0 ranges, 0 bytes",
+            "Changed: 1 ranges, 1 bytes",
+            "  0x00000010-0x00000010  1 bytes",
+        ], diff_list(&[(0x100, 0x200), (0x300, 0x310)], &[], Some(&[(0x10, 0x11)])));
+        assert_eq!("Changed: not compared, pass --values to compare the data", diff_list(&[], &[], None)[2]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_budget_line() -> Result<(),String> {
+        assert_eq!("budget result=pass used=1024 limit=4096 percent=25.0 file=my app.hex", budget_line("my app.hex", None, 1024, 4096));
+        assert_eq!("budget result=fail used=4097 limit=4096 percent=100.0 file=a.hex", budget_line("a.hex", None, 4097, 4096));
+        assert_eq!("budget result=pass region=boot_loader used=0 limit=4096 percent=0.0 file=a.hex", budget_line("a.hex", Some("boot loader"), 0, 4096));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_lines() -> Result<(),String> {
+        assert_eq!(vec![
+            "Checksums of 0x00000100-0x000001ff (256 bytes), gaps filled with 0xff",
+            "  CRC-32         0x0000abcd",
+            "  SHA-256        0101010101010101010101010101010101010101010101010101010101010101",
+        ], checksum_lines(0x100, 0x200, 0xFF, 0xABCD, &[1; 32]));
+        assert_eq!("Checksums of nothing (0 bytes), gaps filled with 0x00", checksum_lines(0x100, 0x100, 0, 0, &[0; 32])[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_crc_list() -> Result<(),String> {
+        assert_eq!(vec![
+            "Page CRC-32s of 65536 byte pages, gaps filled with 0xff: 2",
+            "  0x08000000-0x0800ffff  0x12345678",
+            "  0x08010000-0x0801ffff  0x00000001",
+        ], page_crc_list(&[(0x0800_0000, 0x0801_0000, 0x1234_5678), (0x0801_0000, 0x0802_0000, 1)], 0x10000, 0xFF));
+        Ok(())
+    }
+
+    #[test]
+    fn test_histogram_lines() -> Result<(),String> {
+        let mut counts = [0u64; 256];
+        counts[0xFF] = 1000;
+        counts[0x41] = 10;
+        counts[0x00] = 1;
+        let lines = histogram_lines("Byte values", &counts);
+        assert_eq!("Byte values: 1011 bytes, 3 distinct values, 1.0% printable ASCII", lines[0]);
+        assert_eq!("  Most common  0xff 98.9%, 0x41 1.0%, 0x00 0.1%", lines[1]);
+        assert_eq!("        0 1 2 3 4 5 6 7 8 9 a b c d e f", lines[2]);
+        assert_eq!("  0x00  ▁ · · · · · · · · · · · · · · ·", lines[3]);
+        assert_eq!("  0x40  · ▃ · · · · · · · · · · · · · ·", lines[7]);
+        assert_eq!("  0xf0  · · · · · · · · · · · · · · · █", lines[18]);
+        assert_eq!(vec!["Nothing: 0 bytes, 0 distinct values, 0.0% printable ASCII"], histogram_lines("Nothing", &[0; 256]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_list() -> Result<(),String> {
+        let labels = [Region { name: "app".to_string(), start: 0x1000, size: 0x1000, budget: None }];
+        assert_eq!(vec![
+            "Matches of DEADBEEF: 2",
+            "  0x00000100-0x00000103",
+            "  0x00001ffe-0x00002001  in app",
+        ], match_list("DEADBEEF", &[(0x100, 0x104), (0x1ffe, 0x2002)], &labels));
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_lines() -> Result<(),String> {
+        /* Line 2 writes over the end of line 1, line 3 is after a gap */
+        let contents = ":0400100001020304E2\n:0400120001020304E0\n:0400200001020304D2\n:00000001FF\n";
+        let ranges = [(0x10, 0x16), (0x20, 0x24)];
+        let labels = [Region { name: "boot".to_string(), start: 0x0, size: 0x20, budget: None }];
+        assert_eq!(vec![
+            "0x00000013  occupied, in the range 0x00000010-0x00000015 (6 bytes), in boot",
+            "  Written by line 2, overwriting line 1",
+        ], query_lines(0x13, 0x14, &ranges, &records_in(contents, 0x13, 0x14), &labels));
+        assert_eq!(vec!["0x00000018  free, in the gap 0x00000016-0x0000001f (10 bytes), in boot"],
+                   query_lines(0x18, 0x19, &ranges, &records_in(contents, 0x18, 0x19), &labels));
+        assert_eq!(vec!["0x00000030  free, after the data"], query_lines(0x30, 0x31, &ranges, &[], &[]));
+        assert_eq!(vec![
+            "0x00000000-0x000000ff  10 of 256 bytes occupied, in 2 ranges",
+            "  Written by lines 1, 2, 3",
+        ], query_lines(0x0, 0x100, &ranges, &records_in(contents, 0x0, 0x100), &labels));
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_table() -> Result<(),String> {
+        /* The second range runs over into the next page */
+        assert_eq!(vec![
+            "Pages: 2",
+            "  Page    Used   Fill    First       Last",
+            "  0x0800    272    0.4%  0x08000000  0x0800ffff",
+            "  0x0801  65536  100.0%  0x08010000  0x0801ffff",
+        ], page_table(&[(0x0800_0000, 0x0800_0100), (0x0800_fff0, 0x0802_0000)]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sector_lines() -> Result<(),String> {
+        let layout = SectorLayout { base: 0, sectors: &[(2, 0x100), (1, 0x200)], uniform: 0x400 };
+        assert_eq!(vec![
+            "Sectors to erase: 4, 2048 bytes for 192 bytes of data",
+            "  0x00000000-0x000003ff  3 sectors",
+            "  0x00000800-0x00000bff  1 sector",
+            "Straddling ranges: 1",
+            "  0x000000f0-0x0000010f  32 bytes over 2 sectors, would fit in 1 if aligned",
+            "Shared sectors: 1",
+            "  0x00000100-0x000001ff  shared by 0x000000f0-0x0000010f and 0x00000180-0x0000019f",
+        ], sector_lines(&[(0xf0, 0x110), (0x180, 0x1a0), (0x300, 0x340), (0x800, 0x840)], &layout));
+        Ok(())
+    }
+
+    #[test]
+    fn test_delta_plan() -> Result<(),String> {
+        let layout = SectorLayout { base: 0, sectors: &[], uniform: 0x100 };
+        /* A change at 0x10, data moved from 0x300 to 0x480 */
+        let new_ranges = [(0x0, 0x200), (0x480, 0x490)];
+        assert_eq!(vec![
+            "Sectors to update: 3, 768 bytes (the new image fills 3)",
+            "  0x00000000-0x000000ff  erase and write",
+            "  0x00000300-0x000003ff  erase only",
+            "  0x00000400-0x000004ff  erase and write",
+        ], delta_plan(&[(0x480, 0x490), (0x10, 0x11), (0x300, 0x310)], &new_ranges, &layout));
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_list() -> Result<(),String> {
+        assert_eq!(vec![
+            "Strings of 4 characters or more: 2",
+            "  0x08001000  \"v1.2.3\"",
+            "  0x08001010  \"say \\\"hi\\\"\\t\"",
+        ], string_list(&[(0x0800_1000, "v1.2.3".to_string()), (0x0800_1010, "say \"hi\"\t".to_string())], 4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reference_at_top() -> Result<(),String> {
+        /* 8 bytes at 0xFFFFFFF0 and a 4 byte reference ending at the very top of the address space */
+        let image = load_hex(":02000004FFFFFC\n:08FFF0000102030405060708E5\n:00000001FF\n", LoadOptions { store_values: true, blank_byte: None });
+        let values = image.values.ok_or("Values were kept")?;
+        let lines = reference_report("r.bin", &image.segment_map, &values, &[0; 4], 0xFFFF_FFFC)?;
+        assert_eq!(vec![
+            "Reference r.bin at 0xfffffffc-0xffffffff (4 bytes)",
+            "Missing from the image: 1 ranges, 4 bytes",
+            "  0xfffffffc-0xffffffff  4 bytes",
+            "Only in the image: 1 ranges, 8 bytes",
+            "  0xfffffff0-0xfffffff7  8 bytes",
+            "Different from the reference: 0 ranges, 0 bytes",
+        ], lines);
+        assert!(reference_report("r.bin", &image.segment_map, &values, &[0; 4], 0xFFFF_FFFF).is_err());
+        Ok(())
+    }
+}
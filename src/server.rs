@@ -0,0 +1,255 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::error::Error;
+use crate::hex_loader::read_hex_file;
+use crate::structured::Value;
+use crate::wasm::browser_map;
+
+/* The page, the same one as the WebAssembly build uses. Served here it asks the server for the maps instead */
+const PAGE: &str = include_str!("../web/index.html");
+
+/* The most the request line and headers may take together, well past what a browser sends */
+const MAX_HEAD: u64 = 8 * 1024;
+
+/* The most a client may upload, a hex file of over a megabyte of data */
+const MAX_UPLOAD: usize = 4 * 1024 * 1024;
+
+/* How long a client may take over the whole of a request and response, however it trickles them */
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/* How many requests are answered at once. Connections past these wait to be accepted */
+const WORKERS: usize = 4;
+
+/* What the server shows: the files, read from disk again for every request so the page follows rebuilds */
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub files: Vec<String>,
+    pub bytes_per_line: u16,
+    pub width_symbols: u16,
+}
+
+/* A response: the status, its content type and the body */
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: String) -> Response {
+        Response { status, content_type: "application/json", body }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, Value::object([("error", message.into())]).to_json())
+    }
+}
+
+/* A number from the query string, decimal or 0x prefixed hex */
+fn query_number(query: &str, key: &str) -> Option<u16> {
+    let value = query.split('&').find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))?;
+    match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/**
+ * Answers one request. GET / is the page, GET /files lists the files being served, GET /map?file=N maps one of them
+ * and POST /map maps the hex file in the body. Both maps take line_width and display_width in the query, defaulting to
+ * the command line's.
+ */
+pub fn respond(options: &ServeOptions, method: &str, target: &str, body: &[u8]) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let bytes_per_line = query_number(query, "line_width").unwrap_or(options.bytes_per_line);
+    let width_symbols = query_number(query, "display_width").unwrap_or(options.width_symbols);
+    match (method, path) {
+        ("GET", "/" | "/index.html") => Response { status: 200, content_type: "text/html; charset=utf-8", body: PAGE.to_string() },
+        ("GET", "/files") => {
+            let names = options.files.iter()
+                .map(|path| Path::new(path).file_name().map_or(path.clone(), |name| name.to_string_lossy().to_string()))
+                .collect::<Vec<_>>();
+            Response::json(200, Value::from(names).to_json())
+        },
+        ("GET", "/map") => {
+            let Some(path) = query_number(query, "file").and_then(|idx| options.files.get(idx as usize)) else {
+                return Response::error(404, "No such file");
+            };
+            match read_hex_file(path) {
+                Ok(contents) => Response::json(200, browser_map(&contents, bytes_per_line, width_symbols)),
+                Err(error) => Response::error(500, &error.to_string()),
+            }
+        },
+        ("POST", "/map") => Response::json(200, browser_map(&String::from_utf8_lossy(body), bytes_per_line, width_symbols)),
+        (_, "/" | "/index.html" | "/files" | "/map") => Response::error(405, "Method not allowed"),
+        _ => Response::error(404, "Not found"),
+    }
+}
+
+/**
+ * A connection which fails every read and write once the deadline has passed. Each one waits at most for what is left
+ * of the time, so a client sending or taking a byte at a time cannot hold a worker for longer than that.
+ */
+struct Timed<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Timed<'_> {
+    /* The time left, or a timed out error once there is none */
+    fn remaining(&self) -> std::io::Result<Duration> {
+        match self.deadline.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Ok(left),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the request took too long")),
+        }
+    }
+}
+
+impl Read for Timed<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Timed<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/* Reads a request off the connection and answers it, closing the connection after */
+fn handle(options: &ServeOptions, stream: TcpStream) -> std::io::Result<()> {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut reader = BufReader::new(Timed { stream: &stream, deadline });
+    let mut head = reader.by_ref().take(MAX_HEAD);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if head.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let head_too_long = head.limit() == 0;
+    let refused = head_too_long || content_length > MAX_UPLOAD;
+    let response = if head_too_long {
+        Response::error(431, "The request headers are too long")
+    } else if content_length > MAX_UPLOAD {
+        Response::error(413, &format!("The file is too big, the server takes up to {} MB", MAX_UPLOAD / (1024 * 1024)))
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        respond(options, method, target, &body)
+    };
+    log::debug!("{method} {target} -> {}", response.status);
+    let reason = match response.status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    let mut stream = Timed { stream: &stream, deadline };
+    write!(stream, "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+           response.status, response.content_type, response.body.len())?;
+    stream.write_all(response.body.as_bytes())?;
+    stream.flush()?;
+    /* Reading some of what is left of a refused request before closing lets the client see the response, not a reset */
+    if refused {
+        stream.stream.shutdown(Shutdown::Write)?;
+        std::io::copy(&mut Read::by_ref(&mut stream).take(MAX_HEAD), &mut std::io::sink())?;
+    }
+    Ok(())
+}
+
+/* Binds to address:port, then serves the page and maps until killed, WORKERS connections at a time */
+pub fn serve(address: &str, port: u16, options: ServeOptions) -> Result<(), Error> {
+    let listen = format!("{address}:{port}");
+    let listener = TcpListener::bind(&listen).map_err(|source| Error::io(&listen, source))?;
+    let options = Arc::new(options);
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(WORKERS);
+    let receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..WORKERS {
+        let (options, receiver) = (Arc::clone(&options), Arc::clone(&receiver));
+        thread::spawn(move || {
+            /* The lock is held while waiting for a connection, not while answering it */
+            while let Ok(Ok(stream)) = receiver.lock().map(|receiver| receiver.recv()) {
+                if let Err(error) = handle(&options, stream) {
+                    log::debug!("Connection dropped: {error}");
+                }
+            }
+        });
+    }
+    for stream in listener.incoming() {
+        match stream {
+            /* With every worker busy and the queue full this waits, leaving further connections to the OS backlog */
+            Ok(stream) => sender.send(stream).map_err(|_| Error::Render("The server's workers stopped".to_string()))?,
+            Err(error) => log::warn!("Could not accept a connection: {error}"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use crate::server::{handle, respond, ServeOptions, MAX_HEAD};
+
+    #[test]
+    fn test_respond() -> Result<(),String> {
+        let options = ServeOptions { files: vec!["/nonexistent/app.hex".to_string()], bytes_per_line: 0x1000, width_symbols: 16 };
+        assert_eq!(200, respond(&options, "GET", "/", b"").status);
+        assert_eq!("[\n  \"app.hex\"\n]\n", respond(&options, "GET", "/files", b"").body);
+        let map = respond(&options, "POST", "/map?line_width=0x100&display_width=4", b":0400000001020304F2\n:00000001FF\n");
+        assert!(map.body.contains("\"len\": 256,\n      \"cells\": \"1000\""));
+        assert_eq!(500, respond(&options, "GET", "/map?file=0", b"").status);
+        assert_eq!(404, respond(&options, "GET", "/map?file=1", b"").status);
+        assert_eq!(404, respond(&options, "GET", "/other", b"").status);
+        Ok(())
+    }
+
+    /* Sends a request to handle over a local connection and returns the status line of the response */
+    fn status_of(request: Vec<u8>) -> Result<String, String> {
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let mut client = TcpStream::connect(listener.local_addr().map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+        client.write_all(&request).map_err(|e| e.to_string())?;
+        client.shutdown(Shutdown::Write).map_err(|e| e.to_string())?;
+        let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+        let options = ServeOptions { files: Vec::new(), bytes_per_line: 0x1000, width_symbols: 16 };
+        handle(&options, stream).map_err(|e| e.to_string())?;
+        let mut response = String::new();
+        client.read_to_string(&mut response).map_err(|e| e.to_string())?;
+        Ok(response.lines().next().unwrap_or_default().to_string())
+    }
+
+    #[test]
+    fn test_handle_limits() -> Result<(),String> {
+        assert_eq!("HTTP/1.1 200 OK", status_of(b"GET /files HTTP/1.1\r\n\r\n".to_vec())?);
+        let long_header = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(MAX_HEAD as usize));
+        assert_eq!("HTTP/1.1 431 Request Header Fields Too Large", status_of(long_header.into_bytes())?);
+        let upload = b"POST /map HTTP/1.1\r\nContent-Length: 1000000000\r\n\r\n".to_vec();
+        assert_eq!("HTTP/1.1 413 Payload Too Large", status_of(upload)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use crate::regions::Region;
+use crate::{byte_value, occupied_ranges, SegmentMap, ValueMap};
+
+/* The two magic words starting every UF2 block and the one ending it, at offset 508 of its 512 bytes */
+const UF2_MAGIC_START: [u8; 8] = [0x55, 0x46, 0x32, 0x0A, 0x57, 0x51, 0x5D, 0x9E];
+const UF2_MAGIC_END: [u8; 4] = [0x30, 0x6F, 0xB1, 0x0A];
+const UF2_BLOCK: usize = 512;
+
+/* The magic of MCUboot image headers, as imgtool writes them for Zephyr and other images, and of the older v1 ones */
+const MCUBOOT_MAGIC: [u8; 4] = [0x3D, 0xB8, 0xF3, 0x96];
+const MCUBOOT_MAGIC_V1: [u8; 4] = [0x3C, 0xB8, 0xF3, 0x96];
+/* The magic ending an MCUboot image trailer, which marks a slot as holding an image to swap in */
+const MCUBOOT_TRAILER_MAGIC: [u8; 16] = [0x77, 0xC2, 0x95, 0xF3, 0x60, 0xD2, 0xEF, 0x7F, 0x35, 0x52, 0x50, 0x0F, 0x2C, 0xB6, 0x79, 0x80];
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+
+/* A little endian 16 bit field */
+fn le16(bytes: &[u8]) -> usize {
+    u16::from_le_bytes([bytes[0], bytes[1]]) as usize
+}
+
+/* The length of the signature starting at bytes[0] and what it is called, if one does */
+fn signature_at(bytes: &[u8]) -> Option<(usize, String)> {
+    let has = |offset: usize, magic: &[u8]| bytes.get(offset..offset + magic.len()) == Some(magic);
+    if has(0, &UF2_MAGIC_START) && has(UF2_BLOCK - 4, &UF2_MAGIC_END) {
+        return Some((UF2_BLOCK, "UF2 block".to_string()));
+    }
+    if (has(0, &MCUBOOT_MAGIC) || has(0, &MCUBOOT_MAGIC_V1)) && bytes.len() >= 32 {
+        /* The header size field, typically 0x200, which the image starts after */
+        let header_size = le16(&bytes[8..]).max(32);
+        let version = if has(0, &MCUBOOT_MAGIC) {format!(" {}.{}.{}", bytes[20], bytes[21], le16(&bytes[22..]))} else {" v1".to_string()};
+        return Some((header_size, format!("MCUboot image header{version}")));
+    }
+    if has(0, &MCUBOOT_TRAILER_MAGIC) {
+        return Some((MCUBOOT_TRAILER_MAGIC.len(), "MCUboot trailer magic".to_string()));
+    }
+    /* A FAT boot sector starts with a jump, names its file system at 0x36 (FAT12/16) or 0x52 (FAT32) and ends in 55 AA */
+    if matches!(bytes.first(), Some(0xEB) | Some(0xE9)) && has(510, &[0x55, 0xAA]) && (has(0x36, b"FAT") || has(0x52, b"FAT")) {
+        return Some((512, "FAT boot sector".to_string()));
+    }
+    /* The class byte says 32 or 64 bit, which sets the header size, and the data byte the endianness */
+    if has(0, &ELF_MAGIC) && matches!(bytes.get(4), Some(1) | Some(2)) && matches!(bytes.get(5), Some(1) | Some(2)) {
+        let (size, bits) = if bytes[4] == 1 {(52, 32)} else {(64, 64)};
+        return Some((size, format!("ELF{bits} header")));
+    }
+    None
+}
+
+/* The signatures found in bytes, which start at base, with runs of UF2 blocks joined into one region */
+fn signatures_in(bytes: &[u8], base: u32) -> Vec<Region> {
+    let mut found: Vec<Region> = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let Some((size, name)) = signature_at(&bytes[offset..]) else {
+            offset += 1;
+            continue;
+        };
+        let size = size.min(bytes.len() - offset);
+        let start = base + offset as u32;
+        match found.last_mut() {
+            Some(last) if last_is_block(last, &name, start) => {
+                let blocks = (last.size as usize + size) / UF2_BLOCK;
+                last.size += size as u32;
+                last.name = format!("{blocks} UF2 blocks");
+            },
+            _ => found.push(Region { name, start, size: size as u32, budget: None }),
+        }
+        offset += size;
+    }
+    found
+}
+
+/* Whether a UF2 block at start carries on from the block or blocks before it */
+fn last_is_block(last: &Region, name: &str, start: u32) -> bool {
+    name == "UF2 block" && (last.name == "UF2 block" || last.name.ends_with("UF2 blocks")) && last.start + last.size == start
+}
+
+/**
+ * Looks through the data for well-known headers and magics, UF2 blocks, MCUboot image headers and trailers, FAT boot
+ * sectors and ELF headers, and returns each as a region named after what it is, in address order. Signatures are only
+ * looked for within occupied ranges, never across a gap.
+ */
+pub fn find_signatures(segment_map: &SegmentMap, values: &ValueMap) -> Vec<Region> {
+    let mut found = Vec::new();
+    for (start, end) in occupied_ranges(segment_map) {
+        let bytes: Vec<u8> = (start..end).map(|addr| byte_value(segment_map, values, addr as u32).unwrap_or(0)).collect();
+        found.extend(signatures_in(&bytes, start as u32));
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regions::Region;
+    use crate::signatures::signatures_in;
+
+    #[test]
+    fn test_signatures_in() -> Result<(),String> {
+        let mut bytes = vec![0xFF; 0x800];
+        /* An MCUboot header of 0x200 bytes for version 1.2.3 */
+        bytes[..4].copy_from_slice(&[0x3D, 0xB8, 0xF3, 0x96]);
+        bytes[8..10].copy_from_slice(&[0x00, 0x02]);
+        bytes[20..24].copy_from_slice(&[1, 2, 3, 0]);
+        /* Two UF2 blocks back to back */
+        for block in [0x200, 0x400] {
+            bytes[block..block + 8].copy_from_slice(&[0x55, 0x46, 0x32, 0x0A, 0x57, 0x51, 0x5D, 0x9E]);
+            bytes[block + 508..block + 512].copy_from_slice(&[0x30, 0x6F, 0xB1, 0x0A]);
+        }
+        /* A 32 bit little endian ELF header */
+        bytes[0x700..0x706].copy_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1]);
+        assert_eq!(vec![
+            Region { name: "MCUboot image header 1.2.3".to_string(), start: 0x1000, size: 0x200, budget: None },
+            Region { name: "2 UF2 blocks".to_string(), start: 0x1200, size: 0x400, budget: None },
+            Region { name: "ELF32 header".to_string(), start: 0x1700, size: 52, budget: None },
+        ], signatures_in(&bytes, 0x1000));
+        /* A UF2 block without its end magic is not one */
+        assert!(signatures_in(&bytes[0x200..0x300], 0).is_empty());
+        Ok(())
+    }
+}
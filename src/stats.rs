@@ -0,0 +1,245 @@
+use crate::{byte_value, clip_ranges, occupied_ranges, SegmentMap, ValueMap};
+use crate::deflate::deflate;
+use crate::structured::Value;
+
+/* Headline numbers describing how full an image is */
+#[derive(Debug, Clone, PartialEq)]
+pub struct OccupancyStats {
+    /// Number of addresses holding data
+    pub bytes_used: u64,
+    /// First occupied address
+    pub span_start: u32,
+    /// One past the last occupied address, 0x1_0000_0000 with data in the top byte
+    pub span_end: u64,
+    /// Number of contiguous occupied ranges
+    pub regions: usize,
+    /// Number of 64kb pages holding any data
+    pub pages: usize,
+}
+
+impl OccupancyStats {
+    pub fn from_map(segment_map: &SegmentMap) -> OccupancyStats {
+        OccupancyStats::from_ranges(&occupied_ranges(segment_map))
+    }
+
+    /* The same numbers from the sorted, separate [start, end) occupied ranges alone */
+    pub fn from_ranges(ranges: &[(u64, u64)]) -> OccupancyStats {
+        /* Ranges are sorted, so a page shared with the range before has already been counted */
+        let mut last_page = None;
+        let mut pages = 0;
+        for (start, end) in ranges {
+            let (first, last) = (start >> 16, (end - 1) >> 16);
+            pages += (last - first + 1) as usize - (last_page == Some(first)) as usize;
+            last_page = Some(last);
+        }
+        OccupancyStats {
+            bytes_used: ranges.iter().map(|(start, end)| end - start).sum(),
+            span_start: ranges.first().map_or(0, |r| r.0 as u32),
+            span_end: ranges.last().map_or(0, |r| r.1),
+            regions: ranges.len(),
+            pages,
+        }
+    }
+
+    pub fn span(&self) -> u64 {
+        self.span_end - self.span_start as u64
+    }
+
+    /* How much of the span between the first and last occupied byte holds data */
+    pub fn fill_percent(&self) -> f64 {
+        if self.span() == 0 {0.0} else {self.bytes_used as f64 * 100.0 / self.span() as f64}
+    }
+
+    /* The numbers for --format json and yaml. The span end is one past the last used byte */
+    pub fn to_value(&self) -> Value {
+        Value::object([
+            ("bytes_used", self.bytes_used.into()),
+            ("span_start", self.span_start.into()),
+            ("span_end", self.span_end.into()),
+            ("fill_percent", self.fill_percent().into()),
+            ("ranges", self.regions.into()),
+            ("pages", self.pages.into()),
+        ])
+    }
+
+    /* The numbers as aligned "name  value" lines, as --stats prints them */
+    pub fn summary_lines(&self) -> Vec<String> {
+        let span = match self.span() {
+            0 => "empty".to_string(),
+            span => format!("{:#010x}-{:#010x} ({span} bytes)", self.span_start, self.span_end - 1),
+        };
+        vec![
+            format!("Bytes used     {}", self.bytes_used),
+            format!("Span           {span}"),
+            format!("Fill           {:.1}%", self.fill_percent()),
+            format!("Ranges         {}", self.regions),
+            format!("Pages touched  {}", self.pages),
+        ]
+    }
+}
+
+/* Shannon entropy of a run of bytes in bits per byte, from 0 (all the same) to 8 (evenly spread) */
+pub fn byte_entropy(bytes: &[u8]) -> f64 {
+    let mut counts = [0u32; 256];
+    for byte in bytes {
+        counts[*byte as usize] += 1;
+    }
+    let total = bytes.len() as f64;
+    counts.iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+/**
+ * The entropy of every block_size aligned block of the occupied ranges, as [start, end) and bits per byte in address
+ * order. Only bytes holding data count, so a block cut short by the end of a range covers just the part in the range.
+ */
+pub fn block_entropies(segment_map: &SegmentMap, values: &ValueMap, block_size: u32) -> Vec<(u64, u64, f64)> {
+    let mut blocks = Vec::new();
+    for (start, end) in occupied_ranges(segment_map) {
+        let mut block_start = start;
+        while block_start < end {
+            let block_end = ((block_start / block_size as u64 + 1) * block_size as u64).min(end);
+            let bytes: Vec<u8> = (block_start..block_end).filter_map(|addr| byte_value(segment_map, values, addr as u32)).collect();
+            blocks.push((block_start, block_end, byte_entropy(&bytes)));
+            block_start = block_end;
+        }
+    }
+    blocks
+}
+
+/* How often each byte value occurs among the bytes holding data within [start, end) */
+pub fn byte_histogram(segment_map: &SegmentMap, values: &ValueMap, start: u32, end: u64) -> [u64; 256] {
+    let mut counts = [0u64; 256];
+    for (range_start, range_end) in clip_ranges(&occupied_ranges(segment_map), start as u64, end) {
+        for addr in range_start..range_end {
+            if let Some(value) = byte_value(segment_map, values, addr as u32) {
+                counts[value as usize] += 1;
+            }
+        }
+    }
+    counts
+}
+
+/* Runs of one byte value at least this long count as padding rather than data */
+pub const PADDING_RUN: usize = 16;
+
+/* How many bytes of the data are padding_byte in runs of PADDING_RUN or more */
+pub fn padding_bytes(segment_map: &SegmentMap, values: &ValueMap, padding_byte: u8) -> u64 {
+    let mut padding = 0;
+    for (start, end) in occupied_ranges(segment_map) {
+        let mut run = 0;
+        for addr in start..=end {
+            match (addr < end).then(|| byte_value(segment_map, values, addr as u32)).flatten() {
+                Some(value) if value == padding_byte => run += 1,
+                _ if run >= PADDING_RUN => (padding, run) = (padding + run as u64, 0),
+                _ => run = 0,
+            }
+        }
+    }
+    padding
+}
+
+/**
+ * The byte the image pads with, erased flash 0xFF or zeroed 0x00, whichever makes up more padding, with how many bytes
+ * of padding there are. 0xFF wins a tie, and None means there are no runs of either.
+ */
+pub fn detect_padding(segment_map: &SegmentMap, values: &ValueMap) -> Option<(u8, u64)> {
+    let erased = padding_bytes(segment_map, values, 0xFF);
+    let zeroed = padding_bytes(segment_map, values, 0x00);
+    match (erased, zeroed) {
+        (0, 0) => None,
+        _ if zeroed > erased => Some((0x00, zeroed)),
+        _ => Some((0xFF, erased)),
+    }
+}
+
+/**
+ * The DEFLATE compressed size of every occupied range as [start, end) and bytes in address order, and of all of them
+ * back to back, which is about what a compressed OTA package of the image would carry.
+ */
+pub fn range_compression(segment_map: &SegmentMap, values: &ValueMap) -> (Vec<(u64, u64, usize)>, usize) {
+    let mut all_bytes = Vec::new();
+    let mut sizes = Vec::new();
+    for (start, end) in occupied_ranges(segment_map) {
+        let bytes: Vec<u8> = (start..end).map(|addr| byte_value(segment_map, values, addr as u32).unwrap_or(0)).collect();
+        sizes.push((start, end, deflate(&bytes).len()));
+        all_bytes.extend(bytes);
+    }
+    (sizes, deflate(&all_bytes).len())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::{block_entropies, byte_entropy, byte_histogram, detect_padding, OccupancyStats};
+    use crate::hex_loader::{load_hex, LoadOptions};
+
+    #[test]
+    fn test_byte_entropy() -> Result<(),String> {
+        assert_eq!(0.0, byte_entropy(&[]));
+        assert_eq!(0.0, byte_entropy(&[0xFF; 16]));
+        assert_eq!(1.0, byte_entropy(&[0x00, 0xFF, 0x00, 0xFF]));
+        let every_value: Vec<u8> = (0..=255).collect();
+        assert_eq!(8.0, byte_entropy(&every_value));
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary_lines() -> Result<(),String> {
+        let stats = OccupancyStats { bytes_used: 0x300, span_start: 0x1000, span_end: 0x1400, regions: 2, pages: 1 };
+        assert_eq!(vec![
+            "Bytes used     768",
+            "Span           0x00001000-0x000013ff (1024 bytes)",
+            "Fill           75.0%",
+            "Ranges         2",
+            "Pages touched  1",
+        ], stats.summary_lines());
+        /* Two ranges sharing page 1, one running on into page 2, and the top byte */
+        let ranges = [(0x1_0000, 0x1_0010), (0x1_fff0, 0x2_0010), (0xFFFF_FFFF, 0x1_0000_0000)];
+        let stats = OccupancyStats::from_ranges(&ranges);
+        assert_eq!((0x10 + 0x20 + 1, 3, 0x1_0000_0000), (stats.bytes_used, stats.pages, stats.span_end));
+
+        /* Data up to the very last byte of the address space */
+        let image = load_hex(":02000004FFFFFC\n:10FFF000000102030405060708090A0B0C0D0E0F89\n:00000001FF\n", LoadOptions::default());
+        let stats = OccupancyStats::from_map(&image.segment_map);
+        assert_eq!((16, 0xFFFFFFF0, 0x1_0000_0000, 100.0), (stats.bytes_used, stats.span_start, stats.span_end, stats.fill_percent()));
+        assert_eq!("Span           0xfffffff0-0xffffffff (16 bytes)", stats.summary_lines()[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_entropies() -> Result<(),String> {
+        /* 0x0-0x5 alternates two values, 0x6-0x7 repeats one, split at the 4 byte block boundary */
+        let image = load_hex(":0600000000FF00FF00FFFD\n:020006001111D6\n:00000001FF\n", LoadOptions { store_values: true, blank_byte: None });
+        let values = image.values.ok_or("values were not stored")?;
+        assert_eq!(vec![(0x0, 0x4, 1.0), (0x4, 0x8, 1.5)], block_entropies(&image.segment_map, &values, 4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_byte_histogram() -> Result<(),String> {
+        let image = load_hex(":0600000000FF00FF00FFFD\n:020006001111D6\n:00000001FF\n", LoadOptions { store_values: true, blank_byte: None });
+        let values = image.values.ok_or("values were not stored")?;
+        let counts = byte_histogram(&image.segment_map, &values, 0, 1 << 32);
+        assert_eq!((3, 3, 2, 8), (counts[0x00], counts[0xFF], counts[0x11], counts.iter().sum::<u64>()));
+        assert_eq!(2, byte_histogram(&image.segment_map, &values, 5, 7)[0x11] + byte_histogram(&image.segment_map, &values, 5, 7)[0xFF]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_padding() -> Result<(),String> {
+        /* 16 zeroes, 16 counting bytes, 20 0xFF and 15 zeroes, too few to count as padding */
+        let contents = ":1000000000000000000000000000000000000000F0\n:100010000102030405060708090A0B0C0D0E0F1058\n\
+                        :10002000FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFE0\n:13003000FFFFFFFF000000000000000000000000000000C1\n:00000001FF\n";
+        let image = load_hex(contents, LoadOptions { store_values: true, blank_byte: None });
+        let values = image.values.ok_or("values were not stored")?;
+        assert_eq!(Some((0xFF, 20)), detect_padding(&image.segment_map, &values));
+        let image = load_hex(":1000000000000000000000000000000000000000F0\n:00000001FF\n", LoadOptions { store_values: true, blank_byte: None });
+        assert_eq!(Some((0x00, 16)), detect_padding(&image.segment_map, image.values.as_ref().ok_or("values were not stored")?));
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+/* How reports are printed: as text for people, or as JSON or YAML for scripts */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Yaml,
+}
+
+/**
+ * A report as data, to be written as JSON or YAML. Objects keep their fields in the order given so the output is the
+ * same from run to run and scripts can rely on it.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(u64),
+    Float(f64),
+    Str(String),
+    List(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Value {
+        Value::Int(value)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(value: u32) -> Value {
+        Value::Int(value as u64)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Value {
+        Value::Int(value as u64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Value {
+        Value::Float(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::Str(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Value {
+        Value::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+/* A JSON string literal, escaping what JSON needs escaped */
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for chr in text.chars() {
+        match chr {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            chr if (chr as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", chr as u32)),
+            chr => quoted.push(chr),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/* A range of addresses as an object. The end is one past the last byte, so a range can end at the top of the address space */
+pub fn range_value(start: u64, end: u64) -> Value {
+    Value::object([("start", start.into()), ("end", end.into()), ("bytes", (end - start).into())])
+}
+
+/* Ranges as a list of range_value objects */
+pub fn ranges_value(ranges: &[(u64, u64)]) -> Value {
+    Value::List(ranges.iter().map(|(start, end)| range_value(*start, *end)).collect())
+}
+
+impl Value {
+    pub fn object<const N: usize>(fields: [(&str, Value); N]) -> Value {
+        Value::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    /* An object with the fields of another object added after its own. Anything else is left as it is */
+    pub fn with_fields(self, other: Value) -> Value {
+        match (self, other) {
+            (Value::Object(mut fields), Value::Object(more)) => {
+                fields.extend(more);
+                Value::Object(fields)
+            },
+            (value, _) => value,
+        }
+    }
+
+    /* A value which is the same written as JSON or YAML, or None for lists and objects */
+    fn scalar_text(&self) -> Option<String> {
+        match self {
+            Value::Null => Some("null".to_string()),
+            Value::Bool(value) => Some(value.to_string()),
+            Value::Int(value) => Some(value.to_string()),
+            Value::Float(value) if value.is_finite() => Some(value.to_string()),
+            Value::Float(_) => Some("null".to_string()),
+            /* YAML takes JSON's double quoted strings as they are */
+            Value::Str(text) => Some(json_string(text)),
+            Value::List(items) if items.is_empty() => Some("[]".to_string()),
+            Value::Object(fields) if fields.is_empty() => Some("{}".to_string()),
+            Value::List(_) | Value::Object(_) => None,
+        }
+    }
+
+    fn write_json(&self, indent: usize, out: &mut String) {
+        if let Some(text) = self.scalar_text() {
+            out.push_str(&text);
+            return;
+        }
+        let pad = " ".repeat(indent + 2);
+        let (open, close) = if matches!(self, Value::List(_)) {('[', ']')} else {('{', '}')};
+        out.push(open);
+        let mut first = true;
+        let mut item = |key: Option<&str>, value: &Value, out: &mut String| {
+            out.push_str(if first {"\n"} else {",\n"});
+            first = false;
+            out.push_str(&pad);
+            if let Some(key) = key {
+                out.push_str(&format!("{}: ", json_string(key)));
+            }
+            value.write_json(indent + 2, out);
+        };
+        match self {
+            Value::List(items) => items.iter().for_each(|value| item(None, value, out)),
+            Value::Object(fields) => fields.iter().for_each(|(key, value)| item(Some(key), value, out)),
+            _ => {},
+        }
+        out.push_str(&format!("\n{}{close}", " ".repeat(indent)));
+    }
+
+    /* Writes a list or object as a block of lines at the indent, or a scalar after a space, then a line break */
+    fn write_yaml(&self, indent: usize, out: &mut String) {
+        if let Some(text) = self.scalar_text() {
+            out.push_str(&format!(" {text}\n"));
+            return;
+        }
+        out.push('\n');
+        self.write_yaml_block(indent, out);
+    }
+
+    fn write_yaml_block(&self, indent: usize, out: &mut String) {
+        let pad = " ".repeat(indent);
+        match self {
+            Value::List(items) => for value in items {
+                out.push_str(&format!("{pad}-"));
+                match value.scalar_text() {
+                    Some(text) => out.push_str(&format!(" {text}\n")),
+                    /* A list or object in a list starts on the line of its dash */
+                    None => {
+                        let mut block = String::new();
+                        value.write_yaml_block(indent + 2, &mut block);
+                        out.push(' ');
+                        out.push_str(&block[indent + 2..]);
+                    },
+                }
+            },
+            Value::Object(fields) => for (key, value) in fields {
+                out.push_str(&format!("{pad}{key}:"));
+                value.write_yaml(indent + 2, out);
+            },
+            _ => {},
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(0, &mut out);
+        out.push('\n');
+        out
+    }
+
+    pub fn to_yaml(&self) -> String {
+        match self.scalar_text() {
+            Some(text) => format!("{text}\n"),
+            None => {
+                let mut out = String::new();
+                self.write_yaml_block(0, &mut out);
+                out
+            },
+        }
+    }
+
+    /* The value in a machine readable format, or None for text, which each report writes its own way */
+    pub fn formatted(&self, format: OutputFormat) -> Option<String> {
+        match format {
+            OutputFormat::Text => None,
+            OutputFormat::Json => Some(self.to_json()),
+            OutputFormat::Yaml => Some(self.to_yaml()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::structured::{ranges_value, Value};
+
+    #[test]
+    fn test_value_formats() -> Result<(),String> {
+        let value = Value::List(vec![Value::object([
+            ("file", "a \"b\".hex".into()),
+            ("ranges", ranges_value(&[(0x100, 0x180)])),
+            ("entry", Value::Null),
+            ("lines", vec![3usize, 4].into()),
+            ("issues", Value::List(Vec::new())),
+        ])]);
+        assert_eq!("[\n  {\n    \"file\": \"a \\\"b\\\".hex\",\n    \"ranges\": [\n      {\n        \"start\": 256,\n        \"end\": 384,\n        \"bytes\": 128\n      }\n    ],\n    \"entry\": null,\n    \"lines\": [\n      3,\n      4\n    ],\n    \"issues\": []\n  }\n]\n", value.to_json());
+        assert_eq!("- file: \"a \\\"b\\\".hex\"\n  ranges:\n    - start: 256\n      end: 384\n      bytes: 128\n  entry: null\n  lines:\n    - 3\n    - 4\n  issues: []\n", value.to_yaml());
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use crate::regions::Region;
+
+/* The text of the first <tag>...</tag> in xml, trimmed */
+fn tag_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let open = format!("<{tag}>");
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(xml[start..end].trim())
+}
+
+/* An SVD number, which is decimal or 0x prefixed hex */
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/* The [start, end) offsets from the base address covered by every <addressBlock> in xml, if there are any */
+fn block_span(xml: &str) -> Option<(u64, u64)> {
+    let mut span: Option<(u64, u64)> = None;
+    for block in xml.split("<addressBlock>").skip(1) {
+        let (Some(offset), Some(size)) = (tag_text(block, "offset").and_then(parse_number), tag_text(block, "size").and_then(parse_number)) else {
+            continue;
+        };
+        span = Some(span.map_or((offset, offset + size), |(start, end)| (start.min(offset), end.max(offset + size))));
+    }
+    span
+}
+
+/* A peripheral as declared, before any derivedFrom is followed */
+struct Peripheral<'a> {
+    name: &'a str,
+    base: u64,
+    /// The block span if any blocks were given
+    span: Option<(u64, u64)>,
+    derived_from: Option<&'a str>,
+}
+
+/**
+ * Reads the address range of every peripheral in a CMSIS-SVD file, from its base address and address blocks, as
+ * regions named after the peripherals. A peripheral derived from another without blocks of its own takes the other's
+ * blocks. Only the peripheral level is read, the registers inside are left out.
+ */
+pub fn parse_svd(contents: &str) -> Result<Vec<Region>, String> {
+    if !contents.contains("<peripherals>") {
+        return Err("Not an SVD file, there is no <peripherals> list".to_string());
+    }
+    let mut peripherals = Vec::new();
+    for element in contents.split("<peripheral").skip(1) {
+        /* <peripherals> itself splits off too, and the registers have names of their own */
+        let Some(element) = element.strip_prefix('>').or_else(|| element.strip_prefix(' ')) else {
+            continue;
+        };
+        let (attributes, body) = element.split_once('>').filter(|_| element.starts_with("derivedFrom")).unwrap_or(("", element));
+        let own = body.split("<registers>").next().unwrap_or(body);
+        let (Some(name), Some(base)) = (tag_text(own, "name"), tag_text(own, "baseAddress").and_then(parse_number)) else {
+            continue;
+        };
+        peripherals.push(Peripheral { name, base, span: block_span(own), derived_from: attributes.split('"').nth(1) });
+    }
+    let mut regions = Vec::new();
+    for peripheral in &peripherals {
+        let span = peripheral.span.or_else(|| {
+            let parent = peripheral.derived_from?;
+            peripherals.iter().find(|other| other.name == parent).and_then(|other| other.span)
+        });
+        let Some((start, end)) = span else {
+            continue;
+        };
+        let (Ok(start), Ok(size)) = (u32::try_from(peripheral.base + start), u32::try_from(end - start)) else {
+            continue;
+        };
+        regions.push(Region { name: peripheral.name.to_string(), start, size, budget: None });
+    }
+    regions.sort_by_key(|region| region.start);
+    Ok(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regions::Region;
+    use crate::svd::parse_svd;
+
+    #[test]
+    fn test_parse_svd() -> Result<(),String> {
+        let contents = r#"<?xml version="1.0" encoding="utf-8"?>
+<device schemaVersion="1.3">
+  <name>PART</name>
+  <peripherals>
+    <peripheral>
+      <name>GPIOA</name>
+      <baseAddress>0x40020000</baseAddress>
+      <addressBlock><offset>0x0</offset><size>0x400</size><usage>registers</usage></addressBlock>
+      <registers>
+        <register><name>MODER</name><addressOffset>0x0</addressOffset></register>
+      </registers>
+    </peripheral>
+    <peripheral derivedFrom="GPIOA">
+      <name>GPIOB</name>
+      <baseAddress>0x40020400</baseAddress>
+    </peripheral>
+    <peripheral>
+      <name>USART1</name>
+      <baseAddress>1073811456</baseAddress>
+      <addressBlock><offset>0x0</offset><size>0x10</size></addressBlock>
+      <addressBlock><offset>0x20</offset><size>0x10</size></addressBlock>
+    </peripheral>
+  </peripherals>
+</device>
+"#;
+        assert_eq!(vec![
+            Region { name: "USART1".to_string(), start: 0x4001_1000, size: 0x30, budget: None },
+            Region { name: "GPIOA".to_string(), start: 0x4002_0000, size: 0x400, budget: None },
+            Region { name: "GPIOB".to_string(), start: 0x4002_0400, size: 0x400, budget: None },
+        ], parse_svd(contents)?);
+        assert!(parse_svd("<device></device>").is_err());
+        Ok(())
+    }
+}
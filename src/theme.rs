@@ -0,0 +1,134 @@
+use crossterm::style::{Attribute, Color, ContentStyle};
+
+/* The built-in color themes, in the order the viewer cycles through them */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ThemeName {
+    Default,
+    Monochrome,
+    Solarized,
+    HighContrast,
+}
+
+impl ThemeName {
+    pub fn next(self) -> ThemeName {
+        match self {
+            ThemeName::Default => ThemeName::Monochrome,
+            ThemeName::Monochrome => ThemeName::Solarized,
+            ThemeName::Solarized => ThemeName::HighContrast,
+            ThemeName::HighContrast => ThemeName::Default,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ThemeName::Default => "default",
+            ThemeName::Monochrome => "monochrome",
+            ThemeName::Solarized => "solarized",
+            ThemeName::HighContrast => "high-contrast",
+        }
+    }
+}
+
+/**
+ * How each kind of map character is drawn. The highlight style covers the highlighted character and any selection.
+ * The overlay of two files draws data found in just one of them as only_a (the file on screen) or only_b (the other).
+ * The entry style marks the character holding the execution start address, and found those covering a match of :find.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub data: ContentStyle,
+    pub blank: ContentStyle,
+    pub highlight: ContentStyle,
+    pub only_a: ContentStyle,
+    pub only_b: ContentStyle,
+    pub entry: ContentStyle,
+    pub found: ContentStyle,
+}
+
+/**
+ * The characters of the occupancy map: for a character whose bytes all hold data, for one with none, and for one with
+ * only some. Given on the command line or in the config file as the three in that order, e.g. "#.+".
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyphs {
+    pub occupied: char,
+    pub empty: char,
+    pub partial: char,
+}
+
+impl Default for Glyphs {
+    fn default() -> Glyphs {
+        Glyphs { occupied: '▓', empty: '░', partial: '▒' }
+    }
+}
+
+impl std::str::FromStr for Glyphs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Glyphs, String> {
+        match s.chars().collect::<Vec<_>>()[..] {
+            [occupied, empty, partial] => Ok(Glyphs { occupied, empty, partial }),
+            _ => Err(format!("{s} is not three characters, for occupied, empty and partly occupied")),
+        }
+    }
+}
+
+fn colored(foreground: Option<Color>, background: Option<Color>) -> ContentStyle {
+    ContentStyle { foreground_color: foreground, background_color: background, ..ContentStyle::new() }
+}
+
+fn underlined() -> ContentStyle {
+    let mut style = ContentStyle::new();
+    style.attributes.set(Attribute::Underlined);
+    style
+}
+
+fn reversed() -> ContentStyle {
+    let mut style = ContentStyle::new();
+    style.attributes.set(Attribute::Reverse);
+    style
+}
+
+impl Theme {
+    pub fn new(name: ThemeName) -> Theme {
+        match name {
+            ThemeName::Default => Theme {
+                data: colored(Some(Color::Green), None),
+                blank: colored(Some(Color::DarkGrey), None),
+                highlight: colored(Some(Color::Black), Some(Color::Yellow)),
+                only_a: colored(Some(Color::Red), None),
+                only_b: colored(Some(Color::Cyan), None),
+                entry: colored(Some(Color::Magenta), None),
+                found: colored(Some(Color::Black), Some(Color::Cyan)),
+            },
+            /* No colors at all, for terminals without them or for copying the map as text */
+            ThemeName::Monochrome => Theme {
+                data: ContentStyle::new(),
+                blank: ContentStyle::new(),
+                highlight: reversed(),
+                only_a: ContentStyle::new(),
+                only_b: ContentStyle::new(),
+                entry: ContentStyle::new(),
+                found: underlined(),
+            },
+            ThemeName::Solarized => Theme {
+                data: colored(Some(Color::Rgb { r: 0x26, g: 0x8b, b: 0xd2 }), Some(Color::Rgb { r: 0x00, g: 0x2b, b: 0x36 })),
+                blank: colored(Some(Color::Rgb { r: 0x07, g: 0x36, b: 0x42 }), Some(Color::Rgb { r: 0x00, g: 0x2b, b: 0x36 })),
+                highlight: colored(Some(Color::Rgb { r: 0x00, g: 0x2b, b: 0x36 }), Some(Color::Rgb { r: 0xb5, g: 0x89, b: 0x00 })),
+                only_a: colored(Some(Color::Rgb { r: 0xdc, g: 0x32, b: 0x2f }), Some(Color::Rgb { r: 0x00, g: 0x2b, b: 0x36 })),
+                only_b: colored(Some(Color::Rgb { r: 0x2a, g: 0xa1, b: 0x98 }), Some(Color::Rgb { r: 0x00, g: 0x2b, b: 0x36 })),
+                entry: colored(Some(Color::Rgb { r: 0xd3, g: 0x36, b: 0x82 }), Some(Color::Rgb { r: 0x00, g: 0x2b, b: 0x36 })),
+                found: colored(Some(Color::Rgb { r: 0x00, g: 0x2b, b: 0x36 }), Some(Color::Rgb { r: 0x2a, g: 0xa1, b: 0x98 })),
+            },
+            ThemeName::HighContrast => Theme {
+                data: colored(Some(Color::White), Some(Color::Black)),
+                blank: colored(Some(Color::DarkBlue), Some(Color::Black)),
+                highlight: colored(Some(Color::Black), Some(Color::Magenta)),
+                only_a: colored(Some(Color::Red), Some(Color::Black)),
+                only_b: colored(Some(Color::Yellow), Some(Color::Black)),
+                entry: colored(Some(Color::Cyan), Some(Color::Black)),
+                found: colored(Some(Color::Black), Some(Color::Green)),
+            },
+        }
+    }
+}
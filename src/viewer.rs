@@ -0,0 +1,2256 @@
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use crossterm::style::{ContentStyle, Stylize};
+use ihex::Record;
+use crossterm::{cursor, execute, terminal};
+use clap_num::maybe_hex;
+use std::fs;
+use std::panic;
+use std::io::{self, stdout};
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, SystemTime};
+use crate::regions::{self, Region};
+use crate::stats::{byte_entropy, OccupancyStats};
+use crate::hex_loader::{self, HexImage, HexReader, LoadEvent, LoadOptions, LoadedPage};
+use crate::exports;
+use crate::pattern::{self, is_printable};
+use crate::clipboard;
+use crate::frame::Frame;
+use crate::devices::Flash;
+use crate::theme::{Glyphs, Theme, ThemeName};
+use crate::{byte_value, clip_ranges, count_set_bytes, is_byte_set, map_rows, occupied_ranges, MapRow, SegmentMap, ValueMap, IHEX_SEGMENT_BYTES};
+
+/* Overlay characters for data in only one of the two files, on the side of the screen that file's pane would be */
+const CHR_ONLY_A: char = '▌';
+const CHR_ONLY_B: char = '▐';
+/* The character holding the execution start address */
+const CHR_ENTRY: char = '◆';
+/* Characters for the density, value and entropy views, from lowest to highest */
+const CHR_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/* Screen layout: the map with the address gutter on the left, then a status bar and a message line at the bottom */
+const MAP_TOP: u16 = 0;
+const FOOTER_ROWS: u16 = 2;
+const GUTTER_W: usize = 10;
+/* The minimap comes after the map, with a column of space before it, then the scrollbar takes the last column */
+const MINIMAP_W: usize = 2;
+const SCROLLBAR_W: usize = 1;
+const CHR_TRACK_V: char = '│';
+const CHR_TRACK_H: char = '─';
+const CHR_THUMB: char = '█';
+/* How many characters Ctrl-Left / Ctrl-Right scroll a map wider than the screen */
+const SIDEWAYS_STEP: usize = 8;
+/* How often the files are checked for changes in watch mode */
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+/* How often the map takes in what background loads have parsed */
+const LOAD_INTERVAL: Duration = Duration::from_millis(100);
+/* Panels sit against the right edge. The hexdump needs "0x00000000  " + 16 * "xx " + 16 ASCII characters + a border */
+const INSPECTOR_W: usize = 12 + 16 * 3 + 16 + 2;
+const BOOKMARKS_W: usize = 40;
+const RECORDS_W: usize = 46;
+
+/* Every key binding, as listed by the help overlay */
+const KEY_HELP: &[(&str, &str)] = &[
+    ("Arrows", "Move the cursor, scrolling along"),
+    ("C-arrows", "Scroll a line / a wide map sideways"),
+    ("PgUp / PgDn", "Scroll one screen"),
+    ("Home / End", "Jump to the top / bottom"),
+    ("+ / -", "Zoom in / out"),
+    ("[ / ]", "Fewer / more characters per line"),
+    ("[c / ]c", "Previous / next difference, comparing"),
+    ("L / W", "Type a line width / display width"),
+    ("g", "Go to an address"),
+    (":", "Type a command, :help lists them"),
+    ("/", "Search for an address or region"),
+    ("n / p", "Next / previous block of data"),
+    ("m", "Bookmark the current address"),
+    ("'", "Jump back to the last bookmark"),
+    ("b", "List bookmarks, 1-9 jumps to one"),
+    ("Tab / S-Tab", "Next / previous file"),
+    ("1 - 9", "Show that file"),
+    ("S-arrows", "Select a range of cells"),
+    ("w", "Write the selection to .hex / .bin"),
+    ("a", "Name the selection as a region"),
+    ("y", "Copy the selection / highlighted address"),
+    ("P", "Write the screen to a text file"),
+    ("Enter", "Hexdump of the highlight / selection"),
+    ("r", "Hex records behind the highlight"),
+    ("Left click", "Inspect a cell, drag to select"),
+    ("t", "Next color theme"),
+    ("M", "Show / hide the minimap"),
+    ("s", "Split view with the next file"),
+    ("o", "Overlay the next file"),
+    ("v", "Show occupancy / density / values / entropy / text"),
+    ("?", "Show this help"),
+    ("q / Esc / C-c", "Quit"),
+];
+
+/* Extra bindings of the vim keymap, listed by the help overlay after the common ones */
+const VIM_KEY_HELP: &[(&str, &str)] = &[
+    ("h j k l", "Move the highlight"),
+    ("gg / G", "Jump to the top / bottom"),
+    ("C-d / C-u", "Scroll half a screen"),
+    ("N", "Previous block of data"),
+];
+
+/* The commands of the : prompt as (name, arguments, what it does). Any unambiguous start of a name works too */
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("goto", "<address or region>", "Scroll to and highlight an address"),
+    ("width", "<bytes>", "Set the bytes per line"),
+    ("display", "<characters>", "Set the characters per line, 0 fits the terminal"),
+    ("theme", "[name]", "Use a color theme, or the next one"),
+    ("cells", "[occupancy|density|value|entropy|text]", "Choose what the characters show, or the next"),
+    ("file", "<number>", "Show that file"),
+    ("split", "", "Split view with the next file"),
+    ("overlay", "", "Overlay the next file"),
+    ("minimap", "", "Show / hide the minimap"),
+    ("copy", "", "Copy the selection / highlighted address"),
+    ("write", "<path>", "Write the selection to .hex / .bin"),
+    ("name", "[name]", "Name the selection as a region, no name removes it"),
+    ("snapshot", "<path>", "Write the screen to a text file"),
+    ("find", "[hex bytes or text]", "Mark where the data holds a pattern and go to a match, no pattern goes to the next"),
+    ("diff", "<next|prev>", "Jump to the next / previous difference between the compared files"),
+    ("export", "<ld|bitmask|regions|gdb|gaps> <path>", "Export the file on screen like the --export options"),
+    ("help", "", "List the commands"),
+    ("quit", "", "Quit"),
+];
+
+/* Looks a command up by its name or the start of one, which must then only fit one command */
+fn find_command(name: &str) -> Result<&'static (&'static str, &'static str, &'static str), String> {
+    if let Some(command) = COMMANDS.iter().find(|(command, _, _)| *command == name) {
+        return Ok(command);
+    }
+    let matching: Vec<_> = COMMANDS.iter().filter(|(command, _, _)| command.starts_with(name)).collect();
+    match matching[..] {
+        [command] => Ok(command),
+        [] => Err(format!("No command {name}, :help lists them")),
+        _ => Err(format!("{name} could be {}", matching.iter().map(|(command, _, _)| *command).collect::<Vec<_>>().join(", "))),
+    }
+}
+
+/* Which set of key bindings the viewer uses. The vim keymap adds its bindings on top of the default ones */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Keymap {
+    Default,
+    Vim,
+}
+
+/* How the map is laid out when the viewer opens */
+#[derive(Debug, Clone)]
+pub struct ViewOptions {
+    pub bytes_per_line: u16,
+    pub width_symbols: u16,
+    /// Fit width_symbols to the terminal, following resizes
+    pub auto_width: bool,
+    /// Written for unoccupied bytes when saving a selection as binary
+    pub fill_byte: u8,
+    pub theme: ThemeName,
+    /// The characters for occupied, empty and partly occupied cells
+    pub glyphs: Glyphs,
+    pub keymap: Keymap,
+    /// Reload files when they change on disk, using these options
+    pub watch: Option<LoadOptions>,
+    /// The region file the labels came from, which regions named in the viewer are saved to
+    pub regions_path: Option<String>,
+    /// The device flash, whose start and end are marked on the map
+    pub flash: Option<Flash>,
+    /// Labels from the build, e.g. linker map sections, shown and searched like the regions but never saved
+    pub annotations: Vec<Region>,
+    /// Start with the first file overlaid on the second
+    pub overlay: bool,
+}
+
+/* The most characters per line that fit the terminal between the gutter and minimap, and never more than one per byte */
+pub fn fit_width(bytes_per_line: u16) -> u16 {
+    fit_pane_width(bytes_per_line, 1)
+}
+
+/* The same for each of several panes side by side, each with its own gutter */
+fn fit_pane_width(bytes_per_line: u16, panes: u16) -> u16 {
+    (pane_width(panes).saturating_sub(GUTTER_W as u16)).clamp(1, bytes_per_line.max(1))
+}
+
+/* The columns each of several panes gets, leaving the minimap, the scrollbar and a column between panes */
+fn pane_width(panes: u16) -> u16 {
+    let (width, _) = terminal::size().unwrap_or((80, 24));
+    let panes = panes.max(1);
+    width.saturating_sub((MINIMAP_W + SCROLLBAR_W) as u16 + panes - 1) / panes
+}
+
+/* Where the thumb of a scrollbar along a track of the given length starts and how long it is */
+fn scroll_thumb(track: usize, shown: usize, total: usize, first: usize) -> (usize, usize) {
+    if total <= shown {
+        return (0, track);
+    }
+    let len = (track * shown / total).clamp(1, track);
+    let start = (track * first / total).min(track - len);
+    (start, len)
+}
+
+/* A minimap character for the share of set characters it summarizes, only fully set or blank at the extremes */
+fn minimap_char(set: u64, total: u64) -> char {
+    match set {
+        0 => '░',
+        set if set == total => '█',
+        set if set * 2 < total => '▒',
+        _ => '▓',
+    }
+}
+
+/* The address and length covered by one character of a row. The last character also takes the remainder */
+fn cell_span(row: &MapRow, cell: usize) -> (u32, u32) {
+    let cells = row.cells.len() as u32;
+    let bytes_per_char = row.len / cells;
+    let len = if cell as u32 == cells - 1 {row.len - bytes_per_char * (cells - 1)} else {bytes_per_char};
+    (row.addr + cell as u32 * bytes_per_char, len)
+}
+
+/**
+ * The character and style of a cell. With a second file to overlay, cells show which of the two hold data. A level
+ * from the value or entropy view replaces the data character with a bar of that height, otherwise a cell with only
+ * some of its bytes set gets the partial glyph.
+ */
+fn cell_glyph(set: bool, full: bool, overlay_set: Option<bool>, level: Option<usize>, theme: &Theme, glyphs: &Glyphs) -> (char, ContentStyle) {
+    match (set, overlay_set) {
+        (true, Some(false)) => (CHR_ONLY_A, theme.only_a),
+        (false, Some(true)) => (CHR_ONLY_B, theme.only_b),
+        (true, _) => match level {
+            Some(level) => (CHR_LEVELS[level], theme.data),
+            None => (if full {glyphs.occupied} else {glyphs.partial}, theme.data),
+        },
+        (false, _) => (glyphs.empty, theme.blank),
+    }
+}
+
+/* How a second file is shown next to the one on screen */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareMode {
+    Off,
+    /* Side by side panes */
+    Split,
+    /* One map marking where the files differ */
+    Overlay,
+}
+
+/* What each character of the map shows about the bytes it covers */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CellSource {
+    /* Whether any byte holds data */
+    Occupancy,
+    /* How many of the bytes hold data */
+    Density,
+    /* The average value of the bytes holding data */
+    Value,
+    /* The entropy of the bytes holding data */
+    Entropy,
+    /* How many of the bytes holding data are printable ASCII, so strings stand out */
+    Text,
+}
+
+impl CellSource {
+    fn next(self) -> CellSource {
+        match self {
+            CellSource::Occupancy => CellSource::Density,
+            CellSource::Density => CellSource::Value,
+            CellSource::Value => CellSource::Entropy,
+            CellSource::Entropy => CellSource::Text,
+            CellSource::Text => CellSource::Occupancy,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            CellSource::Occupancy => "occupancy",
+            CellSource::Density => "density",
+            CellSource::Value => "average value",
+            CellSource::Entropy => "entropy",
+            CellSource::Text => "text",
+        }
+    }
+}
+
+/* What a line of typed input on the status line is for */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromptKind {
+    Goto,
+    Search,
+    Command,
+    WriteSelection,
+    RegionName,
+    Snapshot,
+    LineWidth,
+    DisplayWidth,
+}
+
+struct Prompt {
+    kind: PromptKind,
+    input: String,
+}
+
+/* Parses a typed address, which is hex with or without a 0x prefix */
+fn parse_address(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u32::from_str_radix(&digits.replace('_', ""), 16).ok()
+}
+
+/* Where a file is scrolled to and what is highlighted, saved to go back to */
+#[derive(Debug, Clone, Copy)]
+struct ViewPosition {
+    top: usize,
+    highlight: Option<(usize, usize)>,
+    anchor: Option<(usize, usize)>,
+}
+
+/* A file still being parsed on a worker thread */
+struct Loading {
+    events: Receiver<LoadEvent>,
+    /* Bytes of the file parsed so far, and its size */
+    done: usize,
+    total: usize,
+}
+
+/**
+ * One loaded file and the view onto it. Each file keeps its own scroll position and highlight, so switching back
+ * to it picks up where it was left.
+ */
+struct Document {
+    file_path: String,
+    segment_map: SegmentMap,
+    /* Byte values, when the file was loaded with them */
+    values: Option<ValueMap>,
+    /* The execution start address, if the file gives one */
+    entry: Option<u32>,
+    rows: Vec<MapRow>,
+    /* Running count of set characters before each row, for summing any run of rows at once */
+    set_before: Vec<u64>,
+    /* Exact [start, end) occupied ranges, for hopping between data */
+    ranges: Vec<(u64, u64)>,
+    /* Where the data holds the pattern of the last :find, as [start, end) in address order */
+    found: Vec<(u64, u64)>,
+    stats: OccupancyStats,
+    /* Index of the first row shown */
+    top: usize,
+    /* The highlighted (row index, character) */
+    highlight: Option<(usize, usize)>,
+    /* Where a selection started. Everything between it and the highlight is selected */
+    anchor: Option<(usize, usize)>,
+    /* When the file was last changed on disk, as of loading it */
+    modified: Option<SystemTime>,
+    /* Set until the background load of the file finishes */
+    loading: Option<Loading>,
+}
+
+impl Document {
+    fn new(file_path: String, image: HexImage) -> Document {
+        Document {
+            ranges: occupied_ranges(&image.segment_map),
+            found: Vec::new(),
+            stats: OccupancyStats::from_map(&image.segment_map),
+            segment_map: image.segment_map,
+            values: image.values,
+            entry: image.entry,
+            rows: Vec::new(),
+            set_before: Vec::new(),
+            top: 0,
+            highlight: None,
+            anchor: None,
+            modified: file_modified(&file_path),
+            file_path,
+            loading: None,
+        }
+    }
+
+    /* Adds pages from a load still in progress. The rows need rebuilding afterwards */
+    fn add_pages(&mut self, pages: Vec<LoadedPage>) {
+        for loaded in pages {
+            self.segment_map.insert(loaded.page, loaded.segment);
+            if let (Some(values), Some(page_values)) = (self.values.as_mut(), loaded.values) {
+                values.insert(loaded.page, page_values);
+            }
+        }
+        self.ranges = occupied_ranges(&self.segment_map);
+        self.stats = OccupancyStats::from_map(&self.segment_map);
+    }
+
+    /* Swaps in a freshly loaded image of the file. The rows need rebuilding afterwards */
+    fn replace_image(&mut self, image: HexImage) {
+        self.ranges = occupied_ranges(&image.segment_map);
+        self.stats = OccupancyStats::from_map(&image.segment_map);
+        self.segment_map = image.segment_map;
+        self.values = image.values;
+        self.entry = image.entry;
+        /* The matches were of the old contents */
+        self.found.clear();
+    }
+
+    /* Every file is built with the pages touched by any file so their rows line up for comparison */
+    fn build_rows(&mut self, bytes_per_line: u16, width_symbols: u16, shared_pages: &[u16]) {
+        self.rows = map_rows(&self.segment_map, bytes_per_line, width_symbols, shared_pages);
+        self.set_before = std::iter::once(0)
+            .chain(self.rows.iter().scan(0, |total, row| {
+                *total += row.cells.iter().filter(|set| **set).count() as u64;
+                Some(*total)
+            }))
+            .collect();
+    }
+
+    /* The row holding an address, or the closest row before it */
+    fn row_for_addr(&self, addr: u32) -> usize {
+        self.rows.partition_point(|row| row.addr <= addr).saturating_sub(1)
+    }
+
+    fn top_addr(&self) -> u32 {
+        self.rows.get(self.top).map_or(0, |row| row.addr)
+    }
+
+    /* Whether every byte of [start, start + len) holds data, len staying within one page as a cell does */
+    fn cell_full(&self, start: u32, len: u32) -> bool {
+        self.segment_map.get(&((start / IHEX_SEGMENT_BYTES) as u16))
+            .is_some_and(|segment| count_set_bytes(segment, (start % IHEX_SEGMENT_BYTES) as u16, len) == len)
+    }
+
+    /**
+     * Where the bytes in [start, start + len) fall on the scale of CHR_LEVELS, by how many of them hold data or by the
+     * average value or entropy of those that do. None for the occupancy view, without stored values for the value and
+     * entropy views or if none of the bytes hold data.
+     */
+    fn cell_level(&self, start: u32, len: u32, source: CellSource) -> Option<usize> {
+        if source == CellSource::Density {
+            let set = count_set_bytes(self.segment_map.get(&((start / IHEX_SEGMENT_BYTES) as u16))?, (start % IHEX_SEGMENT_BYTES) as u16, len);
+            /* Rounded up, so a single byte still shows and only a full cell gets the full bar */
+            let level = (set as u64 * CHR_LEVELS.len() as u64).div_ceil(len as u64) as usize;
+            return level.checked_sub(1);
+        }
+        let values = self.values.as_ref()?;
+        let bytes: Vec<u8> = (start as u64..start as u64 + len as u64)
+            .filter_map(|addr| byte_value(&self.segment_map, values, addr as u32))
+            .collect();
+        if bytes.is_empty() {
+            return None;
+        }
+        let fraction = match source {
+            CellSource::Occupancy | CellSource::Density => return None,
+            CellSource::Value => bytes.iter().map(|byte| *byte as f64).sum::<f64>() / bytes.len() as f64 / 256.0,
+            CellSource::Entropy => byte_entropy(&bytes) / 8.0,
+            CellSource::Text => bytes.iter().filter(|byte| is_printable(**byte)).count() as f64 / bytes.len() as f64,
+        };
+        Some(((fraction * CHR_LEVELS.len() as f64) as usize).min(CHR_LEVELS.len() - 1))
+    }
+
+    fn position(&self) -> ViewPosition {
+        ViewPosition { top: self.top, highlight: self.highlight, anchor: self.anchor }
+    }
+
+    fn set_position(&mut self, position: ViewPosition) {
+        (self.top, self.highlight, self.anchor) = (position.top, position.highlight, position.anchor);
+    }
+
+    /* The first and last selected (row index, character), in map order */
+    fn selected_cells(&self) -> Option<((usize, usize), (usize, usize))> {
+        let (anchor, highlight) = (self.anchor?, self.highlight?);
+        Some((anchor.min(highlight), anchor.max(highlight)))
+    }
+}
+
+/* Every page touched by any of the files, in order */
+fn sorted_pages(docs: &[Document]) -> Vec<u16> {
+    let mut pages: Vec<u16> = docs.iter().flat_map(|doc| doc.segment_map.keys().cloned()).collect();
+    pages.sort();
+    pages.dedup();
+    pages
+}
+
+/**
+ * The first address from `from` on (or back from it) where two files differ, or agree if `differing` is false. Files
+ * differ in an address when only one of them has data there or, with values stored for both, the byte values differ.
+ */
+fn scan_differences(a: &Document, b: &Document, from: u32, forward: bool, differing: bool) -> Option<u32> {
+    let from_page = (from / IHEX_SEGMENT_BYTES) as u16;
+    let from_offset = (from % IHEX_SEGMENT_BYTES) as u16;
+    let pages: Box<dyn Iterator<Item = u16>> = if forward {Box::new(from_page..=u16::MAX)} else {Box::new((0..=from_page).rev())};
+    for page in pages {
+        let offsets = match (page == from_page, forward) {
+            (true, true) => from_offset..=u16::MAX,
+            (true, false) => 0..=from_offset,
+            _ => 0..=u16::MAX,
+        };
+        let page_base = page as u32 * IHEX_SEGMENT_BYTES;
+        let (segment_a, segment_b) = (a.segment_map.get(&page), b.segment_map.get(&page));
+        /* Pages neither file touches agree throughout */
+        if segment_a.is_none() && segment_b.is_none() {
+            if !differing {
+                return Some(page_base + if forward {*offsets.start()} else {*offsets.end()} as u32);
+            }
+            continue;
+        }
+        let values = a.values.as_ref().and_then(|values| values.get(&page))
+            .zip(b.values.as_ref().and_then(|values| values.get(&page)));
+        let differs = |offset: &u16| {
+            let set_a = segment_a.is_some_and(|segment| is_byte_set(segment, *offset));
+            let set_b = segment_b.is_some_and(|segment| is_byte_set(segment, *offset));
+            let values_differ = values.is_some_and(|(values_a, values_b)| values_a[*offset as usize] != values_b[*offset as usize]);
+            (set_a != set_b || set_a && values_differ) == differing
+        };
+        let found = if forward {offsets.into_iter().find(differs)} else {offsets.rev().find(differs)};
+        if let Some(offset) = found {
+            return Some(page_base + offset as u32);
+        }
+    }
+    None
+}
+
+/* Points at the first problem loading got past, a line which could not be read or else the end of file, or None when the file was fine */
+fn load_problem_text(file_path: &str, image: &HexImage) -> Option<String> {
+    let count = image.errors.len() + image.issues.len();
+    let first = match (image.errors.first(), image.issues.first()) {
+        (Some(error), _) => format!("line {}: {}", error.line, error.reason_text()),
+        (None, Some(issue)) => issue.to_string(),
+        (None, None) => return None,
+    };
+    let more = if count > 1 {format!(" (and {} more)", count - 1)} else {String::new()};
+    Some(format!("{file_path} {first}{more}"))
+}
+
+fn file_modified(file_path: &str) -> Option<SystemTime> {
+    fs::metadata(file_path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/* The address gutter text of a row, padded so the cells line up */
+fn gutter_text(addr: u32, hex_width: usize) -> String {
+    format!("{:<GUTTER_W$}", format!("{addr:#0hex_width$x}"))
+}
+
+/* How wide the gutter addresses of a set of rows have to be to all line up */
+fn gutter_hex_width(rows: &[MapRow]) -> usize {
+    let max_addr = rows.last().map_or(0, |row| row.addr as u64 + row.len as u64 - 1);
+    format!("{max_addr:#x}").len()
+}
+
+/**
+ * Renders the whole map of an image as plain text lines, the same as the interactive view shows them: the address
+ * gutter, the cells, then any region labels.
+ */
+pub fn map_text(image: HexImage, labels: &[Region], bytes_per_line: u16, width_symbols: u16, glyphs: Glyphs) -> Vec<String> {
+    let mut doc = Document::new(String::new(), image);
+    let pages = sorted_pages(std::slice::from_ref(&doc));
+    doc.build_rows(bytes_per_line, width_symbols, &pages);
+    let hex_width = gutter_hex_width(&doc.rows);
+    doc.rows.iter()
+        .map(|row| {
+            let mut line = gutter_text(row.addr, hex_width);
+            line.extend(row.cells.iter().enumerate().map(|(cell, set)| {
+                let (start, len) = cell_span(row, cell);
+                match (*set, doc.cell_full(start, len)) {
+                    (false, _) => glyphs.empty,
+                    (true, true) => glyphs.occupied,
+                    (true, false) => glyphs.partial,
+                }
+            }));
+            if let Some(label) = regions::line_label(labels, row.addr, row.len) {
+                line.push(' ');
+                line.push_str(&label);
+            }
+            line
+        })
+        .collect()
+}
+
+/**
+ * The interactive map. All lines are computed up front and the terminal shows a scrollable window onto them. Every
+ * file given is kept in memory and one of them is shown at a time.
+ */
+pub struct Viewer {
+    docs: Vec<Document>,
+    /* Index into docs of the file on screen */
+    active: usize,
+    labels: Vec<Region>,
+    regions_path: Option<String>,
+    flash: Option<Flash>,
+    annotations: Vec<Region>,
+    bytes_per_line: u16,
+    width_symbols: u16,
+    auto_width: bool,
+    fill_byte: u8,
+    theme: ThemeName,
+    glyphs: Glyphs,
+    keymap: Keymap,
+    watch: Option<LoadOptions>,
+    /* Set after a g in the vim keymap, waiting for the second key */
+    pending_g: bool,
+    /* Set after [ (false) or ] (true) while comparing, waiting for a c to jump between differences */
+    pending_bracket: Option<bool>,
+    /* Shown on the bottom line, e.g. the details of a clicked cell */
+    status: String,
+    /* Input being typed on the status line, which takes all key presses while open */
+    prompt: Option<Prompt>,
+    /* The top, highlight and anchor from before a search, restored if it is cancelled */
+    search_origin: Option<ViewPosition>,
+    /* The (start, length) shown in the hexdump panel while it is open */
+    inspector: Option<(u32, u32)>,
+    /* The lines of the record panel while it is open */
+    records: Option<Vec<String>>,
+    /* Bookmarked addresses in the order they were made. They apply to every file, so builds can be compared */
+    bookmarks: Vec<u32>,
+    show_bookmarks: bool,
+    show_minimap: bool,
+    /* Show the next file alongside (or on top of) the one on screen */
+    compare: CompareMode,
+    source: CellSource,
+    show_help: bool,
+    /* The first character of each line shown, when the lines are wider than the screen. Shared by every file */
+    left: usize,
+    /* What is on the terminal now, to only write what changes */
+    last_frame: Option<Frame>,
+    /* Set by the quit command, as the prompt cannot end the event loop itself */
+    quit: bool,
+}
+
+impl Viewer {
+    /* Takes each file's path and image in the order given. The first one is shown on start */
+    pub fn new(files: Vec<(String, HexImage)>, labels: Vec<Region>, options: ViewOptions) -> Viewer {
+        let docs = files.into_iter().map(|(file_path, image)| Document::new(file_path, image)).collect();
+        Viewer::with_docs(docs, labels, options)
+    }
+
+    /**
+     * Takes each file's path and contents, which are parsed on worker threads. The map comes up straight away and
+     * fills in as the parsing goes.
+     */
+    pub fn load_in_background(files: Vec<(String, HexReader)>, labels: Vec<Region>, options: ViewOptions, load_options: LoadOptions) -> Viewer {
+        let docs = files.into_iter()
+            .map(|(file_path, hex)| {
+                let total = hex.size;
+                let empty = HexImage {
+                    segment_map: SegmentMap::new(),
+                    values: if load_options.store_values {Some(ValueMap::new())} else {None},
+                    errors: Vec::new(),
+                    issues: Vec::new(),
+                    entry: None,
+                };
+                let mut doc = Document::new(file_path, empty);
+                let events = hex_loader::load_in_background(hex, load_options);
+                doc.loading = Some(Loading { events, done: 0, total });
+                doc
+            })
+            .collect();
+        Viewer::with_docs(docs, labels, options)
+    }
+
+    fn with_docs(mut docs: Vec<Document>, labels: Vec<Region>, options: ViewOptions) -> Viewer {
+        let pages = sorted_pages(&docs);
+        for doc in &mut docs {
+            doc.build_rows(options.bytes_per_line, options.width_symbols, &pages);
+        }
+        let compare = if options.overlay && docs.len() > 1 {CompareMode::Overlay} else {CompareMode::Off};
+        Viewer {
+            docs,
+            active: 0,
+            labels,
+            regions_path: options.regions_path,
+            flash: options.flash,
+            annotations: options.annotations,
+            bytes_per_line: options.bytes_per_line,
+            width_symbols: options.width_symbols,
+            auto_width: options.auto_width,
+            fill_byte: options.fill_byte,
+            theme: options.theme,
+            glyphs: options.glyphs,
+            keymap: options.keymap,
+            watch: options.watch,
+            pending_g: false,
+            pending_bracket: None,
+            status: String::new(),
+            prompt: None,
+            search_origin: None,
+            inspector: None,
+            records: None,
+            bookmarks: Vec::new(),
+            show_bookmarks: false,
+            show_minimap: true,
+            compare,
+            source: CellSource::Occupancy,
+            show_help: false,
+            left: 0,
+            last_frame: None,
+            quit: false,
+        }
+    }
+
+    fn doc(&self) -> &Document {
+        &self.docs[self.active]
+    }
+
+    fn doc_mut(&mut self) -> &mut Document {
+        &mut self.docs[self.active]
+    }
+
+    /* The two files being compared, the file on screen and the one after it */
+    fn compare_pair(&self) -> Option<(usize, usize)> {
+        if self.compare != CompareMode::Off && self.docs.len() > 1 {
+            Some((self.active, (self.active + 1) % self.docs.len()))
+        } else {
+            None
+        }
+    }
+
+    fn panes(&self) -> u16 {
+        if self.compare == CompareMode::Split {2} else {1}
+    }
+
+    /* Switches to a comparison mode, or back to a single map if it is already on, refitting an auto width map */
+    fn toggle_compare(&mut self, mode: CompareMode) {
+        if self.docs.len() < 2 {
+            self.status = "Comparing needs a second --file".to_string();
+            return;
+        }
+        self.compare = if self.compare == mode {CompareMode::Off} else {mode};
+        if self.auto_width {
+            self.set_bytes_per_line(self.bytes_per_line);
+        }
+        self.status = match self.compare_pair() {
+            Some((a, b)) => format!("{} vs {}, Tab changes the pair", self.docs[a].file_path, self.docs[b].file_path),
+            None => String::new(),
+        };
+        if self.compare == CompareMode::Overlay {
+            self.status.push_str(&format!(", {CHR_ONLY_A} only in the first, {CHR_ONLY_B} only in the second"));
+        }
+    }
+
+    /* Shows another loaded file. The hexdump panel shows the previous file's bytes so it is closed */
+    fn switch_to(&mut self, idx: usize) {
+        if idx >= self.docs.len() {
+            self.status = format!("There is no file {}, {} loaded", idx + 1, self.docs.len());
+            return;
+        }
+        /* Comparing scrolls both files together, so the new file takes over the position */
+        let (top, highlight) = (self.doc().top, self.doc().highlight);
+        self.active = idx;
+        self.inspector = None;
+        self.records = None;
+        if self.compare != CompareMode::Off {
+            self.doc_mut().top = top;
+            self.doc_mut().highlight = highlight;
+        }
+        let top = self.doc().top.min(self.max_top());
+        self.doc_mut().top = top;
+        self.status = format!("File {} of {}: {}", idx + 1, self.docs.len(), self.doc().file_path);
+    }
+
+    /* How many map rows fit above the footer */
+    fn visible_rows(&self) -> usize {
+        let (_, height) = terminal::size().unwrap_or((80, 24));
+        let scrollbar = if self.scrolls_sideways() {1} else {0};
+        height.saturating_sub(MAP_TOP + FOOTER_ROWS + scrollbar).max(1) as usize
+    }
+
+    /* How many characters of each line fit on screen */
+    fn visible_cells(&self) -> usize {
+        (pane_width(self.panes()) as usize).saturating_sub(GUTTER_W).max(1)
+    }
+
+    /* Whether the lines are wider than the screen, which then scrolls sideways too */
+    fn scrolls_sideways(&self) -> bool {
+        self.width_symbols as usize > self.visible_cells()
+    }
+
+    /* The first character of each line on screen */
+    fn first_cell(&self) -> usize {
+        self.left.min((self.width_symbols as usize).saturating_sub(self.visible_cells()))
+    }
+
+    fn scroll_sideways(&mut self, delta: isize) {
+        self.left = self.first_cell().saturating_add_signed(delta);
+        self.left = self.first_cell();
+    }
+
+    /* Scrolls sideways just enough to bring a character onto the screen */
+    fn reveal_cell(&mut self, cell: usize) {
+        let first = self.first_cell();
+        if cell < first {
+            self.left = cell;
+        } else if cell >= first + self.visible_cells() {
+            self.left = cell + 1 - self.visible_cells();
+        }
+    }
+
+    fn max_top(&self) -> usize {
+        self.doc().rows.len().saturating_sub(self.visible_rows())
+    }
+
+    fn scroll_by(&mut self, delta: isize) {
+        let top = self.doc().top.saturating_add_signed(delta).min(self.max_top());
+        self.doc_mut().top = top;
+    }
+
+    /* Re-renders every file at a new line width, keeping the same address at the top of each */
+    fn set_bytes_per_line(&mut self, bytes_per_line: u16) {
+        self.bytes_per_line = bytes_per_line;
+        self.width_symbols = if self.auto_width {fit_pane_width(bytes_per_line, self.panes())} else {self.width_symbols.min(bytes_per_line)};
+        self.relayout();
+    }
+
+    /* Re-renders every file with a new number of characters per line, 0 fitting the terminal */
+    fn set_width_symbols(&mut self, width_symbols: u16) {
+        self.auto_width = width_symbols == 0;
+        self.width_symbols = if self.auto_width {fit_pane_width(self.bytes_per_line, self.panes())} else {width_symbols.min(self.bytes_per_line)};
+        self.relayout();
+    }
+
+    /* Describes the current layout, warning when the line does not divide evenly between the characters */
+    fn describe_layout(&self) -> String {
+        let mut text = format!("{:#x} bytes per line over {} characters, {} bytes/char",
+                               self.bytes_per_line, self.width_symbols, self.bytes_per_line / self.width_symbols);
+        let rem = self.bytes_per_line % self.width_symbols;
+        if rem > 0 {
+            text.push_str(&format!(" (the last character also covers the {rem} left over)"));
+        }
+        text
+    }
+
+    /* Rebuilds the rows of every file after a layout change. The characters move so the highlights are dropped */
+    fn relayout(&mut self) {
+        self.rebuild_rows();
+        for doc in &mut self.docs {
+            doc.highlight = None;
+        }
+    }
+
+    /* Rebuilds the rows of every file, keeping the same address at the top of each and the highlight on its line */
+    fn rebuild_rows(&mut self) {
+        let pages = sorted_pages(&self.docs);
+        let visible_rows = self.visible_rows();
+        for doc in &mut self.docs {
+            let top_addr = doc.top_addr();
+            let highlight = doc.highlight.map(|(row_idx, cell)| (doc.rows[row_idx].addr, cell));
+            doc.build_rows(self.bytes_per_line, self.width_symbols, &pages);
+            doc.top = doc.row_for_addr(top_addr).min(doc.rows.len().saturating_sub(visible_rows));
+            doc.highlight = highlight.map(|(addr, cell)| (doc.row_for_addr(addr), cell.min(self.width_symbols as usize - 1)));
+            doc.anchor = None;
+        }
+    }
+
+    /* The row and character covering an address */
+    fn cell_for_addr(&self, addr: u32) -> (usize, usize) {
+        let row_idx = self.doc().row_for_addr(addr);
+        let row = &self.doc().rows[row_idx];
+        let bytes_per_char = (row.len / row.cells.len() as u32).max(1);
+        let cell = (addr.saturating_sub(row.addr) / bytes_per_char) as usize;
+        (row_idx, cell.min(row.cells.len() - 1))
+    }
+
+    /* Scrolls so a row is in the middle of the screen, or as close as the ends of the map allow */
+    fn center_on(&mut self, row_idx: usize) {
+        let top = row_idx.saturating_sub(self.visible_rows() / 2).min(self.max_top());
+        self.doc_mut().top = top;
+    }
+
+    /* Scrolls to and highlights the character holding an address */
+    fn goto_addr(&mut self, addr: u32) {
+        if self.doc().rows.is_empty() {
+            return;
+        }
+        let (row_idx, cell) = self.cell_for_addr(addr);
+        self.center_on(row_idx);
+        self.reveal_cell(cell);
+        self.doc_mut().highlight = Some((row_idx, cell));
+        self.doc_mut().anchor = None;
+        self.status = self.describe_cell(row_idx, cell).unwrap_or_default();
+    }
+
+    /* Reflows an auto width map to the new terminal size and keeps the scroll position in range */
+    fn handle_resize(&mut self) {
+        if self.auto_width {
+            self.set_bytes_per_line(self.bytes_per_line);
+        }
+        let top = self.doc().top.min(self.max_top());
+        self.doc_mut().top = top;
+    }
+
+    /* Halves (zoom in) or doubles (zoom out) the bytes each character covers, within one byte and one page per line */
+    fn zoom(&mut self, zoom_in: bool) {
+        if zoom_in && self.bytes_per_line / 2 >= self.width_symbols {
+            self.set_bytes_per_line(self.bytes_per_line / 2);
+        } else if !zoom_in && self.bytes_per_line <= u16::MAX / 2 {
+            self.set_bytes_per_line(self.bytes_per_line * 2);
+        }
+    }
+
+    /* Halves or doubles the characters per line, within one character and one byte per character */
+    fn widen(&mut self, wider: bool) {
+        let width_symbols = if wider {self.width_symbols.saturating_mul(2)} else {self.width_symbols / 2};
+        self.set_width_symbols(width_symbols.clamp(1, self.bytes_per_line));
+        self.status = self.describe_layout();
+    }
+
+    /* Describes the cell at a row index and character, or None if that is off the map */
+    fn describe_cell(&self, row_idx: usize, cell: usize) -> Option<String> {
+        let row = self.doc().rows.get(row_idx)?;
+        if cell >= row.cells.len() {
+            return None;
+        }
+        let (start, len) = cell_span(row, cell);
+        let set = match self.doc().segment_map.get(&((start / IHEX_SEGMENT_BYTES) as u16)) {
+            Some(segment) => count_set_bytes(segment, (start % IHEX_SEGMENT_BYTES) as u16, len),
+            None => 0,
+        };
+        Some(format!("{start:#010x}-{:#010x}: {set} of {len} bytes set", start as u64 + len as u64 - 1))
+    }
+
+    /* The [start, end) addresses covered by the selection, with a 64 bit end so it can take in the top byte */
+    fn selection(&self) -> Option<(u32, u64)> {
+        let (first, last) = self.doc().selected_cells()?;
+        let (start, _) = cell_span(&self.doc().rows[first.0], first.1);
+        let (last_start, last_len) = cell_span(&self.doc().rows[last.0], last.1);
+        Some((start, last_start as u64 + last_len as u64))
+    }
+
+    /* Opens the hexdump panel on the selection, or else the highlighted character */
+    fn open_inspector(&mut self) {
+        let span = match (self.selection(), self.doc().highlight) {
+            /* A selection of the whole address space is one byte longer than a u32, and no dump shows that much */
+            (Some((start, end)), _) => (start, u32::try_from(end - start as u64).unwrap_or(u32::MAX)),
+            (None, Some((row_idx, cell))) => cell_span(&self.doc().rows[row_idx], cell),
+            (None, None) => {
+                self.status = "Highlight a cell first (click, g, n or p)".to_string();
+                return;
+            },
+        };
+        if self.doc().values.is_none() {
+            self.status = "Byte values were not stored, restart with --values to inspect them".to_string();
+            return;
+        }
+        self.inspector = Some(span);
+    }
+
+    /**
+     * Lists the data records of the file that put bytes into the highlighted character, with the extended address
+     * record each one depends on. The file is read again, so it shows what is on disk now.
+     */
+    fn open_records(&mut self) {
+        let Some((row_idx, cell)) = self.doc().highlight else {
+            self.status = "Highlight a cell first (click, g, n or p)".to_string();
+            return;
+        };
+        let (start, len) = cell_span(&self.doc().rows[row_idx], cell);
+        let mut search = hex_loader::RecordSearch::new(start, start as u64 + len as u64);
+        if let Err(e) = hex_loader::for_each_line(&self.doc().file_path, |line, text| search.line(line, text)) {
+            self.status = format!("Could not read {}: {e}", self.doc().file_path);
+            return;
+        }
+        let found = search.finish();
+        let mut lines = vec![format!("{} records at {start:#010x} +{len:#x} (Esc closes)", found.len())];
+        for record in &found {
+            lines.push(format!("line {:<7} Data {:#010x} +{:#x}", record.line, record.addr(), record.len));
+            let base = match &record.base {
+                Some((line, Record::ExtendedLinearAddress(addr))) => format!("linear base {addr:#06x} from line {line}"),
+                Some((line, Record::ExtendedSegmentAddress(addr))) => format!("segment base {addr:#06x} from line {line}"),
+                _ => "no extended address".to_string(),
+            };
+            lines.push(format!("  {base}"));
+        }
+        self.records = Some(lines);
+    }
+
+    /* Moves the highlight by whole characters or rows, starting a selection from where it was */
+    fn extend_selection(&mut self, rows: isize, cells: isize) {
+        let doc = self.doc_mut();
+        doc.anchor = doc.anchor.or(Some(doc.highlight.unwrap_or((doc.top, 0))));
+        self.move_highlight(rows, cells);
+        self.show_selection();
+    }
+
+    /* Moves the highlight (or the first character on screen) by whole characters or rows, scrolling to keep it shown */
+    fn move_highlight(&mut self, rows: isize, cells: isize) {
+        let visible_rows = self.visible_rows();
+        let doc = self.doc_mut();
+        let Some(last_row) = doc.rows.len().checked_sub(1) else {
+            return;
+        };
+        let (row_idx, cell) = doc.highlight.unwrap_or((doc.top, 0));
+        let width = doc.rows[row_idx].cells.len() as isize;
+        /* Moving sideways past the end of a line continues on the next or previous line */
+        let linear = (row_idx as isize + rows) * width + cell as isize + cells;
+        let linear = linear.clamp(0, last_row as isize * width + width - 1);
+        let moved = ((linear / width) as usize, (linear % width) as usize);
+        doc.highlight = Some(moved);
+        if moved.0 < doc.top {
+            doc.top = moved.0;
+        } else if moved.0 >= doc.top + visible_rows {
+            doc.top = moved.0 + 1 - visible_rows;
+        }
+        self.reveal_cell(moved.1);
+    }
+
+    /* Moves the highlight as a cursor with the arrow keys. The first press puts it on the top left character shown */
+    fn move_cursor(&mut self, rows: isize, cells: isize) {
+        if self.doc().highlight.is_some() {
+            self.step_highlight(rows, cells);
+        } else {
+            let first_cell = self.first_cell();
+            let doc = self.doc_mut();
+            if !doc.rows.is_empty() {
+                doc.highlight = Some((doc.top, first_cell));
+            }
+            self.step_highlight(0, 0);
+        }
+    }
+
+    /* Moves the highlight on its own, dropping any selection, and describes where it lands */
+    fn step_highlight(&mut self, rows: isize, cells: isize) {
+        self.doc_mut().anchor = None;
+        self.move_highlight(rows, cells);
+        if let Some((row_idx, cell)) = self.doc().highlight {
+            self.status = self.describe_cell(row_idx, cell).unwrap_or_default();
+        }
+    }
+
+    /* Puts the selection bounds on the message line */
+    fn show_selection(&mut self) {
+        self.status = match self.selection() {
+            Some((start, end)) => {
+                let ranges = clip_ranges(&self.doc().ranges, start as u64, end);
+                let set: u64 = ranges.iter().map(|(s, e)| e - s).sum();
+                format!("Selected {start:#010x}-{:#010x}: {} bytes, {set} set", end - 1, end - start as u64)
+            },
+            None => "Nothing selected, use shift+arrows or drag with the mouse".to_string(),
+        };
+    }
+
+    /**
+     * Copies the selection as start..end (end exclusive, as gdb's dump memory takes it) to the clipboard, or the
+     * address of the highlighted character when nothing is selected.
+     */
+    fn copy_selection(&mut self) {
+        let text = match (self.selection(), self.doc().highlight) {
+            (Some((start, end)), _) => format!("{start:#010x}..{end:#010x}"),
+            (None, Some((row_idx, cell))) => format!("{:#010x}", cell_span(&self.doc().rows[row_idx], cell).0),
+            (None, None) => {
+                self.status = "Nothing to copy, click a character or select a range first".to_string();
+                return;
+            },
+        };
+        self.status = match clipboard::copy(&mut stdout(), &text) {
+            Ok(()) => format!("Copied {text} to the clipboard"),
+            Err(e) => format!("Could not copy {text}: {e}"),
+        };
+    }
+
+    /* Saves the selected addresses to a .hex (occupied bytes only) or .bin (every byte, gaps filled) file */
+    fn write_selection(&mut self, path: &str) {
+        let Some((start, end)) = self.selection() else {
+            self.status = "Nothing selected".to_string();
+            return;
+        };
+        let doc = self.doc();
+        let Some(values) = &doc.values else {
+            self.status = "Byte values were not stored, restart with --values to write them".to_string();
+            return;
+        };
+        let contents = if path.ends_with(".bin") {
+            Ok(exports::range_bin(&doc.segment_map, values, start as u64, end, self.fill_byte))
+        } else {
+            exports::range_hex(&doc.ranges, &doc.segment_map, values, start as u64, end)
+                .map(String::into_bytes)
+                .map_err(|e| e.to_string())
+        };
+        self.status = match contents.and_then(|contents| fs::write(path, contents).map_err(|e| e.to_string())) {
+            Ok(()) => format!("Wrote {start:#010x}-{:#010x} to {path}", end - 1),
+            Err(e) => format!("Could not write {path}: {e}"),
+        };
+    }
+
+    /* The [start, end) addresses a region named now would cover: the selection, or else the highlighted character */
+    fn annotation_span(&self) -> (u32, u64) {
+        self.selection().unwrap_or_else(|| {
+            let (start, len) = self.current_span();
+            (start, start as u64 + len as u64)
+        })
+    }
+
+    /* Asks for the name of a new region, filled in with the name of one already covering exactly the same addresses */
+    fn open_region_name(&mut self) {
+        let (start, end) = self.annotation_span();
+        let input = self.labels.iter()
+            .find(|region| region.start == start && region.size as u64 == end - start as u64)
+            .map_or(String::new(), |region| region.name.clone());
+        self.prompt = Some(Prompt { kind: PromptKind::RegionName, input });
+    }
+
+    /**
+     * Names the selection (or highlighted character) as a region, renaming a region of exactly the same addresses or
+     * removing it if the name is empty, then saves every region to the --regions file. The file is rewritten in the
+     * format write_regions produces, sorted by address, so any comments in it are lost.
+     */
+    fn name_region(&mut self, name: &str) {
+        let (start, end) = self.annotation_span();
+        let existing = self.labels.iter().position(|region| region.start == start && region.size as u64 == end - start as u64);
+        let action = match (existing, name.is_empty()) {
+            (Some(idx), true) => {
+                let removed = self.labels.remove(idx);
+                format!("Removed region {}", removed.name)
+            },
+            (None, true) => {
+                self.status = format!("No region covers exactly {start:#010x}-{:#010x} to remove", end - 1);
+                return;
+            },
+            (Some(idx), false) => {
+                self.labels[idx].name = name.to_string();
+                format!("Renamed region {start:#010x}-{:#010x} to {name}", end - 1)
+            },
+            (None, false) => {
+                /* Region sizes are 32 bit, so the whole address space cannot be named as one */
+                let Ok(size) = u32::try_from(end - start as u64) else {
+                    self.status = format!("A region can cover at most {:#x} bytes", u32::MAX);
+                    return;
+                };
+                self.labels.push(Region { name: name.to_string(), start, size, budget: None });
+                self.labels.sort_by_key(|region| (region.start, region.size));
+                format!("Named {start:#010x}-{:#010x} {name}", end - 1)
+            },
+        };
+        self.status = match &self.regions_path {
+            Some(path) => match fs::write(path, regions::write_regions(&self.labels, regions::RegionFormat::from_path(path))) {
+                Ok(()) => format!("{action}, saved to {path}"),
+                Err(e) => format!("{action}, but could not save {path}: {e}"),
+            },
+            None => format!("{action}, not saved without --regions"),
+        };
+    }
+
+    /* Classic address / hex / ASCII dump lines of a range, with unset bytes shown as -- */
+    fn hexdump_lines(&self, start: u32, len: u32, max_lines: usize) -> Vec<String> {
+        let doc = self.doc();
+        let Some(values) = &doc.values else {
+            return Vec::new();
+        };
+        let end = start as u64 + len as u64;
+        let mut lines = Vec::new();
+        let mut line_addr = start as u64 & !0xF;
+        while line_addr < end && lines.len() < max_lines {
+            let mut hex = String::with_capacity(16 * 3);
+            let mut ascii = String::with_capacity(16);
+            for addr in line_addr..line_addr + 16 {
+                let page = (addr / IHEX_SEGMENT_BYTES as u64) as u16;
+                let offset = (addr % IHEX_SEGMENT_BYTES as u64) as u16;
+                let in_range = addr >= start as u64 && addr < end;
+                let is_set = in_range && doc.segment_map.get(&page).is_some_and(|segment| is_byte_set(segment, offset));
+                match values.get(&page).filter(|_| is_set) {
+                    Some(page_values) => {
+                        let byte = page_values[offset as usize];
+                        hex.push_str(&format!("{byte:02x} "));
+                        ascii.push(if byte.is_ascii_graphic() || byte == b' ' {byte as char} else {'.'});
+                    },
+                    None => {
+                        hex.push_str(if in_range {"-- "} else {"   "});
+                        ascii.push(' ');
+                    },
+                }
+            }
+            lines.push(format!("{line_addr:#010x}  {hex}{ascii}"));
+            line_addr += 16;
+        }
+        if line_addr < end {
+            lines.push(format!("... {} more bytes", end - line_addr));
+        }
+        lines
+    }
+
+    /* Draws lines in a bordered column of the given width against the right edge, over the map */
+    fn draw_panel(&self, frame: &mut Frame, lines: &[String], panel_w: usize) {
+        let (width, _) = frame.size();
+        let column = (width as usize).saturating_sub(panel_w) as u16;
+        for (i, line) in lines.iter().enumerate() {
+            frame.move_to(column, MAP_TOP + i as u16);
+            frame.print(&format!("│{:<w$}", line, w = panel_w - 1));
+        }
+    }
+
+    fn draw_inspector(&self, frame: &mut Frame, start: u32, len: u32) {
+        let (_, height) = frame.size();
+        let max_lines = height.saturating_sub(MAP_TOP + FOOTER_ROWS + 1) as usize;
+        let title = format!("{start:#010x} +{len:#x} (Esc closes)");
+        let mut lines = vec![title];
+        lines.extend(self.hexdump_lines(start, len, max_lines.saturating_sub(2)));
+        self.draw_panel(frame, &lines, INSPECTOR_W)
+    }
+
+    /**
+     * A one column overview of the whole map next to the scrollbar. Each character sums up an equal share of the
+     * rows, shaded by how many of their characters are set, and the ones covering the rows on screen are highlighted.
+     */
+    fn draw_minimap(&self, frame: &mut Frame, theme: &Theme) {
+        let doc = self.doc();
+        let (width, _) = frame.size();
+        let height = self.visible_rows();
+        let total_rows = doc.rows.len();
+        if total_rows == 0 {
+            return;
+        }
+        let cells_per_row = doc.rows[0].cells.len() as u64;
+        for screen_row in 0..height.min(total_rows) {
+            let first = screen_row * total_rows / height.min(total_rows);
+            let last = ((screen_row + 1) * total_rows / height.min(total_rows)).max(first + 1);
+            let set = doc.set_before[last] - doc.set_before[first];
+            let chr = minimap_char(set, (last - first) as u64 * cells_per_row);
+            let in_view = first < doc.top + height && last > doc.top;
+            let chr_style = if in_view {theme.highlight} else if set > 0 {theme.data} else {theme.blank};
+            frame.move_to(width.saturating_sub(1 + SCROLLBAR_W as u16), MAP_TOP + screen_row as u16);
+            frame.print_styled(chr_style.apply(chr));
+        }
+    }
+
+    /**
+     * A vertical scrollbar in the last column for where the rows on screen are in the whole map and, when the lines
+     * are wider than the screen, a horizontal one under the map for which characters of them are shown.
+     */
+    fn draw_scrollbars(&self, frame: &mut Frame, theme: &Theme) {
+        let doc = self.doc();
+        let (width, _) = frame.size();
+        let rows = self.visible_rows();
+        let (thumb_start, thumb_len) = scroll_thumb(rows, rows, doc.rows.len(), doc.top);
+        for screen_row in 0..rows {
+            let on_thumb = (thumb_start..thumb_start + thumb_len).contains(&screen_row);
+            frame.move_to(width.saturating_sub(1), MAP_TOP + screen_row as u16);
+            frame.print_styled(if on_thumb {theme.data.apply(CHR_THUMB)} else {theme.blank.apply(CHR_TRACK_V)});
+        }
+        if !self.scrolls_sideways() {
+            return;
+        }
+        let cells = self.visible_cells();
+        let (thumb_start, thumb_len) = scroll_thumb(cells, cells, self.width_symbols as usize, self.first_cell());
+        frame.move_to(GUTTER_W as u16, MAP_TOP + rows as u16);
+        for cell in 0..cells {
+            let on_thumb = (thumb_start..thumb_start + thumb_len).contains(&cell);
+            frame.print_styled(if on_thumb {theme.data.apply(CHR_THUMB)} else {theme.blank.apply(CHR_TRACK_H)});
+        }
+    }
+
+    fn draw_records(&self, frame: &mut Frame, lines: &[String]) {
+        let max_lines = self.visible_rows().saturating_sub(1);
+        if lines.len() <= max_lines {
+            self.draw_panel(frame, lines, RECORDS_W);
+            return;
+        }
+        let mut shown = lines[..max_lines - 1].to_vec();
+        shown.push(format!("... {} more lines", lines.len() - shown.len()));
+        self.draw_panel(frame, &shown, RECORDS_W)
+    }
+
+    /* The numbered bookmarks, each with the region it falls in on the current file if any */
+    fn draw_bookmarks(&self, frame: &mut Frame) {
+        let mut lines = vec!["Bookmarks (1-9 jumps)".to_string()];
+        for (i, addr) in self.bookmarks.iter().enumerate() {
+            let region = self.labels.iter().chain(&self.annotations)
+                .find(|r| *addr >= r.start && ((*addr - r.start) as u64) < r.size as u64)
+                .map_or("", |r| r.name.as_str());
+            lines.push(format!("{:>2} {addr:#010x} {region}", i + 1));
+        }
+        if self.bookmarks.is_empty() {
+            lines.push("None yet, m adds one".to_string());
+        }
+        self.draw_panel(frame, &lines, BOOKMARKS_W)
+    }
+
+    /* The persistent bar above the message line: file, occupancy, where the view is and what the cursor is on */
+    fn status_bar(&self, last_shown: usize) -> String {
+        let doc = self.doc();
+        /* With several files loaded the bar leads with which one is on screen */
+        let file = if let Some((_, other)) = self.compare_pair() {
+            let overlay = if self.compare == CompareMode::Overlay {" (overlay)"} else {""};
+            format!("[{}/{}] {} vs {}{}", self.active + 1, self.docs.len(), doc.file_path, self.docs[other].file_path, overlay)
+        } else if self.docs.len() > 1 {
+            format!("[{}/{}] {}", self.active + 1, self.docs.len(), doc.file_path)
+        } else {
+            doc.file_path.clone()
+        };
+        let file = match &doc.loading {
+            Some(loading) => format!("{file} (loading {}%)", (loading.done * 100).checked_div(loading.total).unwrap_or(0)),
+            None => file,
+        };
+        let cursor = doc.highlight
+            .and_then(|(row_idx, cell)| self.describe_cell(row_idx, cell))
+            .map(|cell| format!(" | cursor {cell}"))
+            .unwrap_or_default();
+        format!(
+            " {} | {} bytes used in {} ranges on {} pages | {:.1}% of span | {:#010x} lines {}-{} of {} | {} bytes/char{} | ? help",
+            file, doc.stats.bytes_used, doc.stats.regions, doc.stats.pages, doc.stats.fill_percent(), doc.top_addr(),
+            doc.top + 1, last_shown, doc.rows.len(), self.bytes_per_line / self.width_symbols, cursor)
+    }
+
+    /* A centered box with the key bindings and the current settings */
+    fn draw_help(&self, frame: &mut Frame) {
+        let mut lines: Vec<String> = vec!["Keys".to_string()];
+        let vim_keys = if self.keymap == Keymap::Vim {VIM_KEY_HELP} else {&[]};
+        lines.extend(KEY_HELP.iter().chain(vim_keys).map(|(key, action)| format!("  {key:<13} {action}")));
+        lines.push(String::new());
+        lines.push("Settings".to_string());
+        lines.push(format!("  File            {} ({} of {})", self.doc().file_path, self.active + 1, self.docs.len()));
+        lines.push(format!("  Line width      {:#x} bytes", self.bytes_per_line));
+        lines.push(format!("  Display width   {} characters{}", self.width_symbols, if self.auto_width {" (auto)"} else {""}));
+        lines.push(format!("  Bytes per char  {}", self.bytes_per_line / self.width_symbols));
+        lines.push(format!("  Values stored   {}", if self.doc().values.is_some() {"yes"} else {"no"}));
+        if let Some(flash) = &self.flash {
+            lines.push(format!("  Device flash    {} {:#010x}-{:#010x}", flash.name, flash.start, flash.end().saturating_sub(1)));
+        }
+        lines.push(format!("  Entry point     {}", self.doc().entry.map_or("none".to_string(), |entry| format!("{entry:#010x}"))));
+        lines.push(format!("  Regions loaded  {}", self.labels.len()));
+        lines.push(format!("  Build labels    {}", self.annotations.len()));
+        lines.push(format!("  Cells show      {}", self.source.label()));
+        lines.push(format!("  Theme           {}", self.theme.label()));
+        lines.push(format!("  Keymap          {}", if self.keymap == Keymap::Vim {"vim"} else {"default"}));
+        lines.push(String::new());
+        lines.push("Press any key to close".to_string());
+
+        let (width, height) = frame.size();
+        let box_w = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) + 4;
+        let left = (width as usize).saturating_sub(box_w) / 2;
+        let top = (height as usize).saturating_sub(lines.len() + 2) / 2;
+        frame.move_to(left as u16, top as u16);
+        frame.print(&format!("┌{}┐", "─".repeat(box_w - 2)));
+        for (i, line) in lines.iter().enumerate() {
+            frame.move_to(left as u16, (top + 1 + i) as u16);
+            frame.print(&format!("│ {line:<w$} │", w = box_w - 4));
+        }
+        frame.move_to(left as u16, (top + 1 + lines.len()) as u16);
+        frame.print(&format!("└{}┘", "─".repeat(box_w - 2)));
+    }
+
+    /* The characters of a row as drawn, leaving out the highlight, from first_cell up to max_cells of them */
+    fn row_glyphs(&self, doc: &Document, overlay: Option<&Document>, row_idx: usize, first_cell: usize, max_cells: usize, theme: &Theme) -> Vec<(char, ContentStyle)> {
+        let row = &doc.rows[row_idx];
+        let overlay_cells = overlay.map(|other| &other.rows[row_idx].cells);
+        let first_cell = first_cell.min(row.cells.len());
+        let entry_cell = doc.entry
+            .filter(|entry| row.addr <= *entry && (*entry as u64) < row.addr as u64 + row.len as u64)
+            .map(|entry| (((entry - row.addr) / (row.len / row.cells.len() as u32).max(1)) as usize).min(row.cells.len() - 1));
+        row.cells[first_cell..row.cells.len().min(first_cell.saturating_add(max_cells))].iter()
+            .enumerate()
+            .map(|(cell, set)| (first_cell + cell, set))
+            .map(|(cell, set)| {
+                let level = match self.source {
+                    CellSource::Occupancy => None,
+                    source if *set => {
+                        let (start, len) = cell_span(row, cell);
+                        doc.cell_level(start, len, source)
+                    },
+                    _ => None,
+                };
+                if entry_cell == Some(cell) {
+                    return (CHR_ENTRY, theme.entry);
+                }
+                let (start, len) = cell_span(row, cell);
+                let full = *set && doc.cell_full(start, len);
+                let glyph = cell_glyph(*set, full, overlay_cells.map(|other| other[cell]), level, theme, &self.glyphs);
+                let first_match = doc.found.partition_point(|(_, end)| *end <= start as u64);
+                match doc.found.get(first_match) {
+                    Some((match_start, _)) if *match_start < start as u64 + len as u64 => (glyph.0, theme.found),
+                    _ => glyph,
+                }
+            })
+            .collect()
+    }
+
+    /* The names of the regions and build labels starting on a line, then where the device flash starts or ends on it */
+    fn row_label(&self, row: &MapRow) -> Option<String> {
+        let mut parts: Vec<String> = [&self.labels, &self.annotations].into_iter()
+            .filter_map(|labels| regions::line_label(labels, row.addr, row.len))
+            .collect();
+        if let Some(flash) = &self.flash {
+            let on_row = |addr: u64| row.addr as u64 <= addr && addr < row.addr as u64 + row.len as u64;
+            if on_row(flash.start as u64) {
+                parts.push(format!("[{} flash start]", flash.name));
+            }
+            if on_row(flash.end().saturating_sub(1)) {
+                parts.push(format!("[{} flash end]", flash.name));
+            }
+        }
+        if parts.is_empty() {None} else {Some(parts.join(" "))}
+    }
+
+    /**
+     * Draws the rows of a file from the current top and first character, starting at a column, overlaid with a second
+     * file if given. Side by side panes leave out the region labels. The highlight and selection of the file on screen
+     * show on every pane.
+     */
+    fn draw_pane(&self, frame: &mut Frame, doc: &Document, overlay: Option<&Document>, column: u16, clip: Option<u16>, theme: &Theme) {
+        let top = self.doc().top;
+        let last_shown = (top + self.visible_rows()).min(doc.rows.len());
+        let hex_width = gutter_hex_width(&doc.rows);
+        let first_cell = self.first_cell();
+        let max_cells = self.visible_cells();
+        let selected = self.doc().selected_cells().or(self.doc().highlight.map(|cell| (cell, cell)));
+        for (screen_row, row) in doc.rows[top..last_shown].iter().enumerate() {
+            let row_idx = top + screen_row;
+            frame.move_to(column, MAP_TOP + screen_row as u16);
+            /* Print a hex value of the desired length for the address */
+            frame.print(&gutter_text(row.addr, hex_width));
+            let glyphs = self.row_glyphs(doc, overlay, row_idx, first_cell, max_cells, theme);
+            for (chr, chr_style) in &glyphs {
+                frame.print_styled(chr_style.apply(*chr));
+            }
+            /* The selection (or just the highlighted character) is drawn in the highlight style */
+            if let Some((first, last)) = selected {
+                for (shown, (chr, _)) in glyphs.iter().enumerate() {
+                    let cell = first_cell + shown;
+                    if first <= (row_idx, cell) && (row_idx, cell) <= last {
+                        frame.move_to_column(column + (GUTTER_W + shown) as u16);
+                        frame.print_styled(theme.highlight.apply(*chr));
+                    }
+                }
+            }
+            if let Some(label) = self.row_label(row).filter(|_| clip.is_none()) {
+                frame.move_to_column((GUTTER_W + glyphs.len() + 1) as u16);
+                frame.print(&label);
+            }
+        }
+    }
+
+    /**
+     * The map as it is on screen, as plain text: the status bar, then each visible line with its address gutter, cells
+     * and region labels. Lines run on past the right edge of the screen unless split panes are put side by side; the
+     * highlight, panels, minimap and scrollbars are left out.
+     */
+    pub fn snapshot(&self) -> Vec<String> {
+        let doc = self.doc();
+        let last_shown = (doc.top + self.visible_rows()).min(doc.rows.len());
+        let theme = Theme::new(self.theme);
+        let pane_text = |pane: &Document, overlay: Option<&Document>, row_idx: usize, max_cells: usize| {
+            let mut line = gutter_text(pane.rows[row_idx].addr, gutter_hex_width(&pane.rows));
+            line.extend(self.row_glyphs(pane, overlay, row_idx, self.first_cell(), max_cells, &theme).iter().map(|(chr, _)| chr));
+            line
+        };
+        let mut lines = vec![self.status_bar(last_shown).trim().to_string()];
+        for row_idx in doc.top..last_shown {
+            let line = match (self.compare, self.compare_pair()) {
+                (CompareMode::Split, Some((left, right))) => {
+                    let max_cells = self.visible_cells();
+                    format!("{} {}", pane_text(&self.docs[left], None, row_idx, max_cells), pane_text(&self.docs[right], None, row_idx, max_cells))
+                },
+                (CompareMode::Overlay, Some((a, b))) => pane_text(&self.docs[a], Some(&self.docs[b]), row_idx, usize::MAX),
+                _ => pane_text(doc, None, row_idx, usize::MAX),
+            };
+            let row = &doc.rows[row_idx];
+            match self.row_label(row).filter(|_| self.compare != CompareMode::Split) {
+                Some(label) => lines.push(format!("{line} {label}")),
+                None => lines.push(line),
+            }
+        }
+        lines
+    }
+
+    /* Writes the snapshot of the screen to a text file */
+    fn write_snapshot(&mut self, path: &str) {
+        let mut contents = self.snapshot().join("\n");
+        contents.push('\n');
+        self.status = match fs::write(path, contents) {
+            Ok(()) => format!("Wrote the screen to {path}"),
+            Err(e) => format!("Could not write {path}: {e}"),
+        };
+    }
+
+    /* Draws the whole screen into a frame */
+    fn render(&self) -> Frame {
+        let (width, height) = terminal::size().unwrap_or((80, 24));
+        let mut frame = Frame::new(width, height);
+        let doc = self.doc();
+        let last_shown = (doc.top + self.visible_rows()).min(doc.rows.len());
+        let theme = Theme::new(self.theme);
+
+        /* All files have the same rows, so one scroll position keeps them lined up */
+        match (self.compare, self.compare_pair()) {
+            (CompareMode::Split, Some((left, right))) => {
+                let pane_w = pane_width(2);
+                self.draw_pane(&mut frame, &self.docs[left], None, 0, Some(pane_w), &theme);
+                self.draw_pane(&mut frame, &self.docs[right], None, pane_w + 1, Some(pane_w), &theme);
+            },
+            (CompareMode::Overlay, Some((a, b))) => {
+                self.draw_pane(&mut frame, &self.docs[a], Some(&self.docs[b]), 0, None, &theme);
+            },
+            _ => self.draw_pane(&mut frame, doc, None, 0, None, &theme),
+        }
+
+        if self.show_minimap {
+            self.draw_minimap(&mut frame, &theme);
+        }
+        self.draw_scrollbars(&mut frame, &theme);
+
+        if let Some((start, len)) = self.inspector {
+            self.draw_inspector(&mut frame, start, len);
+        }
+
+        if let Some(lines) = &self.records {
+            self.draw_records(&mut frame, lines);
+        }
+
+        if self.show_bookmarks {
+            self.draw_bookmarks(&mut frame);
+        }
+
+        if self.show_help {
+            self.draw_help(&mut frame);
+        }
+
+        let status_bar = self.status_bar(last_shown);
+        frame.move_to(0, height.saturating_sub(2));
+        frame.print_styled(format!("{status_bar:<w$}", w = width as usize).reverse());
+
+        let status_line = match &self.prompt {
+            Some(prompt) => match prompt.kind {
+                PromptKind::Goto => format!("Go to address: {}", prompt.input),
+                PromptKind::Search => match self.search_target(&prompt.input) {
+                    Some((addr, Some(name))) => format!("Search address or region: {}  ({name} at {addr:#010x})", prompt.input),
+                    Some((addr, None)) => format!("Search address or region: {}  ({addr:#010x})", prompt.input),
+                    None if prompt.input.trim().is_empty() => "Search address or region: ".to_string(),
+                    None => format!("Search address or region: {}  (no match)", prompt.input),
+                },
+                PromptKind::Command => {
+                    let name = prompt.input.split_whitespace().next().unwrap_or_default();
+                    match find_command(name) {
+                        Ok((name, arguments, action)) if !prompt.input.trim().is_empty() => {
+                            format!(":{}  ({}: {action})", prompt.input, format!("{name} {arguments}").trim_end())
+                        },
+                        _ => format!(":{}", prompt.input),
+                    }
+                },
+                PromptKind::WriteSelection => format!("Write selection to (.hex or .bin): {}", prompt.input),
+                PromptKind::RegionName => {
+                    let (start, end) = self.annotation_span();
+                    format!("Name {start:#010x}-{:#010x} (empty removes it): {}", end - 1, prompt.input)
+                },
+                PromptKind::Snapshot => format!("Write the screen as text to: {}", prompt.input),
+                PromptKind::LineWidth => format!("Bytes per line (now {:#x}): {}", self.bytes_per_line, prompt.input),
+                PromptKind::DisplayWidth => format!("Characters per line, 0 to fit (now {}): {}", self.width_symbols, prompt.input),
+            },
+            None => self.status.clone(),
+        };
+        frame.move_to(0, height.saturating_sub(1));
+        frame.print(&status_line);
+        frame
+    }
+
+    /* Puts the screen on the terminal, writing only the rows which changed since the last frame */
+    fn draw(&mut self) -> io::Result<()> {
+        let frame = self.render();
+        frame.write_changes(&mut stdout(), self.last_frame.as_ref())?;
+        self.last_frame = Some(frame);
+        Ok(())
+    }
+
+    /* Acts on a finished prompt */
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        match prompt.kind {
+            PromptKind::Goto => match parse_address(&prompt.input) {
+                Some(addr) => self.goto_addr(addr),
+                None => self.status = format!("Not a hex address: {}", prompt.input),
+            },
+            PromptKind::Search => {
+                self.search_origin = None;
+                if let Some((addr, name)) = self.search_target(&prompt.input) {
+                    self.goto_addr(addr);
+                    if let Some(name) = name {
+                        self.status = format!("{name}: {}", self.status);
+                    }
+                }
+            },
+            PromptKind::Command => self.run_command(&prompt.input),
+            PromptKind::WriteSelection => self.write_selection(prompt.input.trim()),
+            PromptKind::RegionName => self.name_region(prompt.input.trim()),
+            PromptKind::Snapshot => self.write_snapshot(prompt.input.trim()),
+            PromptKind::LineWidth => match maybe_hex::<u16>(prompt.input.trim()) {
+                Ok(bytes_per_line) if bytes_per_line > 0 => {
+                    self.set_bytes_per_line(bytes_per_line);
+                    self.status = self.describe_layout();
+                },
+                _ => self.status = format!("Not a line width: {}", prompt.input),
+            },
+            PromptKind::DisplayWidth => match maybe_hex::<u16>(prompt.input.trim()) {
+                Ok(width_symbols) => {
+                    self.set_width_symbols(width_symbols);
+                    self.status = self.describe_layout();
+                },
+                Err(_) => self.status = format!("Not a display width: {}", prompt.input),
+            },
+        }
+    }
+
+    /**
+     * Runs a line typed at the : prompt, a command name followed by its arguments, e.g. "goto 0x8004000",
+     * "width 0x800" or "export ld memory.ld". The commands are the same actions as the keys.
+     */
+    fn run_command(&mut self, line: &str) {
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            return;
+        };
+        let (name, arguments, _) = match find_command(name) {
+            Ok(command) => command,
+            Err(e) => {
+                self.status = e;
+                return;
+            },
+        };
+        let args: Vec<&str> = words.collect();
+        let rest = line.trim_start().split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim());
+        let usage = format!("Usage: {name} {arguments}").trim_end().to_string();
+        match (*name, &args[..]) {
+            ("goto", [_, ..]) => match self.search_target(rest) {
+                Some((addr, _)) => self.goto_addr(addr),
+                None => self.status = format!("No address or region {rest}"),
+            },
+            ("width", [_]) => self.submit_prompt(Prompt { kind: PromptKind::LineWidth, input: rest.to_string() }),
+            ("display", [_]) => self.submit_prompt(Prompt { kind: PromptKind::DisplayWidth, input: rest.to_string() }),
+            ("theme", []) => {
+                self.theme = self.theme.next();
+                self.status = format!("Theme: {}", self.theme.label());
+            },
+            ("theme", [theme]) => match <ThemeName as clap::ValueEnum>::from_str(theme, true) {
+                Ok(theme) => {
+                    self.theme = theme;
+                    self.status = format!("Theme: {}", self.theme.label());
+                },
+                Err(_) => self.status = format!("No theme {theme}, there are default, monochrome, solarized and high-contrast"),
+            },
+            ("cells", []) => self.cycle_source(),
+            ("cells", [source]) => {
+                let wanted = [CellSource::Occupancy, CellSource::Density, CellSource::Value, CellSource::Entropy, CellSource::Text].into_iter()
+                    .find(|candidate| candidate.label().ends_with(&source.to_lowercase()));
+                match wanted {
+                    Some(wanted) => self.set_source(wanted),
+                    None => self.status = usage,
+                }
+            },
+            ("file", [number]) => match number.parse::<usize>() {
+                Ok(number) if number > 0 => self.switch_to(number - 1),
+                _ => self.status = usage,
+            },
+            ("split", []) => self.toggle_compare(CompareMode::Split),
+            ("overlay", []) => self.toggle_compare(CompareMode::Overlay),
+            ("minimap", []) => self.show_minimap = !self.show_minimap,
+            ("copy", []) => self.copy_selection(),
+            ("write", [_, ..]) => self.write_selection(rest),
+            ("name", _) => self.name_region(rest),
+            ("snapshot", [_, ..]) => self.write_snapshot(rest),
+            ("find", []) => self.jump_to_match(),
+            ("find", [_, ..]) => self.find(rest),
+            ("diff", [direction]) if "next".starts_with(direction) => self.jump_to_difference(true),
+            ("diff", [direction]) if "prev".starts_with(direction) => self.jump_to_difference(false),
+            ("export", [kind, path]) => self.export(kind, path),
+            ("help", []) => {
+                self.status = format!("Commands: {}", COMMANDS.iter().map(|(command, _, _)| *command).collect::<Vec<_>>().join(" "));
+            },
+            ("quit", []) => self.quit = true,
+            _ => self.status = usage,
+        }
+    }
+
+    /* Writes one of the exports of the command line for the file on screen */
+    fn export(&mut self, kind: &str, path: &str) {
+        let doc = self.doc();
+        let contents = match kind {
+            "ld" => Ok(exports::ld_memory_block(&doc.ranges, self.flash.as_ref().map(Flash::end)).into_bytes()),
+            "bitmask" => Ok(exports::occupancy_bitmask(&doc.segment_map)),
+            "regions" => {
+                let detected = regions::regions_from_ranges(&doc.ranges);
+                Ok(regions::write_regions(&detected, regions::RegionFormat::from_path(path)).into_bytes())
+            },
+            "gdb" => exports::ImageFormat::of_file(&doc.file_path)
+                .map(|format| exports::gdb_script(&doc.file_path, format, &doc.ranges, true).into_bytes())
+                .map_err(|e| e.to_string()),
+            "gaps" => exports::gaps_hex(&doc.ranges, self.fill_byte).map(String::into_bytes).map_err(|e| e.to_string()),
+            _ => {
+                self.status = format!("No export {kind}, there are ld, bitmask, regions, gdb and gaps");
+                return;
+            },
+        };
+        self.status = match contents.and_then(|contents| fs::write(path, contents).map_err(|e| e.to_string())) {
+            Ok(()) => format!("Exported {kind} of {} to {path}", doc.file_path),
+            Err(e) => format!("Could not write {path}: {e}"),
+        };
+    }
+
+    /* Edits the open prompt */
+    fn handle_prompt_key(&mut self, key: KeyEvent) {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return;
+        };
+        match key.code {
+            KeyCode::Char(c) => prompt.input.push(c),
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            },
+            KeyCode::Enter => {
+                if let Some(prompt) = self.prompt.take() {
+                    self.submit_prompt(prompt);
+                }
+                return;
+            },
+            KeyCode::Esc => {
+                self.prompt = None;
+                self.restore_search_origin();
+                return;
+            },
+            _ => {},
+        }
+        if self.search_origin.is_some() {
+            self.preview_search();
+        }
+    }
+
+    /**
+     * Where typed search text leads, with the name of the region if it matched one. Region names are matched first (leading text,
+     * then anywhere in the name, ignoring case) so names made of hex digits still work, then the text is taken as an
+     * address.
+     */
+    fn search_target(&self, text: &str) -> Option<(u32, Option<String>)> {
+        let text = text.trim().to_lowercase();
+        if text.is_empty() {
+            return None;
+        }
+        let region = self.labels.iter().chain(&self.annotations)
+            .find(|region| region.name.to_lowercase().starts_with(&text))
+            .or_else(|| self.labels.iter().chain(&self.annotations).find(|region| region.name.to_lowercase().contains(&text)));
+        if let Some(region) = region {
+            return Some((region.start, Some(region.name.clone())));
+        }
+        parse_address(&text).map(|addr| (addr, None))
+    }
+
+    fn open_search(&mut self) {
+        self.search_origin = Some(self.doc().position());
+        self.prompt = Some(Prompt { kind: PromptKind::Search, input: String::new() });
+    }
+
+    /* Shows where the search text typed so far leads, going back to where the search started if nowhere */
+    fn preview_search(&mut self) {
+        let input = self.prompt.as_ref().map(|prompt| prompt.input.clone()).unwrap_or_default();
+        match self.search_target(&input) {
+            Some((addr, _)) => self.goto_addr(addr),
+            None => {
+                if let Some(origin) = self.search_origin {
+                    self.doc_mut().set_position(origin);
+                }
+            },
+        }
+    }
+
+    fn restore_search_origin(&mut self) {
+        if let Some(origin) = self.search_origin.take() {
+            self.doc_mut().set_position(origin);
+            self.status = String::new();
+        }
+    }
+
+    /* The span of the highlighted character, or of the first character on screen */
+    fn current_span(&self) -> (u32, u32) {
+        let (row_idx, cell) = self.doc().highlight.unwrap_or((self.doc().top, 0));
+        match self.doc().rows.get(row_idx) {
+            Some(row) => cell_span(row, cell),
+            None => (0, 0),
+        }
+    }
+
+    /* Marks every match of a pattern in the file on screen and goes to the first one from the current character on */
+    fn find(&mut self, text: &str) {
+        let pattern = match pattern::parse_pattern(text) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                self.status = e;
+                return;
+            },
+        };
+        let Some(values) = &self.doc().values else {
+            self.status = "Searching the data needs the byte values, open the file with --values".to_string();
+            return;
+        };
+        let found = pattern::find_pattern(&self.doc().segment_map, values, &pattern);
+        self.doc_mut().found = found;
+        if self.doc().found.is_empty() {
+            self.status = format!("No match for {text}");
+            return;
+        }
+        let (start, _) = self.current_span();
+        self.goto_match(start);
+    }
+
+    /* Goes to the first match of the last :find after the current character, from the top again after the last one */
+    fn jump_to_match(&mut self) {
+        if self.doc().found.is_empty() {
+            self.status = "Nothing found, :find <pattern> searches the data".to_string();
+            return;
+        }
+        let (start, len) = self.current_span();
+        self.goto_match(start.saturating_add(len));
+    }
+
+    fn goto_match(&mut self, from: u32) {
+        let found = &self.doc().found;
+        let idx = found.iter().position(|(start, _)| *start >= from as u64).unwrap_or(0);
+        let (count, (start, end)) = (found.len(), found[idx]);
+        self.goto_addr(start as u32);
+        self.status = format!("Match {} of {count} at {start:#010x}-{:#010x}", idx + 1, end - 1);
+    }
+
+    /* Moves to the start of the next (or previous) occupied range after (or before) the current character */
+    fn jump_to_data(&mut self, forward: bool) {
+        let (start, len) = self.current_span();
+        let ranges = &self.doc().ranges;
+        let target = if forward {
+            ranges.iter().find(|range| range.0 >= start as u64 + len as u64).map(|range| range.0 as u32)
+        } else {
+            ranges.iter().rev().find(|range| range.0 < start as u64).map(|range| range.0 as u32)
+        };
+        match target {
+            Some(addr) => self.goto_addr(addr),
+            None => self.status = format!("No more data {}", if forward {"after"} else {"before"}),
+        }
+    }
+
+    /**
+     * Moves to the start of the next (or previous) run of addresses where the compared files differ. Going forward
+     * skips the rest of a difference the current character is in.
+     */
+    fn jump_to_difference(&mut self, forward: bool) {
+        let Some((a, b)) = self.compare_pair() else {
+            self.status = "Differences need two files compared, s or o compares with the next file".to_string();
+            return;
+        };
+        let (a, b) = (&self.docs[a], &self.docs[b]);
+        let (start, len) = self.current_span();
+        let target = if forward {
+            let from = u32::try_from(start as u64 + len as u64).ok();
+            let in_difference = from.is_some_and(|from| from > 0 && scan_differences(a, b, from - 1, true, true) == Some(from - 1));
+            let from = if in_difference {from.and_then(|from| scan_differences(a, b, from, true, false))} else {from};
+            from.and_then(|from| scan_differences(a, b, from, true, true))
+        } else {
+            start.checked_sub(1)
+                .and_then(|from| scan_differences(a, b, from, false, true))
+                .map(|last| scan_differences(a, b, last, false, false).map_or(0, |agreeing| agreeing + 1))
+        };
+        match target {
+            Some(addr) => {
+                self.goto_addr(addr);
+                self.status = format!("Difference at {addr:#010x}, in {}", self.status);
+            },
+            None => self.status = format!("No more differences {}", if forward {"after"} else {"before"}),
+        }
+    }
+
+    /* Remembers the address of the current character */
+    fn add_bookmark(&mut self) {
+        let (addr, _) = self.current_span();
+        if !self.bookmarks.contains(&addr) {
+            self.bookmarks.push(addr);
+        }
+        self.status = format!("Bookmarked {addr:#010x}");
+    }
+
+    /* Jumps to the given (0 based) bookmark */
+    fn goto_bookmark(&mut self, idx: usize) {
+        match self.bookmarks.get(idx) {
+            Some(addr) => self.goto_addr(*addr),
+            None => self.status = format!("There is no bookmark {}", idx + 1),
+        }
+    }
+
+    /* Applies a binding only the vim keymap has, returning false if the key is not one of them */
+    fn handle_vim_key(&mut self, key: KeyEvent) -> bool {
+        let half_page = (self.visible_rows() / 2).max(1) as isize;
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let pending_g = std::mem::take(&mut self.pending_g);
+        match key.code {
+            KeyCode::Char('g') if pending_g => self.doc_mut().top = 0,
+            KeyCode::Char('g') => self.pending_g = true,
+            KeyCode::Char('G') => self.doc_mut().top = self.max_top(),
+            KeyCode::Char('d') if ctrl => self.scroll_by(half_page),
+            KeyCode::Char('u') if ctrl => self.scroll_by(-half_page),
+            KeyCode::Char('h') => self.step_highlight(0, -1),
+            KeyCode::Char('j') => self.step_highlight(1, 0),
+            KeyCode::Char('k') => self.step_highlight(-1, 0),
+            KeyCode::Char('l') => self.step_highlight(0, 1),
+            KeyCode::Char('N') => self.jump_to_data(false),
+            _ => return false,
+        }
+        true
+    }
+
+    /* Applies a key press, returning false once the viewer should close */
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        /* Raw mode turns Ctrl-C into a key press rather than a signal, so it quits from anywhere here */
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return false;
+        }
+        if self.prompt.is_some() {
+            self.handle_prompt_key(key);
+            return !self.quit;
+        }
+        if self.show_help {
+            self.show_help = false;
+            return true;
+        }
+        if self.show_bookmarks {
+            self.show_bookmarks = false;
+            if let KeyCode::Char(c @ '1'..='9') = key.code {
+                self.goto_bookmark(c as usize - '1' as usize);
+            }
+            return true;
+        }
+        if let Some(forward) = self.pending_bracket.take() {
+            match key.code {
+                KeyCode::Char('c') => self.jump_to_difference(forward),
+                KeyCode::Char('[') if !forward => self.widen(false),
+                KeyCode::Char(']') if forward => self.widen(true),
+                /* Anything else drops the bracket and does what it always does */
+                _ => return self.handle_key(key),
+            }
+            return true;
+        }
+        if self.keymap == Keymap::Vim && self.handle_vim_key(key) {
+            return true;
+        }
+        let page = self.visible_rows() as isize;
+        match key.code {
+            KeyCode::Esc if self.inspector.is_some() => self.inspector = None,
+            KeyCode::Esc if self.records.is_some() => self.records = None,
+            KeyCode::Esc if self.doc().anchor.is_some() => self.doc_mut().anchor = None,
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Enter => self.open_inspector(),
+            KeyCode::Char('r') => self.open_records(),
+            KeyCode::Char('?') => self.show_help = true,
+            KeyCode::Up if key.modifiers.contains(KeyModifiers::SHIFT) => self.extend_selection(-1, 0),
+            KeyCode::Down if key.modifiers.contains(KeyModifiers::SHIFT) => self.extend_selection(1, 0),
+            KeyCode::Left if key.modifiers.contains(KeyModifiers::SHIFT) => self.extend_selection(0, -1),
+            KeyCode::Right if key.modifiers.contains(KeyModifiers::SHIFT) => self.extend_selection(0, 1),
+            KeyCode::Up if key.modifiers.contains(KeyModifiers::CONTROL) => self.scroll_by(-1),
+            KeyCode::Down if key.modifiers.contains(KeyModifiers::CONTROL) => self.scroll_by(1),
+            KeyCode::Left if key.modifiers.contains(KeyModifiers::CONTROL) => self.scroll_sideways(-(SIDEWAYS_STEP as isize)),
+            KeyCode::Right if key.modifiers.contains(KeyModifiers::CONTROL) => self.scroll_sideways(SIDEWAYS_STEP as isize),
+            KeyCode::Up => self.move_cursor(-1, 0),
+            KeyCode::Down => self.move_cursor(1, 0),
+            KeyCode::Left => self.move_cursor(0, -1),
+            KeyCode::Right => self.move_cursor(0, 1),
+            KeyCode::PageUp => self.scroll_by(-page),
+            KeyCode::PageDown => self.scroll_by(page),
+            KeyCode::Home => self.doc_mut().top = 0,
+            KeyCode::End => self.doc_mut().top = self.max_top(),
+            KeyCode::Char('+') | KeyCode::Char('=') => self.zoom(true),
+            KeyCode::Char('-') => self.zoom(false),
+            KeyCode::Char(c @ ('[' | ']')) if self.compare != CompareMode::Off => {
+                self.pending_bracket = Some(c == ']');
+                self.status = format!("{c}c: {} difference, {c}{c}: {} characters per line",
+                                      if c == ']' {"next"} else {"previous"}, if c == ']' {"more"} else {"fewer"});
+            },
+            KeyCode::Char('[') => self.widen(false),
+            KeyCode::Char(']') => self.widen(true),
+            KeyCode::Char('L') => self.prompt = Some(Prompt { kind: PromptKind::LineWidth, input: String::new() }),
+            KeyCode::Char('W') => self.prompt = Some(Prompt { kind: PromptKind::DisplayWidth, input: String::new() }),
+            KeyCode::Char('n') => self.jump_to_data(true),
+            KeyCode::Char('p') => self.jump_to_data(false),
+            KeyCode::Char('g') => self.prompt = Some(Prompt { kind: PromptKind::Goto, input: String::new() }),
+            KeyCode::Char('/') => self.open_search(),
+            KeyCode::Char(':') => self.prompt = Some(Prompt { kind: PromptKind::Command, input: String::new() }),
+            KeyCode::Char('w') if self.selection().is_none() => self.show_selection(),
+            KeyCode::Char('w') => self.prompt = Some(Prompt { kind: PromptKind::WriteSelection, input: String::new() }),
+            KeyCode::Char('y') => self.copy_selection(),
+            KeyCode::Char('a') => self.open_region_name(),
+            KeyCode::Char('P') => self.prompt = Some(Prompt { kind: PromptKind::Snapshot, input: String::new() }),
+            KeyCode::Char('M') => self.show_minimap = !self.show_minimap,
+            KeyCode::Char('s') => self.toggle_compare(CompareMode::Split),
+            KeyCode::Char('o') => self.toggle_compare(CompareMode::Overlay),
+            KeyCode::Char('v') => self.cycle_source(),
+            KeyCode::Char('t') => {
+                self.theme = self.theme.next();
+                self.status = format!("Theme: {}", self.theme.label());
+            },
+            KeyCode::Char('m') => self.add_bookmark(),
+            KeyCode::Char('\'') => match self.bookmarks.len() {
+                0 => self.status = "No bookmarks yet, m adds one".to_string(),
+                count => self.goto_bookmark(count - 1),
+            },
+            KeyCode::Char('b') => self.show_bookmarks = true,
+            KeyCode::Tab => self.switch_to((self.active + 1) % self.docs.len()),
+            KeyCode::BackTab => self.switch_to((self.active + self.docs.len() - 1) % self.docs.len()),
+            KeyCode::Char(c @ '1'..='9') => self.switch_to(c as usize - '1' as usize),
+            _ => {},
+        }
+        true
+    }
+
+    /* Moves on to the next thing the characters show. The value and entropy views need the byte values, so without them it goes back to occupancy */
+    fn cycle_source(&mut self) {
+        let next = match self.source.next() {
+            CellSource::Value if self.doc().values.is_none() => CellSource::Occupancy,
+            next => next,
+        };
+        self.set_source(next);
+    }
+
+    fn set_source(&mut self, source: CellSource) {
+        if matches!(source, CellSource::Value | CellSource::Entropy | CellSource::Text) && self.doc().values.is_none() {
+            self.status = "The value, entropy and text views need --values".to_string();
+            return;
+        }
+        self.source = source;
+        self.status = match self.source {
+            CellSource::Occupancy => "Cells show occupancy".to_string(),
+            source => format!("Cells show {}, {} lowest to {} highest", source.label(), CHR_LEVELS[0], CHR_LEVELS[CHR_LEVELS.len() - 1]),
+        };
+    }
+
+    /* The (row index, character) under a screen position, if it is on the map */
+    fn screen_to_cell(&self, column: u16, row: u16) -> Option<(usize, usize)> {
+        let screen_row = row.checked_sub(MAP_TOP)? as usize;
+        let shown = (column as usize).checked_sub(GUTTER_W)?;
+        let cell = self.first_cell() + shown;
+        let row_idx = self.doc().top + screen_row;
+        let on_map = screen_row < self.visible_rows() && shown < self.visible_cells() && cell < self.doc().rows.get(row_idx)?.cells.len();
+        if on_map {Some((row_idx, cell))} else {None}
+    }
+
+    /* A left click on a cell shows what it covers, dragging from there selects */
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        let Some((row_idx, cell)) = self.screen_to_cell(mouse.column, mouse.row) else {
+            return;
+        };
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                self.status = self.describe_cell(row_idx, cell).unwrap_or_default();
+                self.doc_mut().highlight = Some((row_idx, cell));
+                self.doc_mut().anchor = None;
+            },
+            MouseEventKind::Drag(MouseButton::Left) => {
+                let doc = self.doc_mut();
+                doc.anchor = doc.anchor.or(doc.highlight);
+                doc.highlight = Some((row_idx, cell));
+                self.show_selection();
+            },
+            _ => {},
+        }
+    }
+
+    /* Reloads any file whose modification time has changed since it was loaded, returning true if any had */
+    fn reload_changed(&mut self, load_options: LoadOptions) -> bool {
+        let mut changed = false;
+        for (idx, doc) in self.docs.iter_mut().enumerate() {
+            let modified = file_modified(&doc.file_path);
+            if modified.is_none() || modified == doc.modified {
+                continue;
+            }
+            /* A file caught half written is simply loaded again on its next change */
+            doc.modified = modified;
+            changed = true;
+            match hex_loader::load_file(&doc.file_path, load_options) {
+                Ok(image) => {
+                    self.status = load_problem_text(&doc.file_path, &image).unwrap_or(format!("Reloaded {}", doc.file_path));
+                    doc.replace_image(image);
+                },
+                Err(e) => self.status = format!("Could not reload {}: {e}", doc.file_path),
+            }
+            if idx == self.active {
+                self.inspector = None;
+                self.records = None;
+            }
+        }
+        /* A reload can touch new pages, which every file then needs lines for */
+        if changed {
+            self.rebuild_rows();
+        }
+        changed
+    }
+
+    /* Takes in whatever the background loads have parsed since last time, returning true if anything had */
+    fn receive_loaded(&mut self) -> bool {
+        let mut changed = false;
+        let mut new_pages = false;
+        let mut stopped = Vec::new();
+        let mut errors = Vec::new();
+        for doc in &mut self.docs {
+            let Some(mut loading) = doc.loading.take() else {
+                continue;
+            };
+            loop {
+                match loading.events.try_recv() {
+                    Ok(LoadEvent::Progress(done, total)) => (loading.done, loading.total) = (done, total),
+                    Ok(LoadEvent::Pages(pages)) => {
+                        doc.add_pages(pages);
+                        new_pages = true;
+                    },
+                    Ok(LoadEvent::Loaded(image)) => {
+                        errors.extend(load_problem_text(&doc.file_path, &image));
+                        doc.replace_image(image);
+                        new_pages = true;
+                        changed = true;
+                        break;
+                    },
+                    Ok(LoadEvent::Failed(reason)) => {
+                        errors.push(format!("Loading {} stopped early: {reason}", doc.file_path));
+                        changed = true;
+                        break;
+                    },
+                    Err(TryRecvError::Empty) => {
+                        doc.loading = Some(loading);
+                        break;
+                    },
+                    Err(TryRecvError::Disconnected) => {
+                        stopped.push(doc.file_path.clone());
+                        changed = true;
+                        break;
+                    },
+                }
+                changed = true;
+            }
+        }
+        if !errors.is_empty() {
+            self.status = errors.join(" | ");
+        }
+        if !stopped.is_empty() {
+            self.status = format!("Loading {} stopped early", stopped.join(", "));
+        }
+        /* New pages can be ones no file had before, which every file then needs lines for */
+        if new_pages {
+            self.rebuild_rows();
+        }
+        changed
+    }
+
+    /* How long to wait for input before checking on background loads or watched files, if at all */
+    fn poll_interval(&self) -> Option<Duration> {
+        if self.docs.iter().any(|doc| doc.loading.is_some()) {
+            Some(LOAD_INTERVAL)
+        } else if self.watch.is_some() {
+            Some(WATCH_INTERVAL)
+        } else {
+            None
+        }
+    }
+
+    fn event_loop(&mut self) -> io::Result<()> {
+        let mut running = true;
+        let mut redraw = true;
+        while running {
+            redraw |= self.receive_loaded();
+            if redraw {
+                self.draw()?;
+            }
+            redraw = true;
+            if let Some(interval) = self.poll_interval() {
+                if !event::poll(interval)? {
+                    redraw = match self.watch {
+                        Some(load_options) => self.reload_changed(load_options),
+                        None => false,
+                    };
+                    continue;
+                }
+            }
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => running = self.handle_key(key),
+                Event::Mouse(mouse) => self.handle_mouse(mouse),
+                Event::Resize(_, _) => self.handle_resize(),
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Takes over the terminal until the user quits. A panic on the way gives the terminal back before the panic
+     * message is printed, so the message can be read and the shell is usable again.
+     */
+    pub fn run(&mut self) -> io::Result<()> {
+        /* The hook installed before is shared with ours while the view runs, then put back as it was */
+        let previous_hook = Arc::new(panic::take_hook());
+        let chained_hook = Arc::clone(&previous_hook);
+        panic::set_hook(Box::new(move |info| {
+            let _ = restore_terminal();
+            chained_hook(info);
+        }));
+        /* The terminal is put back however far setting it up got, and whatever the view stopped with */
+        let result = execute!(stdout(), terminal::EnterAlternateScreen, cursor::Hide, event::EnableMouseCapture)
+            .and_then(|_| terminal::enable_raw_mode())
+            .and_then(|_| self.event_loop());
+        let restored = restore_terminal();
+        drop(panic::take_hook());
+        match Arc::try_unwrap(previous_hook) {
+            Ok(previous_hook) => panic::set_hook(previous_hook),
+            Err(previous_hook) => panic::set_hook(Box::new(move |info| previous_hook(info))),
+        }
+        result.and(restored)
+    }
+}
+
+/* Undoes everything run sets up on the terminal. Every step is tried even if one fails, and the first failure returned */
+fn restore_terminal() -> io::Result<()> {
+    let raw_mode = terminal::disable_raw_mode();
+    let mouse = execute!(stdout(), event::DisableMouseCapture);
+    let cursor = execute!(stdout(), cursor::Show);
+    let screen = execute!(stdout(), terminal::LeaveAlternateScreen);
+    raw_mode.and(mouse).and(cursor).and(screen)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hex_loader::{load_hex, LoadOptions};
+    use crate::theme::Glyphs;
+    use crate::viewer::{find_command, map_text, parse_address, scan_differences, Document};
+
+    #[test]
+    fn test_parse_address() -> Result<(),String> {
+        assert_eq!(Some(0x1F400), parse_address("0x1F400"));
+        assert_eq!(Some(0x1F400), parse_address(" 1f400 "));
+        assert_eq!(Some(0x08000000), parse_address("0x0800_0000"));
+        assert_eq!(None, parse_address("xyz"));
+        assert_eq!(None, parse_address(""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_command() -> Result<(),String> {
+        assert_eq!("goto", find_command("goto")?.0);
+        assert_eq!("goto", find_command("g")?.0);
+        assert_eq!("snapshot", find_command("sn")?.0);
+        assert!(find_command("s").is_err_and(|e| e.contains("split") && e.contains("snapshot")));
+        assert!(find_command("frobnicate").is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_scan_differences() -> Result<(),String> {
+        /* a has 0x0-0x3, b has 0x2-0x5, agreeing on the value at 0x2 only */
+        let load = |contents: &str, store_values: bool| Document::new(String::new(), load_hex(contents, LoadOptions { store_values, blank_byte: None }));
+        let (a, b) = (load(":0400000001020304F2\n", true), load(":0400020003050607E5\n", true));
+        assert_eq!(Some(0x0), scan_differences(&a, &b, 0x0, true, true));
+        assert_eq!(Some(0x2), scan_differences(&a, &b, 0x0, true, false));
+        assert_eq!(Some(0x3), scan_differences(&a, &b, 0x2, true, true));
+        assert_eq!(Some(0x6), scan_differences(&a, &b, 0x3, true, false));
+        assert_eq!(None, scan_differences(&a, &b, 0x6, true, true));
+        assert_eq!(Some(0x5), scan_differences(&a, &b, 0x10, false, true));
+        assert_eq!(Some(0x2), scan_differences(&a, &b, 0x5, false, false));
+
+        /* Without values only occupancy counts */
+        let (a, b) = (load(":0400000001020304F2\n", false), load(":0400020003050607E5\n", false));
+        assert_eq!(Some(0x4), scan_differences(&a, &b, 0x2, true, true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_text_sparse_pages() -> Result<(),String> {
+        /* Data in page 0x0001 and at 0xF0000000, the pages before and between them a line each */
+        let image = load_hex(":020000040001F9\n:0400000001020304F2\n:02000004F0000A\n:0400000001020304F2\n:00000001FF\n", LoadOptions::default());
+        let lines = map_text(image, &[], 0x8000, 4, Glyphs::default());
+        assert_eq!(vec![
+            "0x00000000░░░░",
+            "0x00010000▒░░░",
+            "0x00018000░░░░",
+            "0x00020000░░░░",
+            "0xf0000000▒░░░",
+            "0xf0008000░░░░",
+        ], lines.iter().map(|line| line.replace(' ', "")).collect::<Vec<_>>());
+        /* Four bytes per character: the first full, the second with two of its four bytes */
+        let image = load_hex(":06000000010203040506E5
+:00000001FF
+", LoadOptions::default());
+        let lines = map_text(image, &[], 0x10, 4, "#.+".parse()?);
+        assert_eq!("0x0000#+..", lines[0].replace(' ', ""));
+        assert!("##".parse::<Glyphs>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use crate::hex_loader::{load_hex, LoadOptions};
+use crate::stats::OccupancyStats;
+use crate::structured::Value;
+use crate::{map_rows, IHEX_SEGMENT_BYTES};
+
+/**
+ * The map of a hex file for the web viewer, as JSON: the headline stats, any unreadable lines and the rows the terminal
+ * viewer would show, each row's cells as a string of 0s and 1s. A bad line width gives {"error": ...} instead.
+ */
+pub fn browser_map(contents: &str, bytes_per_line: u16, width_symbols: u16) -> String {
+    if !IHEX_SEGMENT_BYTES.is_multiple_of(bytes_per_line as u32) {
+        return Value::object([("error", "The line width has to divide 0x10000".into())]).to_json();
+    }
+    if !bytes_per_line.is_multiple_of(width_symbols) {
+        return Value::object([("error", "The display width has to divide the line width".into())]).to_json();
+    }
+    let image = load_hex(contents, LoadOptions::default());
+    let mut pages: Vec<u16> = image.segment_map.keys().copied().collect();
+    pages.sort_unstable();
+    let rows = map_rows(&image.segment_map, bytes_per_line, width_symbols, &pages).into_iter()
+        .map(|row| Value::object([
+            ("addr", row.addr.into()),
+            ("len", row.len.into()),
+            ("cells", row.cells.iter().map(|set| if *set {'1'} else {'0'}).collect::<String>().into()),
+        ]))
+        .collect();
+    Value::object([
+        ("stats", OccupancyStats::from_map(&image.segment_map).to_value()),
+        ("errors", image.errors.iter().map(|error| error.to_string()).collect::<Vec<_>>().into()),
+        ("rows", Value::List(rows)),
+    ]).to_json()
+}
+
+/**
+ * The functions the page calls, over plain linear memory so no bindings generator is needed. The page allocates a
+ * buffer with ihex_alloc, copies the file in, calls ihex_map, then reads as many bytes as it returns from ihex_result.
+ */
+#[cfg(target_arch = "wasm32")]
+mod exports {
+    use std::cell::RefCell;
+
+    thread_local! {
+        static RESULT: RefCell<String> = const { RefCell::new(String::new()) };
+    }
+
+    #[no_mangle]
+    pub extern "C" fn ihex_alloc(len: usize) -> *mut u8 {
+        let mut buffer = Vec::<u8>::with_capacity(len);
+        let ptr = buffer.as_mut_ptr();
+        std::mem::forget(buffer);
+        ptr
+    }
+
+    /**
+     * # Safety
+     * ptr and len have to be from one ihex_alloc call, and the buffer is freed so cannot be used again.
+     */
+    #[no_mangle]
+    pub unsafe extern "C" fn ihex_map(ptr: *mut u8, len: usize, bytes_per_line: u32, width_symbols: u32) -> usize {
+        let contents = Vec::from_raw_parts(ptr, len, len);
+        let map = super::browser_map(&String::from_utf8_lossy(&contents), bytes_per_line as u16, width_symbols as u16);
+        RESULT.with(|result| {
+            *result.borrow_mut() = map;
+            result.borrow().len()
+        })
+    }
+
+    /* The JSON from the last ihex_map call, valid until the next one */
+    #[no_mangle]
+    pub extern "C" fn ihex_result() -> *const u8 {
+        RESULT.with(|result| result.borrow().as_ptr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wasm::browser_map;
+
+    #[test]
+    fn test_browser_map() -> Result<(),String> {
+        let map = browser_map(":0400000001020304F2\n:00000001FF\n", 0x1000, 16);
+        assert!(map.contains("\"bytes_used\": 4,"));
+        assert!(map.contains("\"addr\": 0,\n      \"len\": 4096,\n      \"cells\": \"1000000000000000\""));
+        assert!(map.contains("\"addr\": 61440,"));
+        assert!(browser_map("", 0x1000, 0).contains("\"error\""));
+        Ok(())
+    }
+}